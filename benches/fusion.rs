@@ -0,0 +1,44 @@
+//! Measures how much faster programs of the corpus run once pairs of commands are fused (see
+//! [`OptimizeOptions::fuse_commands`]), which is only worth the extra commands if it is.
+//!
+//! Run with `cargo bench --bench fusion`.
+
+use std::hint::black_box;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use brainfuck_interpreter::{optimize, parse, Interpreter, OptimizeOptions};
+
+/// The number of times each program runs with each option, of which the fastest run is kept.
+const RUNS: usize = 10;
+
+/// Returns the duration of the fastest run of a program on some input.
+fn fastest_run(code: &str, input: &[u8], fuse_commands: bool) -> Duration {
+    let options = OptimizeOptions { fuse_commands, ..OptimizeOptions::default() };
+    let interpreter = Interpreter::new(optimize(parse(code).unwrap(), &options));
+    (0..RUNS).map(|_| {
+        let mut output = Vec::new();
+        let start = Instant::now();
+        assert!(interpreter.run(black_box(input), &mut output).is_continue());
+        let duration = start.elapsed();
+        black_box(output);
+        duration
+    }).min().unwrap()
+}
+
+fn main() {
+    let read = |path: &str| std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join(path)).unwrap();
+    let text: Vec<u8> = b"The quick brown fox jumps over the lazy dog. ".iter().copied().cycle().take(20_000).collect();
+    // Sorting bytes in reverse order is the worst case of a bubble sort.
+    let reversed: Vec<u8> = (0..300).map(|i| b'~' - (i % 90) as u8).collect();
+    let corpus = [
+        ("tests/programs/bubble_sort.b", read("tests/programs/bubble_sort.b"), reversed),
+        ("examples/rot13.b", read("examples/rot13.b"), [&text[..], b"\n"].concat()),
+    ];
+    println!("{:<30} {:>12} {:>12} {:>8}", "program", "unfused", "fused", "speedup");
+    for (name, code, input) in corpus {
+        let unfused = fastest_run(&code, &input, false);
+        let fused = fastest_run(&code, &input, true);
+        println!("{:<30} {:>10.2}ms {:>10.2}ms {:>7.2}x", name, unfused.as_secs_f64() * 1000.0, fused.as_secs_f64() * 1000.0, unfused.as_secs_f64() / fused.as_secs_f64())
+    }
+}
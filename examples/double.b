@@ -0,0 +1,16 @@
+Prints each character of a line twice and stops at the end of the line
+Requires the ext1 dialect: the storage register keeps each character while its cell is cleared
+and the end of the line ends the program from inside two loops
+
+{test name="word" input="abc\n" output="aabbcc\n\n" dialect="ext1"}
+{test name="empty line" input="\n" output="\n\n" dialect="ext1"}
+{test name="rest of the input" input="hi\nignored\n" output="hhii\n\n" dialect="ext1"}
+
++[                  cell 0 stays 1 until the end
+  >,$.              read a character and store it and print it
+  [-]!.             clear it and restore it from the register and print it again
+  ----------        subtract the code of a newline
+  >+<[>-<[-]]       cell 2 is 1 only if the character was a newline
+  >[@]              in which case the program ends
+  <<
+]
@@ -0,0 +1,17 @@
+Prints Hi! using macros
+Requires the macros option
+
+{test name="greeting" output="Hi!\n" macros="true"}
+
+!def zero [-]
+!def add(n) { !rep !n { + } }
+!def sub(n) { !rep !n { - } }
+!def right(n) { !rep !n { > } }
+!def left(n) { !rep !n { < } }
+!def print(n) { !zero !add(!n) . }
+!def newline { !zero !add(10) . }
+
+!print(72)                      H
+!right(1) !print(105)           i
+!left(1) !add(33) !sub(72) .    exclamation mark
+!newline
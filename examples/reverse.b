@@ -0,0 +1,17 @@
+Reverses a line read from the input
+Requires the dual dialect: the second tape is used as a stack
+
+{test name="word" input="stressed\n" output="desserts\n" dialect="dual"}
+{test name="empty line" input="\n" output="\n" dialect="dual"}
+
++[                              while the flag in cell 0 is set
+    >>+<                        set the newline flag in cell 2
+    ,----------[                if the character in cell 1 is not a newline
+        ++++++++++^             push it onto the second tape
+        ~>~                     move the top of the stack
+        >-<                     clear the newline flag
+    ]
+    >[<<->>-]<<                 if the character was a newline then clear the flag in cell 0
+]
+~<[.<]                          pop and print the characters
+++++++++++.                     print a newline
@@ -0,0 +1,36 @@
+Applies ROT13 to a line read from the input
+Letters are rotated by 13 places and other characters are left as they are
+Run it on each line of a file with the per line and keep newline flags
+
+{test name="letters" input="Hello World\n" output="Uryyb Jbeyq\n"}
+{test name="wrapping" input="xyz NOP\n" output="klm ABC\n"}
+{test name="other characters" input="42 @\n" output="42 @\n"}
+{test name="empty line" input="\n" output="\n"}
+
+,----------[                    while the character in cell 0 is not a newline
+    ++++++++++[                 restore it and skip forward if the character is 0
+        >>++++[>++++++++<-]     set up divisor 32 for the division loop
+        <+<-[                   set up the dividend and enter the division loop
+            >+>+>-[>>>]         increase copy and remainder and reduce divisor
+            <[[>+<-]>>+>]       move remainder back to divisor and increase quotient
+            <<<<<-              decrement dividend
+        ]
+    ]>>>[-]+                    zero former divisor and reuse it as a flag
+    >--[-[<->+++[-]]]<[         zero the flag unless quotient was 2 or 3 then check it
+        ++++++++++++<[          set up divisor 13 for the second division loop
+            >-[>+>>]            reduce divisor and increase remainder
+            >[+[<+>-]>+>>]      move remainder back to divisor and increase quotient
+            <<<<<-              decrease dividend
+        ]
+        >>[<+>-]                add remainder back to divisor to get 13
+        >[                      skip forward if quotient was 0
+            -[                  skip forward if quotient was 1
+                -<<[-]>>        zero quotient and divisor if quotient was 2
+            ]<<[<<->>-]>>       zero divisor and subtract 13 from copy if quotient was 1
+        ]<<[<<+>>-]             zero divisor and add 13 to copy if quotient was 0
+    ]
+    <[-]                        clear remainder from first division
+    <.[-]                       print the rotated character from copy
+    <,----------                read the next character
+]
+++++++++++.                     print a newline
@@ -0,0 +1,213 @@
+use std::io;
+use std::io::Write;
+use std::mem;
+
+use brainfuck_interpreter::Command;
+
+/// A range of offsets from the initial position of the pointer, both included, where a bound of
+/// [`None`] means that the range is unbounded on that side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Bounds {
+    min: Option<isize>,
+    max: Option<isize>,
+}
+
+impl Bounds {
+    fn exactly(offset: isize) -> Self {
+        Self { min: Some(offset), max: Some(offset) }
+    }
+
+    /// Returns the offsets obtained by adding any offset of `other` to any offset of `self`.
+    fn shifted(self, other: Self) -> Self {
+        Self {
+            min: self.min.zip(other.min).map(|(a, b)| a + b),
+            max: self.max.zip(other.max).map(|(a, b)| a + b),
+        }
+    }
+
+    /// Returns the smallest range containing both ranges.
+    fn union(self, other: Self) -> Self {
+        Self {
+            min: self.min.zip(other.min).map(|(a, b)| a.min(b)),
+            max: self.max.zip(other.max).map(|(a, b)| a.max(b)),
+        }
+    }
+}
+
+/// Conservative bounds on the cells a program can reach, as computed by `--analyze`.
+#[derive(Debug)]
+pub struct Analysis {
+    /// The cells the pointer can reach, relative to the initial position of the pointer.
+    reached: Bounds,
+    /// The index of the loop that makes the cells on the left unbounded, if any.
+    left_cause: Option<usize>,
+    /// The index of the loop that makes the cells on the right unbounded, if any.
+    right_cause: Option<usize>,
+    /// The indices of the loops whose bodies do not move the pointer back to where they started,
+    /// which makes the bounds imprecise.
+    imprecise_loops: Vec<usize>,
+    /// The number of loops analyzed so far, used to index them.
+    loops: usize,
+}
+
+/// Computes conservative bounds on the cells a program can reach, or returns [`None`] if the
+/// program switches tapes, which the analysis does not support.
+///
+/// The passed commands are expected not to be optimized, so that their loops are the loops of the
+/// source code: loops are indexed in the order in which they appear in the source code, starting at
+/// 0.
+///
+/// Offsets are exact through code without loops. After a loop whose body moves the pointer, the
+/// pointer can be anywhere in the direction(s) the body moves it.
+pub fn analyze(commands: &[Command]) -> Option<Analysis> {
+    let mut analysis = Analysis {
+        reached: Bounds::exactly(0),
+        left_cause: None,
+        right_cause: None,
+        imprecise_loops: Vec::new(),
+        loops: 0,
+    };
+    analysis.sequence(commands, Bounds::exactly(0))?;
+    Some(analysis)
+}
+
+/// Returns the line and column (both starting at 1, and counted in bytes) of each `[` of some
+/// source code.
+fn loop_positions(code: &[u8]) -> Vec<(usize, usize)> {
+    let mut positions = Vec::new();
+    let (mut line, mut column) = (1, 1);
+    for &byte in code {
+        match byte {
+            b'\n' => {
+                line += 1;
+                column = 1;
+                continue;
+            }
+            b'[' => positions.push((line, column)),
+            _ => {}
+        }
+        column += 1
+    }
+    positions
+}
+
+impl Analysis {
+    /// Records that the cells in `bounds` can be reached, while analyzing the loop with the
+    /// specified index (if any).
+    fn reach(&mut self, bounds: Bounds, current_loop: Option<usize>) {
+        let reached = self.reached.union(bounds);
+        if self.reached.min.is_some() && reached.min.is_none() {
+            self.left_cause = self.left_cause.or(current_loop)
+        }
+        if self.reached.max.is_some() && reached.max.is_none() {
+            self.right_cause = self.right_cause.or(current_loop)
+        }
+        self.reached = reached
+    }
+
+    /// Analyzes commands executed with the pointer anywhere in `pointer`, and returns where the
+    /// pointer can be after them.
+    ///
+    /// Loops are analyzed with an explicit stack of the commands left in each, along with where
+    /// the pointer can be, and the index of the loop and the cells reached outside of it, so that
+    /// deeply nested loops do not overflow the call stack.
+    fn sequence(&mut self, commands: &[Command], pointer: Bounds) -> Option<Bounds> {
+        let mut stack = vec![(commands.iter(), pointer, None)];
+        while let Some((commands, pointer, _)) = stack.last_mut() {
+            let Some(command) = commands.next() else {
+                let (_, delta, current_loop) = stack.pop().expect("The stack is not empty");
+                let Some((index, outer_reached)) = current_loop else {
+                    return Some(delta);
+                };
+                let (_, pointer, _) = stack.last_mut().expect("Loops are in a sequence");
+                let body_reached = mem::replace(&mut self.reached, outer_reached);
+                if delta != Bounds::exactly(0) {
+                    self.imprecise_loops.push(index);
+                    if delta.min.is_none_or(|min| min < 0) {
+                        pointer.min = None
+                    }
+                    if delta.max.is_none_or(|max| max > 0) {
+                        pointer.max = None
+                    }
+                }
+                let reached = pointer.shifted(body_reached);
+                self.reach(reached, Some(index));
+                continue;
+            };
+            match command {
+                &Command::Right(amount) | &Command::AddRight(_, amount) => {
+                    *pointer = pointer.shifted(Bounds::exactly(amount));
+                    self.reach(*pointer, None)
+                }
+                &Command::ResetChunk(max_offset) => {
+                    self.reach(pointer.shifted(Bounds::exactly(max_offset)), None)
+                }
+                Command::Move(cells) => {
+                    for &(offset, _) in cells.iter() {
+                        self.reach(pointer.shifted(Bounds::exactly(offset)), None)
+                    }
+                }
+                Command::MoveSpan(span) => {
+                    let ends = [span.start, span.start + span.length as isize - 1];
+                    for offset in ends.into_iter().chain(span.rest.iter().map(|&(offset, _)| offset)) {
+                        self.reach(pointer.shifted(Bounds::exactly(offset)), None)
+                    }
+                }
+                Command::Mul(multiplication) => {
+                    let offsets = multiplication.targets.iter().map(|&(offset, _)| offset);
+                    for offset in offsets.chain([multiplication.operand, multiplication.temporary]) {
+                        self.reach(pointer.shifted(Bounds::exactly(offset)), None)
+                    }
+                }
+                Command::Loop(loop_commands) => {
+                    let index = self.loops;
+                    self.loops += 1;
+                    // Analyze the body relatively to where each iteration starts.
+                    let outer_reached = mem::replace(&mut self.reached, Bounds::exactly(0));
+                    stack.push((loop_commands.iter(), Bounds::exactly(0), Some((index, outer_reached))))
+                }
+                &Command::Scan(stride) => {
+                    *pointer = self.sequence(&[Command::scan_loop(stride)], *pointer)?
+                }
+                Command::SwitchTape => {
+                    return None;
+                }
+                Command::Add(_) | Command::Input | Command::Output | Command::TracedOutput(_) | Command::Reset | Command::DumpTape | Command::Halt
+                | Command::Store | Command::Restore | Command::Random | Command::ExchangeCells | Command::Assert(_) | Command::Position(_, _) => {}
+            }
+        }
+        unreachable!("The sequence returns when its last command is analyzed")
+    }
+
+    /// Writes a report of the analysis, locating loops in the source code of the program.
+    pub fn write_report(&self, code: &[u8], out: &mut impl Write) -> io::Result<()> {
+        let positions = loop_positions(code);
+        let locate = |index: usize| match positions.get(index) {
+            Some((line, column)) => format!("{}:{}", line, column),
+            None => format!("#{}", index),
+        };
+        let min = match (self.reached.min, self.left_cause) {
+            (Some(min), _) => min.to_string(),
+            (None, Some(index)) => format!("unbounded (because of the loop at {})", locate(index)),
+            (None, None) => "unbounded".to_string(),
+        };
+        let max = match (self.reached.max, self.right_cause) {
+            (Some(max), _) => max.to_string(),
+            (None, Some(index)) => format!("unbounded (because of the loop at {})", locate(index)),
+            (None, None) => "unbounded".to_string(),
+        };
+        writeln!(out, "Minimum pointer offset: {}", min)?;
+        writeln!(out, "Maximum pointer offset: {}", max)?;
+        let negative = if self.reached.min.is_some_and(|min| min >= 0) { "no" } else { "yes" };
+        writeln!(out, "Negative cells may be reached: {}", negative)?;
+        if self.imprecise_loops.is_empty() {
+            writeln!(out, "Imprecise loops: none")
+        } else {
+            // Inner loops are analyzed before the loops containing them.
+            let mut indices = self.imprecise_loops.clone();
+            indices.sort();
+            let loops: Vec<_> = indices.into_iter().map(locate).collect();
+            writeln!(out, "Imprecise loops: {}", loops.join(", "))
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::io;
+use std::io::Write;
+use std::ops::ControlFlow;
+
+use brainfuck_interpreter::tape::Tape;
+use brainfuck_interpreter::{annotation, Stop};
+
+/// The fraction of the count of the most executed line from which lines are marked as hot, unless
+/// `--hot-threshold` is passed.
+const DEFAULT_HOT_FRACTION: u64 = 10;
+
+/// A run of the same instruction in the source code, which is executed all at once.
+struct Instruction {
+    byte: u8,
+    /// The offset of the first byte of the run in the source code.
+    position: usize,
+    /// The number of bytes in the run.
+    length: usize,
+}
+
+/// Returns the instructions of some source code, skipping comments and annotations like [`load`]
+/// does. Only the eight standard instructions are kept.
+///
+/// [`load`]: brainfuck_interpreter::load
+fn instructions(code: &[u8]) -> Vec<Instruction> {
+    let mut instructions: Vec<Instruction> = Vec::new();
+    let mut position = 0;
+    while let Some(&byte) = code.get(position) {
+        if byte == b'{' && annotation(&code[position + 1..]).is_some() {
+            position += code[position..].iter().position(|&byte| byte == b'}').unwrap_or(code.len() - position);
+        } else if b"+-<>".contains(&byte) && instructions.last().is_some_and(|last| last.byte == byte && last.position + last.length == position) {
+            instructions.last_mut().unwrap().length += 1
+        } else if b"+-<>[].,".contains(&byte) {
+            instructions.push(Instruction { byte, position, length: 1 })
+        }
+        position += 1
+    }
+    instructions
+}
+
+/// Runs a program on a tape, and returns the number of times each byte of its source code was
+/// executed, which is [`None`] for bytes that are not instructions, along with how the program
+/// ended and the number of steps it executed.
+///
+/// Each instruction is counted separately, so unlike with [`execute`], the counts are those of the
+/// source code whatever the optimizations. The brackets are expected to match, as the program was
+/// loaded before. Counting stops early if the program reads past the end of its input with `--eof
+/// error`, if reading or writing fails, or if it is about to repeat a loop after executing
+/// `max_steps` steps.
+///
+/// Steps are counted like when running the program: each run of instructions is a step, as is each
+/// time a loop is entered.
+///
+/// [`execute`]: brainfuck_interpreter::execute
+fn count(code: &[u8], tape: &mut Tape, max_steps: u64) -> (Vec<Option<u64>>, ControlFlow<Stop>, u64) {
+    let instructions = instructions(code);
+    // The index of the matching bracket of each bracket.
+    let mut jumps = vec![0; instructions.len()];
+    let mut open = Vec::new();
+    for (i, instruction) in instructions.iter().enumerate() {
+        match instruction.byte {
+            b'[' => open.push(i),
+            b']' => {
+                let start = open.pop().expect("Unexpected `]`");
+                jumps[start] = i;
+                jumps[i] = start
+            }
+            _ => {}
+        }
+    }
+    let mut counts = vec![0u64; instructions.len()];
+    let (mut result, mut steps) = (ControlFlow::Continue(()), 0);
+    let mut index = 0;
+    while let Some(instruction) = instructions.get(index) {
+        counts[index] += 1;
+        if instruction.byte != b']' {
+            steps += 1
+        }
+        match instruction.byte {
+            // Only the length modulo 256 matters, as cells wrap around.
+            b'+' => tape.add(0, instruction.length as u64),
+            b'-' => tape.add(0, (instruction.length as u64).wrapping_neg()),
+            b'>' => tape.right_by(instruction.length as isize),
+            b'<' => tape.right_by(-(instruction.length as isize)),
+            b'[' if tape.read() == 0 => index = jumps[index],
+            b']' if tape.read() != 0 => {
+                // An empty loop never ends, but never executes a step either.
+                if steps >= max_steps || (jumps[index] + 1 == index && max_steps != u64::MAX) {
+                    (result, steps) = (ControlFlow::Break(Stop::StepLimit), steps.max(max_steps));
+                    break;
+                }
+                index = jumps[index]
+            }
+            b'.' => {
+                result = tape.output();
+                if result.is_break() {
+                    break;
+                }
+            }
+            // The program stops with `--eof error`, or if reading its input fails.
+            b',' => {
+                result = tape.input();
+                if result.is_break() {
+                    break;
+                }
+            }
+            _ => {}
+        }
+        index += 1
+    }
+    let mut byte_counts = vec![None; code.len()];
+    for (instruction, &count) in instructions.iter().zip(&counts) {
+        byte_counts[instruction.position..instruction.position + instruction.length].fill(Some(count))
+    }
+    (byte_counts, result, steps)
+}
+
+/// Runs a program on a tape, and writes its source code with the number of times the
+/// instructions of each line were executed in a gutter, like `gcov` (see `--annotate`).
+///
+/// Lines executed at least `hot_threshold` times (by default, a tenth of the count of the most
+/// executed line) are marked with `*`. Lines whose instructions were never executed are marked
+/// with `#####`, and lines mixing both are followed by a line marking the instructions that were
+/// never executed with `#`.
+///
+/// Returns how the program ended and the number of steps it executed. If it stopped early, such
+/// as after `max_steps` steps, the counts are those until then.
+pub fn run(code: &[u8], tape: &mut Tape, hot_threshold: Option<u64>, max_steps: u64, out: &mut impl Write) -> io::Result<(ControlFlow<Stop>, u64)> {
+    let (counts, result, steps) = count(code, tape, max_steps);
+    tape.flush()?;
+    // A newline ends the last line rather than starting another one.
+    let code = code.strip_suffix(b"\n").unwrap_or(code);
+    let lines: Vec<(&[u8], &[Option<u64>])> = code.split(|&byte| byte == b'\n').scan(0, |start, line| {
+        let line_counts = &counts[*start..*start + line.len()];
+        *start += line.len() + 1;
+        Some((line, line_counts))
+    }).collect();
+    let totals: Vec<Option<u64>> = lines.iter().map(|(_, line_counts)| line_counts.iter().flatten().copied().reduce(|a, b| a + b)).collect();
+    let hottest = totals.iter().flatten().copied().max().unwrap_or(0);
+    let hot_threshold = hot_threshold.unwrap_or((hottest / DEFAULT_HOT_FRACTION).max(1));
+    for (i, ((line, line_counts), total)) in lines.iter().zip(&totals).enumerate() {
+        let gutter = match total {
+            None => format!("{:>9} ", "-"),
+            Some(0) => format!("{:>9} ", "#####"),
+            Some(total) => format!("{:>9}{}", total, if *total >= hot_threshold { '*' } else { ' ' }),
+        };
+        write!(out, "{}:{:>5}:", gutter, i + 1)?;
+        out.write_all(line)?;
+        writeln!(out)?;
+        if total.is_some_and(|total| total > 0) && line_counts.contains(&Some(0)) {
+            // Tabs are kept, so that the marks line up with the instructions.
+            let marks: String = line.iter().zip(*line_counts).map(|(&byte, count)| match count {
+                Some(0) => '#',
+                _ if byte == b'\t' => '\t',
+                _ => ' ',
+            }).collect();
+            writeln!(out, "{:>9} :{:>5}:{}", "#####", "", marks.trim_end())?
+        }
+    }
+    let instructions = counts.iter().flatten().count();
+    let never_executed = counts.iter().filter(|&&count| count == Some(0)).count();
+    writeln!(out, "Instructions never executed: {} of {}", never_executed, instructions)?;
+    Ok((result, steps))
+}
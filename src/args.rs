@@ -1,29 +1,670 @@
+use std::any::TypeId;
+use std::io;
+use std::io::Write;
+use std::num::NonZeroU64;
+use std::ops::Range;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::{ArgAction, Parser};
+use clap::parser::ValueSource;
+use brainfuck_interpreter::tape::{Eof, TapeOptions};
+use brainfuck_interpreter::{Cell, CellSize, Dialect, MachineOptions, OptimizeOptions, ParseOptions};
+use clap::builder::{PossibleValue, PossibleValuesParser, TypedValueParser};
+use clap::{ArgAction, ArgGroup, ArgMatches, CommandFactory, Parser};
+
+use crate::console::Charset;
+use crate::input::{InputPattern, InputSource};
+use crate::keymap::Keymap;
+use crate::lint::{DiagnosticFormat, Lint};
+use crate::pause;
+use crate::protect::Protection;
+use crate::script::Script;
 
 /// A Brainfuck interpreter that uses an intermediate representation to optimize some patterns in
 /// order to make the execution faster.
-#[derive(Debug, Parser)]
+///
+/// The arguments doing something else than running the program are mutually exclusive, as only one
+/// of them would be honored. The translations (`--emit-*`) may be combined with each other, but not
+/// with the other modes.
+#[derive(Debug, Clone, Parser)]
+// Without this, clap groups all the arguments together, and errors show every one of them in their
+// usage line.
+#[group(skip)]
+#[command(group(ArgGroup::new("mode").args(["generate", "diff", "equiv", "selftest", "test", "tutor", "analyze", "lint", "annotate", "where_output"])))]
 pub struct Args {
     /// A path to the file containing the Brainfuck source code to execute.
-    pub file: PathBuf,
+    #[arg(required_unless_present_any = ["generate", "selftest", "print_effective_config"])]
+    pub file: Option<PathBuf>,
+    /// If passed, prints a Brainfuck program that outputs the specified text (or `stdin` if it is
+    /// `-`) instead of running a program, and prints its length to `stderr`. The text may contain
+    /// escape sequences such as `\xff` for arbitrary bytes.
+    #[arg(long, value_name = "TEXT")]
+    pub generate: Option<String>,
+    /// If passed along with `--generate`, spends more time looking for a shorter program.
+    #[arg(long, requires = "generate")]
+    pub golf: bool,
+    /// A path to a program to run along with the main one, reading its output as input. Can be
+    /// passed multiple times to chain more programs, in which case only the output of the last one
+    /// is printed. If a program ends early, the exit code is that of the first one that did.
+    #[arg(long, value_name = "PATH")]
+    pub pipe: Vec<PathBuf>,
+    /// If passed, runs both the program and the one at the specified path on the same input, and
+    /// reports where their outputs first differ instead of printing them. Exits with status 1 if
+    /// they differ. The whole input is read before running the programs.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["pipe", "io_tcp", "io_listen"])]
+    pub diff: Option<PathBuf>,
+    /// If passed, runs both the program and the one at the specified path on the same random
+    /// inputs, and reports the first input on which their outputs differ instead of printing them.
+    /// Exits with status 1 if one is found. Once an input is exhausted, the programs read 0s.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["pipe", "io_tcp", "io_listen", "synthetic_input"])]
+    pub equiv: Option<PathBuf>,
+    /// The number of random inputs tried by `--equiv`. Programs that read no input are only run
+    /// once.
+    #[arg(long, value_name = "N", default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..), requires = "equiv")]
+    pub runs: u64,
+    /// The maximum length of the random inputs tried by `--equiv`.
+    #[arg(long, value_name = "N", default_value_t = 64, requires = "equiv")]
+    pub max_input_len: usize,
+    /// The number of steps after which each program stops at the next iteration of a loop, on each
+    /// input tried by `--equiv`. Programs running out of steps are only compared on what they
+    /// output so far.
+    #[arg(long, value_name = "N", default_value_t = 10_000_000, requires = "equiv")]
+    pub equiv_steps: u64,
+    /// If passed, `--equiv` also compares the final states of the tapes, as hashed by
+    /// `--print-tape-hash`.
+    #[arg(long, requires = "equiv")]
+    pub compare_tape: bool,
+    /// If passed, expands macros (such as `!def zero [-]` and `!rep 10 { >+ }`) before loading the
+    /// source code. See the README for the supported directives.
+    #[arg(long)]
+    pub macros: bool,
+    /// If passed, memory-maps the source file instead of reading it, which saves memory for very
+    /// large programs. Has no effect unless the `mmap` feature is enabled.
+    #[arg(long)]
+    pub mmap: bool,
     /// If passed, `.` will write hex codes instead of ASCII values.
     #[arg(long)]
     pub hex_output: bool,
     /// If passed, output caused by `.` will be redirected to `/dev/null`.
-    #[arg(long)]
+    #[arg(long, conflicts_with = "hex_output")]
     pub no_output: bool,
+    /// If passed, output caused by `.` is written to the specified file instead of `stdout`, in the
+    /// same format. The file is written through a buffer, which is flushed when the program ends
+    /// and before it waits for a new line of input.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["no_output", "pipe", "io_tcp", "io_listen", "game"])]
+    pub output: Option<PathBuf>,
+    /// If passed, the values output to `stdout` are decoded with the specified character set when
+    /// `stdout` is a console, so that bytes of 0x80 or more are displayed as characters. Output
+    /// that is redirected to a file or a pipe is never decoded, so it is always made of the exact
+    /// values. On Windows, consoles only accept text, so output to a console is decoded as UTF-8
+    /// (with invalid sequences displayed as `�`) unless this is passed. Other terminals display
+    /// bytes as they are configured to.
+    #[arg(long, value_enum, value_name = "CHARSET", conflicts_with_all = ["no_output", "output", "hex_output"])]
+    pub console_charset: Option<Charset>,
     /// Whether to enable loop optimizations (resets and moves).
     #[arg(long, default_value_t = true, action = ArgAction::Set)]
     pub optimize_loops: bool,
     /// Whether to optimize chunk resets (not recommended).
     #[arg(long, default_value_t = false, action = ArgAction::Set)]
     pub optimize_chunk_resets: bool,
+    /// Whether to add the value of a cell to runs of adjacent cells, as when copying an array, in a
+    /// single pass instead of one cell at a time.
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    pub coalesce_moves: bool,
+    /// Whether to fuse pairs of commands that often appear together into single commands.
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    pub fuse_commands: bool,
+    /// Whether to remove the commands that have no effect given the values the cells are known to
+    /// have, such as loops at the start of the program (which are often comments). This is disabled
+    /// when the written cells are printed (e.g., with `--summary`).
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    pub propagate_constants: bool,
+    /// Whether to remove the commands at the end of the program that have no observable effect.
+    /// This is disabled when the final state of the tape is printed (e.g., with `--summary`).
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    pub remove_unobservable_tail: bool,
+    /// If passed, stops optimizing once the specified duration (such as `500ms` or `2s`) has
+    /// elapsed, and runs the program as optimized so far.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub opt_time_budget: Option<Duration>,
+    /// Whether to flatten loops into jumps before running the program, which is faster and does not
+    /// recurse into loops, so deeply nested ones cannot overflow the stack. Otherwise, the tree of
+    /// commands is walked. Instrumented runs (such as with `--profile-commands`) always flatten it.
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    pub flatten_loops: bool,
+    /// If passed, stops the program with an error when it tries to output more than N values.
+    #[arg(long, value_name = "N")]
+    pub max_output: Option<u64>,
+    /// If passed, stops the program with an error when it repeats a loop after executing N steps.
+    /// Each command executed is a step, as is each time a loop is entered, so that a program cannot
+    /// run forever. The limit is only checked when a loop is repeated, so the program may execute a
+    /// few more than N steps. It also applies to `--annotate`. There is no limit on the time a
+    /// program runs for, as a `--timeout` would need checking the clock while it runs.
+    #[arg(long, value_name = "N")]
+    pub max_steps: Option<u64>,
+    /// If passed, allows running a program downloaded from a URL without any limit. Such programs
+    /// are not trusted, so they are refused unless this or `--max-steps` is passed.
+    #[arg(long)]
+    pub allow_unlimited: bool,
+    /// If passed, the program reads the input described by the specified pattern instead of
+    /// `stdin`. The pattern is made of segments separated by `|`, each of which is some text
+    /// optionally followed by `*N` to repeat it N times, such as `header\n|x*1024`. The text may
+    /// contain the escape sequences `\n`, `\xNN`, `\\`, `\|`, and `\*`.
+    #[arg(long, value_name = "PATTERN", value_parser = InputPattern::parse, group = "synthetic_input")]
+    pub input_repeat: Option<InputPattern>,
+    /// If passed, the program reads N bytes that are 0 instead of `stdin`.
+    #[arg(long, value_name = "N", group = "synthetic_input")]
+    pub input_zero: Option<u64>,
+    /// If passed, the program reads N random bytes instead of `stdin`. They can be reproduced with
+    /// `--seed`.
+    #[arg(long, value_name = "N", group = "synthetic_input")]
+    pub input_random: Option<u64>,
+    /// If passed, the program reads from the specified source instead of `stdin`. Can be passed
+    /// multiple times, in which case each source is read once the previous one is exhausted, and
+    /// the program only reaches the end of its input after the last one. A source is `stdin`,
+    /// `file:PATH`, or `text:TEXT`, where the text may contain the escape sequences of
+    /// `--generate`.
+    #[arg(long, value_name = "SOURCE", value_parser = InputSource::parse, conflicts_with_all = ["fixed_input", "synthetic_input", "io_tcp", "io_listen", "equiv"])]
+    pub input_source: Vec<InputSource>,
+    /// If passed, the program reads the specified file instead of `stdin`. The file is read as
+    /// bytes, whatever they are.
+    #[arg(long, visible_alias = "input", value_name = "PATH", group = "fixed_input", conflicts_with_all = ["synthetic_input", "io_tcp", "io_listen", "equiv"])]
+    pub input_file: Option<PathBuf>,
+    /// If passed, the program reads the specified text instead of `stdin`, which may contain the
+    /// escape sequences of `--generate`.
+    #[arg(long, value_name = "TEXT", value_parser = InputSource::parse_text, group = "fixed_input", conflicts_with_all = ["synthetic_input", "io_tcp", "io_listen", "equiv"])]
+    pub input_text: Option<InputSource>,
+    /// If passed, the program reads `stdin` once the file passed with `--input-file` (or the text
+    /// passed with `--input-text`) is exhausted, such as to replay the start of a game before
+    /// playing it.
+    #[arg(long, requires = "fixed_input")]
+    pub then_stdin: bool,
+    /// If passed, exits with status 124 when no input is received within the specified duration
+    /// (such as `500ms` or `5s`) while executing `,`.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, conflicts_with = "synthetic_input")]
+    pub input_timeout: Option<Duration>,
+    /// What `,` does once the input is exhausted.
+    #[arg(long, value_parser = value_parser::<Eof>(), default_value = "zero")]
+    pub eof: Eof,
+    /// If passed, connects to the specified address (such as `localhost:7000`) and uses the
+    /// connection for the input and the output of the program instead of `stdin` and `stdout`.
+    /// Ends the run once the peer disconnects.
+    #[arg(long, value_name = "HOST:PORT", conflicts_with_all = ["io_listen", "pipe", "input_timeout", "synthetic_input"])]
+    pub io_tcp: Option<String>,
+    /// If passed, waits for a single connection on the specified port, and uses it like
+    /// `--io-tcp`.
+    #[arg(long, value_name = "PORT", conflicts_with_all = ["pipe", "input_timeout", "synthetic_input"])]
+    pub io_listen: Option<u16>,
+    /// If passed, reads the specified file mapping keys to the bytes the program receives instead,
+    /// one `KEY = BYTES` per line, such as `Up = w`. Keys are named (`Up`, `Down`, `Left`,
+    /// `Right`, `Home`, `End`, `Enter`, `Tab`, `Space`, `Backspace`, and `Escape`) or written as the
+    /// sequences they send, such as `\x1b[A`. Other input is passed on unchanged.
+    #[arg(long, value_name = "PATH", value_parser = Keymap::read)]
+    pub keymap: Option<Keymap>,
+    /// If passed, runs the program as a game: keys are passed to it as soon as they are pressed,
+    /// its output is shown on the alternate screen of the terminal, and outputting a form feed
+    /// (`\x0c`) clears the screen. The terminal is restored once the program ends.
+    #[arg(long, conflicts_with_all = ["pipe", "io_tcp", "io_listen", "hex_output", "no_output"])]
+    pub game: bool,
+    /// If passed, prints the specified prompt to `stderr` before reading each line of input, when
+    /// the input is a terminal.
+    #[arg(long)]
+    pub prompt: Option<String>,
+    /// If passed, outputs a newline after the program ends, unless its output is empty or already
+    /// ends with a newline.
+    #[arg(long)]
+    pub newline_at_exit: bool,
+    /// If passed, runs the program once for each line of `stdin`, like a filter, each time on a
+    /// blank tape reading the line (without its newline) as its whole input. The program is only
+    /// loaded and optimized once, and the outputs of the runs follow one another.
+    #[arg(long, conflicts_with_all = ["input_source", "fixed_input", "synthetic_input", "input_timeout", "io_tcp", "io_listen", "pipe", "game", "keymap", "prompt", "seed_tape", "tape_init_json", "diff", "equiv"])]
+    pub per_line: bool,
+    /// If passed with `--per-line`, the program also reads the newline at the end of each line.
+    #[arg(long, requires = "per_line")]
+    pub keep_newline: bool,
+    /// If passed with `--per-line`, writes the number of each line followed by `:` before the
+    /// output of the program for that line.
+    #[arg(long, requires = "per_line")]
+    pub number_lines: bool,
+    /// If passed, runs the program as driven by the specified script instead of `stdin` and
+    /// `stdout`, and reports whether it behaved as expected. Each line of the script is
+    /// `expect "TEXT"` (waits for the program to output the text), `send "TEXT"` (passes the text to
+    /// the program), `expect-timeout DURATION` (sets how long to wait, 10s by default), or `eof`
+    /// (ends the input and waits for the program to end). Exits with status 1 if the script fails.
+    #[arg(long, value_name = "PATH", value_parser = Script::read, conflicts_with_all = ["input_source", "fixed_input", "synthetic_input", "input_timeout", "io_tcp", "io_listen", "pipe", "game", "keymap", "prompt", "per_line", "hex_output", "no_output", "output", "diff", "equiv"])]
+    pub script: Option<Script>,
+    /// If passed, prints the value of the current cell to `stdout` as a decimal number on its own
+    /// line after the program ends.
+    #[arg(long)]
+    pub print_exit_cell: bool,
+    /// If passed, prints a hash of the final state of the tape to `stderr` after the program ends.
+    /// The hash only depends on the values of the cells between the leftmost and the rightmost
+    /// non-zero cells, and on the position of the pointer relative to the leftmost non-zero cell.
+    #[arg(long)]
+    pub print_tape_hash: bool,
+    /// If passed, runs a few embedded programs instead of the passed one, and checks that they
+    /// output what they should with the passed options. Exits with status 1 if any check fails.
+    #[arg(long)]
+    pub selftest: bool,
+    /// If passed, runs the tests written in the source code as `{test name="NAME" input="TEXT"
+    /// output="TEXT"}` annotations instead of running the program, and shows how the output of the
+    /// failing ones differs. The path may also be a directory, whose `.b` files are all tested.
+    /// Exits with status 1 if any test fails.
+    #[arg(long, conflicts_with_all = ["pipe", "per_line", "script", "game", "io_tcp", "io_listen"])]
+    pub test: bool,
+    /// The number of steps after which each test run by `--test` stops at the next iteration of a
+    /// loop and fails, unless it sets another limit with `steps="N"`.
+    #[arg(long, value_name = "N", default_value_t = 10_000_000, requires = "test")]
+    pub test_steps: u64,
+    /// If passed, runs the program while explaining each step in plain English, which is meant for
+    /// learning Brainfuck. Only small programs are accepted, and they are stopped after a few
+    /// hundred steps.
+    #[arg(long)]
+    pub tutor: bool,
+    /// If passed, prints conservative bounds on how far the pointer can go from its initial
+    /// position, instead of running the program. Loops whose bodies move the pointer make the
+    /// bounds unbounded in the direction they move it.
+    #[arg(long)]
+    pub analyze: bool,
+    /// If passed, checks the program for likely mistakes (such as loops that never run) and prints
+    /// a warning for each one, instead of running the program. Exits with status 1 if a lint passed
+    /// to `--deny` is found.
+    #[arg(long)]
+    pub lint: bool,
+    /// A lint that `--lint` reports as an error instead of a warning. Can be passed multiple
+    /// times. This takes precedence over `--allow`.
+    #[arg(long, value_enum, value_name = "LINT", requires = "lint")]
+    pub deny: Vec<Lint>,
+    /// A lint that `--lint` does not report. Can be passed multiple times.
+    #[arg(long, value_enum, value_name = "LINT", requires = "lint")]
+    pub allow: Vec<Lint>,
+    /// How `--lint` prints diagnostics, and how errors preventing the program from being loaded
+    /// are printed. With `json`, each one is printed to `stdout` as a JSON object on its own line.
+    #[arg(long, value_enum, value_name = "FORMAT", alias = "lint-format", default_value_t = DiagnosticFormat::Human)]
+    pub diagnostics: DiagnosticFormat,
+    /// If passed, runs the program without printing its output, and then prints its source code
+    /// with the number of times the instructions of each line were executed, like `gcov`. Lines
+    /// that were never executed are marked with `#####`. Only the standard instructions are
+    /// counted.
+    #[arg(long, conflicts_with_all = ["pipe", "per_line", "script", "game", "io_tcp", "io_listen"])]
+    pub annotate: bool,
+    /// The number of executions from which `--annotate` marks a line as hot, with `*`. Defaults to a
+    /// tenth of the count of the most executed line.
+    #[arg(long, value_name = "N", requires = "annotate")]
+    pub hot_threshold: Option<u64>,
+    /// If passed, prints a summary of the run (steps, cells, and how far the pointer went) to
+    /// `stderr` after the program ends.
+    #[arg(long)]
+    pub summary: bool,
+    /// If passed, prints the number of executions and the time spent on each kind of command to
+    /// `stderr` after the program ends. This makes the program run slower.
+    #[arg(long)]
+    pub profile_commands: bool,
     /// If passed, prints timing information to `stderr`.
     #[arg(long)]
     pub time: bool,
-    /// If passed, `?` is treated as a command that prints the tape to standard error.
+    /// If passed, appends a row with timing information about the run to the specified CSV file.
+    #[arg(long, value_name = "PATH")]
+    pub time_output: Option<PathBuf>,
+    /// The size of the cells. With `16` and `32`, cells are unsigned integers that wrap around, and
+    /// with `big`, they are arbitrary-precision signed integers, so loops are not optimized as
+    /// resets, moves, or multiplications. Cells wider than bytes output their lowest byte (or their
+    /// whole value with `--hex-output`). Translating, annotating, comparing, and testing programs
+    /// only support bytes.
+    #[arg(long, value_parser = value_parser::<CellSize>(), default_value = "8")]
+    pub cell_size: CellSize,
+    /// The dialect of Brainfuck the source code is written in.
+    #[arg(long, value_parser = value_parser::<Dialect>(), default_value = "standard")]
+    pub dialect: Dialect,
+    /// The character that stores a random byte in the current cell when using the `random`
+    /// dialect. If `--debug` is passed, `?` always prints the tape.
+    #[arg(long, default_value_t = '%', value_parser = parse_extension_char)]
+    pub random_char: char,
+    /// The seed of the random number generator used by the `random` dialect and `--input-random`.
+    /// If not passed, a seed is chosen randomly.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// If passed, fills the cells in the range set by `--seed-tape-cells` with pseudo-random values
+    /// generated from the specified seed before running the program. This reveals programs relying
+    /// on cells they never initialize being 0.
+    #[arg(long, value_name = "SEED")]
+    pub seed_tape: Option<u64>,
+    /// The range of cells filled by `--seed-tape`, as `FROM..TO` (`TO` is excluded).
+    #[arg(long, value_name = "RANGE", default_value = "0..30000", value_parser = parse_range, requires = "seed_tape")]
+    pub seed_tape_cells: Range<isize>,
+    /// If passed, prints additional information about the run (such as the random seed) to
+    /// `stderr`.
+    #[arg(short, long)]
+    pub verbose: bool,
+    /// If passed, `?` is treated as a command that prints the tape and the position of the pointer
+    /// to standard error.
     #[arg(long)]
     pub debug: bool,
+    /// If passed, writes the tape as JSON to the specified file when the program ends. With
+    /// `--debug`, each `?` also writes the tape to a numbered file next to it (`out.1.json`,
+    /// `out.2.json`, etc. for `out.json`).
+    #[arg(long, value_name = "PATH")]
+    pub tape_dump_json: Option<PathBuf>,
+    /// If passed, initializes the tape from a JSON file, in the format written by
+    /// `--tape-dump-json`.
+    #[arg(long, value_name = "PATH")]
+    pub tape_init_json: Option<PathBuf>,
+    /// If passed, prints the state of the program to `stderr` every N steps, along with the line
+    /// and column of the command about to execute. When `stderr` is a terminal, also waits for
+    /// Enter (`runto M` to run until step M, or `q` to abort) before continuing. Steps are counted
+    /// like with `--summary`, whether it is passed or not.
+    #[arg(long, value_name = "N")]
+    pub step: Option<NonZeroU64>,
+    /// If passed, pauses the program right before it executes step N (counting from 1), and
+    /// prints the state of the program, the line and column of the command about to execute, and
+    /// the cells around the pointer to `stderr`. When `stderr` is a terminal, also waits for Enter
+    /// (`runto M` to run until step M, or `q` to abort) before continuing. Can be passed multiple
+    /// times. Steps are counted like with `--summary`, whether it is passed or not.
+    #[arg(long, value_name = "N")]
+    pub break_at_step: Vec<NonZeroU64>,
+    /// If passed, checks the assertions written in the source code, such as `{assert cell(2)==65}`
+    /// or `{assert ptr>0}`, and stops the program with an error when one does not hold. Otherwise,
+    /// assertions are ignored.
+    #[arg(long)]
+    pub assertions: bool,
+    /// If passed, stops the program with an error before it writes to a cell of the first tape in
+    /// the specified range, as `FROM..TO` (`TO` is excluded), such as a lookup table that must not
+    /// change once initialized. Appending `:after-step=N` only protects the cells after step N. Can
+    /// be passed multiple times.
+    #[arg(long, value_name = "RANGE", value_parser = Protection::parse, conflicts_with = "profile_commands")]
+    pub protect: Vec<Protection>,
+    /// If passed, warns on `stderr` the first time the program does something that other
+    /// interpreters handle differently, such as moving the pointer below cell 0, wrapping a cell or
+    /// reading past the end of the input, along with the line and column of the command doing it,
+    /// and prints a summary after the program ends. The commands that would be optimized away are
+    /// kept, so that they are checked too. This makes the program run slower.
+    #[arg(long, conflicts_with_all = ["profile_commands", "protect"])]
+    pub warn_portability: bool,
+    /// If passed, reports on `stderr` each time the program changes the specified cell of the first
+    /// tape, given as its index or its name (see `{name INDEX NAME}`), along with the step and the
+    /// line and column of the command changing it. Can be passed multiple times. This makes the
+    /// program run slower.
+    #[arg(long, value_name = "CELL", conflicts_with_all = ["profile_commands", "protect", "warn_portability"])]
+    pub watch: Vec<String>,
+    /// If passed, writes the step at which each value was output and the line and column of the
+    /// `.` that output it to the specified file, as CSV. Values output one after the other by the
+    /// same `.` are written as a single row.
+    #[arg(long, value_name = "PATH")]
+    pub output_map: Option<PathBuf>,
+    /// If passed, prints which `.` output the value at the specified offset (starting at 0)
+    /// according to the file written by a previous run with `--output-map`, instead of running the
+    /// program.
+    #[arg(long, value_name = "OFFSET", requires = "output_map")]
+    pub where_output: Option<u64>,
+    /// If passed, writes a Graphviz graph of the loop structure of the optimized program to the
+    /// specified file instead of running it.
+    #[arg(long, value_name = "PATH", conflicts_with = "mode")]
+    pub emit_dot: Option<PathBuf>,
+    /// If passed, writes the optimized program, lowered back to Brainfuck, to the specified file
+    /// instead of running it.
+    #[arg(long, value_name = "PATH", conflicts_with = "mode")]
+    pub emit_bf: Option<PathBuf>,
+    /// If passed, writes the optimized program, translated to LLVM IR, to the specified file instead
+    /// of running it.
+    #[arg(long, value_name = "PATH", conflicts_with = "mode")]
+    pub emit_llvm: Option<PathBuf>,
+    /// If passed, writes the optimized program, translated to x86-64 assembly, to the specified
+    /// file instead of running it.
+    #[arg(long, value_name = "PATH", conflicts_with = "mode")]
+    pub emit_asm: Option<PathBuf>,
+    /// If passed, writes the optimized program, translated to C, to the specified file instead of
+    /// running it.
+    #[arg(long, value_name = "PATH", conflicts_with = "mode")]
+    pub emit_c: Option<PathBuf>,
+    /// If passed, prints the value of each argument, as passed or by default, as TOML instead of
+    /// running a program.
+    #[arg(long)]
+    pub print_effective_config: bool,
+}
+
+/// An enumeration of the library whose values can be passed on the command line, such as
+/// `--eof minus-one`. The library does not depend on clap, so their names and the help shown for
+/// them are listed here.
+pub trait ArgValue: Copy + PartialEq + Send + Sync + 'static {
+    /// Each value, along with its name and its help.
+    const VALUES: &'static [(Self, &'static str, &'static str)];
+
+    /// Returns the name of this value on the command line.
+    fn name(self) -> &'static str {
+        Self::VALUES.iter().find(|&&(value, _, _)| value == self).map(|&(_, name, _)| name).expect("Every value has a name")
+    }
+
+    /// Returns the value with some name, if there is one.
+    fn from_name(name: &str) -> Option<Self> {
+        Self::VALUES.iter().find(|&&(_, other, _)| other == name).map(|&(value, _, _)| value)
+    }
+}
+
+impl ArgValue for Eof {
+    const VALUES: &'static [(Self, &'static str, &'static str)] = &[
+        (Eof::Zero, "zero", "Writes 0 to the current cell, as most interpreters do"),
+        (Eof::MinusOne, "minus-one", "Writes 255 to the current cell (or the largest value with `--cell-size 16` or `32`, and -1 with `--cell-size big`)"),
+        (Eof::Unchanged, "unchanged", "Leaves the current cell unchanged"),
+        (Eof::Error, "error", "Stops the program with exit code 5"),
+    ];
+}
+
+impl ArgValue for CellSize {
+    const VALUES: &'static [(Self, &'static str, &'static str)] = &[
+        (CellSize::Eight, "8", "Bytes, which wrap around"),
+        (CellSize::Sixteen, "16", "Unsigned 16-bit integers, which wrap around. Outputting a cell outputs its lowest byte"),
+        (CellSize::ThirtyTwo, "32", "Unsigned 32-bit integers, which wrap around. Outputting a cell outputs its lowest byte"),
+        (CellSize::Big, "big", "Arbitrary-precision signed integers. Outputting a cell outputs its lowest byte"),
+    ];
+}
+
+impl ArgValue for Dialect {
+    const VALUES: &'static [(Self, &'static str, &'static str)] = &[
+        (Dialect::Standard, "standard", "Standard Brainfuck"),
+        (Dialect::Ext1, "ext1", "Extended Brainfuck Type I, which adds `@` (ends the program), `$` (copies the current cell to a storage register), and `!` (copies the storage register to the current cell)"),
+        (Dialect::Random, "random", "Adds an instruction (`%` by default, see `--random-char`) that stores a random byte in the current cell"),
+        (Dialect::Dual, "dual", "Adds a second tape, with its own pointer, and two instructions: `~` (switches the tape instructions operate on) and `^` (exchanges the values of the current cells of both tapes)"),
+    ];
+}
+
+/// Returns the parser of the values of an enumeration of the library (see [`ArgValue`]).
+fn value_parser<T: ArgValue>() -> impl TypedValueParser<Value = T> {
+    let values = T::VALUES.iter().map(|&(_, name, help)| PossibleValue::new(name).help(help));
+    PossibleValuesParser::new(values).map(|name| T::from_name(&name).expect("Only the names of values are possible"))
+}
+
+/// The arguments that only support byte cells (see `--cell-size`), as they translate programs to
+/// code using bytes, or run them on tapes of bytes.
+const BYTE_CELL_ARGUMENTS: [&str; 10] = ["emit_bf", "emit_llvm", "emit_asm", "emit_c", "annotate", "tutor", "diff", "equiv", "test", "selftest"];
+
+impl Args {
+    /// Checks the constraints between arguments that depend on their values or on what they do,
+    /// which clap cannot check. Returns a message explaining why the arguments do not work
+    /// together if they do not.
+    pub fn validate(&self, matches: &ArgMatches) -> Result<(), String> {
+        let is_passed = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        if self.optimize_chunk_resets && !self.optimize_loops {
+            return Err("`--optimize-chunk-resets true` requires `--optimize-loops true`, as chunk resets are detected from the resets found by loop optimizations".to_string());
+        }
+        let is_stdin_read = (self.input_source.is_empty() && self.input_file.is_none() && self.input_text.is_none()) || self.then_stdin || self.input_source.iter().any(|source| matches!(source, InputSource::Stdin));
+        if self.input_timeout.is_some() && !is_stdin_read {
+            return Err("`--input-timeout` only applies to `stdin`, which is not read with the passed input sources (pass `--then-stdin` or `--input-source stdin` to read it)".to_string());
+        }
+        if self.seed_tape.is_some() && self.tape_init_json.is_some() {
+            return Err("`--tape-init-json` replaces the whole tape, including the cells filled by `--seed-tape`".to_string());
+        }
+        if self.annotate && self.dialect != Dialect::Standard {
+            return Err("`--annotate` only counts the standard instructions, so it only supports the standard dialect".to_string());
+        }
+        if is_passed("random_char") && self.dialect != Dialect::Random {
+            return Err("`--random-char` only applies to the `random` dialect (pass `--dialect random` to use it)".to_string());
+        }
+        if self.cell_size != CellSize::Eight {
+            let command = Self::command();
+            let unsupported = command.get_arguments().find(|arg| BYTE_CELL_ARGUMENTS.contains(&arg.get_id().as_str()) && is_passed(arg.get_id().as_str()));
+            if let Some(arg) = unsupported {
+                return Err(format!("`--{}` only supports byte cells, so it cannot be used with `--cell-size {}`", arg.get_long().unwrap_or_default(), self.cell_size.name()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the options telling how to load source code.
+    pub fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            dialect: self.dialect,
+            random_char: self.random_char,
+            debug: self.debug,
+            trace_output: self.output_map.is_some(),
+            trace_positions: self.step.is_some() || !self.break_at_step.is_empty() || !self.protect.is_empty() || self.warn_portability || !self.watch.is_empty(),
+            assertions: self.assertions,
+            cell_size: self.cell_size,
+        }
+    }
+
+    /// Returns the options telling which optimizations to run, which are disabled when what they
+    /// change is printed (such as the written cells with `--summary`) or checked (such as the
+    /// writes with `--protect`, `--warn-portability` and `--watch`).
+    ///
+    /// Those optimizations also change the number of steps, so they are disabled whenever steps
+    /// are counted to pause or stop the program, which then counts the same steps whatever else is
+    /// printed or checked.
+    pub fn optimize_options(&self) -> OptimizeOptions {
+        let are_steps_observed = self.step.is_some() || !self.break_at_step.is_empty() || self.max_steps.is_some();
+        OptimizeOptions {
+            optimize_loops: self.optimize_loops,
+            optimize_chunk_resets: self.optimize_chunk_resets,
+            propagate_constants: self.propagate_constants,
+            remove_unobservable_tail: self.remove_unobservable_tail,
+            coalesce_moves: self.coalesce_moves,
+            fuse_commands: self.fuse_commands,
+            time_budget: self.opt_time_budget,
+            is_tape_initialized: self.seed_tape.is_some() || self.tape_init_json.is_some(),
+            is_written_extent_observed: self.summary || self.debug || self.tape_dump_json.is_some() || self.warn_portability || are_steps_observed,
+            are_writes_observed: !self.protect.is_empty() || self.warn_portability || !self.watch.is_empty(),
+            is_final_tape_observed: self.summary || self.print_exit_cell || self.print_tape_hash || self.compare_tape || self.tape_dump_json.is_some() || are_steps_observed,
+            cell_size: self.cell_size,
+        }
+    }
+
+    /// Returns the options of the tape the main program runs on.
+    pub fn tape_options(&self) -> TapeOptions {
+        TapeOptions {
+            hex_output: self.hex_output,
+            silent: self.no_output,
+            eof: self.eof,
+        }
+    }
+
+    /// Returns the options of the machines programs run on, with the random number generator of
+    /// the `random` dialect seeded with `seed`.
+    pub fn machine_options<C: Cell>(&self, seed: u64) -> MachineOptions<C> {
+        MachineOptions {
+            dual_tape: self.dialect == Dialect::Dual,
+            step_interval: self.step,
+            breakpoints: self.break_at_step.clone(),
+            on_pause: Some(pause::prompt),
+            on_dump: Some(pause::dump),
+            tape_dump_json: self.tape_dump_json.clone(),
+            max_output: self.max_output,
+            max_steps: self.max_steps,
+            trace_output: self.output_map.is_some(),
+            seed,
+            cancel: None,
+            cancel_interval: None,
+        }
+    }
+}
+
+/// Quotes a string for TOML.
+fn quote(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c)
+            }
+            c if c.is_control() => {
+                quoted.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => {
+                quoted.push(c)
+            }
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Writes the value of each argument as TOML (see `--print-effective-config`), as passed or by
+/// default. Arguments that are neither passed nor have a default value are commented out.
+pub fn write_config(matches: &ArgMatches, out: &mut impl Write) -> io::Result<()> {
+    let numbers = [TypeId::of::<u64>(), TypeId::of::<u16>(), TypeId::of::<usize>(), TypeId::of::<NonZeroU64>()];
+    for arg in Args::command().get_arguments() {
+        let id = arg.get_id().as_str();
+        if id == "print_effective_config" {
+            continue;
+        }
+        let key = arg.get_long().unwrap_or(id);
+        let Some(values) = matches.get_raw(id) else {
+            writeln!(out, "# {} is not set", key)?;
+            continue;
+        };
+        let value_type = arg.get_value_parser().type_id();
+        let values: Vec<String> = values.map(|value| {
+            let value = value.to_string_lossy();
+            if value_type == TypeId::of::<bool>() || numbers.iter().any(|&number| value_type == number) {
+                value.into_owned()
+            } else {
+                quote(&value)
+            }
+        }).collect();
+        let value = if matches!(arg.get_action(), ArgAction::Append) {
+            format!("[{}]", values.join(", "))
+        } else {
+            values.join(", ")
+        };
+        if matches.value_source(id) == Some(ValueSource::CommandLine) {
+            writeln!(out, "{} = {}  # passed", key, value)?
+        } else {
+            writeln!(out, "{} = {}", key, value)?
+        }
+    }
+    Ok(())
+}
+
+/// Parses a duration made of a number followed by a unit (`us`, `ms`, or `s`).
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, seconds_per_unit) = if let Some(number) = s.strip_suffix("us") {
+        (number, 1e-6)
+    } else if let Some(number) = s.strip_suffix("ms") {
+        (number, 1e-3)
+    } else if let Some(number) = s.strip_suffix('s') {
+        (number, 1.0)
+    } else {
+        return Err("expected a unit (`us`, `ms`, or `s`)".to_string());
+    };
+    let number: f64 = number.trim().parse().map_err(|_| format!("`{}` is not a number", number))?;
+    Duration::try_from_secs_f64(number * seconds_per_unit).map_err(|error| error.to_string())
+}
+
+/// Parses a range of cells of the form `FROM..TO`.
+pub fn parse_range(s: &str) -> Result<Range<isize>, String> {
+    let (from, to) = s.split_once("..").ok_or("expected a range of the form `FROM..TO`")?;
+    let from = from.trim().parse().map_err(|_| format!("`{}` is not an index", from))?;
+    let to = to.trim().parse().map_err(|_| format!("`{}` is not an index", to))?;
+    Ok(from..to)
+}
+
+/// Parses a character used by an instruction of a dialect, which must not be one of the standard
+/// instructions.
+fn parse_extension_char(s: &str) -> Result<char, String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if "+-<>[].,".contains(c) => Err(format!("`{}` is a standard instruction", c)),
+        // Source code is read byte by byte, so instructions have to be a single byte.
+        (Some(c), None) if c.is_ascii() => Ok(c),
+        _ => Err("expected a single ASCII character".to_string()),
+    }
 }
@@ -0,0 +1,277 @@
+use std::fmt::Write;
+
+use brainfuck_interpreter::Command;
+
+/// Entry point of the generated program, and runtime stubs for I/O and tape growth.
+///
+/// Throughout the program, `%rbx` holds the index of the current cell, and `%r12` holds the address
+/// of the cell at index 0, so the cell to the right of the pointer by `n` is at `n(%r12,%rbx)`. The
+/// tape is a heap allocation of `bf_size` cells, of which the cell at index `i` is the one at offset
+/// `bf_origin + i`. Indices from `bf_low` to `bf_high` (both included) are valid.
+///
+/// The inactive tape of the `dual` dialect is described by the `bf_other_*` variables, which
+/// `bf_switch` exchanges with those of the active tape (and `bf_other_index` with `%rbx`).
+const RUNTIME: &str = r#"    .data
+bf_tape:    .quad 0
+bf_size:    .quad 0
+bf_origin:  .quad 0
+bf_low:     .quad 0
+bf_high:    .quad -1
+bf_register: .byte 0
+bf_other_tape:   .quad 0
+bf_other_size:   .quad 0
+bf_other_origin: .quad 0
+bf_other_low:    .quad 0
+bf_other_high:   .quad -1
+bf_other_index:  .quad 0
+
+    .text
+# Makes the index in %rdi valid, growing the tape if needed. Updates %r12 if the tape moves.
+bf_ensure:
+    cmpq bf_low(%rip), %rdi
+    jl 1f
+    cmpq bf_high(%rip), %rdi
+    jg 1f
+    ret
+1:
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    # Add `size + |index| + 1` cells on each side, which is enough for the index to be valid.
+    movq %rdi, %rax
+    negq %rax
+    cmovlq %rdi, %rax
+    addq bf_size(%rip), %rax
+    incq %rax
+    movq %rax, %r13
+    leaq (%rax,%rax), %r14
+    addq bf_size(%rip), %r14
+    movq %r14, %rdi
+    movl $1, %esi
+    call calloc@PLT
+    movq %rax, %r15
+    leaq (%rax,%r13), %rdi
+    movq bf_tape(%rip), %rsi
+    movq bf_size(%rip), %rdx
+    call memcpy@PLT
+    movq bf_tape(%rip), %rdi
+    call free@PLT
+    movq %r15, bf_tape(%rip)
+    movq %r14, bf_size(%rip)
+    addq bf_origin(%rip), %r13
+    movq %r13, bf_origin(%rip)
+    leaq (%r15,%r13), %r12
+    movq %r13, %rax
+    negq %rax
+    movq %rax, bf_low(%rip)
+    subq %r13, %r14
+    decq %r14
+    movq %r14, bf_high(%rip)
+    popq %r15
+    popq %r14
+    popq %r13
+    ret
+
+# Reads a byte from the standard input into the current cell, and exits with status 1 at EOF.
+bf_input:
+    subq $8, %rsp
+    call getchar@PLT
+    cmpl $-1, %eax
+    je 1f
+    movb %al, (%r12,%rbx)
+    addq $8, %rsp
+    ret
+1:
+    movl $1, %edi
+    call exit@PLT
+
+# Switches the active tape, allocating the current cell of the new tape if needed.
+bf_switch:
+    subq $8, %rsp
+    movq bf_tape(%rip), %rax
+    xchgq %rax, bf_other_tape(%rip)
+    movq %rax, bf_tape(%rip)
+    movq bf_size(%rip), %rax
+    xchgq %rax, bf_other_size(%rip)
+    movq %rax, bf_size(%rip)
+    movq bf_origin(%rip), %rax
+    xchgq %rax, bf_other_origin(%rip)
+    movq %rax, bf_origin(%rip)
+    movq bf_low(%rip), %rax
+    xchgq %rax, bf_other_low(%rip)
+    movq %rax, bf_low(%rip)
+    movq bf_high(%rip), %rax
+    xchgq %rax, bf_other_high(%rip)
+    movq %rax, bf_high(%rip)
+    xchgq %rbx, bf_other_index(%rip)
+    movq bf_tape(%rip), %r12
+    addq bf_origin(%rip), %r12
+    movq %rbx, %rdi
+    call bf_ensure
+    addq $8, %rsp
+    ret
+
+    .globl main
+main:
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    xorl %edi, %edi
+    call time@PLT
+    movl %eax, %edi
+    call srand@PLT
+    xorl %ebx, %ebx
+    xorl %edi, %edi
+    call bf_ensure
+"#;
+
+/// Translates the passed commands to a standalone x86-64 program in the AT&T assembly syntax.
+///
+/// Each command is preceded by a comment naming it and translates to a few instructions, so that
+/// the listing is easy to follow. The result can be assembled and linked against the C standard
+/// library with, e.g., `cc program.s -o program`. Reading past the end of the input exits with
+/// status 1. Tape dumps (`?`) and assertions are ignored. Random bytes come from the `rand`
+/// function of the C standard library, seeded with the current time.
+pub fn to_asm(commands: &[Command]) -> String {
+    let mut emitter = Emitter::default();
+    emitter.emit(commands);
+    format!("{}{}.Lhalt:\n    popq %r13\n    popq %r12\n    popq %rbx\n    xorl %eax, %eax\n    ret\n\n    .section .note.GNU-stack,\"\",@progbits\n", RUNTIME, emitter.code)
+}
+
+/// Generates the body of the `main` function.
+#[derive(Debug, Default)]
+struct Emitter {
+    /// The generated code.
+    code: String,
+    /// The number of loops emitted so far, used to name their labels.
+    loops: usize,
+}
+
+impl Emitter {
+    /// Emits an instruction.
+    fn line(&mut self, line: impl AsRef<str>) {
+        writeln!(self.code, "    {}", line.as_ref()).unwrap()
+    }
+
+    /// Emits code making the cells with offsets from `from` to `to` (both included) valid.
+    fn ensure(&mut self, from: isize, to: isize) {
+        for offset in [from, to] {
+            self.line(format!("leaq {}(%rbx), %rdi", offset));
+            self.line("call bf_ensure");
+        }
+    }
+
+    fn emit(&mut self, commands: &[Command]) {
+        // Loops are emitted with an explicit stack of the commands left in each and of their
+        // labels, so that deeply nested loops do not overflow the call stack.
+        let mut stack = vec![(commands.iter(), None)];
+        while let Some((commands, _)) = stack.last_mut() {
+            let Some(command) = commands.next() else {
+                if let Some((_, Some(label))) = stack.pop() {
+                    self.line(format!("jmp {}_test", label));
+                    writeln!(self.code, "{}_end:", label).unwrap();
+                }
+                continue;
+            };
+            match command {
+                Command::Loop(_) => self.line("# Loop"),
+                _ => self.line(format!("# {:?}", command)),
+            }
+            match command {
+                &Command::Right(amount) => {
+                    self.line(format!("addq ${}, %rbx", amount));
+                    self.line("movq %rbx, %rdi");
+                    self.line("call bf_ensure");
+                }
+                &Command::Add(amount) => {
+                    self.line(format!("addb ${}, (%r12,%rbx)", amount))
+                }
+                Command::Loop(loop_commands) => {
+                    self.loops += 1;
+                    let label = format!(".Lloop{}", self.loops);
+                    writeln!(self.code, "{}_test:", label).unwrap();
+                    self.line("cmpb $0, (%r12,%rbx)");
+                    self.line(format!("je {}_end", label));
+                    stack.push((loop_commands.iter(), Some(label)))
+                }
+                Command::Input => {
+                    self.line("call bf_input")
+                }
+                Command::Output | Command::TracedOutput(_) => {
+                    self.line("movzbl (%r12,%rbx), %edi");
+                    self.line("call putchar@PLT");
+                }
+                Command::Reset => {
+                    self.line("movb $0, (%r12,%rbx)")
+                }
+                &Command::ResetChunk(max_offset) => {
+                    let from = max_offset.min(0);
+                    self.ensure(from, max_offset.max(0));
+                    self.line(format!("leaq {}(%r12,%rbx), %rdi", from));
+                    self.line("xorl %esi, %esi");
+                    self.line(format!("movl ${}, %edx", max_offset.unsigned_abs() + 1));
+                    self.line("call memset@PLT");
+                }
+                Command::Move(cells) => {
+                    let from = cells.iter().map(|&(offset, _)| offset).min().unwrap_or(0).min(0);
+                    let to = cells.iter().map(|&(offset, _)| offset).max().unwrap_or(0).max(0);
+                    self.ensure(from, to);
+                    self.line("movzbl (%r12,%rbx), %ecx");
+                    for &(cell_offset, multiplier) in cells {
+                        if multiplier == 1 {
+                            self.line(format!("addb %cl, {}(%r12,%rbx)", cell_offset));
+                        } else {
+                            self.line(format!("imull ${}, %ecx, %eax", multiplier));
+                            self.line(format!("addb %al, {}(%r12,%rbx)", cell_offset));
+                        }
+                    }
+                    self.line("movb $0, (%r12,%rbx)");
+                }
+                Command::DumpTape | Command::Assert(_) | Command::Position(_, _) => {}
+                &Command::AddRight(amount, offset) => {
+                    self.line(format!("addb ${}, (%r12,%rbx)", amount));
+                    self.line(format!("addq ${}, %rbx", offset));
+                    self.line("movq %rbx, %rdi");
+                    self.line("call bf_ensure");
+                }
+                Command::Halt => {
+                    self.line("jmp .Lhalt")
+                }
+                Command::Store => {
+                    self.line("movb (%r12,%rbx), %al");
+                    self.line("movb %al, bf_register(%rip)");
+                }
+                Command::Restore => {
+                    self.line("movb bf_register(%rip), %al");
+                    self.line("movb %al, (%r12,%rbx)");
+                }
+                Command::Random => {
+                    self.line("call rand@PLT");
+                    self.line("movb %al, (%r12,%rbx)");
+                }
+                Command::SwitchTape => {
+                    self.line("call bf_switch")
+                }
+                Command::Mul(multiplication) => {
+                    self.emit(&[multiplication.to_loop()])
+                }
+                Command::MoveSpan(span) => {
+                    self.emit(&[span.to_move()])
+                }
+                &Command::Scan(stride) => {
+                    self.emit(&[Command::scan_loop(stride)])
+                }
+                Command::ExchangeCells => {
+                    // `%r13` is preserved by `bf_switch`.
+                    self.line("movzbl (%r12,%rbx), %r13d");
+                    self.line("call bf_switch");
+                    self.line("movb (%r12,%rbx), %al");
+                    self.line("movb %r13b, (%r12,%rbx)");
+                    self.line("movzbl %al, %r13d");
+                    self.line("call bf_switch");
+                    self.line("movb %r13b, (%r12,%rbx)");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::cell::Cell;
+use crate::names::CellNames;
+use crate::tape::Tape;
+
+/// A value an assertion compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    /// The value of the cell at some index (not relative to the pointer).
+    Cell(isize),
+    /// The position of the pointer.
+    Pointer,
+    Literal(i64),
+}
+
+impl Operand {
+    fn parse(text: &str, names: &CellNames) -> Result<Self, String> {
+        if text == "ptr" {
+            return Ok(Self::Pointer);
+        }
+        if let Some(index) = text.strip_prefix("cell(").and_then(|text| text.strip_suffix(')')) {
+            return names.resolve(index.trim()).map(Self::Cell);
+        }
+        text.parse().map(Self::Literal).map_err(|_| format!("invalid operand `{}`", text))
+    }
+
+    fn evaluate<C: Cell>(self, tape: &Tape<'_, C>) -> i64 {
+        match self {
+            Self::Cell(index) => tape.read_relative(index - tape.pointer()).to_i64(),
+            Self::Pointer => tape.pointer() as i64,
+            Self::Literal(value) => value,
+        }
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Cell(index) => write!(f, "cell({})", index),
+            Self::Pointer => write!(f, "ptr"),
+            Self::Literal(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// A comparison between the two operands of an assertion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+}
+
+impl Comparison {
+    /// The comparisons, along with how they are written. `!=` comes first so that its `=` is not
+    /// taken for the start of `==`.
+    const ALL: [(&'static str, Self); 4] = [("!=", Self::NotEqual), ("==", Self::Equal), ("<", Self::Less), (">", Self::Greater)];
+
+    fn holds(self, left: i64, right: i64) -> bool {
+        match self {
+            Self::Equal => left == right,
+            Self::NotEqual => left != right,
+            Self::Less => left < right,
+            Self::Greater => left > right,
+        }
+    }
+}
+
+/// A condition checked while running a program (see `--assertions`), written as
+/// `{assert LEFT OP RIGHT}` in its source code.
+///
+/// Each operand is `cell(i)` (the value of the cell at index `i`, or named `i`), `ptr` (the position
+/// of the pointer), or an integer. The comparison is one of `==`, `!=`, `<`, and `>`.
+#[derive(Debug)]
+pub struct Assertion {
+    /// The condition, as written in the source code.
+    text: String,
+    left: Operand,
+    comparison: Comparison,
+    right: Operand,
+    /// The line and column of the assertion in the source code.
+    position: (usize, usize),
+}
+
+impl Assertion {
+    /// Parses the condition of an assertion (what follows `assert`), in which cells may be referred
+    /// to by their names.
+    pub fn parse(text: &str, position: (usize, usize), names: &CellNames) -> Result<Self, String> {
+        let text = text.trim();
+        let Some((index, symbol, comparison)) = Comparison::ALL.into_iter().find_map(|(symbol, comparison)| {
+            text.find(symbol).map(|index| (index, symbol, comparison))
+        }) else {
+            return Err(format!("missing comparison in `{}`", text));
+        };
+        Ok(Self {
+            text: text.to_string(),
+            left: Operand::parse(text[..index].trim(), names)?,
+            comparison,
+            right: Operand::parse(text[index + symbol.len()..].trim(), names)?,
+            position,
+        })
+    }
+
+    /// Tests whether the assertion holds on a tape.
+    pub fn holds<C: Cell>(&self, tape: &Tape<'_, C>) -> bool {
+        self.comparison.holds(self.left.evaluate(tape), self.right.evaluate(tape))
+    }
+
+    /// Describes why the assertion does not hold on a tape, by giving the actual values of the
+    /// operands that are not literals.
+    pub fn failure<C: Cell>(&self, tape: &Tape<'_, C>) -> String {
+        let (line, column) = self.position;
+        let values: Vec<_> = [self.left, self.right].into_iter()
+            .filter(|operand| !matches!(operand, Operand::Literal(_)))
+            .map(|operand| format!("{} is {}", operand, operand.evaluate(tape)))
+            .collect();
+        format!("assertion `{}` failed at {}:{} ({})", self.text, line, column, values.join(", "))
+    }
+}
+
+impl Display for Assertion {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{{assert {}}}", self.text)
+    }
+}
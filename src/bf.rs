@@ -0,0 +1,131 @@
+use brainfuck_interpreter::Command;
+
+/// Lowers the passed commands back to Brainfuck instructions.
+///
+/// The result is functionally equivalent to the commands: each command is replaced with a short
+/// sequence of instructions with the same effect. [`Command::Random`] is lowered to `random_char`.
+pub fn to_brainfuck(commands: &[Command], random_char: char) -> String {
+    let mut code = String::new();
+    lower(commands, random_char, &mut code);
+    code
+}
+
+/// Pushes the instructions moving the pointer to the right by a specific amount.
+///
+/// Instructions moving the pointer in the opposite direction at the end of the code are removed
+/// instead, so that, for example, a chunk reset followed by a move does not return to its origin
+/// only to leave it again.
+fn push_right(amount: isize, code: &mut String) {
+    let (instruction, opposite) = if amount < 0 { ('<', '>') } else { ('>', '<') };
+    for _ in 0..amount.unsigned_abs() {
+        if code.ends_with(opposite) {
+            code.pop();
+        } else {
+            code.push(instruction)
+        }
+    }
+}
+
+/// Pushes the instructions adding a specific amount to the current cell, which are either a
+/// sequence of `+` or a sequence of `-`, whichever is shorter.
+fn push_add(amount: u64, code: &mut String) {
+    if amount <= 128 {
+        code.extend(std::iter::repeat_n('+', amount as usize));
+    } else {
+        code.extend(std::iter::repeat_n('-', 256 - amount as usize));
+    }
+}
+
+fn lower(commands: &[Command], random_char: char, code: &mut String) {
+    // Loops are lowered with an explicit stack of the commands left in each, so that deeply nested
+    // loops do not overflow the call stack.
+    let mut stack = vec![commands.iter()];
+    while let Some(commands) = stack.last_mut() {
+        let Some(command) = commands.next() else {
+            stack.pop();
+            if !stack.is_empty() {
+                code.push(']')
+            }
+            continue;
+        };
+        match command {
+            &Command::Right(amount) => {
+                push_right(amount, code)
+            }
+            &Command::Add(amount) => {
+                push_add(amount, code)
+            }
+            Command::Loop(loop_commands) => {
+                code.push('[');
+                stack.push(loop_commands.iter())
+            }
+            Command::Input => {
+                code.push(',')
+            }
+            Command::Output | Command::TracedOutput(_) => {
+                code.push('.')
+            }
+            Command::Reset => {
+                code.push_str("[-]")
+            }
+            &Command::ResetChunk(max_offset) => {
+                code.push_str("[-]");
+                for _ in 0..max_offset.abs() {
+                    push_right(max_offset.signum(), code);
+                    code.push_str("[-]")
+                }
+                push_right(-max_offset, code)
+            }
+            Command::Move(cells) => {
+                code.push_str("[-");
+                let mut offset = 0;
+                // Increments of the current cell can be ignored, since it is reset anyway.
+                for &(cell_offset, multiplier) in cells.iter().filter(|(cell_offset, _)| *cell_offset != 0) {
+                    push_right(cell_offset - offset, code);
+                    push_add(multiplier, code);
+                    offset = cell_offset
+                }
+                push_right(-offset, code);
+                code.push(']')
+            }
+            Command::DumpTape => {
+                code.push('?')
+            }
+            &Command::AddRight(amount, offset) => {
+                push_add(amount, code);
+                push_right(offset, code)
+            }
+            Command::Halt => {
+                code.push('@')
+            }
+            Command::Store => {
+                code.push('$')
+            }
+            Command::Restore => {
+                code.push('!')
+            }
+            Command::Random => {
+                code.push(random_char)
+            }
+            Command::SwitchTape => {
+                code.push('~')
+            }
+            Command::Mul(multiplication) => {
+                lower(&[multiplication.to_loop()], random_char, code)
+            }
+            Command::MoveSpan(span) => {
+                lower(&[span.to_move()], random_char, code)
+            }
+            &Command::Scan(stride) => {
+                lower(&[Command::scan_loop(stride)], random_char, code)
+            }
+            Command::ExchangeCells => {
+                code.push('^')
+            }
+            Command::Assert(assertion) => {
+                code.push_str(&assertion.to_string())
+            }
+            Command::Position(_, _) => {}
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::ops::ControlFlow;
+
+use crate::{execute_command, Cell, Command, Machine, Stop};
+
+/// An instruction of a program flattened by [`flatten`], where loops are replaced with jumps.
+#[derive(Debug, Clone, Copy)]
+pub enum Op<'a> {
+    /// Executes a run of commands that are not loops.
+    Commands(&'a [Command]),
+    /// Starts a loop, jumping past the [`Op::JumpIfNonZero`] at the specified index if the current
+    /// cell is 0.
+    JumpIfZero(usize),
+    /// Ends a loop, jumping back to the start of its body (right after the [`Op::JumpIfZero`] at
+    /// the specified index) if the current cell is not 0.
+    JumpIfNonZero(usize),
+}
+
+/// Flattens commands into a linear sequence of instructions, in which the body of each loop is
+/// between a pair of jumps.
+///
+/// Loops are flattened using a stack rather than recursively, so that deeply nested ones cannot
+/// overflow the call stack.
+pub fn flatten(commands: &[Command]) -> Vec<Op<'_>> {
+    let mut ops = Vec::new();
+    // The commands left to flatten in each loop the current command is in, along with the index of
+    // the jump starting the loop.
+    let mut stack = vec![(commands, None)];
+    while let Some((commands, start)) = stack.last_mut() {
+        let length = commands.iter().position(|command| matches!(command, Command::Loop(_))).unwrap_or(commands.len());
+        if length > 0 {
+            ops.push(Op::Commands(&commands[..length]))
+        }
+        match commands.get(length) {
+            Some(Command::Loop(body)) => {
+                *commands = &commands[length + 1..];
+                stack.push((body, Some(ops.len())));
+                // The target is set once the end of the loop is known.
+                ops.push(Op::JumpIfZero(0))
+            }
+            _ => {
+                if let Some(start) = *start {
+                    ops[start] = Op::JumpIfZero(ops.len());
+                    ops.push(Op::JumpIfNonZero(start))
+                }
+                stack.pop();
+            }
+        }
+    }
+    ops
+}
+
+/// Executes a flattened program on a machine like [`execute`](crate::execute) executes the
+/// commands it was flattened from, counting the same steps, but without recursing into loops.
+///
+/// Returns [`ControlFlow::Break`] if the program ended early.
+pub fn execute<C: Cell>(ops: &[Op], machine: &mut Machine<'_, C>) -> ControlFlow<Stop> {
+    execute_with(ops, machine, execute_command)
+}
+
+/// Executes a flattened program on a machine like [`execute`], but executes each command other
+/// than loops with `execute_command`, such as to check it before and after executing it with
+/// [`crate::execute_command`].
+pub fn execute_with<C: Cell>(ops: &[Op], machine: &mut Machine<'_, C>, mut execute_command: impl FnMut(&Command, &mut Machine<'_, C>) -> ControlFlow<Stop>) -> ControlFlow<Stop> {
+    let mut index = 0;
+    while let Some(&op) = ops.get(index) {
+        match op {
+            Op::Commands(commands) => {
+                for command in commands {
+                    execute_command(command, machine)?
+                }
+            }
+            Op::JumpIfZero(end) => {
+                // Entering a loop counts as a step, but repeating it does not.
+                machine.step()?;
+                if machine.tape.is_zero() {
+                    index = end
+                } else {
+                    machine.check_step_limit(end == index + 1)?
+                }
+            }
+            Op::JumpIfNonZero(start) => {
+                if !machine.tape.is_zero() {
+                    // Programs can only run forever by looping, so there is no need to check the
+                    // number of steps more often.
+                    machine.check_step_limit(false)?;
+                    index = start
+                }
+            }
+        }
+        index += 1
+    }
+    ControlFlow::Continue(())
+}
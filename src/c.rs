@@ -0,0 +1,221 @@
+use std::fmt::Write;
+
+use brainfuck_interpreter::tape::Eof;
+use brainfuck_interpreter::Command;
+
+/// Runtime support for the generated program: the tape state and a function returning a pointer to
+/// a cell, which grows the tape in both directions when needed.
+///
+/// The tape is a heap allocation of `size` cells, and the cell at index `i` is stored at
+/// `tape[origin + i]`. Pointers returned by `cell` are invalidated by the next call to `cell`, as
+/// it may reallocate the tape.
+///
+/// The inactive tape of the `dual` dialect is described by `other_tape`, `other_size`,
+/// `other_origin`, and `other_p`, which `switch_tapes` exchanges with the globals of the active
+/// tape. Helpers are `static inline` so that compilers do not warn about the unused ones, and the
+/// register of the `ext1` dialect is only declared if it is used, for the same reason.
+const RUNTIME: &str = r#"#include <stddef.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+static unsigned char *tape;
+static ptrdiff_t size;
+static ptrdiff_t origin;
+static ptrdiff_t p;
+static unsigned char *other_tape;
+static ptrdiff_t other_size;
+static ptrdiff_t other_origin;
+static ptrdiff_t other_p;
+
+static inline unsigned char *cell(ptrdiff_t index) {
+    if (index + origin < 0 || index + origin >= size) {
+        /* Add `size + |index| + 1` cells on each side, which is enough for `index` to be valid. */
+        ptrdiff_t shift = size + (index < 0 ? -index : index) + 1;
+        unsigned char *new_tape = calloc(size + 2 * shift, 1);
+        if (new_tape == NULL) {
+            fputs("error: unable to grow the tape\n", stderr);
+            exit(1);
+        }
+        if (size > 0) {
+            memcpy(new_tape + shift, tape, size);
+        }
+        free(tape);
+        tape = new_tape;
+        size += 2 * shift;
+        origin += shift;
+    }
+    return &tape[origin + index];
+}
+
+static inline void switch_tapes(void) {
+    unsigned char *t = tape;
+    ptrdiff_t s = size, o = origin, q = p;
+    tape = other_tape;
+    size = other_size;
+    origin = other_origin;
+    p = other_p;
+    other_tape = t;
+    other_size = s;
+    other_origin = o;
+    other_p = q;
+}
+"#;
+
+/// Returns the function reading a value into the current cell, which handles the end of the input
+/// as the interpreter does with `eof` (exiting with status 5 for `--eof error`).
+fn input_function(eof: Eof) -> String {
+    let end_of_input = match eof {
+        Eof::Zero => "*cell(p) = 0;",
+        Eof::MinusOne => "*cell(p) = 255;",
+        Eof::Unchanged => "/* The cell is left unchanged. */",
+        Eof::Error => "fflush(stdout);\n        fputs(\"error: the program read past the end of its input\\n\", stderr);\n        exit(5);",
+    };
+    format!("static inline void input(void) {{\n    int value = getchar();\n    if (value == EOF) {{\n        {}\n    }} else {{\n        *cell(p) = (unsigned char) value;\n    }}\n}}\n", end_of_input)
+}
+
+/// Translates the passed commands to a standalone C program.
+///
+/// The generated program only depends on the C standard library, so it can be compiled with, e.g.,
+/// `cc -O2 -Wall program.c`. Cells are `unsigned char`, which wrap around like those of the
+/// interpreter, and the tape grows in both directions as needed. Values are output as raw bytes,
+/// like with the other emitters. Tape dumps (`?`) and assertions are ignored. Random bytes come
+/// from the `rand` function of the C standard library, seeded with the current time.
+pub fn to_c(commands: &[Command], eof: Eof) -> String {
+    let mut emitter = Emitter { code: String::new(), depth: 1, is_register_used: false, is_random_used: false };
+    emitter.emit(commands);
+    let register = if emitter.is_register_used { "static unsigned char reg;\n\n" } else { "" };
+    let seed = if emitter.is_random_used { "    srand((unsigned) time(NULL));\n" } else { "" };
+    format!("{}\n{}\n{}int main(void) {{\n{}{}    return 0;\n}}\n", RUNTIME, input_function(eof), register, seed, emitter.code)
+}
+
+/// The deepest indentation of the emitted code, in blocks, so that the size of the code of deeply
+/// nested loops does not grow quadratically with their depth.
+const MAX_INDENTATION: usize = 32;
+
+/// Generates the body of the `main` function.
+#[derive(Debug)]
+struct Emitter {
+    /// The generated code.
+    code: String,
+    /// The number of blocks the next line is nested in, which sets its indentation.
+    depth: usize,
+    /// Whether the program uses the register, in which case it is declared.
+    is_register_used: bool,
+    /// Whether the program generates random bytes, in which case the generator is seeded.
+    is_random_used: bool,
+}
+
+impl Emitter {
+    /// Emits a line of code.
+    fn line(&mut self, line: impl AsRef<str>) {
+        writeln!(self.code, "{}{}", "    ".repeat(self.depth.min(MAX_INDENTATION)), line.as_ref()).unwrap()
+    }
+
+    /// Emits a block of code, whose lines are emitted by `f`, after a line opening it.
+    fn block(&mut self, opening: impl AsRef<str>, f: impl FnOnce(&mut Self)) {
+        self.line(format!("{}{{", opening.as_ref()));
+        self.depth += 1;
+        f(self);
+        self.depth -= 1;
+        self.line("}")
+    }
+
+    fn emit(&mut self, commands: &[Command]) {
+        // Loops are emitted with an explicit stack of the commands left in each, so that deeply
+        // nested loops do not overflow the call stack.
+        let mut stack = vec![commands.iter()];
+        while let Some(commands) = stack.last_mut() {
+            let Some(command) = commands.next() else {
+                stack.pop();
+                if !stack.is_empty() {
+                    self.depth -= 1;
+                    self.line("}")
+                }
+                continue;
+            };
+            match command {
+                &Command::Right(amount) => {
+                    self.line(format!("p += {};", amount))
+                }
+                &Command::Add(amount) => {
+                    self.line(format!("*cell(p) += {};", amount))
+                }
+                Command::Loop(loop_commands) => {
+                    self.line("while (*cell(p)) {");
+                    self.depth += 1;
+                    stack.push(loop_commands.iter())
+                }
+                Command::Input => {
+                    self.line("input();")
+                }
+                Command::Output | Command::TracedOutput(_) => {
+                    self.line("putchar(*cell(p));")
+                }
+                Command::Reset => {
+                    self.line("*cell(p) = 0;")
+                }
+                &Command::ResetChunk(max_offset) => {
+                    // Make sure both ends of the chunk are allocated before taking a pointer to its
+                    // first cell, as growing the tape moves it.
+                    let length = max_offset.unsigned_abs() + 1;
+                    self.line(format!("cell(p + {});", max_offset.max(0)));
+                    self.line(format!("memset(cell(p + {}), 0, {});", max_offset.min(0), length))
+                }
+                Command::Move(cells) => {
+                    self.block("", |emitter| {
+                        emitter.line("unsigned char value = *cell(p);");
+                        for &(cell_offset, multiplier) in cells.iter() {
+                            emitter.line(format!("*cell(p + {}) += value * {};", cell_offset, multiplier))
+                        }
+                        emitter.line("*cell(p) = 0;")
+                    })
+                }
+                Command::DumpTape | Command::Assert(_) | Command::Position(_, _) => {}
+                &Command::AddRight(amount, offset) => {
+                    self.line(format!("*cell(p) += {};", amount));
+                    self.line(format!("p += {};", offset))
+                }
+                Command::Halt => {
+                    self.line("exit(0);")
+                }
+                Command::Store => {
+                    self.is_register_used = true;
+                    self.line("reg = *cell(p);")
+                }
+                Command::Restore => {
+                    self.is_register_used = true;
+                    self.line("*cell(p) = reg;")
+                }
+                Command::Random => {
+                    self.is_random_used = true;
+                    self.line("*cell(p) = (unsigned char) rand();")
+                }
+                Command::SwitchTape => {
+                    self.line("switch_tapes();")
+                }
+                Command::Mul(multiplication) => {
+                    self.emit(&[multiplication.to_loop()])
+                }
+                Command::MoveSpan(span) => {
+                    self.emit(&[span.to_move()])
+                }
+                &Command::Scan(stride) => {
+                    self.emit(&[Command::scan_loop(stride)])
+                }
+                Command::ExchangeCells => {
+                    self.block("", |emitter| {
+                        emitter.line("unsigned char value = *cell(p);");
+                        emitter.line("unsigned char other_value;");
+                        emitter.line("switch_tapes();");
+                        emitter.line("other_value = *cell(p);");
+                        emitter.line("*cell(p) = value;");
+                        emitter.line("switch_tapes();");
+                        emitter.line("*cell(p) = other_value;")
+                    })
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use std::fmt::{Debug, Display, LowerHex};
+use std::io;
+
+use num_bigint::{BigInt, Sign};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// The size of the cells of a tape, which sets the type of the cells of the [`Tape`] and of the
+/// [`Machine`] running a program (see [`Cell`]).
+///
+/// [`Tape`]: crate::tape::Tape
+/// [`Machine`]: crate::Machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CellSize {
+    /// Bytes, which wrap around.
+    #[default]
+    Eight,
+    /// Unsigned 16-bit integers, which wrap around. Outputting a cell outputs its lowest byte.
+    Sixteen,
+    /// Unsigned 32-bit integers, which wrap around. Outputting a cell outputs its lowest byte.
+    ThirtyTwo,
+    /// Arbitrary-precision signed integers. Outputting a cell outputs its lowest byte.
+    Big,
+}
+
+impl CellSize {
+    /// Reduces an amount added to cells of this size (see [`Command::Add`]) to its canonical value,
+    /// which is less than the number of values of the cells if they wrap around.
+    ///
+    /// Amounts are unsigned integers modulo 2<sup>64</sup>, which is a multiple of the number of
+    /// values of the cells of every fixed size. Arbitrary-precision cells read them as signed, in
+    /// two's complement.
+    ///
+    /// [`Command::Add`]: crate::Command::Add
+    pub fn wrap(self, amount: u64) -> u64 {
+        match self {
+            Self::Eight => amount & u64::from(u8::MAX),
+            Self::Sixteen => amount & u64::from(u16::MAX),
+            Self::ThirtyTwo => amount & u64::from(u32::MAX),
+            Self::Big => amount,
+        }
+    }
+
+    /// Returns the amount decrementing a cell of this size by 1.
+    pub fn minus_one(self) -> u64 {
+        self.wrap(u64::MAX)
+    }
+
+    /// Returns an amount added to cells of this size as a signed integer, taking the amounts from
+    /// half the number of values of the cells on as decrements (as `-` is loaded as adding the
+    /// largest value).
+    pub fn signed(self, amount: u64) -> i64 {
+        match self {
+            Self::Eight => amount as u8 as i8 as i64,
+            Self::Sixteen => amount as u16 as i16 as i64,
+            Self::ThirtyTwo => amount as u32 as i32 as i64,
+            Self::Big => amount as i64,
+        }
+    }
+
+    /// Whether cells of this size wrap around, which the optimizations of loops rely on: a loop
+    /// decrementing a cell by 1 always ends, for example, but only if it cannot become negative.
+    pub fn wraps(self) -> bool {
+        self != Self::Big
+    }
+}
+
+/// The value of a cell of a tape.
+///
+/// Cells are bytes by default, but they may also be wider (see [`CellSize`]). Amounts (such as of
+/// [`Command::Add`](crate::Command::Add)) are passed as they are stored in commands, reduced modulo
+/// 2<sup>64</sup> (see [`CellSize::wrap`]).
+pub trait Cell: Clone + Default + PartialEq + Debug + Display + LowerHex + Serialize + DeserializeOwned + 'static {
+    /// The size of these cells.
+    const SIZE: CellSize;
+
+    /// Returns the value of a cell after adding an amount to a cell that is 0.
+    fn from_amount(amount: u64) -> Self;
+
+    /// Returns the sum of two values, wrapping around if cells have a fixed size.
+    fn plus(&self, other: &Self) -> Self;
+
+    /// Returns the product of two values, wrapping around if cells have a fixed size.
+    fn times(&self, other: &Self) -> Self;
+
+    /// Tests if this value is 0, which ends loops.
+    fn is_zero(&self) -> bool;
+
+    /// Returns the value of a cell after reading a byte.
+    fn from_byte(byte: u8) -> Self;
+
+    /// Returns the lowest byte of this value (in two's complement), which `.` outputs.
+    fn low_byte(&self) -> u8;
+
+    /// Returns this value as a signed integer, saturating if it does not fit, as assertions
+    /// compare it.
+    fn to_i64(&self) -> i64;
+
+    /// Writes this value as hexadecimal, followed by a newline, as `.` does with `--hex-output`.
+    fn write_hex(&self, out: &mut dyn io::Write) -> io::Result<()>;
+
+    /// Appends this value to a canonical representation of a tape (see
+    /// [`Tape::canonical_state`](crate::tape::Tape::canonical_state)), as little-endian bytes.
+    /// Arbitrary-precision values are preceded by their number of bytes, as 8 little-endian bytes.
+    fn extend_canonical(&self, state: &mut Vec<u8>);
+
+    /// Adds an amount to this value, wrapping around if cells have a fixed size.
+    #[inline(always)]
+    fn add(&mut self, amount: u64) {
+        *self = self.plus(&Self::from_amount(amount))
+    }
+}
+
+/// Implements [`Cell`] for an unsigned integer type, whose values wrap around.
+macro_rules! impl_wrapping_cell {
+    ($type:ty, $size:expr) => {
+        impl Cell for $type {
+            const SIZE: CellSize = $size;
+
+            #[inline(always)]
+            fn from_amount(amount: u64) -> Self {
+                // Truncating is the same as reducing modulo the number of values.
+                amount as $type
+            }
+
+            #[inline(always)]
+            fn plus(&self, other: &Self) -> Self {
+                self.wrapping_add(*other)
+            }
+
+            #[inline(always)]
+            fn times(&self, other: &Self) -> Self {
+                self.wrapping_mul(*other)
+            }
+
+            #[inline(always)]
+            fn is_zero(&self) -> bool {
+                *self == 0
+            }
+
+            fn from_byte(byte: u8) -> Self {
+                byte.into()
+            }
+
+            fn low_byte(&self) -> u8 {
+                *self as u8
+            }
+
+            fn to_i64(&self) -> i64 {
+                (*self).into()
+            }
+
+            fn write_hex(&self, out: &mut dyn io::Write) -> io::Result<()> {
+                writeln!(out, "0x{:0width$x}", self, width = 2 * size_of::<$type>())
+            }
+
+            fn extend_canonical(&self, state: &mut Vec<u8>) {
+                state.extend_from_slice(&self.to_le_bytes())
+            }
+        }
+    };
+}
+
+impl_wrapping_cell!(u8, CellSize::Eight);
+impl_wrapping_cell!(u16, CellSize::Sixteen);
+impl_wrapping_cell!(u32, CellSize::ThirtyTwo);
+
+impl Cell for BigInt {
+    const SIZE: CellSize = CellSize::Big;
+
+    fn from_amount(amount: u64) -> Self {
+        (amount as i64).into()
+    }
+
+    fn plus(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn times(&self, other: &Self) -> Self {
+        self * other
+    }
+
+    fn is_zero(&self) -> bool {
+        self.sign() == Sign::NoSign
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        byte.into()
+    }
+
+    fn low_byte(&self) -> u8 {
+        u8::try_from(self & BigInt::from(u8::MAX)).expect("The lowest byte fits in a byte")
+    }
+
+    fn to_i64(&self) -> i64 {
+        i64::try_from(self).unwrap_or(if self.sign() == Sign::Minus { i64::MIN } else { i64::MAX })
+    }
+
+    fn write_hex(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        let sign = if self.sign() == Sign::Minus { "-" } else { "" };
+        writeln!(out, "{}0x{:x}", sign, self.magnitude())
+    }
+
+    fn extend_canonical(&self, state: &mut Vec<u8>) {
+        let bytes = self.to_signed_bytes_le();
+        state.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        state.extend_from_slice(&bytes)
+    }
+
+    fn add(&mut self, amount: u64) {
+        *self += amount as i64
+    }
+}
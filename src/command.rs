@@ -0,0 +1,224 @@
+use crate::assertion::Assertion;
+use crate::cell::CellSize;
+
+/// Commands represent higher level concepts than regular Brainfuck instructions. The goal is that a
+/// specific command can be executed in less time than it would take for it to be executed if it was
+/// made up of multiple regular Brainfuck instructions with the same effect.
+///
+/// Programs are usually loaded with [`parse`](crate::parse) or [`load`](crate::load), and then
+/// [optimized](crate::optimize), but they may also be built by hand. Commands standing for loops
+/// ([`Command::Move`], [`Command::MoveSpan`], [`Command::Mul`], and [`Command::Scan`]) are only
+/// equivalent to them if their targets and their operands are not the current cell, which the
+/// optimizations guarantee. [`Command::SwitchTape`] and [`Command::ExchangeCells`] panic unless the
+/// machine has a second tape (see [`MachineOptions::dual_tape`](crate::MachineOptions::dual_tape)).
+#[derive(Debug)]
+pub enum Command {
+    /// Moves the pointer to the right by a specific amount (to the left if negative).
+    Right(isize),
+    /// Adds a specific amount to the current cell.
+    ///
+    /// Amounts are stored modulo 2<sup>64</sup>, and reduced modulo the number of values of the
+    /// cells (see [`CellSize::wrap`](crate::CellSize::wrap)), so that a program loaded for byte
+    /// cells only ever adds amounts up to 255.
+    Add(u64),
+    /// Repeats commands until the current cell reaches 0.
+    Loop(Box<[Command]>),
+    /// Sets the value of the current cell to a byte read from `stdin`.
+    Input,
+    /// Outputs the value of the current cell to `stdout`.
+    Output,
+    /// A [`Command::Output`] that records which `.` output each value (see `--output-map`), with
+    /// the offset of the `.` in the source code.
+    TracedOutput(usize),
+    /// Resets the value of the current cell to 0.
+    Reset,
+    /// Resets the values of the cells between the current cell and a specific cell (both included)
+    /// to 0.
+    ResetChunk(isize),
+    /// Moves the value of the current cell to the cells at a specific position (relative to the
+    /// current cell).
+    ///
+    /// Specifically:
+    /// - For each element `(i, n)` in the vector, adds `n` times the value of the current cell to
+    ///   the value of the cell `i` cells to the right of the current cell;
+    /// - Resets the current cell.
+    ///
+    /// The pointer is *not* moved.
+    Move(Box<[(isize, u64)]>),
+    /// Prints the tape and the position of the pointer to standard error (see `--debug`).
+    ///
+    /// This is never removed or moved by optimizations, so that dumps show the tape as the source
+    /// code says it is at this point.
+    DumpTape,
+    /// Adds a specific amount to the current cell, then moves the pointer to the right by a specific
+    /// amount (to the left if negative).
+    ///
+    /// This is a fusion of [`Command::Add`] and [`Command::Right`], which very often appear one after
+    /// the other.
+    AddRight(u64, isize),
+    /// Ends the program immediately.
+    Halt,
+    /// Copies the value of the current cell to the storage register.
+    Store,
+    /// Copies the value of the storage register to the current cell.
+    Restore,
+    /// Sets the value of the current cell to a random byte.
+    Random,
+    /// Switches the tape that other commands operate on (see the `dual` dialect).
+    SwitchTape,
+    /// Exchanges the values of the current cells of both tapes (see the `dual` dialect).
+    ExchangeCells,
+    /// Multiplies the value of the current cell by the value of another cell (see
+    /// [`Multiplication`]), then resets the current cell.
+    Mul(Box<Multiplication>),
+    /// A [`Command::Move`] adding the value of the current cell to a run of adjacent cells (see
+    /// [`MoveSpan`]).
+    MoveSpan(Box<MoveSpan>),
+    /// Stops the program with an error if a condition does not hold (see `--assertions`).
+    Assert(Box<Assertion>),
+    /// Moves the pointer to the right by a specific amount (to the left if negative) until the
+    /// current cell is 0, as in `[>]` or `[<<]`.
+    Scan(isize),
+    /// Sets the position in the source code (line and column, see
+    /// [`line_column`](crate::source::line_column)) of the commands that follow, which pauses show
+    /// (see `--break-at-step`).
+    ///
+    /// This is not a step, and optimizations see through it, so that programs loaded with positions
+    /// (see [`ParseOptions::trace_positions`](crate::ParseOptions::trace_positions)) count the same
+    /// steps as without them.
+    Position(usize, usize),
+}
+
+impl Drop for Command {
+    /// Drops the loops nested in a loop using a stack rather than recursively, so that deeply nested
+    /// ones cannot overflow the call stack.
+    fn drop(&mut self) {
+        let Command::Loop(body) = self else {
+            return;
+        };
+        let mut stack = vec![std::mem::take(body)];
+        while let Some(mut commands) = stack.pop() {
+            for command in commands.iter_mut() {
+                if let Command::Loop(body) = command {
+                    stack.push(std::mem::take(body))
+                }
+            }
+            // The loops left in `commands` are empty, so dropping them does not recurse.
+        }
+    }
+}
+
+/// A loop that adds the product of the value of the current cell and the value of another cell
+/// (the operand) to some cells, using a temporary cell to restore the operand after each iteration.
+///
+/// In Brainfuck, this is `[-` followed by a move of the operand to the targets and the temporary
+/// cell, a move of the temporary cell back to the operand, and `]`.
+///
+/// If the temporary cell is not initially 0, its value is added to the operand by the first
+/// iteration, so if `a`, `b`, and `t` are the initial values of the current cell, the operand, and the
+/// temporary cell, and `a` is not 0:
+/// - `b + (a - 1) * (b + t)` is added to the targets (times their multipliers);
+/// - The operand is set to `b + t`;
+/// - The temporary cell and the current cell are reset.
+#[derive(Debug)]
+pub struct Multiplication {
+    /// The offset of the operand, relative to the current cell.
+    pub operand: isize,
+    /// The offset of the temporary cell, relative to the current cell.
+    pub temporary: isize,
+    /// The offsets of the cells the product is added to, relative to the current cell, with their
+    /// multipliers.
+    pub targets: Box<[(isize, u64)]>,
+}
+
+impl Multiplication {
+    /// Returns the loop this multiplication was recognized from, which emitters can translate
+    /// instead. Emitters only support byte cells, so the loop decrements the current cell by 255.
+    pub fn to_loop(&self) -> Command {
+        let mut operand_move: Vec<(isize, u64)> = self.targets.iter().map(|&(offset, multiplier)| (offset - self.operand, multiplier)).collect();
+        operand_move.push((self.temporary - self.operand, 1));
+        Command::Loop(Box::new([
+            Command::Add(CellSize::Eight.minus_one()),
+            Command::Right(self.operand),
+            Command::Move(operand_move.into_boxed_slice()),
+            Command::Right(self.temporary - self.operand),
+            Command::Move(Box::new([(self.operand - self.temporary, 1)])),
+            Command::Right(-self.temporary),
+        ]))
+    }
+}
+
+/// A move (see [`Command::Move`]) whose targets include a run of adjacent cells with a multiplier of
+/// 1, such as when copying an array. The value of the current cell is added to the whole run in a
+/// single pass, which the compiler can vectorize, instead of one cell at a time.
+#[derive(Debug)]
+pub struct MoveSpan {
+    /// The offset of the first cell of the run, relative to the current cell.
+    pub start: isize,
+    /// The number of cells in the run.
+    pub length: usize,
+    /// The other targets of the move, with their multipliers.
+    pub rest: Box<[(isize, u64)]>,
+}
+
+impl MoveSpan {
+    /// Returns the move this was recognized from (up to the order of its targets), which emitters
+    /// can translate instead.
+    pub fn to_move(&self) -> Command {
+        let run = (0..self.length as isize).map(|i| (self.start + i, 1));
+        Command::Move(run.chain(self.rest.iter().copied()).collect())
+    }
+}
+
+// Large payloads are boxed so that the most common commands stay small.
+const _: () = assert!(std::mem::size_of::<Command>() <= 24);
+
+impl Command {
+    /// Returns the name of the variant of this command.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Right(_) => "Right",
+            Self::Add(_) => "Add",
+            Self::Loop(_) => "Loop",
+            Self::Input => "Input",
+            Self::Output | Self::TracedOutput(_) => "Output",
+            Self::Reset => "Reset",
+            Self::ResetChunk(_) => "ResetChunk",
+            Self::Move(_) => "Move",
+            Self::DumpTape => "DumpTape",
+            Self::AddRight(_, _) => "AddRight",
+            Self::Halt => "Halt",
+            Self::Store => "Store",
+            Self::Restore => "Restore",
+            Self::Random => "Random",
+            Self::SwitchTape => "SwitchTape",
+            Self::ExchangeCells => "ExchangeCells",
+            Self::Mul(_) => "Mul",
+            Self::MoveSpan(_) => "MoveSpan",
+            Self::Assert(_) => "Assert",
+            Self::Scan(_) => "Scan",
+            Self::Position(_, _) => "Position",
+        }
+    }
+
+    /// Returns the loop a [`Command::Scan`] with the passed stride was recognized from, which
+    /// emitters can translate instead.
+    pub fn scan_loop(stride: isize) -> Self {
+        Self::Loop(Box::new([Self::Right(stride)]))
+    }
+
+    /// Tests if this command is useful.
+    ///
+    /// A command is useful if it is not functionally equivalent to doing nothing.
+    pub fn is_useful(&self) -> bool {
+        !matches!(self, Self::Right(0) | Self::Add(0))
+    }
+
+    /// Tests if this comment increments the current cell by an odd amount.
+    pub fn is_odd_increment(&self) -> bool {
+        match self {
+            Self::Add(n) => n % 2 != 0,
+            _ => false,
+        }
+    }
+}
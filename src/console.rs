@@ -0,0 +1,174 @@
+use std::io;
+use std::io::{IsTerminal, Write};
+
+use clap::ValueEnum;
+
+/// A character set used to display the values a program outputs to a console (see
+/// `--console-charset`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Charset {
+    /// Values are decoded as UTF-8, and invalid sequences are displayed as `�`.
+    Utf8,
+    /// Each value is displayed as the Unicode character with the same code (ISO-8859-1).
+    Latin1,
+    /// Each value is displayed as in the original IBM PC character set (code page 437), which many
+    /// programs drawing boxes or games expect.
+    Cp437,
+}
+
+/// The characters of code page 437 for the values from 0x80 to 0xff. Lower values are the same as
+/// in ASCII.
+const CP437_HIGH_HALF: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// The replacement character, which invalid UTF-8 sequences are displayed as.
+const REPLACEMENT: &str = "\u{fffd}";
+
+/// Returns the character set values output to `stdout` have to be decoded with, or [`None`] if
+/// they are written as they are.
+///
+/// Values are only ever decoded when `stdout` is a console, so that redirected output is always
+/// made of the exact values. Consoles on Windows only accept text, so values are decoded as UTF-8
+/// there when no character set is passed. Other terminals accept any byte, and display it as they
+/// are configured to.
+fn display_charset(charset: Option<Charset>, is_console: bool) -> Option<Charset> {
+    match (charset, is_console) {
+        (_, false) => None,
+        (Some(charset), true) => Some(charset),
+        (None, true) => cfg!(windows).then_some(Charset::Utf8),
+    }
+}
+
+/// Returns the writer the output of a program should be written to to be displayed on `stdout`
+/// with the passed character set (see [`display_charset`]), or [`None`] if it can be written to
+/// `stdout` as it is.
+pub fn stdout(charset: Option<Charset>) -> Option<Console<io::Stdout>> {
+    display_charset(charset, io::stdout().is_terminal()).map(|charset| Console::new(io::stdout(), charset))
+}
+
+/// A writer decoding the bytes written to it with a character set, and writing them to another
+/// writer as UTF-8.
+///
+/// With [`Charset::Utf8`], the bytes of a character may be written one at a time, so an incomplete
+/// sequence at the end of a write is kept until the next one, and displayed as `�` if it is still
+/// incomplete when the writer is dropped.
+#[derive(Debug)]
+pub struct Console<W: Write> {
+    out: W,
+    charset: Charset,
+    /// The start of a UTF-8 sequence that has not been written yet.
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Console<W> {
+    pub fn new(out: W, charset: Charset) -> Self {
+        Self { out, charset, pending: Vec::new() }
+    }
+
+    /// Writes the valid UTF-8 at the start of the pending bytes, replacing invalid sequences, and
+    /// keeps an incomplete sequence at their end.
+    fn write_pending_utf8(&mut self) -> io::Result<()> {
+        loop {
+            match std::str::from_utf8(&self.pending) {
+                Ok(text) => {
+                    self.out.write_all(text.as_bytes())?;
+                    self.pending.clear();
+                    return Ok(());
+                }
+                Err(error) => {
+                    let valid = error.valid_up_to();
+                    self.out.write_all(&self.pending[..valid])?;
+                    let Some(invalid) = error.error_len() else {
+                        self.pending.drain(..valid);
+                        return Ok(());
+                    };
+                    self.out.write_all(REPLACEMENT.as_bytes())?;
+                    self.pending.drain(..valid + invalid);
+                }
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Console<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.charset {
+            Charset::Utf8 => {
+                self.pending.extend_from_slice(buf);
+                self.write_pending_utf8()?
+            }
+            Charset::Latin1 => {
+                let text: String = buf.iter().map(|&byte| char::from(byte)).collect();
+                self.out.write_all(text.as_bytes())?
+            }
+            Charset::Cp437 => {
+                let text: String = buf.iter().map(|&byte| match byte {
+                    0x00..=0x7f => char::from(byte),
+                    _ => CP437_HIGH_HALF.chars().nth(byte as usize - 0x80).expect("Code page 437 has 128 high characters"),
+                }).collect();
+                self.out.write_all(text.as_bytes())?
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Drop for Console<W> {
+    fn drop(&mut self) {
+        if !self.pending.is_empty() {
+            let _ = self.out.write_all(REPLACEMENT.as_bytes()).and_then(|_| self.out.flush());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes some bytes to a console one at a time, as the tape does, and returns what it
+    /// displays.
+    fn display(bytes: &[u8], charset: Charset) -> String {
+        let mut out = Vec::new();
+        let mut console = Console::new(&mut out, charset);
+        for &byte in bytes {
+            console.write_all(&[byte]).unwrap()
+        }
+        drop(console);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn redirected_output_is_never_decoded() {
+        for charset in [None, Some(Charset::Utf8), Some(Charset::Latin1), Some(Charset::Cp437)] {
+            assert_eq!(display_charset(charset, false), None)
+        }
+    }
+
+    #[test]
+    fn console_output_is_decoded_as_utf8_by_default_only_on_windows() {
+        let expected = if cfg!(windows) { Some(Charset::Utf8) } else { None };
+        assert_eq!(display_charset(None, true), expected);
+        assert_eq!(display_charset(Some(Charset::Cp437), true), Some(Charset::Cp437))
+    }
+
+    #[test]
+    fn code_page_437_has_a_character_for_each_high_value() {
+        assert_eq!(CP437_HIGH_HALF.chars().count(), 128)
+    }
+
+    #[test]
+    fn utf8_sequences_written_one_byte_at_a_time_are_kept_whole() {
+        assert_eq!(display("é!".as_bytes(), Charset::Utf8), "é!");
+        assert_eq!(display(b"a\xffb", Charset::Utf8), "a\u{fffd}b");
+        assert_eq!(display(b"a\xc3", Charset::Utf8), "a\u{fffd}")
+    }
+
+    #[test]
+    fn single_byte_charsets_display_each_value_as_a_character() {
+        assert_eq!(display(b"A\xe9\xff", Charset::Latin1), "Aéÿ");
+        assert_eq!(display(b"A\x80\xc9\xff", Charset::Cp437), "AÇ╔\u{a0}")
+    }
+}
@@ -0,0 +1,55 @@
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use brainfuck_interpreter::Command;
+
+use crate::args::Args;
+use crate::{chained_input, run_captured, synthetic_input};
+
+/// Returns the input of the compared programs, which is the synthetic or chained input if one is
+/// passed (e.g., with `--input-repeat` or `--input-source`), and the whole of `stdin` otherwise.
+pub fn input(args: &Args, seed: u64) -> Vec<u8> {
+    match synthetic_input(args, seed).or_else(|| chained_input(args)) {
+        Some(input) => input.collect::<io::Result<_>>().expect("Unable to read input"),
+        None => {
+            let mut bytes = Vec::new();
+            io::Read::read_to_end(&mut io::stdin(), &mut bytes).expect("Unable to read input");
+            bytes
+        }
+    }
+}
+
+/// Runs two programs on the same input, and writes where their outputs first differ (if they do)
+/// along with the number of bytes that differ (see `--diff`).
+///
+/// The step at which a program output the first differing byte is found by running it again until
+/// right before this byte, so that the outputs do not have to be compared while running.
+///
+/// Returns whether the outputs are identical.
+pub fn compare(programs: [(&Path, &[Command]); 2], input: &[u8], args: &Args, seed: u64, out: &mut impl Write) -> io::Result<bool> {
+    let runs = programs.map(|(_, commands)| {
+        let (output, machine, _) = run_captured(commands, Box::new(Vec::from(input).into_iter()), args.max_output, args.max_steps, args, seed);
+        (output, machine.steps)
+    });
+    let [(first, _), (second, _)] = &runs;
+    let length = first.len().max(second.len());
+    let differs = |&i: &usize| first.get(i) != second.get(i);
+    let Some(offset) = (0..length).find(differs) else {
+        writeln!(out, "Outputs are identical ({} bytes)", length)?;
+        return Ok(true);
+    };
+    writeln!(out, "Outputs first differ at offset {}:", offset)?;
+    for ((path, commands), (output, steps)) in programs.iter().zip(&runs) {
+        match output.get(offset) {
+            Some(value) => {
+                let (_, machine, _) = run_captured(commands, Box::new(Vec::from(input).into_iter()), Some(offset as u64), None, args, seed);
+                writeln!(out, "  {}: 0x{:02x}, output at step {}", path.display(), value, machine.steps)?
+            }
+            None => writeln!(out, "  {}: end of output, after {} steps", path.display(), steps)?,
+        }
+    }
+    let differing = (0..length).filter(differs).count();
+    writeln!(out, "Differing bytes: {} (the outputs are {} and {} bytes long)", differing, first.len(), second.len())?;
+    Ok(false)
+}
@@ -0,0 +1,113 @@
+use std::io;
+use std::io::Write;
+
+use brainfuck_interpreter::Command;
+
+/// Writes a graph of the loop structure of the passed commands in the Graphviz DOT format.
+///
+/// Each loop is a node labeled with its size and with how it was optimized, and edges represent
+/// nesting. Commands between two loops are summarized as a single node per straight-line segment.
+///
+/// Nodes are numbered in the order they are written (e.g., `n3` is the third node after the
+/// program), so names are stable for a given program, and stay short however deep loops are nested.
+pub fn write_dot(commands: &[Command], out: &mut impl Write) -> io::Result<()> {
+    let (size, loop_sizes) = sizes(commands);
+    writeln!(out, "digraph program {{")?;
+    writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+    writeln!(out, "    n [label=\"program\\n{}\", shape=ellipse];", count(size, "command"))?;
+    write_nodes(commands, loop_sizes, out)?;
+    writeln!(out, "}}")
+}
+
+/// Formats a number followed by a noun, which is pluralized if needed.
+fn count(n: usize, noun: &str) -> String {
+    if n == 1 {
+        format!("1 {}", noun)
+    } else {
+        format!("{} {}s", n, noun)
+    }
+}
+
+/// Returns the number of commands in the passed commands, including commands in nested loops, and
+/// the number of commands in the body of each loop, in the order loops appear in the code.
+fn sizes(commands: &[Command]) -> (usize, Vec<usize>) {
+    let mut loop_sizes = Vec::new();
+    // The commands left in each loop being walked, along with the index of its size and the number
+    // of commands walked before entering it.
+    let mut stack = vec![(commands.iter(), None, 0)];
+    let mut walked = 0;
+    while let Some((commands, _, _)) = stack.last_mut() {
+        match commands.next() {
+            Some(Command::Loop(body)) => {
+                walked += 1;
+                loop_sizes.push(0);
+                stack.push((body.iter(), Some(loop_sizes.len() - 1), walked))
+            }
+            Some(_) => {
+                walked += 1
+            }
+            None => {
+                if let Some((_, Some(index), start)) = stack.pop() {
+                    loop_sizes[index] = walked - start
+                }
+            }
+        }
+    }
+    (walked, loop_sizes)
+}
+
+/// Writes a node for each loop and straight-line segment in the passed commands, and an edge from
+/// the parent node to each of them. `loop_sizes` are the sizes of the bodies of the loops, as
+/// returned by [`sizes`].
+fn write_nodes(commands: &[Command], loop_sizes: Vec<usize>, out: &mut impl Write) -> io::Result<()> {
+    /// Writes the node summarizing a straight-line segment of a specific length, if there is one.
+    fn write_segment(segment: Option<usize>, parent: &str, nodes: &mut usize, out: &mut impl Write) -> io::Result<()> {
+        if let Some(length) = segment {
+            *nodes += 1;
+            let name = format!("n{}", nodes);
+            writeln!(out, "    {} [label=\"{}\", shape=plaintext];", name, count(length, "command"))?;
+            writeln!(out, "    {} -> {} [style=dashed];", parent, name)?;
+        }
+        Ok(())
+    }
+
+    let mut loop_sizes = loop_sizes.into_iter();
+    let mut nodes = 0;
+    // The commands left in each loop being walked, along with the name of its node and the length
+    // of its current straight-line segment. Loops are walked with an explicit stack so that deeply
+    // nested loops do not overflow the call stack.
+    let mut stack = vec![(commands.iter(), "n".to_string(), None)];
+    while let Some((commands, parent, segment)) = stack.last_mut() {
+        let Some(command) = commands.next() else {
+            let (_, parent, segment) = stack.pop().expect("The stack is not empty");
+            write_segment(segment, &parent, &mut nodes, out)?;
+            continue;
+        };
+        let label = match command {
+            Command::Loop(_) => format!("loop\\n{}\\ngeneric", count(loop_sizes.next().expect("Each loop has a size"), "command")),
+            Command::Reset => "loop\\nreset".to_string(),
+            Command::ResetChunk(offset) => format!("loops\\nreset of {}", count(offset.unsigned_abs() + 1, "cell")),
+            Command::Move(cells) => format!("loop\\nmove to {}", count(cells.len(), "cell")),
+            Command::MoveSpan(span) => format!("loop\\nmove to {}", count(span.length + span.rest.len(), "cell")),
+            Command::Mul(multiplication) => format!("loops\\nmultiplication into {}", count(multiplication.targets.len(), "cell")),
+            Command::Scan(stride) => format!("loop\\nscan {} by {}", if *stride < 0 { "left" } else { "right" }, count(stride.unsigned_abs(), "cell")),
+            _ => {
+                *segment = Some(segment.map_or(1, |length| length + 1));
+                continue;
+            }
+        };
+        let parent = parent.clone();
+        write_segment(segment.take(), &parent, &mut nodes, out)?;
+        nodes += 1;
+        let name = format!("n{}", nodes);
+        match command {
+            Command::Loop(_) => writeln!(out, "    {} [label=\"{}\"];", name, label)?,
+            _ => writeln!(out, "    {} [label=\"{}\", style=filled, fillcolor=palegreen];", name, label)?,
+        }
+        writeln!(out, "    {} -> {};", parent, name)?;
+        if let Command::Loop(body) = command {
+            stack.push((body.iter(), name, None));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use std::io;
+use std::io::Write;
+use std::iter;
+use std::ops::ControlFlow;
+use std::path::Path;
+
+use brainfuck_interpreter::rng::Rng;
+use brainfuck_interpreter::{timings, Command, Stop};
+
+use crate::args::Args;
+use crate::run_captured;
+
+/// The number of bytes of each output shown when two programs differ.
+const SHOWN_OUTPUT_LENGTH: usize = 64;
+
+/// What a program did on an input tried by [`compare`].
+struct Outcome {
+    output: Vec<u8>,
+    steps: u64,
+    /// Whether the program ended before running out of steps.
+    ended: bool,
+    /// The canonical state of the tape once the program stopped, if tapes are compared.
+    tape: Option<Vec<u8>>,
+}
+
+impl Outcome {
+    /// Runs a program on an input, after which it reads 0s.
+    fn run(commands: &[Command], input: &[u8], args: &Args, seed: u64) -> Self {
+        let input = Vec::from(input).into_iter().chain(iter::repeat(0));
+        let (output, machine, result) = run_captured(commands, Box::new(input), args.max_output, Some(args.equiv_steps), args, seed);
+        Self {
+            output,
+            steps: machine.steps,
+            ended: result != ControlFlow::Break(Stop::StepLimit),
+            tape: args.compare_tape.then(|| machine.tape.canonical_state()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let shown = &self.output[..self.output.len().min(SHOWN_OUTPUT_LENGTH)];
+        let ellipsis = if shown.len() < self.output.len() { "..." } else { "" };
+        let end = if self.ended { "ended" } else { "ran out of steps" };
+        let bytes = if self.output.len() == 1 { "byte" } else { "bytes" };
+        let mut description = format!("output \"{}\"{} ({} {}), {} after {} steps", shown.escape_ascii(), ellipsis, self.output.len(), bytes, end, self.steps);
+        if let Some(tape) = &self.tape {
+            description += &format!(", tape hash {:016x}", timings::hash(tape))
+        }
+        description
+    }
+}
+
+/// Returns whether some commands read input.
+fn reads_input(commands: &[Command]) -> bool {
+    // Loops are searched with an explicit stack, so that deeply nested loops do not overflow the
+    // call stack.
+    let mut stack = vec![commands];
+    while let Some(commands) = stack.pop() {
+        for command in commands {
+            match command {
+                Command::Input => return true,
+                Command::Loop(loop_commands) => stack.push(loop_commands),
+                _ => {}
+            }
+        }
+    }
+    false
+}
+
+/// Returns whether two programs behaved differently, or [`None`] if it cannot be known because one
+/// of them ran out of steps before outputting as much as the other one.
+fn differ(first: &Outcome, second: &Outcome) -> Option<bool> {
+    let is_prefix = |a: &Outcome, b: &Outcome| b.output.starts_with(&a.output);
+    match (first.ended, second.ended) {
+        (true, true) => Some(first.output != second.output || first.tape != second.tape),
+        (true, false) => (!is_prefix(second, first)).then_some(true),
+        (false, true) => (!is_prefix(first, second)).then_some(true),
+        (false, false) => (!is_prefix(first, second) && !is_prefix(second, first)).then_some(true),
+    }
+}
+
+/// Runs two programs on the same random inputs, and writes the first input on which they behave
+/// differently, if any (see `--equiv`).
+///
+/// The inputs only depend on `seed`, so that the same ones are tried again when passing the same
+/// seed. Both programs may execute the same number of steps on each input.
+///
+/// Returns whether no difference was found.
+pub fn compare(programs: [(&Path, &[Command]); 2], args: &Args, seed: u64, out: &mut impl Write) -> io::Result<bool> {
+    let reads_input = programs.iter().any(|(_, commands)| reads_input(commands));
+    let trials = if reads_input { args.runs } else { 1 };
+    // The generator of the inputs is not shared with the programs, as with `--input-random`.
+    let mut rng = Rng::new(seed);
+    let mut inconclusive = 0;
+    for trial in 1..=trials {
+        let length = if reads_input { rng.next_u64() % (args.max_input_len as u64 + 1) } else { 0 };
+        let input: Vec<u8> = (0..length).map(|_| rng.next_byte()).collect();
+        let outcomes = programs.map(|(_, commands)| Outcome::run(commands, &input, args, seed));
+        match differ(&outcomes[0], &outcomes[1]) {
+            Some(true) => {
+                writeln!(out, "Programs differ on input \"{}\" (trial {} of {}, seed {}):", input.escape_ascii(), trial, trials, seed)?;
+                for ((path, _), outcome) in programs.iter().zip(&outcomes) {
+                    writeln!(out, "  {}: {}", path.display(), outcome.describe())?
+                }
+                return Ok(false);
+            }
+            Some(false) => {}
+            None => inconclusive += 1,
+        }
+    }
+    let plural = if trials == 1 { "" } else { "s" };
+    write!(out, "No difference found in {} trial{} (seed {})", trials, plural, seed)?;
+    if inconclusive > 0 {
+        let verb = if inconclusive == 1 { "was" } else { "were" };
+        write!(out, ", but {} {} inconclusive as a program ran out of steps", inconclusive, verb)?;
+    }
+    writeln!(out)?;
+    Ok(true)
+}
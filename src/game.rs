@@ -0,0 +1,199 @@
+use std::io;
+use std::io::{IsTerminal, Write};
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The byte programs output to clear the screen in game mode (see `--game`).
+const FORM_FEED: u8 = 0x0c;
+
+/// Clears the screen and moves the cursor to its top left corner.
+const CLEAR_SCREEN: &[u8] = b"\x1b[2J\x1b[H";
+
+const ENTER_ALTERNATE_SCREEN: &[u8] = b"\x1b[?1049h";
+
+const LEAVE_ALTERNATE_SCREEN: &[u8] = b"\x1b[?1049l";
+
+/// Whether the terminal has to be restored before the process exits.
+static IS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the alternate screen has to be left before the process exits.
+static IS_ON_ALTERNATE_SCREEN: AtomicBool = AtomicBool::new(false);
+
+/// A writer clearing the screen when a form feed is written to it, and passing other bytes on
+/// unchanged.
+pub struct Screen<W: Write>(pub W);
+
+impl<W: Write> Write for Screen<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match buf.iter().position(|&byte| byte == FORM_FEED) {
+            Some(0) => {
+                self.0.write_all(CLEAR_SCREEN)?;
+                Ok(1)
+            }
+            Some(length) => self.0.write(&buf[..length]),
+            None => self.0.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(unix)]
+mod terminal {
+    use std::sync::OnceLock;
+
+    /// The settings of the terminal before switching to raw input.
+    static ORIGINAL_SETTINGS: OnceLock<libc::termios> = OnceLock::new();
+
+    /// Makes the terminal pass keys to the program as soon as they are pressed, without showing
+    /// them. Ctrl+C still interrupts the program. Returns whether this succeeded.
+    pub fn enable_raw_input() -> bool {
+        // SAFETY: `termios` is a plain structure, which `tcgetattr` fills in.
+        unsafe {
+            let mut settings: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut settings) != 0 {
+                return false;
+            }
+            let _ = ORIGINAL_SETTINGS.set(settings);
+            settings.c_lflag &= !(libc::ICANON | libc::ECHO);
+            settings.c_cc[libc::VMIN] = 1;
+            settings.c_cc[libc::VTIME] = 0;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &settings) == 0
+        }
+    }
+
+    /// Restores the settings the terminal had before [`enable_raw_input`], if it was called.
+    ///
+    /// This is safe to call from a signal handler.
+    pub fn restore_input() {
+        if let Some(settings) = ORIGINAL_SETTINGS.get() {
+            // SAFETY: `settings` was filled in by `tcgetattr`.
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, settings) };
+        }
+    }
+
+    /// Writes bytes to `stdout` directly, bypassing its buffer, which may be locked. This is safe
+    /// to call from a signal handler.
+    pub fn write_stdout(bytes: &[u8]) {
+        // SAFETY: the pointer and the length come from a valid slice.
+        unsafe { libc::write(libc::STDOUT_FILENO, bytes.as_ptr().cast(), bytes.len()) };
+    }
+
+    extern "C" fn restore_at_exit() {
+        super::restore()
+    }
+
+    extern "C" fn restore_on_signal(signal: libc::c_int) {
+        super::restore();
+        // SAFETY: `_exit` is safe to call from a signal handler.
+        unsafe { libc::_exit(128 + signal) }
+    }
+
+    /// Makes sure the terminal is restored when the process exits, including when it is
+    /// interrupted (e.g., with Ctrl+C) or terminated.
+    pub fn restore_on_exit() {
+        // SAFETY: the handlers only call functions that are safe to call from a signal handler.
+        unsafe {
+            libc::atexit(restore_at_exit);
+            libc::signal(libc::SIGINT, restore_on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+            libc::signal(libc::SIGTERM, restore_on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod terminal {
+    use std::io::Write;
+
+    /// Raw input is not supported on this platform, so keys are only passed to the program once
+    /// Enter is pressed.
+    pub fn enable_raw_input() -> bool {
+        false
+    }
+
+    pub fn restore_input() {}
+
+    pub fn write_stdout(bytes: &[u8]) {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(bytes).and_then(|_| stdout.flush());
+    }
+
+    /// There is no way to run code when the process exits without dependencies on this platform,
+    /// so the terminal is only restored once the program ends or panics.
+    pub fn restore_on_exit() {}
+}
+
+/// Restores the terminal as it was before [`enable`], if it has not been restored yet.
+fn restore() {
+    if !IS_ENABLED.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    terminal::restore_input();
+    if IS_ON_ALTERNATE_SCREEN.swap(false, Ordering::SeqCst) {
+        terminal::write_stdout(LEAVE_ALTERNATE_SCREEN)
+    }
+}
+
+/// Enables game mode (see `--game`): if `stdin` is a terminal, keys are passed to the program as
+/// soon as they are pressed, and if `stdout` is a terminal, the program is shown on the alternate
+/// screen of the terminal, as full-screen applications do.
+///
+/// The terminal is restored when the process exits, panics, or is interrupted, or when [`disable`]
+/// is called.
+pub fn enable() {
+    IS_ENABLED.store(true, Ordering::SeqCst);
+    terminal::restore_on_exit();
+    let previous_hook = panic::take_hook();
+    // Restore the terminal first, so that the message is shown on the main screen.
+    panic::set_hook(Box::new(move |info| {
+        restore();
+        previous_hook(info)
+    }));
+    if io::stdin().is_terminal() && !terminal::enable_raw_input() {
+        eprintln!("Keys are only passed to the program once Enter is pressed, as the terminal cannot be switched to raw input")
+    }
+    if io::stdout().is_terminal() {
+        IS_ON_ALTERNATE_SCREEN.store(true, Ordering::SeqCst);
+        terminal::write_stdout(ENTER_ALTERNATE_SCREEN)
+    }
+}
+
+/// Restores the terminal as it was before [`enable`].
+pub fn disable() {
+    restore()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes some bytes to a screen, all at once or one at a time, and returns what it passes on.
+    fn display(bytes: &[u8], is_buffered: bool) -> Vec<u8> {
+        let mut screen = Screen(Vec::new());
+        if is_buffered {
+            screen.write_all(bytes).unwrap()
+        } else {
+            for &byte in bytes {
+                screen.write_all(&[byte]).unwrap()
+            }
+        }
+        screen.0
+    }
+
+    #[test]
+    fn form_feeds_clear_the_screen() {
+        for is_buffered in [false, true] {
+            assert_eq!(display(b"ab\x0ccd\x0c\x0c", is_buffered), b"ab\x1b[2J\x1b[Hcd\x1b[2J\x1b[H\x1b[2J\x1b[H")
+        }
+    }
+
+    #[test]
+    fn other_bytes_are_passed_on_unchanged() {
+        let bytes: Vec<u8> = (0..=255).filter(|&byte| byte != FORM_FEED).collect();
+        for is_buffered in [false, true] {
+            assert_eq!(display(&bytes, is_buffered), bytes)
+        }
+    }
+}
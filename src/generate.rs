@@ -0,0 +1,80 @@
+/// Parses the escape sequences of a text passed to `--generate`, and returns its bytes.
+///
+/// `\xNN` stands for the byte with the hexadecimal value `NN`, `\n` for a newline, and `\\` for a
+/// backslash. Other backslashes are kept as they are.
+pub fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(char.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&digits, 16).map_err(|_| format!("invalid escape sequence `\\x{}`", digits))?;
+                bytes.push(byte)
+            }
+            Some('n') => bytes.push(b'\n'),
+            Some('\\') => bytes.push(b'\\'),
+            Some(char) => {
+                bytes.push(b'\\');
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(char.encode_utf8(&mut buffer).as_bytes())
+            }
+            None => bytes.push(b'\\'),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Returns the shortest code adding `amount` to the current cell found by trying multiplications
+/// by factors up to `max_factor`, using the cell to the right as a counter.
+///
+/// The counter has to be 0 before, and is 0 after. If `both_directions` is `true`, subtracting
+/// `256 - amount` is also tried.
+fn add(amount: u8, max_factor: usize, both_directions: bool) -> String {
+    let delta = amount as isize;
+    let shortest = if delta > 128 { delta - 256 } else { delta };
+    let deltas = if both_directions { vec![delta, delta - 256] } else { vec![shortest] };
+    let repeat = |amount: isize| {
+        let char = if amount < 0 { '-' } else { '+' };
+        char.to_string().repeat(amount.unsigned_abs())
+    };
+    let mut best = repeat(shortest);
+    for &delta in &deltas {
+        for factor in 2..=max_factor as isize {
+            // Try both the product right below and right above the delta.
+            let quotient = delta / factor;
+            for multiplier in [quotient, quotient + delta.signum()] {
+                if multiplier == 0 {
+                    continue;
+                }
+                let code = format!(">{}[<{}>-]<{}", repeat(factor), repeat(multiplier), repeat(delta - factor * multiplier));
+                if code.len() < best.len() {
+                    best = code
+                }
+            }
+        }
+    }
+    best
+}
+
+/// Generates a Brainfuck program printing the passed bytes.
+///
+/// Each byte is obtained by adding the difference with the previous byte to the same cell, either
+/// with `+` or `-`, or with a loop multiplying two factors when it is shorter. With `golf`, more
+/// factors are tried, in both directions, which takes longer but may find a shorter program.
+pub fn generate(bytes: &[u8], golf: bool) -> String {
+    let (max_factor, both_directions) = if golf { (255, true) } else { (16, false) };
+    let mut code = String::new();
+    let mut current = 0u8;
+    for &byte in bytes {
+        code.push_str(&add(byte.wrapping_sub(current), max_factor, both_directions));
+        code.push('.');
+        current = byte
+    }
+    code
+}
@@ -0,0 +1,142 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use brainfuck_interpreter::tape;
+use brainfuck_interpreter::tape::Input;
+
+use crate::generate::unescape;
+
+
+/// A pattern describing some input, as passed to `--input-repeat`.
+///
+/// It is made of segments separated by `|`. Each segment is some text, optionally followed by `*N`
+/// to repeat it `N` times. In the text, `\n`, `\xNN`, `\\`, `\|`, and `\*` stand for a newline,
+/// the byte with the hexadecimal value `NN`, and the escaped character respectively.
+#[derive(Debug, Clone)]
+pub struct InputPattern {
+    /// The bytes of each segment, along with the number of times they are repeated.
+    segments: Vec<(Arc<[u8]>, u64)>,
+}
+
+impl InputPattern {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut bytes = Vec::new();
+        // Stores the repetition count once a `*` is reached.
+        let mut count: Option<String> = None;
+        let mut chars = s.chars();
+        loop {
+            let char = chars.next();
+            match (char, &mut count) {
+                (None | Some('|'), _) => {
+                    let count = match count.take() {
+                        Some(count) => count.parse().map_err(|_| format!("invalid repetition count `{}`", count))?,
+                        None => 1,
+                    };
+                    segments.push((Arc::from(bytes.split_off(0)), count));
+                    if char.is_none() {
+                        return Ok(Self { segments });
+                    }
+                }
+                (Some(char), Some(count)) => count.push(char),
+                (Some('*'), None) => count = Some(String::new()),
+                (Some('\\'), None) => match chars.next() {
+                    Some('n') => bytes.push(b'\n'),
+                    Some('x') => {
+                        let digits: String = chars.by_ref().take(2).collect();
+                        let byte = u8::from_str_radix(&digits, 16).map_err(|_| format!("invalid escape sequence `\\x{}`", digits))?;
+                        bytes.push(byte)
+                    }
+                    Some(char @ ('\\' | '|' | '*')) => bytes.push(char as u8),
+                    Some(char) => return Err(format!("invalid escape sequence `\\{}`", char)),
+                    None => return Err("unfinished escape sequence".to_string()),
+                },
+                (Some(char), None) => {
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(char.encode_utf8(&mut buffer).as_bytes())
+                }
+            }
+        }
+    }
+
+    /// Returns an iterator over the bytes described by this pattern, which are generated as they
+    /// are needed.
+    pub fn bytes(&self) -> impl Iterator<Item=u8> {
+        self.segments.clone().into_iter().flat_map(|(bytes, count)| {
+            (0..count).flat_map(move |_| {
+                let bytes = Arc::clone(&bytes);
+                (0..bytes.len()).map(move |i| bytes[i])
+            })
+        })
+    }
+}
+
+/// Returns an iterator over `count` bytes that are 0 (see `--input-zero`).
+pub fn zeros(count: u64) -> impl Iterator<Item=u8> {
+    (0..count).map(|_| 0)
+}
+
+/// Returns an iterator over `count` bytes generated by `next` (see `--input-random`).
+pub fn generated(count: u64, mut next: impl FnMut() -> u8) -> impl Iterator<Item=u8> {
+    (0..count).map(move |_| next())
+}
+
+/// A source of input, as passed to `--input-source`.
+#[derive(Debug, Clone)]
+pub enum InputSource {
+    /// Some bytes, written as `text:TEXT`.
+    Text(Vec<u8>),
+    /// The contents of a file, written as `file:PATH`.
+    File(PathBuf),
+    /// Standard input, written as `stdin`.
+    Stdin,
+}
+
+impl InputSource {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if s == "stdin" {
+            Ok(Self::Stdin)
+        } else if let Some(path) = s.strip_prefix("file:") {
+            Ok(Self::File(PathBuf::from(path)))
+        } else if let Some(text) = s.strip_prefix("text:") {
+            Self::parse_text(text)
+        } else {
+            Err(format!("expected `stdin`, `file:PATH`, or `text:TEXT`, found `{}`", s))
+        }
+    }
+
+    /// Parses some text which may contain the escape sequences of `--generate`, as passed to
+    /// `--input-text`.
+    pub fn parse_text(s: &str) -> Result<Self, String> {
+        Ok(Self::Text(unescape(s)?))
+    }
+
+    /// Returns an iterator over the bytes of this source. Reading standard input fails if no byte
+    /// is available within `timeout`, if one is passed.
+    ///
+    /// Files are opened immediately, so that a missing file is reported before the program runs
+    /// (with an error naming it), but only read as the program needs them.
+    pub fn bytes(&self, timeout: Option<Duration>) -> io::Result<Input<'static>> {
+        match self {
+            Self::Text(bytes) => Ok(Box::new(bytes.clone().into_iter().map(Ok))),
+            Self::File(path) => {
+                let file = File::open(path).map_err(|error| io::Error::new(error.kind(), format!("unable to open input file {}: {}", path.display(), error)))?;
+                Ok(Box::new(BufReader::new(file).bytes()))
+            }
+            Self::Stdin => Ok(tape::stdin(timeout).unwrap_or_else(|error| {
+                eprintln!("Ignoring the input timeout, as input cannot be read on another thread: {}", error);
+                tape::stdin(None).expect("Reading stdin without a timeout never fails")
+            })),
+        }
+    }
+}
+
+/// Returns an iterator over the bytes of some sources, in which each source is read once the
+/// previous one is exhausted. It only ends once the last source is exhausted.
+pub fn chain(sources: Vec<Input<'static>>) -> impl Iterator<Item=io::Result<u8>> {
+    sources.into_iter().flatten()
+}
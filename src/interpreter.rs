@@ -0,0 +1,146 @@
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::ops::ControlFlow;
+
+use crate::source::ParseError;
+use crate::tape::{Eof, Tape, TapeOptions};
+use crate::{bytecode, optimize, parse, Command, Machine, MachineOptions, OptimizeOptions, StepHook, Stop};
+
+/// Functions called while an [`Interpreter`] runs a program (see [`Interpreter::run_with`]), all
+/// optional, which may borrow data for the lifetime `'a`. Those that are not set cost nothing.
+#[derive(Default)]
+pub struct Hooks<'a> {
+    /// Called with each value the program outputs once it is written to the output.
+    pub on_output: Option<&'a mut dyn FnMut(u8)>,
+    /// Called for each value the program inputs, instead of reading the input, until it returns
+    /// [`None`] at the end of the input.
+    pub on_input: Option<&'a mut dyn FnMut() -> Option<u8>>,
+    /// Called every few steps, which can stop the program (see [`Machine::on_step`]).
+    pub on_step: Option<StepHook<'a>>,
+}
+
+impl fmt::Debug for Hooks<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_output", &self.on_output.is_some())
+            .field("on_input", &self.on_input.is_some())
+            .field("on_step", &self.on_step)
+            .finish()
+    }
+}
+
+// Interpreters are shared across threads (see `Interpreter`).
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Interpreter>();
+    assert_send_sync::<Command>();
+};
+
+/// A writer calling [`Hooks::on_output`] with the values written to another writer.
+struct Observed<'a, W> {
+    writer: W,
+    on_output: &'a mut dyn FnMut(u8),
+}
+
+impl<W: Write> Write for Observed<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = self.writer.write(buf)?;
+        buf[..length].iter().for_each(|&value| (self.on_output)(value));
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A program along with the options to run it with, which can be run any number of times on any
+/// input and output, such as in-memory buffers.
+///
+/// Running a program does not modify the interpreter, as each run has its own tapes, so one
+/// interpreter can be shared by threads running the program on different inputs at the same time,
+/// such as in an [`Arc`](std::sync::Arc).
+///
+/// ```
+/// use brainfuck_interpreter::Interpreter;
+///
+/// let interpreter = Interpreter::from_source(",[.,]").unwrap();
+/// let mut output = Vec::new();
+/// interpreter.run(&b"Hello"[..], &mut output);
+/// assert_eq!(output, b"Hello");
+/// ```
+#[derive(Debug)]
+pub struct Interpreter {
+    /// The program, usually [optimized](crate::optimize).
+    pub commands: Vec<Command>,
+    /// What `,` does once the input is exhausted.
+    pub eof: Eof,
+    /// The options of the machines the program runs on.
+    pub options: MachineOptions,
+}
+
+impl Interpreter {
+    /// Creates an interpreter running the passed program with the default options.
+    pub fn new(commands: Vec<Command>) -> Self {
+        Self {
+            commands,
+            eof: Eof::Zero,
+            options: MachineOptions::default(),
+        }
+    }
+
+    /// Creates an interpreter running some standard Brainfuck source code (see [`parse`]),
+    /// optimized with the default options.
+    pub fn from_source(code: &str) -> Result<Self, ParseError> {
+        Ok(Self::new(optimize(parse(code)?, &OptimizeOptions::default())))
+    }
+
+    /// Runs the program on blank tapes until it ends, reading values from `input` and writing them
+    /// as they are to `output`. Returns why the program stopped, if it stopped early, which
+    /// includes reading or writing failing ([`Stop::InputError`] and [`Stop::OutputError`]).
+    ///
+    /// The input is buffered, but the output is written one value at a time, so it should be
+    /// buffered if writing is slow.
+    pub fn run(&self, input: impl Read, output: impl Write) -> ControlFlow<Stop> {
+        self.run_with(input, output, Hooks::default())
+    }
+
+    /// Runs the program like [`Interpreter::run`], also calling the passed hooks, which returns
+    /// [`Stop::Cancelled`] if [`Hooks::on_step`] stopped the program.
+    ///
+    /// ```
+    /// use std::num::NonZeroU64;
+    /// use std::ops::ControlFlow;
+    /// use brainfuck_interpreter::{Hooks, Interpreter, StepHook, Stop};
+    ///
+    /// let interpreter = Interpreter::from_source("+[.]").unwrap();
+    /// let mut outputs = 0;
+    /// let hooks = Hooks {
+    ///     on_output: Some(&mut |_| outputs += 1),
+    ///     on_step: Some(StepHook { interval: NonZeroU64::new(100).unwrap(), function: &mut |_| ControlFlow::Break(()) }),
+    ///     ..Hooks::default()
+    /// };
+    /// assert_eq!(interpreter.run_with(std::io::empty(), std::io::sink(), hooks), ControlFlow::Break(Stop::Cancelled));
+    /// // The 100th step, which would output a 98th value, is not executed.
+    /// assert_eq!(outputs, 97);
+    /// ```
+    pub fn run_with<'a>(&self, input: impl Read + 'a, output: impl Write + 'a, hooks: Hooks<'a>) -> ControlFlow<Stop> {
+        let mut tape = Tape::new(&TapeOptions { eof: self.eof, ..TapeOptions::default() });
+        match hooks.on_input {
+            Some(on_input) => tape.set_input(Box::new(std::iter::from_fn(on_input))),
+            None => tape.set_reader(input),
+        }
+        match hooks.on_output {
+            Some(on_output) => tape.set_raw_output(Box::new(Observed { writer: output, on_output })),
+            None => tape.set_raw_output(Box::new(output)),
+        }
+        let mut machine = Machine::new(tape, &self.options);
+        machine.on_step = hooks.on_step;
+        let result = bytecode::execute(&bytecode::flatten(&self.commands), &mut machine);
+        match (result, machine.tape.flush()) {
+            (ControlFlow::Continue(()), Err(error)) => ControlFlow::Break(Stop::OutputError(error.kind())),
+            (result, _) => result,
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+use std::fs;
+
+use crate::generate::unescape;
+
+/// The keys that can be named in a keymap, along with the sequences terminals send for them.
+const NAMED_KEYS: [(&str, &[u8]); 11] = [
+    ("Up", b"\x1b[A"),
+    ("Down", b"\x1b[B"),
+    ("Right", b"\x1b[C"),
+    ("Left", b"\x1b[D"),
+    ("Home", b"\x1b[H"),
+    ("End", b"\x1b[F"),
+    ("Enter", b"\n"),
+    ("Tab", b"\t"),
+    ("Space", b" "),
+    ("Backspace", b"\x7f"),
+    ("Escape", b"\x1b"),
+];
+
+/// A mapping from the sequences of bytes sent by some keys to the bytes a program receives instead
+/// (see `--keymap`).
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    keys: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Keymap {
+    /// Reads a keymap from a file, made of one `KEY = BYTES` line per key.
+    ///
+    /// `KEY` is either the name of a key (such as `Up` or `Enter`) or the sequence of bytes it sends
+    /// (such as `\x1b[A`), and `BYTES` is what the program receives instead, which may be empty.
+    /// Both may contain the escape sequences of `--generate`. Empty lines and lines starting with
+    /// `#` are ignored.
+    pub fn read(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("unable to read `{}`: {}", path, error))?;
+        let mut keys: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+            let Some((key, bytes)) = line.split_once('=') else {
+                return Err(error(format!("expected `KEY = BYTES`, found `{}`", line)));
+            };
+            let key = key.trim();
+            let sequence = match NAMED_KEYS.iter().find(|(name, _)| *name == key) {
+                Some((_, sequence)) => sequence.to_vec(),
+                None => unescape(key).map_err(error)?,
+            };
+            if sequence.is_empty() {
+                return Err(error("missing key".to_string()));
+            }
+            if keys.iter().any(|(other, _)| *other == sequence) {
+                return Err(error(format!("`{}` is mapped twice", key)));
+            }
+            keys.push((sequence, unescape(bytes.trim()).map_err(error)?))
+        }
+        Ok(Self { keys })
+    }
+
+    /// Returns an iterator over some input in which the sequences of the mapped keys are replaced
+    /// by the bytes they are mapped to.
+    ///
+    /// Bytes that may start the sequence of a key are only passed on once the next bytes show it is
+    /// not one, so a key whose sequence starts another one (such as `Escape`) waits for the next key.
+    pub fn apply(&self, input: Box<dyn Iterator<Item=u8>>) -> impl Iterator<Item=u8> {
+        let keys = self.keys.clone();
+        let mut input = input;
+        // The bytes read but not yet matched, and the bytes to pass on.
+        let mut pending = Vec::new();
+        let mut output = VecDeque::new();
+        std::iter::from_fn(move || loop {
+            if let Some(byte) = output.pop_front() {
+                return Some(byte);
+            }
+            let extends_key = |pending: &[u8]| keys.iter().any(|(key, _)| key.len() > pending.len() && key.starts_with(pending));
+            while extends_key(&pending) {
+                match input.next() {
+                    Some(byte) => pending.push(byte),
+                    None => break,
+                }
+            }
+            if pending.is_empty() {
+                return None;
+            }
+            let matched = keys.iter().filter(|(key, _)| pending.starts_with(key)).max_by_key(|(key, _)| key.len());
+            match matched {
+                Some((key, bytes)) => {
+                    output.extend(bytes);
+                    pending.drain(..key.len());
+                }
+                None => output.push_back(pending.remove(0)),
+            }
+        })
+    }
+}
@@ -0,0 +1,40 @@
+//! A Brainfuck interpreter that uses an intermediate representation to optimize some patterns in
+//! order to make the execution faster.
+//!
+//! Programs are loaded as [commands](Command) with [`parse`] (or [`load`] for other dialects and
+//! options), [optimized](optimize), and run with an [`Interpreter`], or on a [`Machine`] for more
+//! control over the tapes.
+//!
+//! ```
+//! use brainfuck_interpreter::{optimize, parse, Interpreter, OptimizeOptions};
+//!
+//! let code = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+//! let commands = optimize(parse(code).unwrap(), &OptimizeOptions::default());
+//! let mut output = Vec::new();
+//! Interpreter::new(commands).run(std::io::empty(), &mut output);
+//! assert_eq!(output, b"Hello World!\n");
+//! ```
+
+#![warn(missing_debug_implementations)]
+
+pub use crate::cell::{Cell, CellSize};
+pub use crate::command::{Command, MoveSpan, Multiplication};
+pub use crate::interpreter::{Hooks, Interpreter};
+pub use crate::machine::{execute, execute_command, numbered_path, write_tape_json, Machine, MachineOptions, Resume, StepHook, StepInfo, Stop, DEFAULT_CANCEL_INTERVAL};
+pub use crate::optimize::{optimize, optimize_within_budget, OptimizeOptions, OptimizeReport};
+pub use crate::parse::{annotation, load, parse, Dialect, ParseOptions, ANNOTATIONS};
+
+mod command;
+mod parse;
+mod optimize;
+mod machine;
+mod interpreter;
+pub mod cell;
+pub mod tape;
+pub mod source;
+pub mod names;
+pub mod assertion;
+pub mod rng;
+pub mod provenance;
+pub mod timings;
+pub mod bytecode;
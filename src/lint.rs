@@ -0,0 +1,518 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use brainfuck_interpreter::source::{ParseError, ParseErrorKind};
+use brainfuck_interpreter::{annotation, Dialect};
+
+use crate::args::Args;
+
+/// A check for a likely mistake in a program (see `--lint`). The name of each lint is its code,
+/// which does not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Lint {
+    /// A loop with an empty body (`[]`), which never ends if it is entered.
+    EmptyLoop,
+    /// A loop that starts on a cell that is always 0, so that its body never runs.
+    DeadLoop,
+    /// A loop whose body cannot change the current cell, so that it never ends once entered.
+    InfiniteLoop,
+    /// Changes to a cell that are overwritten (by `,` or a reset) before the cell is read.
+    DeadStore,
+    /// Commands at the end of the program that have no observable effect.
+    UnobservableTail,
+    /// A chain of `+` or `-` that makes a cell known to be 0 initially go past 255 or below 0,
+    /// which relies on cells wrapping around.
+    WrapAround,
+    /// The pointer moving left of the initial cell, which some interpreters do not support.
+    NegativeCell,
+}
+
+/// How diagnostics are printed (see `--diagnostics`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiagnosticFormat {
+    /// One line per diagnostic, followed by the location of the code it is about.
+    Human,
+    /// One JSON object per line for each diagnostic, with its code, severity, message, file, and
+    /// span.
+    Json,
+}
+
+/// How much a lint matters, as set by `--allow` and `--deny`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Severity {
+    Warning,
+    Error,
+}
+
+/// Something wrong with a part of a program, found by a lint or when loading it, which is printed
+/// the same way in every format.
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    /// What is wrong, which does not change: the name of the lint (see [`Lint`]), or the kind of
+    /// error (see [`ParseErrorKind::code`]).
+    code: String,
+    severity: Severity,
+    message: String,
+    /// The path of the program.
+    file: String,
+    /// The offset of the first byte of the code the diagnostic is about.
+    start: usize,
+    /// The offset of the byte after the code the diagnostic is about.
+    end: usize,
+    /// The line (starting at 1) of the first byte of the code the diagnostic is about.
+    line: usize,
+    /// The column (starting at 1, and counted in bytes) of the first byte of the code the diagnostic
+    /// is about.
+    column: usize,
+    /// The line of the last byte of the code the diagnostic is about.
+    end_line: usize,
+    /// The column of the byte after the last byte of the code the diagnostic is about, on its line.
+    end_column: usize,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic about a non-empty span of some source code, given the start of its lines
+    /// (see [`line_starts`]).
+    fn new(code: String, severity: Severity, message: String, path: &Path, span: Range<usize>, line_starts: &[usize]) -> Self {
+        let (line, column) = position(line_starts, span.start);
+        let (end_line, last_column) = position(line_starts, span.end - 1);
+        Self { code, severity, message, file: path.display().to_string(), start: span.start, end: span.end, line, column, end_line, end_column: last_column + 1 }
+    }
+
+    /// Creates a diagnostic for an error that prevents a program from being loaded.
+    pub fn parse_error(path: &Path, error: &ParseError) -> Self {
+        let message = match &error.kind {
+            ParseErrorKind::UnmatchedClose => "unmatched ']'".to_string(),
+            ParseErrorKind::UnmatchedOpen => "unmatched '['".to_string(),
+            ParseErrorKind::InvalidAssertion(reason) => format!("invalid assertion: {}", reason),
+        };
+        Self {
+            code: error.kind.code().to_string(),
+            severity: Severity::Error,
+            message,
+            file: path.display().to_string(),
+            start: error.offset,
+            end: error.offset + 1,
+            line: error.line,
+            column: error.column,
+            end_line: error.line,
+            end_column: error.column + 1,
+        }
+    }
+
+    /// Writes this diagnostic in the passed format.
+    pub fn write(&self, format: DiagnosticFormat, out: &mut impl Write) -> io::Result<()> {
+        match format {
+            DiagnosticFormat::Human => {
+                let severity = match self.severity {
+                    Severity::Warning => "warning",
+                    Severity::Error => "error",
+                };
+                writeln!(out, "{}[{}]: {}", severity, self.code, self.message)?;
+                writeln!(out, "  --> {}:{}:{}", self.file, self.line, self.column)
+            }
+            DiagnosticFormat::Json => {
+                serde_json::to_writer(&mut *out, self)?;
+                writeln!(out)
+            }
+        }
+    }
+}
+
+/// What an instruction of the source code does, with successive `+` and `-` (and `<` and `>`)
+/// merged.
+#[derive(Debug)]
+enum Kind {
+    Right(isize),
+    Add(i64),
+    /// A loop, along with the net amount its body moves the pointer by, if it is known (see
+    /// [`displacement`]), which is computed once, as the loop is parsed.
+    Loop(Vec<Instruction>, Option<isize>),
+    Input,
+    Output,
+    /// An instruction of the dialect, which the lints know nothing about.
+    Other,
+}
+
+/// An instruction along with the bytes of the source code it comes from.
+#[derive(Debug)]
+struct Instruction {
+    kind: Kind,
+    span: Range<usize>,
+}
+
+impl Instruction {
+    /// Returns the net amount this instruction moves the pointer by, or [`None`] if it is not
+    /// known.
+    fn displacement(&self) -> Option<isize> {
+        match &self.kind {
+            &Kind::Right(amount) => Some(amount),
+            &Kind::Loop(_, displacement) => (displacement? == 0).then_some(0),
+            Kind::Add(_) | Kind::Input | Kind::Output => Some(0),
+            Kind::Other => None,
+        }
+    }
+
+    /// Tests if this is a loop whose body only adds an odd amount to the current cell, which
+    /// always resets it.
+    fn is_reset(&self) -> bool {
+        match &self.kind {
+            Kind::Loop(body, _) => matches!(body[..], [Instruction { kind: Kind::Add(amount), .. }] if amount % 2 != 0),
+            _ => false,
+        }
+    }
+}
+
+impl Drop for Instruction {
+    /// Drops the instructions of nested loops one loop at a time rather than recursively, so that
+    /// deeply nested loops do not overflow the stack.
+    fn drop(&mut self) {
+        let Kind::Loop(body, _) = &mut self.kind else {
+            return;
+        };
+        let mut stack = vec![std::mem::take(body)];
+        while let Some(mut instructions) = stack.pop() {
+            for instruction in instructions.iter_mut() {
+                if let Kind::Loop(body, _) = &mut instruction.kind {
+                    stack.push(std::mem::take(body))
+                }
+            }
+        }
+    }
+}
+
+/// Returns the net amount some instructions move the pointer by, or [`None`] if it is not known.
+fn displacement(instructions: &[Instruction]) -> Option<isize> {
+    instructions.iter().map(Instruction::displacement).sum()
+}
+
+/// Tests if a byte is an instruction of the dialect of the program (other than the standard ones),
+/// as `load` would find it.
+fn is_dialect_instruction(byte: u8, args: &Args) -> bool {
+    match args.dialect {
+        _ if byte == b'?' && args.debug => true,
+        Dialect::Standard => false,
+        Dialect::Ext1 => matches!(byte, b'@' | b'$' | b'!'),
+        Dialect::Random => char::from(byte) == args.random_char,
+        Dialect::Dual => matches!(byte, b'~' | b'^'),
+    }
+}
+
+/// Parses source code into instructions, and returns them, or the offset of the first unmatched
+/// bracket.
+fn parse(code: &[u8], args: &Args) -> Result<Vec<Instruction>, usize> {
+    let mut bytes = code.iter().copied().enumerate();
+    let mut instructions: Vec<Instruction> = Vec::new();
+    // The instructions parsed before each loop being parsed, along with the offset of its `[`.
+    let mut loops = Vec::new();
+    while let Some((offset, byte)) = bytes.next() {
+        let end = offset + 1;
+        let kind = match byte {
+            b'>' => Kind::Right(1),
+            b'<' => Kind::Right(-1),
+            b'+' => Kind::Add(1),
+            b'-' => Kind::Add(-1),
+            b'[' => {
+                loops.push((std::mem::take(&mut instructions), offset));
+                continue;
+            }
+            b']' => {
+                let Some((outer_instructions, open)) = loops.pop() else {
+                    return Err(offset);
+                };
+                let body = std::mem::replace(&mut instructions, outer_instructions);
+                let displacement = displacement(&body);
+                instructions.push(Instruction { kind: Kind::Loop(body, displacement), span: open..end });
+                continue;
+            }
+            b'.' => Kind::Output,
+            b',' => Kind::Input,
+            b'{' if annotation(&code[end..]).is_some() => {
+                // Annotations are skipped up to their `}`, as when loading the program.
+                bytes.find(|&(_, byte)| byte == b'}');
+                continue;
+            }
+            byte if is_dialect_instruction(byte, args) => Kind::Other,
+            _ => continue,
+        };
+        match (instructions.last_mut(), &kind) {
+            (Some(Instruction { kind: Kind::Right(amount), span }), &Kind::Right(right)) => {
+                *amount += right;
+                span.end = end
+            }
+            (Some(Instruction { kind: Kind::Add(amount), span }), &Kind::Add(add)) => {
+                *amount += add;
+                span.end = end
+            }
+            _ => instructions.push(Instruction { kind, span: offset..end }),
+        }
+    }
+    match loops.last() {
+        Some(&(_, open)) => Err(open),
+        None => Ok(instructions),
+    }
+}
+
+/// Tests if a loop body may change the cell the loop starts on, which is the only way the loop
+/// can end.
+fn may_change_current_cell(body: &[Instruction]) -> bool {
+    let mut offset = 0;
+    let mut total = 0;
+    for instruction in body {
+        match instruction.kind {
+            Kind::Right(amount) => offset += amount,
+            Kind::Add(amount) if offset == 0 => total += amount,
+            Kind::Add(_) | Kind::Output => {}
+            Kind::Loop(..) | Kind::Input | Kind::Other => return true,
+        }
+    }
+    // A body ending on another cell makes the loop test that cell, which may be 0.
+    offset != 0 || total.rem_euclid(256) != 0
+}
+
+/// Returns the offset of the first byte of each line of some source code, which [`position`] locates
+/// bytes with.
+fn line_starts(code: &[u8]) -> Vec<usize> {
+    let newlines = code.iter().enumerate().filter(|&(_, &byte)| byte == b'\n');
+    std::iter::once(0).chain(newlines.map(|(offset, _)| offset + 1)).collect()
+}
+
+/// Returns the line and column (both starting at 1, and counted in bytes) of a byte of some source
+/// code, given the start of its lines (see [`line_starts`]).
+fn position(line_starts: &[usize], offset: usize) -> (usize, usize) {
+    let line = line_starts.partition_point(|&start| start <= offset);
+    (line, offset - line_starts[line - 1] + 1)
+}
+
+/// What is known about the tape at some point of a program.
+///
+/// Cells are indexed relatively to an arbitrary cell, which is where the pointer was when the
+/// state was created.
+#[derive(Debug)]
+struct State {
+    /// The index of the cell the pointer is on.
+    pointer: isize,
+    /// The offset of the pointer from its initial position, if it is known.
+    offset: Option<isize>,
+    /// The values of the cells that were changed, or [`None`] for those whose values are not
+    /// known.
+    values: HashMap<isize, Option<u8>>,
+    /// Whether the cells missing from `values` are 0.
+    is_blank: bool,
+    /// The spans of the changes to each cell that the program has not read yet.
+    unread_changes: HashMap<isize, Vec<Range<usize>>>,
+}
+
+impl State {
+    fn new(offset: Option<isize>, is_blank: bool) -> Self {
+        Self {
+            pointer: 0,
+            offset,
+            values: HashMap::new(),
+            is_blank,
+            unread_changes: HashMap::new(),
+        }
+    }
+
+    /// Returns the value of the current cell, if it is known.
+    fn value(&self) -> Option<u8> {
+        match self.values.get(&self.pointer) {
+            Some(&value) => value,
+            None => self.is_blank.then_some(0),
+        }
+    }
+
+    /// Forgets the values of all cells.
+    fn forget_values(&mut self) {
+        self.values.clear();
+        self.is_blank = false
+    }
+}
+
+/// Finds the problems of a program.
+#[derive(Debug)]
+struct Linter<'a> {
+    /// The start of each line of the source code (see [`line_starts`]).
+    line_starts: &'a [usize],
+    /// The problems found so far, with the spans they are about.
+    problems: Vec<(Lint, Range<usize>, String)>,
+    /// Whether a [`Lint::NegativeCell`] was found, as only the first one is reported.
+    has_negative_cell: bool,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, lint: Lint, span: Range<usize>, message: impl Into<String>) {
+        self.problems.push((lint, span, message.into()))
+    }
+
+    /// Reports the unread changes to the current cell, which `what` (at `span`) overwrites.
+    fn overwrite(&mut self, state: &mut State, span: &Range<usize>, what: &str) {
+        let (line, column) = position(self.line_starts, span.start);
+        for change in state.unread_changes.remove(&state.pointer).unwrap_or_default() {
+            self.report(Lint::DeadStore, change, format!("this change is overwritten by {} at {}:{} before the cell is read", what, line, column))
+        }
+    }
+
+    /// Lints instructions executed in the passed state, which is updated as they are.
+    ///
+    /// Loops are linted with an explicit stack of the instructions left in each, along with the
+    /// state their body is executed in and whether it is balanced, so that deeply nested loops do
+    /// not overflow the call stack.
+    fn sequence(&mut self, instructions: &[Instruction], state: State) {
+        let mut stack = vec![(instructions.iter(), state, false)];
+        while let Some((instructions, state, _)) = stack.last_mut() {
+            let Some(instruction) = instructions.next() else {
+                let (_, _, is_balanced) = stack.pop().expect("The stack is not empty");
+                if let Some((_, state, _)) = stack.last_mut() {
+                    Self::leave_loop(state, is_balanced)
+                }
+                continue;
+            };
+            let span = &instruction.span;
+            match &instruction.kind {
+                &Kind::Right(amount) => {
+                    state.pointer += amount;
+                    if let Some(offset) = &mut state.offset {
+                        *offset += amount;
+                        if *offset < 0 && !self.has_negative_cell {
+                            self.has_negative_cell = true;
+                            let message = format!("the pointer moves to cell {}, left of the initial cell, which some interpreters do not support", offset);
+                            self.report(Lint::NegativeCell, span.clone(), message)
+                        }
+                    }
+                }
+                &Kind::Add(amount) => {
+                    if let Some(value) = state.value() {
+                        let new_value = i64::from(value) + amount;
+                        if !(0..=255).contains(&new_value) {
+                            // The cells of some interpreters do not wrap around, or are larger.
+                            let message = format!("this takes a cell from {} to {}, which relies on cells wrapping around", value, new_value);
+                            self.report(Lint::WrapAround, span.clone(), message)
+                        }
+                        state.values.insert(state.pointer, Some(new_value.rem_euclid(256) as u8));
+                    }
+                    state.unread_changes.entry(state.pointer).or_default().push(span.clone())
+                }
+                Kind::Input => {
+                    self.overwrite(state, span, "`,`");
+                    state.values.insert(state.pointer, None);
+                }
+                Kind::Output => {
+                    state.unread_changes.remove(&state.pointer);
+                }
+                Kind::Loop(body, _) => {
+                    if let Some((body_state, is_balanced)) = self.enter_loop(instruction, body, state) {
+                        stack.push((body.iter(), body_state, is_balanced))
+                    }
+                }
+                Kind::Other => {
+                    // Instructions of dialects may change any cell, or switch tapes.
+                    state.forget_values();
+                    state.offset = None;
+                    state.unread_changes.clear()
+                }
+            }
+        }
+    }
+
+    /// Lints a loop before its body, and returns the state its body is executed in and whether
+    /// the body is balanced, or [`None`] if the body does not have to be linted.
+    fn enter_loop(&mut self, instruction: &Instruction, body: &[Instruction], state: &mut State) -> Option<(State, bool)> {
+        let span = &instruction.span;
+        if state.value() == Some(0) {
+            // The body is never executed (which is how comments are often written), so there is
+            // nothing else to report.
+            self.report(Lint::DeadLoop, span.clone(), "this loop never runs, as the current cell is always 0 here");
+            return None;
+        }
+        if body.is_empty() {
+            self.report(Lint::EmptyLoop, span.clone(), "this loop never ends if the current cell is not 0")
+        }
+        if instruction.is_reset() {
+            self.overwrite(state, span, "the reset");
+            state.values.insert(state.pointer, Some(0));
+            return None;
+        }
+        if !body.is_empty() && !may_change_current_cell(body) {
+            self.report(Lint::InfiniteLoop, span.clone(), "this loop never ends once entered, as its body does not change the current cell")
+        }
+        // Each iteration starts where the previous one started if the body is balanced.
+        let is_balanced = instruction.displacement() == Some(0);
+        Some((State::new(state.offset.filter(|_| is_balanced), false), is_balanced))
+    }
+
+    /// Updates the state a loop is executed in once its body has been linted.
+    fn leave_loop(state: &mut State, is_balanced: bool) {
+        if !is_balanced {
+            state.offset = None
+        }
+        // The body may have read or changed any cell.
+        state.forget_values();
+        state.unread_changes.clear();
+        state.values.insert(state.pointer, Some(0));
+    }
+}
+
+/// Returns the index of the first instruction of the commands at the end of a program that have no
+/// observable effect, which are the same as the optimizer removes (see
+/// `--remove-unobservable-tail`).
+fn unobservable_tail(instructions: &[Instruction]) -> usize {
+    let observable = instructions.iter().rposition(|instruction| {
+        !matches!(instruction.kind, Kind::Right(_) | Kind::Add(_)) && !instruction.is_reset()
+    });
+    observable.map_or(0, |index| index + 1)
+}
+
+/// Checks a program for likely mistakes (see `--lint`), and writes a diagnostic for each one that
+/// is not allowed with `--allow`, in the format chosen with `--diagnostics`.
+///
+/// Returns whether no lint denied with `--deny` was found. If the brackets of the program do not
+/// match, a diagnostic for the first unmatched one is written instead.
+pub fn run(path: &Path, code: &[u8], args: &Args, out: &mut impl Write) -> io::Result<bool> {
+    let line_starts = line_starts(code);
+    let instructions = match parse(code, args) {
+        Ok(instructions) => instructions,
+        Err(offset) => {
+            let kind = if code[offset] == b'[' { ParseErrorKind::UnmatchedOpen } else { ParseErrorKind::UnmatchedClose };
+            Diagnostic::parse_error(path, &ParseError::new(kind, code, offset)).write(args.diagnostics, out)?;
+            return Ok(false);
+        }
+    };
+    let mut linter = Linter {
+        line_starts: &line_starts,
+        problems: Vec::new(),
+        has_negative_cell: false,
+    };
+    linter.sequence(&instructions, State::new(Some(0), true));
+    let tail = unobservable_tail(&instructions);
+    if let Some(last) = instructions.get(tail..).and_then(<[_]>::last) {
+        let span = instructions[tail].span.start..last.span.end;
+        // Changes in the tail are part of it, so they are not reported on their own.
+        linter.problems.retain(|(lint, change, _)| *lint != Lint::DeadStore || change.start < span.start);
+        linter.report(Lint::UnobservableTail, span, "these commands at the end of the program have no observable effect")
+    }
+    let mut diagnostics: Vec<Diagnostic> = linter.problems.into_iter().filter_map(|(lint, span, message)| {
+        let severity = if args.deny.contains(&lint) {
+            Severity::Error
+        } else if args.allow.contains(&lint) {
+            return None;
+        } else {
+            Severity::Warning
+        };
+        let code = lint.to_possible_value().expect("Lints are not skipped").get_name().to_string();
+        Some(Diagnostic::new(code, severity, message, path, span, &line_starts))
+    }).collect();
+    diagnostics.sort_by_key(|diagnostic| diagnostic.start);
+    for diagnostic in &diagnostics {
+        diagnostic.write(args.diagnostics, out)?
+    }
+    Ok(diagnostics.iter().all(|diagnostic| diagnostic.severity != Severity::Error))
+}
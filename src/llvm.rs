@@ -0,0 +1,321 @@
+use std::fmt::Write;
+
+use brainfuck_interpreter::Command;
+
+/// Runtime support for the generated program: the tape state and a function returning a pointer to
+/// a cell, which grows the tape in both directions when needed.
+///
+/// The tape is a heap allocation of `@size` cells, and the cell at index `i` is stored at
+/// `@tape[@origin + i]`. Pointers returned by `@cell` are invalidated by the next call to `@cell`,
+/// as it may reallocate the tape.
+///
+/// The inactive tape of the `dual` dialect is described by `@other_tape`, `@other_size` and
+/// `@other_origin`, which `@switch` exchanges with the globals of the active tape.
+const RUNTIME: &str = r#"declare i32 @getchar()
+declare i32 @putchar(i32)
+declare i8* @calloc(i64, i64)
+declare void @free(i8*)
+declare void @exit(i32)
+declare i32 @rand()
+declare void @srand(i32)
+declare i64 @time(i64*)
+declare void @llvm.memcpy.p0i8.p0i8.i64(i8*, i8*, i64, i1)
+declare void @llvm.memset.p0i8.i64(i8*, i8, i64, i1)
+
+@tape = internal global i8* null
+@size = internal global i64 0
+@origin = internal global i64 0
+@register = internal global i8 0
+@other_tape = internal global i8* null
+@other_size = internal global i64 0
+@other_origin = internal global i64 0
+
+define internal i8* @cell(i64 %index) {
+entry:
+  %origin = load i64, i64* @origin
+  %size = load i64, i64* @size
+  %i = add i64 %index, %origin
+  %in_bounds = icmp ult i64 %i, %size
+  br i1 %in_bounds, label %found, label %grow
+
+found:
+  %tape = load i8*, i8** @tape
+  %cell = getelementptr i8, i8* %tape, i64 %i
+  ret i8* %cell
+
+grow:
+  ; Add `size + |index| + 1` cells on each side, which is enough for `index` to be valid.
+  %negative = icmp slt i64 %index, 0
+  %negated = sub i64 0, %index
+  %abs = select i1 %negative, i64 %negated, i64 %index
+  %shift.0 = add i64 %size, %abs
+  %shift = add i64 %shift.0, 1
+  %added = shl i64 %shift, 1
+  %new_size = add i64 %size, %added
+  %new_tape = call i8* @calloc(i64 %new_size, i64 1)
+  %old_tape = load i8*, i8** @tape
+  %destination = getelementptr i8, i8* %new_tape, i64 %shift
+  call void @llvm.memcpy.p0i8.p0i8.i64(i8* %destination, i8* %old_tape, i64 %size, i1 false)
+  call void @free(i8* %old_tape)
+  %new_origin = add i64 %origin, %shift
+  store i8* %new_tape, i8** @tape
+  store i64 %new_size, i64* @size
+  store i64 %new_origin, i64* @origin
+  %result = call i8* @cell(i64 %index)
+  ret i8* %result
+}
+
+define internal void @input(i8* %cell) {
+entry:
+  %char = call i32 @getchar()
+  %eof = icmp eq i32 %char, -1
+  br i1 %eof, label %error, label %store
+
+error:
+  call void @exit(i32 1)
+  unreachable
+
+store:
+  %value = trunc i32 %char to i8
+  store i8 %value, i8* %cell
+  ret void
+}
+
+define internal void @switch() {
+entry:
+  %tape = load i8*, i8** @tape
+  %other_tape = load i8*, i8** @other_tape
+  store i8* %other_tape, i8** @tape
+  store i8* %tape, i8** @other_tape
+  %size = load i64, i64* @size
+  %other_size = load i64, i64* @other_size
+  store i64 %other_size, i64* @size
+  store i64 %size, i64* @other_size
+  %origin = load i64, i64* @origin
+  %other_origin = load i64, i64* @other_origin
+  store i64 %other_origin, i64* @origin
+  store i64 %origin, i64* @other_origin
+  ret void
+}
+"#;
+
+/// Translates the passed commands to a standalone program in the textual LLVM IR format.
+///
+/// The generated module only depends on the C standard library, so it can be compiled with, e.g.,
+/// `clang -O2 program.ll`. Reading past the end of the input exits with status 1. Tape dumps (`?`)
+/// and assertions are ignored. Random bytes come from the `rand` function of the C standard
+/// library, seeded with the current time.
+pub fn to_llvm(commands: &[Command]) -> String {
+    let mut emitter = Emitter::default();
+    emitter.emit(commands);
+    format!("{}\ndefine i32 @main() {{\nentry:\n  %ptr = alloca i64\n  store i64 0, i64* %ptr\n  %other_ptr = alloca i64\n  store i64 0, i64* %other_ptr\n  %now = call i64 @time(i64* null)\n  %seed = trunc i64 %now to i32\n  call void @srand(i32 %seed)\n{}  ret i32 0\n}}\n", RUNTIME, emitter.code)
+}
+
+/// Generates the body of the `main` function.
+#[derive(Debug, Default)]
+struct Emitter {
+    /// The generated code.
+    code: String,
+    /// The number of temporaries created so far, used to name new ones.
+    temporaries: usize,
+    /// The number of loops emitted so far, used to name their labels.
+    loops: usize,
+    /// The number of halts emitted so far, used to name the labels following them.
+    halts: usize,
+}
+
+impl Emitter {
+    /// Returns the name of a new temporary.
+    fn temporary(&mut self) -> String {
+        self.temporaries += 1;
+        format!("%t{}", self.temporaries)
+    }
+
+    /// Emits a line of code.
+    fn line(&mut self, line: impl AsRef<str>) {
+        writeln!(self.code, "  {}", line.as_ref()).unwrap()
+    }
+
+    /// Emits code computing the index of the cell to the right of the pointer by a specific
+    /// offset, and returns the name of the temporary holding it.
+    fn index(&mut self, offset: isize) -> String {
+        let pointer = self.temporary();
+        self.line(format!("{} = load i64, i64* %ptr", pointer));
+        if offset == 0 {
+            pointer
+        } else {
+            let index = self.temporary();
+            self.line(format!("{} = add i64 {}, {}", index, pointer, offset));
+            index
+        }
+    }
+
+    /// Emits code computing a pointer to the cell to the right of the pointer by a specific offset,
+    /// and returns the name of the temporary holding it.
+    fn cell(&mut self, offset: isize) -> String {
+        let index = self.index(offset);
+        let cell = self.temporary();
+        self.line(format!("{} = call i8* @cell(i64 {})", cell, index));
+        cell
+    }
+
+    /// Emits code loading the value of the cell to the right of the pointer by a specific offset,
+    /// and returns the name of the temporary holding it.
+    fn load(&mut self, offset: isize) -> String {
+        let cell = self.cell(offset);
+        let value = self.temporary();
+        self.line(format!("{} = load i8, i8* {}", value, cell));
+        value
+    }
+
+    /// Emits code adding a value to the cell to the right of the pointer by a specific offset.
+    fn add(&mut self, offset: isize, amount: &str) {
+        let cell = self.cell(offset);
+        let value = self.temporary();
+        self.line(format!("{} = load i8, i8* {}", value, cell));
+        let sum = self.temporary();
+        self.line(format!("{} = add i8 {}, {}", sum, value, amount));
+        self.line(format!("store i8 {}, i8* {}", sum, cell));
+    }
+
+    /// Emits code switching the active tape, along with its pointer.
+    fn switch(&mut self) {
+        self.line("call void @switch()");
+        let pointer = self.temporary();
+        self.line(format!("{} = load i64, i64* %ptr", pointer));
+        let other_pointer = self.temporary();
+        self.line(format!("{} = load i64, i64* %other_ptr", other_pointer));
+        self.line(format!("store i64 {}, i64* %ptr", other_pointer));
+        self.line(format!("store i64 {}, i64* %other_ptr", pointer));
+    }
+
+    fn emit(&mut self, commands: &[Command]) {
+        // Loops are emitted with an explicit stack of the commands left in each and of their
+        // labels, so that deeply nested loops do not overflow the call stack.
+        let mut stack = vec![(commands.iter(), None)];
+        while let Some((commands, _)) = stack.last_mut() {
+            let Some(command) = commands.next() else {
+                if let Some((_, Some(label))) = stack.pop() {
+                    self.line(format!("br label %{}.test", label));
+                    writeln!(self.code, "{}.end:", label).unwrap();
+                }
+                continue;
+            };
+            match command {
+                Command::Loop(_) => self.line("; Loop"),
+                _ => self.line(format!("; {:?}", command)),
+            }
+            match command {
+                &Command::Right(amount) => {
+                    let index = self.index(amount);
+                    self.line(format!("store i64 {}, i64* %ptr", index));
+                }
+                &Command::Add(amount) => {
+                    self.add(0, &amount.to_string())
+                }
+                Command::Loop(loop_commands) => {
+                    self.loops += 1;
+                    let label = format!("loop{}", self.loops);
+                    self.line(format!("br label %{}.test", label));
+                    writeln!(self.code, "{}.test:", label).unwrap();
+                    let value = self.load(0);
+                    let condition = self.temporary();
+                    self.line(format!("{} = icmp ne i8 {}, 0", condition, value));
+                    self.line(format!("br i1 {}, label %{}.body, label %{}.end", condition, label, label));
+                    writeln!(self.code, "{}.body:", label).unwrap();
+                    stack.push((loop_commands.iter(), Some(label)))
+                }
+                Command::Input => {
+                    let cell = self.cell(0);
+                    self.line(format!("call void @input(i8* {})", cell));
+                }
+                Command::Output | Command::TracedOutput(_) => {
+                    let value = self.load(0);
+                    let char = self.temporary();
+                    self.line(format!("{} = zext i8 {} to i32", char, value));
+                    self.line(format!("call i32 @putchar(i32 {})", char));
+                }
+                Command::Reset => {
+                    let cell = self.cell(0);
+                    self.line(format!("store i8 0, i8* {}", cell));
+                }
+                &Command::ResetChunk(max_offset) => {
+                    // Make sure both ends of the chunk are allocated before taking a pointer to its
+                    // first cell, as growing the tape moves it.
+                    let first = max_offset.min(0);
+                    self.cell(max_offset.max(0));
+                    let cell = self.cell(first);
+                    let length = max_offset.unsigned_abs() + 1;
+                    self.line(format!("call void @llvm.memset.p0i8.i64(i8* {}, i8 0, i64 {}, i1 false)", cell, length));
+                }
+                Command::Move(cells) => {
+                    let value = self.load(0);
+                    for &(cell_offset, multiplier) in cells {
+                        let product = self.temporary();
+                        self.line(format!("{} = mul i8 {}, {}", product, value, multiplier));
+                        self.add(cell_offset, &product);
+                    }
+                    let cell = self.cell(0);
+                    self.line(format!("store i8 0, i8* {}", cell));
+                }
+                Command::DumpTape | Command::Assert(_) | Command::Position(_, _) => {}
+                &Command::AddRight(amount, offset) => {
+                    self.add(0, &amount.to_string());
+                    let index = self.index(offset);
+                    self.line(format!("store i64 {}, i64* %ptr", index));
+                }
+                Command::Halt => {
+                    // Code following the halt is unreachable, but still needs a block.
+                    self.halts += 1;
+                    self.line("call void @exit(i32 0)");
+                    self.line("unreachable");
+                    writeln!(self.code, "halt{}.after:", self.halts).unwrap();
+                }
+                Command::Store => {
+                    let value = self.load(0);
+                    self.line(format!("store i8 {}, i8* @register", value));
+                }
+                Command::Restore => {
+                    let value = self.temporary();
+                    self.line(format!("{} = load i8, i8* @register", value));
+                    let cell = self.cell(0);
+                    self.line(format!("store i8 {}, i8* {}", value, cell));
+                }
+                Command::Random => {
+                    let random = self.temporary();
+                    self.line(format!("{} = call i32 @rand()", random));
+                    let value = self.temporary();
+                    self.line(format!("{} = trunc i32 {} to i8", value, random));
+                    let cell = self.cell(0);
+                    self.line(format!("store i8 {}, i8* {}", value, cell));
+                }
+                Command::SwitchTape => {
+                    self.switch()
+                }
+                Command::Mul(multiplication) => {
+                    self.emit(&[multiplication.to_loop()])
+                }
+                Command::MoveSpan(span) => {
+                    self.emit(&[span.to_move()])
+                }
+                &Command::Scan(stride) => {
+                    self.emit(&[Command::scan_loop(stride)])
+                }
+                Command::ExchangeCells => {
+                    // The pointer to the first cell stays valid while the other tape is active, as
+                    // only the active tape can be reallocated.
+                    let cell = self.cell(0);
+                    let value = self.temporary();
+                    self.line(format!("{} = load i8, i8* {}", value, cell));
+                    self.switch();
+                    let other_cell = self.cell(0);
+                    let other_value = self.temporary();
+                    self.line(format!("{} = load i8, i8* {}", other_value, other_cell));
+                    self.line(format!("store i8 {}, i8* {}", value, other_cell));
+                    self.switch();
+                    self.line(format!("store i8 {}, i8* {}", other_value, cell));
+                }
+            }
+        }
+    }
+}
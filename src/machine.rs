@@ -0,0 +1,507 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::num::NonZeroU64;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::cell::Cell;
+use crate::names::CellNames;
+use crate::provenance::OutputMap;
+use crate::rng::Rng;
+use crate::tape::Tape;
+use crate::Command;
+
+/// How often a program stuck in an empty loop checks whether it was cancelled.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The reason why a program stopped before reaching its end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The program executed [`Command::Halt`].
+    Halt,
+    /// The user aborted the program while it was paused (see `--step`).
+    Abort,
+    /// The program tried to output more values than allowed by `--max-output`.
+    OutputLimit,
+    /// An assertion failed (see `--assertions`).
+    AssertionFailed,
+    /// The program repeated a loop after executing as many steps as allowed (see `--max-steps`
+    /// and `--equiv`).
+    StepLimit,
+    /// The program tried to write to a protected cell (see `--protect`).
+    ProtectedWrite,
+    /// The program read past the end of its input with `--eof error`.
+    EndOfInput,
+    /// No input was available within the timeout (see `--input-timeout`).
+    InputTimeout,
+    /// Reading the input failed.
+    InputError(io::ErrorKind),
+    /// Writing the output failed.
+    OutputError(io::ErrorKind),
+    /// Writing a tape dump as JSON failed (see `--tape-dump-json`).
+    TapeDumpError(io::ErrorKind),
+    /// The program was cancelled, as the function called every few steps asked to stop it (see
+    /// [`Machine::on_step`]), or as its cancellation flag was set (see [`MachineOptions::cancel`]).
+    /// The machine still holds the number of steps executed and the position of the program.
+    Cancelled,
+}
+
+/// What a paused program does next (see [`MachineOptions::on_pause`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// The program continues until the next pause.
+    Continue,
+    /// The program continues, and also pauses after the specified step.
+    RunTo(u64),
+    /// The program stops with [`Stop::Abort`].
+    Abort,
+}
+
+/// Where a running program is, as passed to [`StepHook::function`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepInfo {
+    /// The number of steps executed so far, including the one about to execute.
+    pub steps: u64,
+    /// The position in the source code (line and column) of the command about to execute, if the
+    /// program was loaded with positions (see [`Machine::position`]).
+    pub position: Option<(usize, usize)>,
+}
+
+/// A function called every few steps while a program runs (see [`Machine::on_step`]), which may
+/// borrow data for the lifetime `'a`.
+pub struct StepHook<'a> {
+    /// The number of steps between two calls.
+    pub interval: NonZeroU64,
+    /// The function, which stops the program with [`Stop::Cancelled`] if it returns
+    /// [`ControlFlow::Break`].
+    pub function: &'a mut dyn FnMut(StepInfo) -> ControlFlow<()>,
+}
+
+impl fmt::Debug for StepHook<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StepHook").field("interval", &self.interval).finish_non_exhaustive()
+    }
+}
+
+/// The options a [`Machine`] is created with, for cells of type `C`.
+#[derive(Debug, Clone, Default)]
+pub struct MachineOptions<C: Cell = u8> {
+    /// Whether the machine has a second tape, as the `dual` dialect needs.
+    pub dual_tape: bool,
+    /// If set, the program pauses every time this many steps have been executed.
+    pub step_interval: Option<NonZeroU64>,
+    /// The steps after which the program pauses, in any order.
+    pub breakpoints: Vec<NonZeroU64>,
+    /// The function called when the program pauses, along with whether it paused at a breakpoint
+    /// rather than after [`MachineOptions::step_interval`] steps. It usually shows the state of
+    /// the machine to the user, and asks them what to do next. Pausing does nothing without it.
+    pub on_pause: Option<fn(&Machine<'_, C>, bool) -> Resume>,
+    /// The function called when the program executes [`Command::DumpTape`], which usually shows
+    /// the tapes to the user (see [`Tape::dump`]). Dumping does nothing else without it.
+    pub on_dump: Option<fn(&Machine<'_, C>)>,
+    /// If set, tape dumps are also written as JSON to numbered files derived from this path.
+    pub tape_dump_json: Option<PathBuf>,
+    /// The maximum number of values the program may output.
+    pub max_output: Option<u64>,
+    /// The number of steps after which the program stops when it repeats a loop.
+    pub max_steps: Option<u64>,
+    /// Whether to record which [`Command::TracedOutput`] outputs each value.
+    pub trace_output: bool,
+    /// The seed of the random number generator of the `random` dialect.
+    pub seed: u64,
+    /// If set, the program stops with [`Stop::Cancelled`] once this flag is set, such as by
+    /// another thread. Like [`MachineOptions::max_steps`], it is checked when a loop is repeated,
+    /// as only loops can make a program run forever.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// The minimum number of steps between two checks of [`MachineOptions::cancel`], which is
+    /// [`DEFAULT_CANCEL_INTERVAL`] if not set.
+    pub cancel_interval: Option<NonZeroU64>,
+}
+
+/// The minimum number of steps between two checks of [`MachineOptions::cancel`] by default, which
+/// makes checking it cost nothing measurable, while a cancelled program still stops within a
+/// fraction of a millisecond.
+pub const DEFAULT_CANCEL_INTERVAL: u64 = 1 << 16;
+
+
+/// The state of a running program, whose input and output may borrow data for the lifetime `'io`,
+/// and whose cells are of type `C` (bytes by default).
+#[derive(Debug)]
+pub struct Machine<'io, C: Cell = u8> {
+    /// The tape the program operates on.
+    pub tape: Tape<'io, C>,
+    /// The inactive tape of the `dual` dialect, if it is used. Switching tapes exchanges its cells
+    /// with those of [`Machine::tape`], so that the input and output stay attached to the latter.
+    pub other_tape: Option<Tape<'io, C>>,
+    /// The storage register of the Extended Type I dialect.
+    pub register: C,
+    /// The random number generator of the `random` dialect.
+    pub rng: Rng,
+    /// The number of commands executed so far, including each time a loop is entered, but not each
+    /// time it is repeated.
+    pub steps: u64,
+    /// If set, the program pauses every time this many steps have been executed (see `--step`).
+    pub step_interval: Option<NonZeroU64>,
+    /// The steps after which the program pauses (see `--break-at-step`), in decreasing order so
+    /// that the next one is the last.
+    pub breakpoints: Vec<u64>,
+    /// The function called when the program pauses (see [`MachineOptions::on_pause`]).
+    pub on_pause: Option<fn(&Machine<'_, C>, bool) -> Resume>,
+    /// The function called when the program dumps the tape (see [`MachineOptions::on_dump`]).
+    pub on_dump: Option<fn(&Machine<'_, C>)>,
+    /// The function called every few steps, if any, which can stop the program. Unlike
+    /// [`Machine::on_pause`], it may capture its environment, such as to count events in an
+    /// embedding.
+    pub on_step: Option<StepHook<'io>>,
+    /// If set, tape dumps are also written as JSON to numbered files derived from this path (see
+    /// `--tape-dump-json`).
+    pub tape_dump_json: Option<PathBuf>,
+    /// The number of tape dumps written as JSON so far.
+    pub tape_dumps: usize,
+    /// The maximum number of values the program may output (see `--max-output`).
+    pub max_output: Option<u64>,
+    /// The number of steps after which the program stops when it repeats a loop, which is
+    /// [`u64::MAX`] if there is no limit.
+    pub max_steps: u64,
+    /// The names of the cells, shown in tape dumps.
+    pub names: CellNames,
+    /// The commands that output each value, if they are recorded (see `--output-map`).
+    pub output_map: Option<OutputMap>,
+    /// Why the last assertion that failed does not hold (see [`Assertion::failure`]), which is left
+    /// to the caller to report once the program stopped with [`Stop::AssertionFailed`].
+    ///
+    /// [`Assertion::failure`]: crate::assertion::Assertion::failure
+    pub failed_assertion: Option<String>,
+    /// The position in the source code (line and column) of the command about to execute, if the
+    /// program was loaded with positions (see [`Command::Position`]).
+    pub position: Option<(usize, usize)>,
+    /// The flag cancelling the program once set (see [`MachineOptions::cancel`]).
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// The minimum number of steps between two checks of [`Machine::cancel`].
+    pub cancel_interval: u64,
+    /// The number of steps from which [`Machine::cancel`] is checked again.
+    next_cancel_check: u64,
+}
+
+impl<'io, C: Cell> Machine<'io, C> {
+    pub fn new(tape: Tape<'io, C>, options: &MachineOptions<C>) -> Self {
+        Self {
+            tape,
+            other_tape: options.dual_tape.then(Tape::detached),
+            register: C::default(),
+            rng: Rng::new(options.seed),
+            steps: 0,
+            step_interval: options.step_interval,
+            breakpoints: {
+                let mut breakpoints: Vec<u64> = options.breakpoints.iter().map(|step| step.get()).collect();
+                breakpoints.sort_by(|a, b| b.cmp(a));
+                breakpoints.dedup();
+                breakpoints
+            },
+            on_pause: options.on_pause,
+            on_dump: options.on_dump,
+            on_step: None,
+            tape_dump_json: options.tape_dump_json.clone(),
+            tape_dumps: 0,
+            max_output: options.max_output,
+            max_steps: options.max_steps.unwrap_or(u64::MAX),
+            names: CellNames::default(),
+            output_map: options.trace_output.then(OutputMap::default),
+            failed_assertion: None,
+            position: None,
+            cancel: options.cancel.clone(),
+            cancel_interval: options.cancel_interval.map_or(DEFAULT_CANCEL_INTERVAL, NonZeroU64::get),
+            next_cancel_check: 0,
+        }
+    }
+
+    /// Resets the tapes and the storage register, so that another run starts from a blank state.
+    /// The steps keep being counted.
+    pub fn reset(&mut self) {
+        self.tape.reset();
+        if let Some(other_tape) = &mut self.other_tape {
+            other_tape.reset()
+        }
+        self.register = C::default()
+    }
+
+    /// Counts a step, calling [`Machine::on_step`] and pausing if needed (see
+    /// [`MachineOptions::step_interval`] and [`MachineOptions::breakpoints`]).
+    pub fn step(&mut self) -> ControlFlow<Stop> {
+        self.steps += 1;
+        if let Some(hook) = &mut self.on_step {
+            if self.steps.is_multiple_of(hook.interval.get()) && (hook.function)(StepInfo { steps: self.steps, position: self.position }).is_break() {
+                return ControlFlow::Break(Stop::Cancelled);
+            }
+        }
+        if self.breakpoints.last() == Some(&self.steps) {
+            self.breakpoints.pop();
+            return self.pause(true);
+        }
+        if let Some(interval) = self.step_interval {
+            if self.steps.is_multiple_of(interval.get()) {
+                return self.pause(false);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Returns [`ControlFlow::Break`] if a loop cannot be repeated because the program has executed
+    /// as many steps as allowed (see [`MachineOptions::max_steps`]), or because it was cancelled
+    /// (see [`MachineOptions::cancel`]).
+    ///
+    /// An empty loop never ends, but never executes a step either, so it uses up all the steps
+    /// left, and waits until the program is cancelled.
+    #[inline]
+    pub fn check_step_limit(&mut self, is_empty_loop: bool) -> ControlFlow<Stop> {
+        if is_empty_loop && self.max_steps != u64::MAX {
+            self.steps = self.max_steps
+        }
+        if self.steps >= self.max_steps {
+            return ControlFlow::Break(Stop::StepLimit);
+        }
+        if self.cancel.is_some() && (is_empty_loop || self.steps >= self.next_cancel_check) {
+            return self.check_cancel(is_empty_loop);
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Returns [`ControlFlow::Break`] if [`Machine::cancel`] is set, after waiting until it is if
+    /// `wait` is `true`.
+    #[cold]
+    fn check_cancel(&mut self, wait: bool) -> ControlFlow<Stop> {
+        let Some(cancel) = &self.cancel else {
+            return ControlFlow::Continue(());
+        };
+        self.next_cancel_check = self.steps.saturating_add(self.cancel_interval);
+        while wait && !cancel.load(Ordering::Relaxed) {
+            thread::sleep(CANCEL_POLL_INTERVAL)
+        }
+        if cancel.load(Ordering::Relaxed) {
+            return ControlFlow::Break(Stop::Cancelled);
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Calls [`Machine::on_pause`], if set, and does what it returns.
+    ///
+    /// Returns [`ControlFlow::Break`] if it asked to abort the program.
+    fn pause(&mut self, at_breakpoint: bool) -> ControlFlow<Stop> {
+        let Some(on_pause) = self.on_pause else {
+            return ControlFlow::Continue(());
+        };
+        match on_pause(self, at_breakpoint) {
+            Resume::Continue => {}
+            Resume::RunTo(step) => {
+                // Keep the breakpoints in decreasing order.
+                let index = self.breakpoints.partition_point(|&breakpoint| breakpoint > step);
+                if step > self.steps && self.breakpoints.get(index) != Some(&step) {
+                    self.breakpoints.insert(index, step)
+                }
+            }
+            Resume::Abort => return ControlFlow::Break(Stop::Abort),
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Executes commands on a machine by walking the tree of commands (see
+/// [`bytecode::execute`](crate::bytecode::execute) for the default interpreter).
+///
+/// The loops the current command is in are kept on a stack rather than recursed into, so that
+/// deeply nested ones cannot overflow the call stack.
+///
+/// Returns [`ControlFlow::Break`] if the program ended early, in which case no more commands should
+/// be executed.
+pub fn execute<C: Cell>(commands: &[Command], machine: &mut Machine<'_, C>) -> ControlFlow<Stop> {
+    // The commands left to execute in each loop the current command is in, along with the body of
+    // the loop, which is [`None`] for the program itself.
+    let mut stack = vec![(commands, None)];
+    while let Some((commands, body)) = stack.last_mut() {
+        match commands.split_first() {
+            Some((Command::Loop(loop_commands), rest)) => {
+                *commands = rest;
+                machine.step()?;
+                if !machine.tape.is_zero() {
+                    machine.check_step_limit(loop_commands.is_empty())?;
+                    stack.push((loop_commands, Some(&**loop_commands)))
+                }
+            }
+            Some((command, rest)) => {
+                *commands = rest;
+                execute_command(command, machine)?
+            }
+            None => match *body {
+                Some(body) if !machine.tape.is_zero() => {
+                    // Programs can only run forever by looping, so there is no need to check the
+                    // number of steps more often.
+                    machine.check_step_limit(body.is_empty())?;
+                    *commands = body
+                }
+                _ => {
+                    stack.pop();
+                }
+            },
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+/// Executes a command on a machine, counting a step. Loops are executed with [`execute`], and
+/// positions are not steps (see [`Command::Position`]).
+#[inline(always)]
+pub fn execute_command<C: Cell>(command: &Command, machine: &mut Machine<'_, C>) -> ControlFlow<Stop> {
+    match command {
+        Command::Loop(_) => return execute(std::slice::from_ref(command), machine),
+        &Command::Position(line, column) => {
+            machine.position = Some((line, column));
+            return ControlFlow::Continue(());
+        }
+        _ => {}
+    }
+    machine.step()?;
+    match command {
+        Command::Right(amount) => {
+            machine.tape.right_by(*amount)
+        }
+        Command::Add(amount) => {
+            machine.tape.add(0, *amount)
+        }
+        Command::Loop(_) | Command::Position(_, _) => {
+            unreachable!("Loops are executed by `execute`, and positions before counting a step")
+        }
+        Command::Input => {
+            machine.tape.input()?
+        }
+        Command::Output => {
+            if machine.max_output == Some(machine.tape.output_count()) {
+                return ControlFlow::Break(Stop::OutputLimit)
+            }
+            machine.tape.output()?
+        }
+        &Command::TracedOutput(position) => {
+            if machine.max_output == Some(machine.tape.output_count()) {
+                return ControlFlow::Break(Stop::OutputLimit)
+            }
+            machine.tape.output()?;
+            if let Some(output_map) = &mut machine.output_map {
+                output_map.record(position, machine.steps)
+            }
+        }
+        // Like the loops they replace, resets and moves do not write to any cell when the current
+        // cell is 0, so that they do not change which cells are written (see `--summary`).
+        &Command::Reset => {
+            if !machine.tape.is_zero() {
+                machine.tape.write(C::default())
+            }
+        }
+        &Command::ResetChunk(max_offset) => {
+            machine.tape.clear(max_offset)
+        }
+        Command::Move(cells) => {
+            if !machine.tape.is_zero() {
+                let value = machine.tape.read();
+                for &(cell_offset, multiplier) in cells.iter() {
+                    machine.tape.add_cell(cell_offset, &value.times(&C::from_amount(multiplier)))
+                }
+                machine.tape.write(C::default())
+            }
+        }
+        Command::MoveSpan(span) => {
+            if !machine.tape.is_zero() {
+                let value = machine.tape.read();
+                machine.tape.add_span(span.start, span.length, &value);
+                for &(cell_offset, multiplier) in span.rest.iter() {
+                    machine.tape.add_cell(cell_offset, &value.times(&C::from_amount(multiplier)))
+                }
+                machine.tape.write(C::default())
+            }
+        }
+        &Command::Scan(stride) => {
+            machine.tape.scan(stride)
+        }
+        Command::DumpTape => {
+            if let Some(on_dump) = machine.on_dump {
+                on_dump(machine)
+            }
+            if let Some(path) = &machine.tape_dump_json {
+                machine.tape_dumps += 1;
+                if let Err(error) = write_tape_json(&machine.tape, &numbered_path(path, machine.tape_dumps)) {
+                    return ControlFlow::Break(Stop::TapeDumpError(error.kind()));
+                }
+            }
+        }
+        &Command::AddRight(amount, offset) => {
+            machine.tape.add(0, amount);
+            machine.tape.right_by(offset)
+        }
+        Command::Halt => {
+            return ControlFlow::Break(Stop::Halt)
+        }
+        Command::Store => {
+            machine.register = machine.tape.read()
+        }
+        Command::Restore => {
+            machine.tape.write(machine.register.clone())
+        }
+        Command::Random => {
+            machine.tape.write(C::from_byte(machine.rng.next_byte()))
+        }
+        Command::SwitchTape => {
+            let other_tape = machine.other_tape.as_mut().expect("Switching tapes requires the `dual` dialect");
+            machine.tape.swap_cells(other_tape)
+        }
+        Command::Mul(multiplication) => {
+            if !machine.tape.is_zero() {
+                let count = machine.tape.read();
+                let operand = machine.tape.read_relative(multiplication.operand);
+                let restored_operand = operand.plus(&machine.tape.read_relative(multiplication.temporary));
+                let product = operand.plus(&count.plus(&C::from_amount(u64::MAX)).times(&restored_operand));
+                for &(cell_offset, multiplier) in multiplication.targets.iter() {
+                    machine.tape.add_cell(cell_offset, &product.times(&C::from_amount(multiplier)))
+                }
+                machine.tape.write_relative(multiplication.operand, restored_operand);
+                machine.tape.write_relative(multiplication.temporary, C::default());
+                machine.tape.write(C::default())
+            }
+        }
+        Command::ExchangeCells => {
+            let other_tape = machine.other_tape.as_mut().expect("Exchanging cells requires the `dual` dialect");
+            let value = machine.tape.read();
+            machine.tape.write(other_tape.read());
+            other_tape.write(value)
+        }
+        Command::Assert(assertion) => {
+            if !assertion.holds(&machine.tape) {
+                machine.failed_assertion = Some(assertion.failure(&machine.tape));
+                return ControlFlow::Break(Stop::AssertionFailed)
+            }
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+
+/// Returns the path of the `n`-th numbered file derived from a path, which is `name.n.ext` for
+/// `name.ext`.
+pub fn numbered_path(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!("{}.{}.{}", stem, n, extension.to_string_lossy())),
+        None => path.with_file_name(format!("{}.{}", stem, n)),
+    }
+}
+
+/// Writes the contents of a tape as JSON to a file.
+pub fn write_tape_json<C: Cell>(tape: &Tape<'_, C>, path: &Path) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut out, &tape.state())?;
+    writeln!(out)?;
+    out.flush()
+}
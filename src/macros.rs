@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+/// An error that occurred while expanding macros.
+#[derive(Debug)]
+pub struct Error {
+    message: String,
+    /// The names of the macros being expanded when the error occurred, outermost first.
+    chain: Vec<String>,
+    /// The line and column (both starting at 1) of the outermost directive that failed.
+    location: (usize, usize),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.location.0, self.location.1, self.message)?;
+        if !self.chain.is_empty() {
+            write!(f, " (in {})", self.chain.join(" -> "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A macro defined with `!def`.
+#[derive(Debug)]
+struct Macro {
+    parameters: Vec<String>,
+    body: String,
+}
+
+/// Expands the macros of some source code (see `--macros`), and returns the resulting code.
+///
+/// The following directives are supported:
+/// - `!def name body` defines a macro named `name` which expands to the rest of the line, and
+///   `!def name { body }` to the code between the braces, which may span several lines.
+/// - `!def name(a, b) { body }` defines a macro with parameters, which are referred to as `!a` and
+///   `!b` in its body, and are replaced with the arguments of the call before it is expanded.
+/// - `!name` and `!name(x, y)` expand a macro defined earlier.
+/// - `!rep n { body }` expands to `n` copies of the code between the braces.
+///
+/// Macros are expanded recursively, and a macro calling itself (directly or not) is an error, as it
+/// would never stop expanding. An `!` that is not followed by a name, or followed by the name of a
+/// macro that is not defined, is left as is, so that it can still be used by dialects and in
+/// comments (such as `Say hi!there`).
+pub fn expand(code: &str) -> Result<String, Error> {
+    let mut expander = Expander::default();
+    let mut expanded = String::new();
+    expander.expand(code, &mut expanded)?;
+    Ok(expanded)
+}
+
+#[derive(Debug, Default)]
+struct Expander {
+    macros: HashMap<String, Macro>,
+    /// The names of the macros being expanded, outermost first.
+    chain: Vec<String>,
+}
+
+/// A cursor over the code to expand.
+struct Cursor<'a> {
+    code: &'a str,
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<char> {
+        self.code[self.position..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let char = self.peek()?;
+        self.position += char.len_utf8();
+        Some(char)
+    }
+
+    /// Skips spaces and tabs, but not newlines, which end single-line definitions.
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.bump();
+        }
+    }
+
+    /// Consumes the longest run of characters that match `predicate`, and returns it.
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let start = self.position;
+        while self.peek().is_some_and(&predicate) {
+            self.bump();
+        }
+        &self.code[start..self.position]
+    }
+
+    fn name(&mut self) -> &'a str {
+        self.take_while(|char| char.is_ascii_alphanumeric() || char == '_')
+    }
+
+    /// Consumes the rest of the line, without the newline.
+    fn line(&mut self) -> &'a str {
+        self.take_while(|char| char != '\n')
+    }
+
+    /// Consumes a block delimited by braces, and returns its contents.
+    fn block(&mut self) -> Result<&'a str, String> {
+        if self.bump() != Some('{') {
+            return Err("expected `{`".to_string());
+        }
+        let start = self.position;
+        let mut depth = 0;
+        loop {
+            match self.bump() {
+                Some('{') => depth += 1,
+                Some('}') if depth == 0 => return Ok(&self.code[start..self.position - 1]),
+                Some('}') => depth -= 1,
+                Some(_) => {}
+                None => return Err("unclosed `{`".to_string()),
+            }
+        }
+    }
+
+    /// Consumes a parenthesized list of comma-separated items, if there is one, and returns them.
+    /// Items may contain parentheses, as long as they are balanced.
+    fn list(&mut self) -> Result<Vec<&'a str>, String> {
+        if self.peek() != Some('(') {
+            return Ok(Vec::new());
+        }
+        self.bump();
+        let mut items = Vec::new();
+        let mut start = self.position;
+        let mut depth = 0;
+        loop {
+            match self.bump() {
+                Some('(') => depth += 1,
+                Some(',') if depth == 0 => {
+                    items.push(self.code[start..self.position - 1].trim());
+                    start = self.position
+                }
+                Some(')') if depth == 0 => {
+                    items.push(self.code[start..self.position - 1].trim());
+                    return Ok(items);
+                }
+                Some(')') => depth -= 1,
+                Some(_) => {}
+                None => return Err("unclosed `(`".to_string()),
+            }
+        }
+    }
+}
+
+/// Replaces each reference to a parameter in the body of a macro with the corresponding argument.
+fn substitute(body: &str, parameters: &[String], arguments: &[&str]) -> String {
+    let mut cursor = Cursor { code: body, position: 0 };
+    let mut result = String::new();
+    while let Some(char) = cursor.bump() {
+        if char == '!' {
+            let name = cursor.name();
+            match parameters.iter().position(|parameter| parameter == name) {
+                Some(i) => result.push_str(arguments[i]),
+                None => {
+                    result.push('!');
+                    result.push_str(name)
+                }
+            }
+        } else {
+            result.push(char)
+        }
+    }
+    result
+}
+
+impl Expander {
+    fn expand(&mut self, code: &str, out: &mut String) -> Result<(), Error> {
+        let mut cursor = Cursor { code, position: 0 };
+        loop {
+            let start = cursor.position;
+            match cursor.bump() {
+                Some('!') => {}
+                Some(char) => {
+                    out.push(char);
+                    continue;
+                }
+                None => return Ok(()),
+            }
+            let name = cursor.name();
+            if name.is_empty() {
+                out.push('!');
+                continue;
+            }
+            self.directive(name, &mut cursor, out).map_err(|mut error| {
+                // Errors are located in the code that was passed to the interpreter, which is the
+                // code being expanded when no macro is.
+                if self.chain.is_empty() {
+                    let before = &code[..start];
+                    let line = before.matches('\n').count() + 1;
+                    let column = before.chars().count() - before.rfind('\n').map_or(0, |i| before[..=i].chars().count()) + 1;
+                    error.location = (line, column);
+                }
+                error
+            })?;
+        }
+    }
+
+    /// Returns an error with the specified message, occurring in the macro being expanded.
+    fn error(&self, message: String) -> Error {
+        Error { message, chain: self.chain.clone(), location: (0, 0) }
+    }
+
+    /// Handles the directive named `name`, of which the `!` and the name have been consumed.
+    fn directive(&mut self, name: &str, cursor: &mut Cursor, out: &mut String) -> Result<(), Error> {
+        match name {
+            "def" => {
+                cursor.skip_spaces();
+                let name = cursor.name();
+                if name.is_empty() {
+                    return Err(self.error("expected a macro name after `!def`".to_string()));
+                }
+                let parameters = cursor.list().map_err(|message| self.error(message))?.into_iter().map(str::to_string).collect();
+                cursor.skip_spaces();
+                let body = if cursor.peek() == Some('{') {
+                    cursor.block().map_err(|message| self.error(message))?
+                } else {
+                    cursor.line()
+                };
+                self.macros.insert(name.to_string(), Macro { parameters, body: body.to_string() });
+                Ok(())
+            }
+            "rep" => {
+                cursor.skip_spaces();
+                let count = cursor.name();
+                let count: usize = count.parse().map_err(|_| self.error(format!("invalid repetition count `{}`", count)))?;
+                cursor.skip_spaces();
+                let body = cursor.block().map_err(|message| self.error(message))?;
+                let mut expanded = String::new();
+                self.expand(body, &mut expanded)?;
+                out.push_str(&expanded.repeat(count));
+                Ok(())
+            }
+            _ => {
+                let Some(called) = self.macros.get(name) else {
+                    out.push('!');
+                    out.push_str(name);
+                    return Ok(());
+                };
+                let arguments = if called.parameters.is_empty() { Vec::new() } else { cursor.list().map_err(|message| self.error(message))? };
+                if arguments.len() != called.parameters.len() {
+                    return Err(self.error(format!("macro `{}` expects {} arguments, but {} were passed", name, called.parameters.len(), arguments.len())));
+                }
+                if self.chain.iter().any(|caller| caller == name) {
+                    return Err(self.error(format!("macro `{}` is recursive", name)));
+                }
+                let body = substitute(&called.body, &called.parameters, &arguments);
+                self.chain.push(name.to_string());
+                let result = self.expand(&body, out);
+                self.chain.pop();
+                result
+            }
+        }
+    }
+}
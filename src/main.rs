@@ -2,338 +2,845 @@
 
 use std::fmt::Display;
 use std::fs;
-use std::time::SystemTime;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufWriter, IsTerminal, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::{mem, process, thread};
+use std::time::{Duration, Instant};
 
-use clap::Parser;
+use brainfuck_interpreter::rng::Rng;
+use brainfuck_interpreter::source::{ParseError, Source};
+use brainfuck_interpreter::tape::{Input, Tape, TapeOptions, TapeState};
+use brainfuck_interpreter::names::CellNames;
+use brainfuck_interpreter::{bytecode, execute, execute_command, load, names, optimize_within_budget, provenance, source, timings, write_tape_json, Cell, CellSize, Command, Dialect, Machine, OptimizeReport, Stop};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, FromArgMatches};
+use num_bigint::BigInt;
 
 use crate::args::Args;
-use crate::tape::Tape;
+use crate::input::InputSource;
+use crate::lint::DiagnosticFormat;
+use crate::pipe::{PipeReader, PipeWriter};
+use crate::portability::Portability;
+use crate::profile::Profile;
 
-mod tape;
 mod args;
+mod dot;
+mod bf;
+mod llvm;
+mod asm;
+mod c;
+mod console;
+mod profile;
+mod pipe;
+mod tcp;
+mod diff;
+mod equiv;
+mod macros;
+mod analyze;
+mod lint;
+mod generate;
+mod tutor;
+mod input;
+mod selftest;
+mod keymap;
+mod game;
+mod protect;
+mod portability;
+mod script;
+mod annotate;
+mod testing;
+mod pause;
+mod watch;
 
 
-/// Commands represent higher level concepts than regular Brainfuck instructions. The goal is that a
-/// specific command can be executed in less time than it would take for it to be executed if it was
-/// made up of multiple regular Brainfuck instructions with the same effect.
-#[derive(Debug)]
-enum Command {
-    /// Moves the pointer to the right by a specific amount (to the left if negative).
-    Right(isize),
-    /// Adds a specific amount to the current cell.
-    Add(u8),
-    /// Repeats commands until the current cell reaches 0.
-    Loop(Vec<Command>),
-    /// Sets the value of the current cell to a byte read from `stdin`.
-    Input,
-    /// Outputs the value of the current cell to `stdout`.
-    Output,
-    /// Resets the value of the current cell to 0.
-    Reset,
-    /// Resets the values of the cells between the current cell and a specific cell (both included)
-    /// to 0.
-    ResetChunk(isize),
-    /// Moves the value of the current cell to the cells at a specific position (relative to the
-    /// current cell).
-    ///
-    /// Specifically:
-    /// - For each element `(i, n)` in the vector, adds `n` times the value of the current cell to
-    /// the value of the cell `i` cells to the right of the current cell;
-    /// - Resets the current cell.
-    ///
-    /// The pointer is *not* moved.
-    Move(Vec<(isize, u8)>),
-    /// Prints the tape to standard error.
-    Debug,
-}
+/// The exit code of the interpreter when the program exceeds a limit (such as `--max-output` or
+/// `--max-steps`).
+const LIMIT_EXIT_CODE: i32 = 3;
+
+/// The exit code of the interpreter when an assertion fails (see `--assertions`), or when the
+/// program writes to a protected cell (see `--protect`).
+const ASSERTION_EXIT_CODE: i32 = 4;
+
+/// The exit code of the interpreter when the program reads past the end of its input with `--eof
+/// error`.
+const END_OF_INPUT_EXIT_CODE: i32 = 5;
 
-impl Command {
-    /// Tests if this command is useful.
-    ///
-    /// A command is useful if it is not functionally equivalent to doing nothing.
-    fn is_useful(&self) -> bool {
-        !matches!(self, Self::Right(0) | Self::Add(0))
+/// The exit code of the interpreter when reading input times out (see `--input-timeout`), as with
+/// `timeout`.
+const INPUT_TIMEOUT_EXIT_CODE: i32 = 124;
+
+
+/// Executes commands on a machine with the interpreter chosen by `--flatten-loops`.
+fn execute_program<C: Cell>(commands: &[Command], machine: &mut Machine<'_, C>, args: &Args) -> ControlFlow<Stop> {
+    if args.flatten_loops {
+        bytecode::execute(&bytecode::flatten(commands), machine)
+    } else {
+        execute(commands, machine)
     }
+}
+
 
-    /// Tests if this comment increments the current cell by an odd amount.
-    fn is_odd_increment(&self) -> bool {
-        match self {
-            Self::Add(n) => n % 2 == 1,
-            _ => false,
+/// Executes commands on a machine like [`execute`], while recording the time spent on each kind of
+/// command in a profile.
+///
+/// The time recorded for generic loops only includes testing the current cell, as the commands in
+/// their bodies are recorded separately, so the total is close to the time spent running.
+fn execute_profiled<C: Cell>(commands: &[Command], machine: &mut Machine<'_, C>, profile: &mut Profile) -> ControlFlow<Stop> {
+    let ops = bytecode::flatten(commands);
+    let (start, start_steps) = (Instant::now(), machine.steps);
+    let (mut count, mut time) = (0, Duration::ZERO);
+    let result = bytecode::execute_with(&ops, machine, |command, machine| {
+        // Positions are not steps, and take no time worth recording.
+        if let Command::Position(_, _) = command {
+            return execute_command(command, machine);
         }
+        let start = Instant::now();
+        let result = execute_command(command, machine);
+        let elapsed = start.elapsed();
+        profile.record(command.name(), elapsed);
+        count += 1;
+        time += elapsed;
+        result
+    });
+    // Entering a loop is the only other step, and the rest of the time is spent testing the current
+    // cell at the start and at the end of the loops.
+    let loops = machine.steps - start_steps - count;
+    if loops > 0 {
+        profile.record_all("Loop", loops, start.elapsed().saturating_sub(time))
     }
+    result
 }
 
 
-/// Loads Brainfuck instructions from an iterator and pushes them to a vector as [commands](Command)
-/// until the iterator yields `end`. Returns the constructed vector.
-fn load(instructions: &mut impl Iterator<Item=char>, end: Option<char>, debug: bool) -> Vec<Command> {
-    let mut commands = Vec::new();
-    loop {
-        match instructions.next() {
-            c if c == end => {
-                return commands;
-            }
-            Some('<') => {
-                match commands.last_mut() {
-                    Some(Command::Right(amount)) => *amount -= 1,
-                    _ => commands.push(Command::Right(-1)),
-                }
-            }
-            Some('>') => {
-                match commands.last_mut() {
-                    Some(Command::Right(amount)) => *amount += 1,
-                    _ => commands.push(Command::Right(1)),
-                }
-            }
-            Some('+') => {
-                match commands.last_mut() {
-                    Some(Command::Add(amount)) => *amount = amount.wrapping_add(1),
-                    _ => commands.push(Command::Add(1)),
-                }
-            }
-            Some('-') => {
-                match commands.last_mut() {
-                    Some(Command::Add(amount)) => *amount = amount.wrapping_sub(1),
-                    _ => commands.push(Command::Add(u8::MAX)),
-                }
-            }
-            Some('[') => {
-                let loop_content = load(instructions, Some(']'), debug);
-                commands.push(Command::Loop(loop_content))
-            }
-            Some('.') => {
-                commands.push(Command::Output)
-            }
-            Some(',') => {
-                commands.push(Command::Input)
-            }
-            Some('?') if debug => {
-                commands.push(Command::Debug)
-            }
-            Some(']') => {
-                panic!("Unexpected `]`")
+/// Returns the input passed with `--input-repeat`, `--input-zero`, or `--input-random`, if any.
+fn synthetic_input(args: &Args, seed: u64) -> Option<Input<'static>> {
+    if let Some(pattern) = &args.input_repeat {
+        return Some(Box::new(pattern.bytes().map(Ok)));
+    }
+    if let Some(count) = args.input_zero {
+        return Some(Box::new(input::zeros(count).map(Ok)));
+    }
+    if let Some(count) = args.input_random {
+        // The generator of the `random` dialect is not shared, so that the input does not depend on
+        // how many random values the program generates.
+        let mut rng = Rng::new(seed);
+        return Some(Box::new(input::generated(count, move || rng.next_byte()).map(Ok)));
+    }
+    None
+}
+
+/// Returns the input passed with `--input-source` (or with `--input-file` or `--input-text`, and
+/// `--then-stdin`), in which each source is read once the previous one is exhausted, if any. Exits
+/// the process if a file cannot be opened.
+fn chained_input(args: &Args) -> Option<Input<'static>> {
+    let mut sources = args.input_source.clone();
+    if let Some(path) = &args.input_file {
+        sources.push(InputSource::File(path.clone()))
+    }
+    sources.extend(args.input_text.clone());
+    if args.then_stdin {
+        sources.push(InputSource::Stdin)
+    }
+    if sources.is_empty() {
+        return None;
+    }
+    let sources = sources.iter().map(|source| source.bytes(args.input_timeout)).collect::<io::Result<_>>().unwrap_or_else(|error| {
+        eprintln!("error: {}", error);
+        process::exit(1)
+    });
+    Some(Box::new(input::chain(sources)))
+}
+
+
+/// A writer keeping the bytes written to it, which can still be read once it is owned by a tape.
+#[derive(Debug, Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Recorder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs a program on some input until it ends, tries to output more than `max_output` values, or
+/// repeats a loop after executing `max_steps` steps, and returns what it output along with the
+/// machine it ran on and why it stopped.
+fn run_captured(commands: &[Command], input: Box<dyn Iterator<Item=u8>>, max_output: Option<u64>, max_steps: Option<u64>, args: &Args, seed: u64) -> (Vec<u8>, Machine<'static>, ControlFlow<Stop>) {
+    let recorder = Recorder::default();
+    let mut tape = Tape::new(&TapeOptions { eof: args.eof, ..TapeOptions::default() });
+    tape.set_input(input);
+    tape.set_raw_output(Box::new(recorder.clone()));
+    let mut machine = Machine::new(tape, &args.machine_options(seed));
+    machine.max_output = max_output;
+    machine.max_steps = max_steps.unwrap_or(u64::MAX);
+    let result = execute_program(commands, &mut machine, args);
+    // Recorders never fail to write.
+    machine.tape.flush().expect("Unable to write output");
+    let output = std::mem::take(&mut *recorder.0.lock().unwrap());
+    (output, machine, result)
+}
+
+
+/// Reads the source code of a program (see [`Source::read`]), exiting the process if it cannot be
+/// read.
+fn read_source(path: &Path, mmap: bool) -> Source {
+    Source::read(path, mmap).unwrap_or_else(|error| {
+        eprintln!("error: {}", error);
+        process::exit(1)
+    })
+}
+
+/// Loads and optimizes a program other than the main one (such as a program of a pipeline), exiting
+/// the process if it is invalid.
+fn load_file(path: &Path, args: &Args) -> Vec<Command> {
+    let source = read_source(path, false);
+    let code = source::blank_shebang(source.as_bytes());
+    let code = &*code;
+    let expanded_code;
+    let code = if args.macros {
+        expanded_code = expand_macros(code);
+        expanded_code.as_bytes()
+    } else {
+        code
+    };
+    let commands = load(code, &args.parse_options(), &collect_names(code)).unwrap_or_else(|error| parse_error(&error, path, args));
+    let (commands, report) = optimize_within_budget(commands, &args.optimize_options());
+    if args.verbose {
+        report_optimization(&report)
+    }
+    commands
+}
+
+/// Prints an error preventing a program from being loaded, in the format chosen with
+/// `--diagnostics`, and exits the process.
+fn parse_error(error: &ParseError, path: &Path, args: &Args) -> ! {
+    match args.diagnostics {
+        DiagnosticFormat::Human => eprintln!("error: {} in {}", error, path.display()),
+        format => lint::Diagnostic::parse_error(path, error).write(format, &mut io::stdout()).expect("Unable to write diagnostics"),
+    }
+    process::exit(1)
+}
+
+/// Prints what the optimizer did to a program to `stderr` (see `--verbose`).
+fn report_optimization(report: &OptimizeReport) {
+    if report.unwrapped_loops > 0 {
+        eprintln!("Unwrapped {} loops around a loop testing the same cell", report.unwrapped_loops);
+    }
+    if report.removed_constant_commands > 0 {
+        eprintln!("Removed {} commands operating on cells known to be 0", report.removed_constant_commands);
+    }
+    if report.removed_tail_commands > 0 {
+        eprintln!("Removed {} unobservable commands at the end of the program", report.removed_tail_commands);
+    }
+    if report.is_truncated {
+        eprintln!("Optimization stopped after exceeding the time budget");
+    }
+}
+
+
+/// Runs a program of a pipeline (see `--pipe`), reading the output of the previous program as
+/// input. Its output is passed to the next program if there is one.
+///
+/// Returns how the program ended, after reporting why if it ended early.
+fn run_piped<C: Cell>(path: &Path, args: &Args, seed: u64, input: PipeReader, output: Option<PipeWriter>) -> ControlFlow<Stop> {
+    let commands = load_file(path, args);
+    let mut tape = Tape::<C>::with_cells(&args.tape_options());
+    tape.set_input(Box::new(input));
+    if let Some(output) = output {
+        tape.set_raw_output(Box::new(BufWriter::new(output)))
+    }
+    let mut machine = Machine::new(tape, &args.machine_options(seed));
+    let result = execute_program(&commands, &mut machine, args);
+    if args.newline_at_exit && matches!(result, ControlFlow::Continue(()) | ControlFlow::Break(Stop::Halt)) {
+        machine.tape.finish_line().expect("Unable to write output")
+    }
+    let result = match (result, machine.tape.flush()) {
+        (ControlFlow::Continue(()), Err(error)) => ControlFlow::Break(Stop::OutputError(error.kind())),
+        (result, _) => result,
+    };
+    report_stop(result, machine.steps, &machine.tape, machine.failed_assertion.as_deref(), args, &format!("piped program {}: ", path.display()));
+    result
+}
+
+
+/// Reports why a program ended early on `stderr`, if it did, after executing the specified number
+/// of steps on a tape, with why the assertion that failed does not hold if one did. The message
+/// starts with the specified context, which says which program of a pipeline it is if it is not the
+/// main one.
+///
+/// Writes to protected cells are reported as they happen.
+fn report_stop<C: Cell>(result: ControlFlow<Stop>, steps: u64, tape: &Tape<'_, C>, failed_assertion: Option<&str>, args: &Args, context: &str) {
+    let ControlFlow::Break(stop) = result else {
+        return;
+    };
+    match stop {
+        Stop::OutputLimit => {
+            eprintln!("error: {}exceeded output limit after {} values", context, tape.output_count())
+        }
+        Stop::StepLimit => {
+            eprintln!("error: {}exceeded step limit after {} steps", context, steps)
+        }
+        Stop::EndOfInput => {
+            eprintln!("error: {}the program read past the end of its input at step {}", context, steps)
+        }
+        Stop::InputTimeout => {
+            if let Some(timeout) = args.input_timeout {
+                eprintln!("error: {}no input received within {:?}", context, timeout)
             }
-            None => {
-                panic!("Unexpected EOF")
+        }
+        Stop::InputError(kind) => {
+            eprintln!("error: {}unable to read input: {}", context, kind)
+        }
+        Stop::OutputError(kind) => {
+            eprintln!("error: {}unable to write output: {}", context, kind)
+        }
+        Stop::TapeDumpError(kind) => {
+            eprintln!("error: {}unable to write tape dump: {}", context, kind)
+        }
+        Stop::AssertionFailed => {
+            if let Some(failure) = failed_assertion {
+                eprintln!("error: {}{}", context, failure)
             }
-            _ => {}
         }
+        Stop::Halt | Stop::Abort | Stop::Cancelled | Stop::ProtectedWrite => {}
     }
 }
 
 
-/// Returns a command that is functionally equivalent to a loop containing the passed commands.
-fn optimize_loop(commands: Vec<Command>) -> Command {
-    /// Tries to optimize a loop with the passed body as a move.
-    ///
-    /// If possible, returns [`Some(result)`], where `result` is a vector that can be used to
-    /// construct [`Command::Move`]. Otherwise, returns [`None`].
-    fn try_optimize_as_move(commands: &Vec<Command>) -> Option<Vec<(isize, u8)>> {
-        let mut is_origin_decremented = false;
-        // Note that, if a cell is incremented multiple times, at different places within the loop,
-        // this will result in the vector containing multiple entries for this cell. Using a HashMap
-        // to solve this "problem" results in much higher optimization times, though. So Vec it is.
-        let mut increments = Vec::new();
-        let mut offset = 0;
-        for command in commands {
-            match command {
-                Command::Add(u8::MAX) if offset == 0 => {
-                    if is_origin_decremented {
-                        return None;
-                    } else {
-                        is_origin_decremented = true
-                    }
-                }
-                Command::Add(amount) => {
-                    increments.push((offset, *amount))
-                }
-                Command::Right(amount) => {
-                    offset += amount
-                }
-                _ => {
-                    return None;
-                }
-            }
+/// Returns the exit code of the interpreter for a program that ended this way, if it did not end
+/// normally. Aborting a paused program is not a failure, as the user chose to.
+fn exit_code(result: ControlFlow<Stop>) -> Option<i32> {
+    match result {
+        ControlFlow::Continue(()) | ControlFlow::Break(Stop::Halt | Stop::Abort | Stop::Cancelled) => None,
+        ControlFlow::Break(Stop::OutputLimit | Stop::StepLimit) => Some(LIMIT_EXIT_CODE),
+        ControlFlow::Break(Stop::AssertionFailed | Stop::ProtectedWrite) => Some(ASSERTION_EXIT_CODE),
+        ControlFlow::Break(Stop::EndOfInput) => Some(END_OF_INPUT_EXIT_CODE),
+        ControlFlow::Break(Stop::InputTimeout) => Some(INPUT_TIMEOUT_EXIT_CODE),
+        ControlFlow::Break(Stop::InputError(_) | Stop::OutputError(_) | Stop::TapeDumpError(_)) => Some(1),
+    }
+}
+
+
+/// Runs a program once for each line of `stdin` (see `--per-line`), each time on blank tapes reading
+/// the line as its whole input, until a run ends otherwise than normally.
+fn run_per_line<C: Cell>(machine: &mut Machine<'_, C>, args: &Args, mut run: impl FnMut(&mut Machine<'_, C>) -> ControlFlow<Stop>) -> ControlFlow<Stop> {
+    let mut stdin = io::stdin().lock();
+    for number in 1.. {
+        let mut line = Vec::new();
+        if stdin.read_until(b'\n', &mut line).expect("Unable to read input") == 0 {
+            break;
+        }
+        if !args.keep_newline && line.last() == Some(&b'\n') {
+            line.pop();
         }
-        if is_origin_decremented && offset == 0 {
-            Some(increments)
+        machine.reset();
+        machine.tape.set_input(Box::new(line.into_iter()));
+        if args.number_lines {
+            machine.tape.print(&format!("{}:", number)).expect("Unable to write output")
+        }
+        match run(machine) {
+            ControlFlow::Continue(()) | ControlFlow::Break(Stop::Halt) => {}
+            result => return result,
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+
+/// Collects the names given to cells in some source code (see [`names::collect`]), printing the
+/// warnings about them to `stderr`.
+fn collect_names(code: &[u8]) -> CellNames {
+    let (names, warnings) = names::collect(code);
+    for warning in warnings {
+        eprintln!("Warning: {}", warning)
+    }
+    names
+}
+
+/// Expands the macros of some source code (see `--macros`), exiting the process if it is invalid.
+///
+/// Bytes that are not valid UTF-8 can only be part of comments, so they are replaced.
+fn expand_macros(code: &[u8]) -> String {
+    macros::expand(&String::from_utf8_lossy(code)).unwrap_or_else(|error| {
+        eprintln!("error: unable to expand macros: {}", error);
+        process::exit(1)
+    })
+}
+
+
+/// Formats a line of timing information, as printed by `--time`.
+fn format_timing(description: impl Display, duration: Duration) -> String {
+    format!("{:16}\t{:>10.3} ms", description, duration.as_secs_f64() * 1000.0)
+}
+
+/// Calls `f`, and returns its result along with the time it took to run.
+///
+/// If `out` is passed, a line describing the measurement is also written to it.
+fn time<T, W: Write + ?Sized>(description: impl Display, out: Option<&mut W>, f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let result = f();
+    let duration = start.elapsed();
+    if let Some(out) = out {
+        writeln!(out, "{}", format_timing(description, duration)).expect("Unable to write timing information");
+    }
+    (result, duration)
+}
+
+
+fn main() {
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    if let Err(message) = args.validate(&matches) {
+        Args::command().error(ErrorKind::ArgumentConflict, message).exit()
+    }
+    if args.print_effective_config {
+        args::write_config(&matches, &mut io::stdout().lock()).expect("Unable to write configuration");
+        return;
+    }
+
+    if let Some(text) = &args.generate {
+        let bytes = if text == "-" {
+            let mut bytes = Vec::new();
+            io::Read::read_to_end(&mut io::stdin(), &mut bytes).expect("Unable to read text");
+            bytes
         } else {
-            None
+            generate::unescape(text).unwrap_or_else(|error| {
+                eprintln!("Unable to read text: {}", error);
+                process::exit(1)
+            })
+        };
+        let code = generate::generate(&bytes, args.golf);
+        println!("{}", code);
+        eprintln!("Generated program length: {}", code.len());
+        return;
+    }
+
+    if args.selftest {
+        if !selftest::run(&args, &mut io::stdout()).expect("Unable to write self-test results") {
+            process::exit(1)
         }
+        return;
     }
 
-    if commands.len() == 1 && commands[0].is_odd_increment() {
-        Command::Reset
-    } else if let Some(increments) = try_optimize_as_move(&commands) {
-        Command::Move(increments)
+    let path = args.file.as_deref().expect("A source file is required");
+    // Tests have step limits, so they may run downloaded programs.
+    if args.test {
+        let seed = args.seed.unwrap_or_else(Rng::entropy_seed);
+        if !testing::run(path, &args, seed, &mut io::stdout()).expect("Unable to write test results") {
+            process::exit(1)
+        }
+        return;
+    }
+    // Downloaded programs could run forever unless the number of steps is limited.
+    if source::is_url(path) && args.max_steps.is_none() && !args.allow_unlimited {
+        eprintln!("error: refusing to run a program downloaded from a URL without limits: pass `--max-steps` to limit it, or `--allow-unlimited` to run it anyway");
+        process::exit(1)
+    }
+    let source = read_source(path, args.mmap);
+    let code = source::blank_shebang(source.as_bytes());
+    let code = &*code;
+    let expanded_code;
+    let code = if args.macros {
+        expanded_code = expand_macros(code);
+        expanded_code.as_bytes()
     } else {
-        Command::Loop(commands)
+        code
+    };
+
+    if args.lint {
+        if !lint::run(path, code, &args, &mut io::stdout().lock()).expect("Unable to write diagnostics") {
+            process::exit(1)
+        }
+        return;
     }
-}
 
+    if let (Some(offset), Some(map_path)) = (args.where_output, &args.output_map) {
+        if !provenance::is_map_of(map_path, code).expect("Unable to read output map") {
+            eprintln!("Warning: the output map was written for another version of the program");
+        }
+        if !provenance::locate(map_path, offset, &mut io::stdout()).expect("Unable to read output map") {
+            eprintln!("The program output fewer than {} values", offset + 1);
+            process::exit(1)
+        }
+        return;
+    }
 
-/// Recursively optimizes chunk resets in the passed commands (including in nested loops).
-fn optimize_chunk_resets(commands: impl Iterator<Item=Command>) -> impl Iterator<Item=Command> {
-    #[derive(Clone, Copy, PartialEq, Eq)]
-    enum State {
-        OutsideChunk,
-        /// Associated value is the current offset from initial position.
-        ExpectReset(isize),
-        /// Associated value is the current offset from initial position.
-        ExpectRight(isize),
-    }
-    let mut optimized_commands = Vec::new();
-    let mut state = State::OutsideChunk;
-    for command in commands {
-        match (state, command) {
-            (State::OutsideChunk, Command::Reset) => {
-                state = State::ExpectRight(0)
-            }
-            (State::OutsideChunk, command) => {
-                optimized_commands.push(command)
-            }
-            (State::ExpectRight(0), Command::Right(direction)) if direction.abs() == 1 => {
-                state = State::ExpectReset(direction)
-            }
-            (State::ExpectRight(0), command) => {
-                // This is actually a special case of a later case, but this one returns `Reset`
-                // instead of `ResetChunk(0)`, which I guess is more optimized (though I have not
-                // ran any test)...
-                optimized_commands.push(Command::Reset);
-                state = State::OutsideChunk;
-                optimized_commands.push(command)
-            }
-            (State::ExpectReset(current_offset), Command::Reset) => {
-                state = State::ExpectRight(current_offset)
-            }
-            (State::ExpectReset(current_offset), command) => {
-                let extreme_cell_offset = current_offset - current_offset.signum();
-                optimized_commands.push(Command::ResetChunk(extreme_cell_offset));
-                optimized_commands.push(Command::Right(current_offset));
-                state = State::OutsideChunk;
-                optimized_commands.push(command)
-            }
-            (State::ExpectRight(current_offset), Command::Right(amount)) if amount == current_offset.signum() => {
-                state = State::ExpectReset(current_offset + amount)
-            }
-            (State::ExpectRight(current_offset), command) => {
-                optimized_commands.push(Command::ResetChunk(current_offset));
-                optimized_commands.push(Command::Right(current_offset));
-                state = State::OutsideChunk;
-                optimized_commands.push(command)
-            }
+    if args.tutor {
+        tutor::run(code, &mut io::stdout().lock()).expect("Unable to run program");
+        return;
+    }
+
+    let mut timing_output = args.time.then(|| Box::new(io::stderr()) as Box<dyn Write>);
+
+    let names = collect_names(code);
+    let (commands, load_duration) = time("Loading source", timing_output.as_deref_mut(), || load(code, &args.parse_options(), &names));
+    let commands = commands.unwrap_or_else(|error| parse_error(&error, path, &args));
+
+    // The analysis locates loops in the source code, so it needs the loops before optimization.
+    if args.analyze {
+        match analyze::analyze(&commands) {
+            Some(analysis) => analysis.write_report(code, &mut io::stdout()).expect("Unable to write analysis"),
+            None => eprintln!("Programs switching tapes cannot be analyzed"),
         }
+        return;
+    }
+
+    let ((optimized_commands, report), optimize_duration) = time("Optimizing", timing_output.as_deref_mut(), || optimize_within_budget(commands, &args.optimize_options()));
+    if args.verbose {
+        report_optimization(&report)
+    }
+
+    if let Some(path) = &args.emit_dot {
+        let mut out = BufWriter::new(File::create(path).expect("Unable to create DOT file"));
+        dot::write_dot(&optimized_commands, &mut out).expect("Unable to write DOT file");
+        out.flush().expect("Unable to write DOT file");
     }
-    match state {
-        State::ExpectReset(current_offset) => {
-            let extreme_cell_offset = current_offset - current_offset.signum();
-            optimized_commands.push(Command::ResetChunk(extreme_cell_offset));
-            optimized_commands.push(Command::Right(current_offset))
+
+    if let Some(path) = &args.emit_bf {
+        fs::write(path, bf::to_brainfuck(&optimized_commands, args.random_char) + "\n").expect("Unable to write Brainfuck file");
+    }
+
+    if let Some(path) = &args.emit_llvm {
+        fs::write(path, llvm::to_llvm(&optimized_commands)).expect("Unable to write LLVM IR file");
+    }
+
+    if let Some(path) = &args.emit_asm {
+        fs::write(path, asm::to_asm(&optimized_commands)).expect("Unable to write assembly file");
+    }
+
+    if let Some(path) = &args.emit_c {
+        fs::write(path, c::to_c(&optimized_commands, args.eof)).expect("Unable to write C file");
+    }
+
+    if args.emit_dot.is_some() || args.emit_bf.is_some() || args.emit_llvm.is_some() || args.emit_asm.is_some() || args.emit_c.is_some() {
+        return;
+    }
+
+    let seed = args.seed.unwrap_or_else(Rng::entropy_seed);
+    if args.verbose && (args.dialect == Dialect::Random || args.input_random.is_some()) {
+        eprintln!("Random seed: {}", seed);
+    }
+
+    if args.annotate {
+        let mut tape = Tape::new(&TapeOptions { silent: true, eof: args.eof, ..TapeOptions::default() });
+        if let Some(input) = synthetic_input(&args, seed).or_else(|| chained_input(&args)) {
+            tape.set_fallible_input(input)
+        }
+        let max_steps = args.max_steps.unwrap_or(u64::MAX);
+        let (result, steps) = annotate::run(code, &mut tape, args.hot_threshold, max_steps, &mut io::stdout().lock()).expect("Unable to write annotated source");
+        report_stop(result, steps, &tape, None, &args, "");
+        if let Some(exit_code) = exit_code(result) {
+            process::exit(exit_code)
+        }
+        return;
+    }
+
+    if let Some(other_path) = &args.diff {
+        let other_commands = load_file(other_path, &args);
+        let input = diff::input(&args, seed);
+        let programs = [(path, optimized_commands.as_slice()), (other_path.as_path(), other_commands.as_slice())];
+        if !diff::compare(programs, &input, &args, seed, &mut io::stdout().lock()).expect("Unable to write comparison") {
+            process::exit(1)
         }
-        State::ExpectRight(current_offset) => {
-            optimized_commands.push(Command::ResetChunk(current_offset));
-            optimized_commands.push(Command::Right(current_offset))
+        return;
+    }
+
+    if let Some(other_path) = &args.equiv {
+        let other_commands = load_file(other_path, &args);
+        let programs = [(path, optimized_commands.as_slice()), (other_path.as_path(), other_commands.as_slice())];
+        if !equiv::compare(programs, &args, seed, &mut io::stdout().lock()).expect("Unable to write comparison") {
+            process::exit(1)
         }
-        _ => {}
+        return;
+    }
+
+    let program = Program {
+        path,
+        code,
+        commands: optimized_commands,
+        names,
+        load_duration,
+        optimize_duration,
+    };
+    match args.cell_size {
+        CellSize::Eight => run::<u8>(&args, program, seed, timing_output),
+        CellSize::Sixteen => run::<u16>(&args, program, seed, timing_output),
+        CellSize::ThirtyTwo => run::<u32>(&args, program, seed, timing_output),
+        CellSize::Big => run::<BigInt>(&args, program, seed, timing_output),
     }
-    optimized_commands.into_iter()
 }
 
-/// Returns a vector of commands that is functionally equivalent to the passed commands.
-fn optimize(commands: Vec<Command>, args: &Args) -> Vec<Command> {
-    let commands_iter = commands.into_iter()
-        // Recursive call
-        .map(|command| match command {
-            Command::Loop(content) => Command::Loop(optimize(content, args)),
-            _ => command
-        })
-        // Optimize trivial loops
-        .map(|command| {
-            if args.optimize_loops {
-                match command {
-                    Command::Loop(content) => optimize_loop(content),
-                    _ => command
-                }
-            } else {
-                command
-            }
+
+/// The main program, loaded and optimized.
+#[derive(Debug)]
+struct Program<'a> {
+    path: &'a Path,
+    code: &'a [u8],
+    commands: Vec<Command>,
+    names: CellNames,
+    load_duration: Duration,
+    optimize_duration: Duration,
+}
+
+/// Runs the main program on cells of type `C` (see `--cell-size`), along with the programs it is
+/// piped to, and reports how it ran. The process exits with an error code if it did not end
+/// normally.
+///
+/// The commands of the program are leaked rather than freed, so this must be the last thing the
+/// process does.
+fn run<C: Cell>(args: &Args, program: Program<'_>, seed: u64, mut timing_output: Option<Box<dyn Write>>) {
+    let Program { path, code, commands: optimized_commands, names, load_duration, optimize_duration } = program;
+    if let Some(script) = &args.script {
+        let (input, program_input) = pipe::pipe();
+        let (program_output, output) = pipe::pipe();
+        thread::scope(|scope| {
+            let program = scope.spawn(|| {
+                let mut tape = Tape::<C>::with_cells(&TapeOptions { eof: args.eof, ..TapeOptions::default() });
+                tape.set_input(Box::new(program_input));
+                // The output is not buffered, so that prompts reach the script right away.
+                tape.set_raw_output(Box::new(program_output));
+                let mut machine = Machine::new(tape, &args.machine_options(seed));
+                execute_program(&optimized_commands, &mut machine, args)
+            });
+            let has_failed = || !matches!(program.join(), Ok(ControlFlow::Continue(()) | ControlFlow::Break(Stop::Halt)));
+            let is_success = script.run(input, output, has_failed, &mut io::stdout().lock()).expect("Unable to write script report");
+            // The program may still be waiting for input, in which case it is not worth waiting for.
+            process::exit(if is_success { 0 } else { 1 })
         })
-        .filter(Command::is_useful);
-    if args.optimize_chunk_resets {
-        optimize_chunk_resets(commands_iter).collect()
-    } else {
-        commands_iter.collect()
     }
-}
 
+    // Each piped program runs on its own thread, reading from the previous one through a pipe.
+    let mut main_output = None;
+    let mut piped_programs = Vec::new();
+    for (i, path) in args.pipe.iter().enumerate().rev() {
+        let (writer, reader) = pipe::pipe();
+        let output = main_output.replace(writer);
+        let (path, args) = (path.clone(), args.clone());
+        piped_programs.push(thread::Builder::new()
+            .name(format!("pipe {}", i + 1))
+            .spawn(move || run_piped::<C>(&path, &args, seed, reader, output))
+            .expect("Unable to start piped program"));
+    }
 
-fn execute(commands: &[Command], tape: &mut Tape) {
-    for command in commands {
-        match command {
-            Command::Right(amount) => {
-                tape.right_by(*amount)
-            }
-            Command::Add(amount) => {
-                tape.add(0, *amount)
-            }
-            Command::Loop(loop_commands) => {
-                while tape.read() != 0 {
-                    execute(loop_commands, tape)
-                }
-            }
-            Command::Input => {
-                tape.input()
-            }
-            Command::Output => {
-                tape.output()
-            }
-            &Command::Reset => {
-                tape.write(0)
+    let mut profile = args.profile_commands.then(Profile::default);
+    let mut portability = args.warn_portability.then(|| Portability::new(C::SIZE));
+    let ops = args.flatten_loops.then(|| bytecode::flatten(&optimized_commands));
+    // Input files are opened before the terminal is set up, so that a missing one is reported on
+    // it as it is.
+    let chained_input = chained_input(args);
+    let ((mut machine, result), run_duration) = time("Running", timing_output.as_deref_mut(), || {
+        let mut tape = Tape::<C>::with_cells(&args.tape_options());
+        if let Some(console) = console::stdout(args.console_charset) {
+            tape.set_output(Box::new(console))
+        }
+        if let Some(output) = main_output {
+            tape.set_raw_output(Box::new(BufWriter::new(output)))
+        }
+        if let Some(path) = &args.output {
+            tape.set_output(Box::new(BufWriter::new(File::create(path).expect("Unable to create output file"))))
+        }
+        if args.game {
+            game::enable();
+            match console::stdout(args.console_charset) {
+                Some(console) => tape.set_output(Box::new(game::Screen(console))),
+                None => tape.set_output(Box::new(game::Screen(io::stdout()))),
             }
-            &Command::ResetChunk(max_offset) => {
-                tape.fill(max_offset, 0)
+            tape.set_key_input()
+        }
+        if let Some(input) = synthetic_input(args, seed) {
+            tape.set_fallible_input(input)
+        }
+        let stream = match (&args.io_tcp, args.io_listen) {
+            (Some(address), _) => Some(tcp::connect(address)),
+            (_, Some(port)) => Some(tcp::accept(port)),
+            _ => None,
+        };
+        let is_connected = stream.is_some();
+        if let Some(stream) = stream {
+            let input = stream.and_then(|stream| Ok((tcp::bytes(&stream)?, stream)));
+            let (input, stream) = input.unwrap_or_else(|error| {
+                eprintln!("error: {}", error);
+                process::exit(1)
+            });
+            tape.set_fallible_input(input);
+            tape.set_raw_output(Box::new(BufWriter::new(tcp::Sender::new(stream))))
+        }
+        if let Some(input) = chained_input {
+            // The timeout only applies to `stdin`, if it is one of the sources.
+            tape.set_fallible_input(input)
+        } else if let Some(timeout) = args.input_timeout {
+            if let Err(error) = tape.set_input_timeout(timeout) {
+                eprintln!("Ignoring the input timeout, as input cannot be read on another thread: {}", error)
             }
-            Command::Move(cells) => {
-                let value = tape.read();
-                for &(cell_offset, multiplier) in cells {
-                    tape.add(cell_offset, value.wrapping_mul(multiplier))
-                }
-                tape.write(0)
+        }
+        if let Some(keymap) = &args.keymap {
+            tape.map_input(|input| keymap.apply(input))
+        }
+        if let Some(prompt) = &args.prompt {
+            if io::stdin().is_terminal() && !is_connected {
+                tape.set_prompt(prompt.clone(), Box::new(io::stderr()))
             }
-            Command::Debug => {
-                eprintln!("{}", tape)
+        }
+        if let Some(tape_seed) = args.seed_tape {
+            eprintln!("Tape seed: {}", tape_seed);
+            let mut rng = Rng::new(tape_seed);
+            let values: Vec<C> = args.seed_tape_cells.clone().map(|_| C::from_byte(rng.next_byte())).collect();
+            tape.write_slice(args.seed_tape_cells.start, &values);
+        }
+        if let Some(path) = &args.tape_init_json {
+            let state: TapeState<C> = serde_json::from_reader(File::open(path).expect("Unable to open tape file")).expect("Unable to read tape file");
+            tape.load_state(&state);
+        }
+        let watched: Vec<isize> = args.watch.iter().map(|cell| names.resolve(cell).unwrap_or_else(|error| {
+            eprintln!("error: --watch {}: {}", cell, error);
+            process::exit(1)
+        })).collect();
+        let mut machine = Machine::new(tape, &args.machine_options(seed));
+        machine.names = names;
+        let mut run = |machine: &mut Machine<'_, C>| {
+            let result = match (&mut profile, &mut portability) {
+                (Some(profile), _) => execute_profiled(&optimized_commands, machine, profile),
+                (_, Some(portability)) => portability::execute_checked(&optimized_commands, machine, portability),
+                _ if !args.protect.is_empty() => protect::execute_protected(&optimized_commands, machine, &args.protect),
+                _ if !watched.is_empty() => watch::execute_watched(&optimized_commands, machine, &watched),
+                _ => match &ops {
+                    Some(ops) => bytecode::execute(ops, machine),
+                    None => execute(&optimized_commands, machine),
+                },
+            };
+            if args.newline_at_exit && matches!(result, ControlFlow::Continue(()) | ControlFlow::Break(Stop::Halt)) {
+                machine.tape.finish_line().expect("Unable to write output")
             }
+            result
+        };
+        let result = if args.per_line {
+            run_per_line(&mut machine, args, run)
+        } else {
+            run(&mut machine)
+        };
+        let result = match (result, machine.tape.flush()) {
+            (ControlFlow::Continue(()), Err(error)) => ControlFlow::Break(Stop::OutputError(error.kind())),
+            (result, _) => result,
+        };
+        if args.game {
+            game::disable()
         }
+        (machine, result)
+    });
+
+    if let Some(profile) = &profile {
+        profile.write_table(&mut io::stderr()).expect("Unable to write profile");
     }
-}
 
+    if let Some(portability) = &portability {
+        portability.write_summary(&mut io::stderr()).expect("Unable to write portability summary");
+    }
 
-fn time<T>(description: impl Display, do_time: bool, f: impl FnOnce() -> T) -> T {
-    if do_time {
-        let start = SystemTime::now();
-        let result = f();
-        let end = SystemTime::now();
-        let duration = end.duration_since(start).unwrap();
-        eprintln!("{:16}\t{:>10.3} ms", description, duration.as_secs_f64() * 1000.0);
-        result
-    } else {
-        f()
+    report_stop(result, machine.steps, &machine.tape, machine.failed_assertion.as_deref(), args, "");
+
+    if let Some(path) = &args.tape_dump_json {
+        write_tape_json(&machine.tape, path).expect("Unable to write tape dump file");
     }
-}
 
+    if let (Some(path), Some(output_map)) = (&args.output_map, &machine.output_map) {
+        output_map.write(path, code).expect("Unable to write output map");
+    }
 
-fn main() {
-    let args = Args::parse();
+    if args.print_exit_cell {
+        // The value is on its own line, even if the output of the program does not end with one.
+        if args.output.is_none() {
+            machine.tape.finish_line().and_then(|_| machine.tape.flush()).expect("Unable to write output")
+        }
+        let mut stdout = io::stdout();
+        writeln!(stdout, "{}", machine.tape.read()).and_then(|_| stdout.flush()).expect("Unable to write exit cell");
+    }
+
+    if args.print_tape_hash {
+        eprintln!("Tape hash: {:016x}", timings::hash(&machine.tape.canonical_state()));
+    }
 
-    let code = fs::read_to_string(&args.file).expect("Unable to read source file");
+    if args.summary {
+        let (min_pointer, max_pointer) = machine.tape.pointer_extent();
+        let written_cells = match machine.tape.written_extent() {
+            Some((min_written, max_written)) => format!("{} to {}", min_written, max_written),
+            None => "none".to_string(),
+        };
+        eprintln!("Steps:           {}", machine.steps);
+        eprintln!("Allocated cells: {}", machine.tape.allocated_cells());
+        eprintln!("Pointer range:   {} to {}", min_pointer, max_pointer);
+        eprintln!("Written cells:   {}", written_cells);
+    }
 
-    let commands = time("Loading source", args.time, || load(&mut code.chars(), None, args.debug));
+    if let Some(timings_path) = &args.time_output {
+        let optimizations = [
+            (args.optimize_loops, "loops"),
+            (args.optimize_chunk_resets, "chunk-resets"),
+            (args.coalesce_moves, "coalesce-moves"),
+            (args.fuse_commands, "fuse-commands"),
+        ];
+        let record = timings::RunRecord {
+            program: path,
+            program_hash: timings::hash(code),
+            optimizations: optimizations.iter().filter(|(enabled, _)| *enabled).map(|&(_, name)| name).collect(),
+            load: load_duration,
+            optimize: optimize_duration,
+            run: run_duration,
+            steps: machine.steps,
+            peak_cells: machine.tape.allocated_cells(),
+        };
+        timings::append_to_csv(timings_path, &record).expect("Unable to write timings file");
+    }
 
-    let optimized_commands = time("Optimizing", args.time, || optimize(commands, &args));
+    // The process is about to exit, so freeing each loop of a large program would only take time:
+    // 2 million loops (`[.>[.<]]` repeated a million times) take about 0.2 s to free, a tenth of
+    // the whole run. The flattened program is a single allocation, so it is freed.
+    drop(ops);
+    mem::forget(optimized_commands);
 
-    time("Running", args.time, || {
-        let mut tape = Tape::new(args.hex_output, args.no_output);
-        execute(&optimized_commands, &mut tape)
-    });
+    // Dropping the machine closes the input of the first piped program, so that all of them can end.
+    drop(machine);
+    // The programs of the pipeline are joined in order, so that the exit code is that of the first
+    // one that did not end normally, as programs after it may only have failed because of it.
+    let mut exit_code = exit_code(result);
+    for piped_program in piped_programs.into_iter().rev() {
+        match piped_program.join() {
+            Ok(result) => exit_code = exit_code.or(self::exit_code(result)),
+            Err(_) => process::exit(101),
+        }
+    }
+    if let Some(exit_code) = exit_code {
+        process::exit(exit_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timings_are_aligned() {
+        assert_eq!(format_timing("Loading source", Duration::from_micros(1_500)), "Loading source  \t     1.500 ms");
+        assert_eq!(format_timing("Running", Duration::from_secs(12)), "Running         \t 12000.000 ms");
+        // Longer descriptions push the duration to the right rather than being cut.
+        assert_eq!(format_timing("Optimizing the program", Duration::ZERO), "Optimizing the program\t     0.000 ms")
+    }
+
+    #[test]
+    fn timing_writes_a_line_only_if_asked_to() {
+        let mut out = Vec::new();
+        let ((), duration) = time("Sleeping", Some(&mut out), || thread::sleep(Duration::from_millis(10)));
+        assert!(duration >= Duration::from_millis(10), "{:?}", duration);
+        assert_eq!(String::from_utf8(out).unwrap(), format_timing("Sleeping", duration) + "\n");
+        let (result, _) = time("Adding", None::<&mut Vec<u8>>, || 1 + 1);
+        assert_eq!(result, 2)
+    }
 }
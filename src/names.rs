@@ -0,0 +1,130 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{annotation, source};
+
+/// Names given to cells with `{name INDEX NAME}` annotations in the source code.
+///
+/// An index starting with `+` or `-` is relative to the position of the pointer when the annotation
+/// is first reached, which is only known if no loop moving the pointer ended before.
+#[derive(Debug, Clone, Default)]
+pub struct CellNames {
+    by_index: BTreeMap<isize, String>,
+    by_name: HashMap<String, isize>,
+}
+
+impl CellNames {
+    /// Returns the name of the cell at some index, if it has one.
+    pub fn name(&self, index: isize) -> Option<&str> {
+        self.by_index.get(&index).map(String::as_str)
+    }
+
+    /// Returns the index of the cell with some name, if there is one.
+    pub fn index(&self, name: &str) -> Option<isize> {
+        self.by_name.get(name).copied()
+    }
+
+    /// Returns the index of a cell written as its name or as its index, as in assertions.
+    pub fn resolve(&self, text: &str) -> Result<isize, String> {
+        match self.index(text) {
+            Some(index) => Ok(index),
+            None => text.parse().map_err(|_| format!("invalid cell index or unknown name `{}`", text)),
+        }
+    }
+
+    /// Describes the cell at some index along with its name, if it has one, as `cell 3 (`counter`)`.
+    pub fn describe(&self, index: isize) -> String {
+        match self.name(index) {
+            Some(name) => format!("cell {} (`{}`)", index, name),
+            None => format!("cell {}", index),
+        }
+    }
+
+    /// Gives a name to a cell, replacing its previous name and the cell that previously had this
+    /// name, if any. A warning is added to `warnings` in both cases.
+    fn insert(&mut self, index: isize, name: &str, location: (usize, usize), warnings: &mut Vec<String>) {
+        let (line, column) = location;
+        if let Some(previous) = self.by_name.insert(name.to_string(), index).filter(|&previous| previous != index) {
+            warnings.push(format!("the name `{}` at {}:{} moves from cell {} to cell {}", name, line, column, previous, index));
+            self.by_index.remove(&previous);
+        }
+        if let Some(previous) = self.by_index.insert(index, name.to_string()).filter(|previous| previous != name) {
+            warnings.push(format!("cell {} is renamed from `{}` to `{}` at {}:{}", index, previous, name, line, column));
+            self.by_name.remove(&previous);
+        }
+    }
+}
+
+/// Tests whether a name can be given to a cell, which is the case if it is made of ASCII letters,
+/// digits, and underscores, does not start with a digit, and is not `ptr` (see `--assertions`).
+fn is_valid(name: &str) -> bool {
+    name.starts_with(|char: char| char.is_ascii_alphabetic() || char == '_')
+        && name.chars().all(|char| char.is_ascii_alphanumeric() || char == '_')
+        && name != "ptr"
+}
+
+/// Parses what follows `name` in an annotation, and returns the index of the cell, which is
+/// relative if the second value is `true`, along with its name.
+fn parse(text: &str) -> Result<(isize, bool, &str), String> {
+    let [index, name] = text.split_whitespace().collect::<Vec<_>>()[..] else {
+        return Err(format!("expected an index and a name in `{}`", text.trim()));
+    };
+    let is_relative = index.starts_with(['+', '-']);
+    let index = index.parse().map_err(|_| format!("invalid cell index `{}`", index))?;
+    if !is_valid(name) {
+        return Err(format!("invalid name `{}`", name));
+    }
+    Ok((index, is_relative, name))
+}
+
+/// Collects the names given to cells in some source code, along with warnings for the annotations
+/// that cannot be used and for the names that are given again, which are left to the caller to
+/// print.
+///
+/// Names are only metadata: they are collected separately from [`load`](crate::load), so that they
+/// do not depend on how the program is optimized.
+pub fn collect(code: &[u8]) -> (CellNames, Vec<String>) {
+    let mut names = CellNames::default();
+    let mut warnings = Vec::new();
+    // The position of the pointer, if it is known, and its position at the start of each loop the
+    // code is in.
+    let mut pointer = Some(0);
+    let mut loops = Vec::new();
+    let mut position = 0;
+    while let Some(&byte) = code.get(position) {
+        position += 1;
+        match byte {
+            b'>' => pointer = pointer.map(|pointer| pointer + 1),
+            b'<' => pointer = pointer.map(|pointer| pointer - 1),
+            b'[' => loops.push(pointer),
+            b']' => {
+                let start = loops.pop();
+                if start.is_some_and(|start| start != pointer) {
+                    pointer = None
+                }
+            }
+            b'{' => {
+                let Some(keyword) = annotation(&code[position..]) else {
+                    continue;
+                };
+                // Annotations are always closed (see `annotation`).
+                let length = code[position..].iter().position(|&byte| byte == b'}').unwrap_or(code.len() - position);
+                let location = source::line_column(code, position - 1);
+                let text = String::from_utf8_lossy(&code[position + keyword.len()..position + length]);
+                position += length + 1;
+                if keyword != b"name" {
+                    continue;
+                }
+                match parse(&text) {
+                    Ok((index, false, name)) => names.insert(index, name, location, &mut warnings),
+                    Ok((offset, true, name)) => match pointer {
+                        Some(pointer) => names.insert(pointer + offset, name, location, &mut warnings),
+                        None => warnings.push(format!("ignoring the name `{}` at {}:{}, as the position of the pointer is not known there", name, location.0, location.1)),
+                    },
+                    Err(error) => warnings.push(format!("ignoring the name annotation at {}:{}: {}", location.0, location.1, error)),
+                }
+            }
+            _ => {}
+        }
+    }
+    (names, warnings)
+}
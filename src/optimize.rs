@@ -0,0 +1,912 @@
+use std::collections::HashMap;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use crate::{CellSize, Command, MoveSpan, Multiplication};
+
+/// The options telling which optimizations [`optimize`] runs, and what it may not change.
+///
+/// Some optimizations change what the program leaves on the tape (such as by not resetting cells
+/// that are never read again), so they are disabled when the tape is observed.
+#[derive(Debug, Clone)]
+pub struct OptimizeOptions {
+    /// Whether to optimize loops (such as resets and moves).
+    pub optimize_loops: bool,
+    /// Whether to optimize resets of adjacent cells as chunk resets.
+    pub optimize_chunk_resets: bool,
+    /// Whether to remove the commands that have no effect given the values the cells are known to
+    /// have.
+    pub propagate_constants: bool,
+    /// Whether to remove the commands at the end of the program that have no observable effect.
+    pub remove_unobservable_tail: bool,
+    /// Whether to add the value of a cell to runs of adjacent cells in a single pass.
+    pub coalesce_moves: bool,
+    /// Whether to fuse pairs of commands that often appear together into single commands.
+    pub fuse_commands: bool,
+    /// If set, optimization stops once this duration has elapsed, leaving the remaining loops as
+    /// they are.
+    pub time_budget: Option<Duration>,
+    /// Whether the cells may initially hold values other than 0.
+    pub is_tape_initialized: bool,
+    /// Whether which cells the program writes to is observed (such as in tape dumps).
+    pub is_written_extent_observed: bool,
+    /// Whether each write is observed, even of a value a cell already holds (such as when cells are
+    /// protected).
+    pub are_writes_observed: bool,
+    /// Whether the tape is observed once the program ends.
+    pub is_final_tape_observed: bool,
+    /// The size of the cells the program runs on. Loops are only optimized as resets, moves, and
+    /// multiplications if the cells wrap around, as a loop decrementing a cell that cannot wrap
+    /// around never ends if the cell is negative.
+    pub cell_size: CellSize,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        Self {
+            optimize_loops: true,
+            optimize_chunk_resets: false,
+            propagate_constants: true,
+            remove_unobservable_tail: true,
+            coalesce_moves: true,
+            fuse_commands: true,
+            time_budget: None,
+            is_tape_initialized: false,
+            is_written_extent_observed: false,
+            are_writes_observed: false,
+            is_final_tape_observed: false,
+            cell_size: CellSize::Eight,
+        }
+    }
+}
+
+
+/// Rebuilds commands from their innermost loops outwards, using a stack rather than recursing so
+/// that deeply nested loops cannot overflow the call stack.
+///
+/// The body of each loop is rebuilt first if `enter` returns `true` when the loop is reached, and
+/// `rebuild_loop` then returns the command replacing the loop given its rebuilt body. Other loops
+/// are kept as they are. Each sequence of commands (the top-level one, or the body of a loop) is
+/// replaced with what `rebuild_sequence` returns for it once its loops have been rebuilt.
+fn rebuild_loops(commands: Vec<Command>, mut enter: impl FnMut() -> bool, mut rebuild_sequence: impl FnMut(Vec<Command>) -> Vec<Command>, mut rebuild_loop: impl FnMut(Vec<Command>) -> Command) -> Vec<Command> {
+    // The commands left to rebuild in each sequence the current command is in, along with the
+    // commands of this sequence rebuilt so far.
+    let mut stack = vec![(commands.into_iter(), Vec::new())];
+    loop {
+        let (commands, rebuilt) = stack.last_mut().unwrap();
+        match commands.next() {
+            Some(mut command) => match &mut command {
+                Command::Loop(body) if enter() => {
+                    // Commands cannot be moved out of, as they drop their loops iteratively.
+                    stack.push((std::mem::take(body).into_vec().into_iter(), Vec::new()))
+                }
+                _ => {
+                    rebuilt.push(command)
+                }
+            },
+            None => {
+                let (_, rebuilt) = stack.pop().unwrap();
+                let rebuilt = rebuild_sequence(rebuilt);
+                match stack.last_mut() {
+                    Some((_, parent)) => parent.push(rebuild_loop(rebuilt)),
+                    None => return rebuilt,
+                }
+            }
+        }
+    }
+}
+
+
+/// Returns a command that is functionally equivalent to a loop containing the passed commands, on
+/// cells of the passed size.
+fn optimize_loop(commands: Vec<Command>, cell_size: CellSize) -> Command {
+    /// Tries to optimize a loop with the passed body as a move.
+    ///
+    /// If possible, returns [`Some(result)`], where `result` is a vector that can be used to
+    /// construct [`Command::Move`]. Otherwise, returns [`None`].
+    fn try_optimize_as_move(commands: &Vec<Command>, cell_size: CellSize) -> Option<Vec<(isize, u64)>> {
+        let mut is_origin_decremented = false;
+        // Note that, if a cell is incremented multiple times, at different places within the loop,
+        // this will result in the vector containing multiple entries for this cell. Using a HashMap
+        // to solve this "problem" results in much higher optimization times, though. So Vec it is.
+        let mut increments = Vec::new();
+        let mut offset = 0;
+        for command in commands {
+            match command {
+                &Command::Add(amount) if amount == cell_size.minus_one() && offset == 0 => {
+                    if is_origin_decremented {
+                        return None;
+                    } else {
+                        is_origin_decremented = true
+                    }
+                }
+                Command::Add(_) if offset == 0 => {
+                    // Other changes to the current cell change how many times the loop repeats.
+                    return None;
+                }
+                Command::Add(amount) => {
+                    increments.push((offset, *amount))
+                }
+                Command::Right(amount) => {
+                    offset += amount
+                }
+                _ => {
+                    return None;
+                }
+            }
+        }
+        if is_origin_decremented && offset == 0 {
+            Some(increments)
+        } else {
+            None
+        }
+    }
+
+    /// Tries to optimize a loop with the passed body (whose own loops have already been optimized)
+    /// as a multiplication.
+    fn try_optimize_as_multiplication(commands: &[Command], cell_size: CellSize) -> Option<Multiplication> {
+        let mut is_origin_decremented = false;
+        let mut moves = Vec::new();
+        let mut offset = 0;
+        for command in commands {
+            match command {
+                &Command::Add(amount) if amount == cell_size.minus_one() && offset == 0 && !is_origin_decremented => {
+                    is_origin_decremented = true
+                }
+                Command::Right(amount) => {
+                    offset += amount
+                }
+                Command::Move(cells) => {
+                    moves.push((offset, cells))
+                }
+                _ => {
+                    return None;
+                }
+            }
+        }
+        let &[(operand, operand_move), (temporary, temporary_move)] = moves.as_slice() else {
+            return None;
+        };
+        if !is_origin_decremented || offset != 0 || operand == 0 || temporary == 0 || operand == temporary {
+            return None;
+        }
+        // Increments of the origin of a move can be ignored, since it is reset anyway.
+        let temporary_targets: Vec<_> = temporary_move.iter().filter(|&&(cell_offset, _)| cell_offset != 0).collect();
+        if temporary_targets != [&(operand - temporary, 1)] {
+            return None;
+        }
+        // Sum the multipliers of each cell the operand is moved to.
+        let mut targets: Vec<(isize, u64)> = Vec::new();
+        for &(cell_offset, multiplier) in operand_move.iter().filter(|&&(cell_offset, _)| cell_offset != 0) {
+            match targets.iter_mut().find(|(target, _)| *target == operand + cell_offset) {
+                Some((_, total)) => *total = cell_size.wrap(total.wrapping_add(multiplier)),
+                None => targets.push((operand + cell_offset, multiplier)),
+            }
+        }
+        let temporary_index = targets.iter().position(|&(target, _)| target == temporary)?;
+        if targets.remove(temporary_index).1 != 1 || targets.iter().any(|&(target, multiplier)| target == 0 && multiplier != 0) {
+            return None;
+        }
+        targets.retain(|&(_, multiplier)| multiplier != 0);
+        Some(Multiplication {
+            operand,
+            temporary,
+            targets: targets.into_boxed_slice(),
+        })
+    }
+
+    // Loops are recognized from the commands that are steps, so positions are set aside, and put
+    // back if the loop stays a loop.
+    let (commands, positions) = take_positions(commands);
+    if let [Command::Right(stride)] = commands[..] {
+        Command::Scan(stride)
+    } else if !cell_size.wraps() {
+        Command::Loop(put_back_positions(commands, positions).into_boxed_slice())
+    } else if commands.len() == 1 && commands[0].is_odd_increment() {
+        Command::Reset
+    } else if let Some(increments) = try_optimize_as_move(&commands, cell_size) {
+        Command::Move(increments.into_boxed_slice())
+    } else if let Some(multiplication) = try_optimize_as_multiplication(&commands, cell_size) {
+        Command::Mul(Box::new(multiplication))
+    } else {
+        Command::Loop(put_back_positions(commands, positions).into_boxed_slice())
+    }
+}
+
+/// Removes the positions from commands (see [`Command::Position`]), and returns the other
+/// commands, along with the positions and the number of other commands before each of them.
+fn take_positions(commands: Vec<Command>) -> (Vec<Command>, Vec<(usize, Command)>) {
+    if !commands.iter().any(|command| matches!(command, Command::Position(_, _))) {
+        return (commands, Vec::new());
+    }
+    let mut others = Vec::with_capacity(commands.len());
+    let mut positions = Vec::new();
+    for command in commands {
+        match command {
+            Command::Position(_, _) => positions.push((others.len(), command)),
+            _ => others.push(command),
+        }
+    }
+    (others, positions)
+}
+
+/// Puts back the positions removed from commands by [`take_positions`].
+fn put_back_positions(commands: Vec<Command>, positions: Vec<(usize, Command)>) -> Vec<Command> {
+    if positions.is_empty() {
+        return commands;
+    }
+    let mut merged = Vec::with_capacity(commands.len() + positions.len());
+    let mut positions = positions.into_iter().peekable();
+    for (i, command) in commands.into_iter().enumerate() {
+        while let Some((_, position)) = positions.next_if(|&(index, _)| index == i) {
+            merged.push(position)
+        }
+        merged.push(command)
+    }
+    merged.extend(positions.map(|(_, position)| position));
+    merged
+}
+
+/// Removes the positions that are not followed by a command (see [`Command::Position`]), such as
+/// those of removed commands, so that a loop is only empty if it would be without positions.
+fn drop_unused_positions(commands: impl Iterator<Item=Command>) -> impl Iterator<Item=Command> {
+    let mut used_commands = Vec::new();
+    let mut position = None;
+    for command in commands {
+        match command {
+            Command::Position(_, _) => position = Some(command),
+            _ => used_commands.extend(position.take().into_iter().chain([command])),
+        }
+    }
+    used_commands.into_iter()
+}
+
+
+/// Recursively optimizes chunk resets in the passed commands (including in nested loops).
+fn optimize_chunk_resets(commands: impl Iterator<Item=Command>) -> impl Iterator<Item=Command> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum State {
+        OutsideChunk,
+        /// Associated value is the current offset from initial position.
+        ExpectReset(isize),
+        /// Associated value is the current offset from initial position.
+        ExpectRight(isize),
+    }
+    let mut optimized_commands = Vec::new();
+    let mut state = State::OutsideChunk;
+    // The last position in a chunk, which is that of the command after it once it ends (see
+    // `Command::Position`).
+    let mut position = None;
+    for command in commands {
+        match (state, command) {
+            (State::ExpectReset(_) | State::ExpectRight(_), command @ Command::Position(_, _)) => {
+                position = Some(command)
+            }
+            (State::OutsideChunk, Command::Reset) => {
+                state = State::ExpectRight(0)
+            }
+            (State::OutsideChunk, command) => {
+                optimized_commands.push(command)
+            }
+            (State::ExpectRight(0), Command::Right(direction)) if direction.abs() == 1 => {
+                state = State::ExpectReset(direction)
+            }
+            (State::ExpectRight(0), command) => {
+                // This is actually a special case of a later case, but this one returns `Reset`
+                // instead of `ResetChunk(0)`, which I guess is more optimized (though I have not
+                // ran any test)...
+                optimized_commands.push(Command::Reset);
+                state = State::OutsideChunk;
+                optimized_commands.extend(position.take());
+                optimized_commands.push(command)
+            }
+            (State::ExpectReset(current_offset), Command::Reset) => {
+                state = State::ExpectRight(current_offset)
+            }
+            (State::ExpectReset(current_offset), command) => {
+                let extreme_cell_offset = current_offset - current_offset.signum();
+                optimized_commands.push(Command::ResetChunk(extreme_cell_offset));
+                optimized_commands.push(Command::Right(current_offset));
+                state = State::OutsideChunk;
+                optimized_commands.extend(position.take());
+                optimized_commands.push(command)
+            }
+            (State::ExpectRight(current_offset), Command::Right(amount)) if amount == current_offset.signum() => {
+                state = State::ExpectReset(current_offset + amount)
+            }
+            (State::ExpectRight(current_offset), command) => {
+                optimized_commands.push(Command::ResetChunk(current_offset));
+                optimized_commands.push(Command::Right(current_offset));
+                state = State::OutsideChunk;
+                optimized_commands.extend(position.take());
+                optimized_commands.push(command)
+            }
+        }
+    }
+    match state {
+        State::ExpectReset(current_offset) => {
+            let extreme_cell_offset = current_offset - current_offset.signum();
+            optimized_commands.push(Command::ResetChunk(extreme_cell_offset));
+            optimized_commands.push(Command::Right(current_offset))
+        }
+        State::ExpectRight(current_offset) => {
+            optimized_commands.push(Command::ResetChunk(current_offset));
+            optimized_commands.push(Command::Right(current_offset))
+        }
+        _ => {}
+    }
+    optimized_commands.into_iter()
+}
+
+/// The values of the cells known while propagating constants (see [`propagate_constants`]), indexed
+/// relatively to the initial position of the pointer.
+///
+/// Values are stored like the amounts of [`Command::Add`] (see [`CellSize::wrap`]).
+struct KnownCells {
+    pointer: isize,
+    /// The values of the cells that differ from `default`, where [`None`] means unknown.
+    values: HashMap<isize, Option<u64>>,
+    /// The value of the other cells, if known.
+    default: Option<u64>,
+    /// The size of the cells.
+    cell_size: CellSize,
+}
+
+impl KnownCells {
+    fn get(&self, offset: isize) -> Option<u64> {
+        self.values.get(&(self.pointer + offset)).copied().unwrap_or(self.default)
+    }
+
+    fn set(&mut self, offset: isize, value: Option<u64>) {
+        self.values.insert(self.pointer + offset, value);
+    }
+
+    /// Adds a amount to a cell, where [`None`] means an unknown amount.
+    ///
+    /// Cells that do not wrap around become unknown if their value no longer fits in an `i64`.
+    fn add(&mut self, offset: isize, amount: Option<u64>) {
+        let value = self.get(offset).zip(amount).and_then(|(value, amount)| match self.cell_size.wraps() {
+            true => Some(self.cell_size.wrap(value.wrapping_add(amount))),
+            false => (value as i64).checked_add(amount as i64).map(|value| value as u64),
+        });
+        self.set(offset, value)
+    }
+
+    /// Forgets the values of all cells.
+    fn forget(&mut self) {
+        self.values.clear();
+        self.default = None
+    }
+}
+
+/// Pushes the offsets (relative to the current cell) of the cells the passed commands may write to,
+/// and returns how much they move the pointer, or [`None`] if this depends on the values of the
+/// cells (or if they switch tapes).
+fn written_offsets(commands: &[Command], written: &mut Vec<isize>) -> Option<isize> {
+    // The commands left in each loop the current command is in, along with the offset of the pointer
+    // so far. Loops are tracked on a stack rather than by recursing, like in `rebuild_loops`.
+    let mut stack = vec![(commands.iter(), 0)];
+    loop {
+        let (commands, offset) = stack.last_mut().unwrap();
+        let Some(command) = commands.next() else {
+            let (_, loop_offset) = stack.pop().unwrap();
+            match stack.last() {
+                // Cells written by a loop that moves the pointer depend on the number of repetitions.
+                Some(&(_, offset)) if offset != loop_offset => return None,
+                Some(_) => continue,
+                None => return Some(loop_offset),
+            }
+        };
+        let offset = *offset;
+        match command {
+            &Command::Right(amount) => {
+                stack.last_mut().unwrap().1 += amount
+            }
+            &Command::AddRight(_, amount) => {
+                written.push(offset);
+                stack.last_mut().unwrap().1 += amount
+            }
+            Command::Add(_) | Command::Input | Command::Reset | Command::Restore | Command::Random | Command::ExchangeCells => {
+                written.push(offset)
+            }
+            &Command::ResetChunk(max_offset) => {
+                written.extend((max_offset.min(0)..=max_offset.max(0)).map(|cell_offset| offset + cell_offset))
+            }
+            Command::Move(cells) => {
+                written.push(offset);
+                written.extend(cells.iter().map(|&(cell_offset, _)| offset + cell_offset))
+            }
+            Command::MoveSpan(span) => {
+                written.push(offset);
+                written.extend((0..span.length as isize).map(|i| offset + span.start + i));
+                written.extend(span.rest.iter().map(|&(cell_offset, _)| offset + cell_offset))
+            }
+            Command::Mul(multiplication) => {
+                written.extend([0, multiplication.operand, multiplication.temporary].map(|cell_offset| offset + cell_offset));
+                written.extend(multiplication.targets.iter().map(|&(cell_offset, _)| offset + cell_offset))
+            }
+            Command::Loop(loop_commands) => {
+                stack.push((loop_commands.iter(), offset))
+            }
+            // The pointer moves by a distance that depends on the values of the cells.
+            Command::SwitchTape | Command::Scan(_) => {
+                return None;
+            }
+            Command::Output | Command::TracedOutput(_) | Command::DumpTape | Command::Halt | Command::Store | Command::Assert(_) | Command::Position(_, _) => {}
+        }
+    }
+}
+
+/// Removes the commands that have no effect given what is known about the values of the cells, and
+/// returns the number of commands removed.
+///
+/// All cells are 0 when the program starts, and the values of the cells are tracked through the
+/// top-level commands. Loops entered on a cell known to be 0 are removed, along with resets, moves,
+/// and multiplications of such cells. After a loop, the current cell is known to be 0, and only the
+/// cells the loop may write to are forgotten, unless it may move the pointer, in which case all
+/// cells are.
+fn propagate_constants(commands: &mut Vec<Command>, cell_size: CellSize) -> usize {
+    let mut known = KnownCells { pointer: 0, values: HashMap::new(), default: Some(0), cell_size };
+    let length = commands.len();
+    commands.retain(|command| {
+        let is_zero = known.get(0) == Some(0);
+        match command {
+            Command::Loop(_) | Command::Reset | Command::Move(_) | Command::MoveSpan(_) | Command::Mul(_) | Command::Scan(_) if is_zero => {
+                return false;
+            }
+            &Command::Right(amount) => {
+                known.pointer += amount
+            }
+            &Command::Add(amount) => {
+                known.add(0, Some(amount))
+            }
+            &Command::AddRight(amount, offset) => {
+                known.add(0, Some(amount));
+                known.pointer += offset
+            }
+            Command::Reset => {
+                known.set(0, Some(0))
+            }
+            &Command::ResetChunk(max_offset) => {
+                for offset in max_offset.min(0)..=max_offset.max(0) {
+                    known.set(offset, Some(0))
+                }
+            }
+            Command::Move(cells) => {
+                let value = known.get(0);
+                for &(offset, multiplier) in cells.iter() {
+                    known.add(offset, value.map(|value| value.wrapping_mul(multiplier)))
+                }
+                known.set(0, Some(0))
+            }
+            Command::Input | Command::Restore | Command::Random | Command::ExchangeCells => {
+                known.set(0, None)
+            }
+            Command::Loop(_) | Command::MoveSpan(_) | Command::Mul(_) | Command::Scan(_) => {
+                let mut written = Vec::new();
+                match written_offsets(std::slice::from_ref(command), &mut written) {
+                    Some(_) => {
+                        for offset in written {
+                            known.set(offset, None)
+                        }
+                    }
+                    None => known.forget(),
+                }
+                known.set(0, Some(0))
+            }
+            // The cells of the other tape are not tracked.
+            Command::SwitchTape => {
+                known.forget()
+            }
+            // Assertions only read cells, whose values are not changed by the removed commands.
+            Command::Output | Command::TracedOutput(_) | Command::DumpTape | Command::Halt | Command::Store | Command::Assert(_) | Command::Position(_, _) => {}
+        }
+        true
+    });
+    length - commands.len()
+}
+
+/// Recursively replaces the loops whose body is a single command leaving the current cell at 0 with
+/// this command, and returns the number of loops replaced.
+///
+/// Such a loop never repeats, so it is only a wrapper around its body, as in `[[->+<]]`, which is
+/// common in generated code. The body is then executed even when the cell is 0, which only changes
+/// something for resets and moves of the current cell: they then write to cells without changing
+/// them, so they are only unwrapped if `are_writes_observed` is `false`. Chunk resets are never
+/// unwrapped, as they would zero the other cells of their chunk.
+fn unwrap_nested_loops(commands: &mut Vec<Command>, are_writes_observed: bool) -> usize {
+    let mut unwrapped = 0;
+    *commands = rebuild_loops(mem::take(commands), || true, |commands| commands, |body| {
+        let mut command = Command::Loop(body.into_boxed_slice());
+        while let Command::Loop(body) = &mut command {
+            // A loop containing a single command also contains its position, if the program was
+            // loaded with positions.
+            let mut executed = body.iter().enumerate().filter(|(_, command)| !matches!(command, Command::Position(_, _)));
+            let (index, is_wrapper) = match (executed.next(), executed.next()) {
+                (Some((index, Command::Loop(_) | Command::Mul(_) | Command::Scan(_))), None) => (index, true),
+                (Some((index, Command::Reset | Command::Move(_))), None) => (index, !are_writes_observed),
+                _ => (0, false),
+            };
+            if !is_wrapper {
+                break;
+            }
+            command = mem::replace(&mut body[index], Command::Halt);
+            unwrapped += 1
+        }
+        command
+    });
+    unwrapped
+}
+
+/// Removes the commands at the end of the program that have no observable effect, such as cleaning
+/// up the tape, and returns the number of commands removed.
+///
+/// Loops are kept, along with everything before them: one that never ends would make the program
+/// hang, which is observable.
+fn remove_unobservable_tail(commands: &mut Vec<Command>) -> usize {
+    let mut removed = 0;
+    while let Some(command) = commands.last() {
+        if matches!(command, Command::Loop(_) | Command::Input | Command::Output | Command::TracedOutput(_) | Command::DumpTape | Command::Assert(_)) {
+            break;
+        }
+        if let Some(Command::Position(_, _)) = commands.pop() {
+            continue;
+        }
+        removed += 1
+    }
+    removed
+}
+
+/// Recursively fuses pairs of commands that are very often executed one after the other into
+/// single commands, which saves the cost of dispatching the second one.
+///
+/// This must be done after all other optimizations, as they do not know about fused commands.
+fn fuse_commands(commands: Vec<Command>) -> Vec<Command> {
+    rebuild_loops(commands, || true, |commands| {
+        let mut fused_commands = Vec::new();
+        for command in commands {
+            match (fused_commands.as_slice(), command) {
+                // The position of the move is dropped, as the fused command is at that of the addition.
+                ([.., Command::Add(amount)] | [.., Command::Add(amount), Command::Position(_, _)], Command::Right(offset)) => {
+                    let amount = *amount;
+                    let index = fused_commands.iter().rposition(|command| matches!(command, Command::Add(_))).unwrap();
+                    fused_commands.truncate(index);
+                    fused_commands.push(Command::AddRight(amount, offset))
+                }
+                (_, command) => {
+                    fused_commands.push(command)
+                }
+            }
+        }
+        fused_commands
+    }, |body| Command::Loop(body.into_boxed_slice()))
+}
+
+/// The minimum number of adjacent cells a move must add the current cell to for [`coalesce_moves`]
+/// to turn it into a [`Command::MoveSpan`]. Shorter runs are faster to add to one cell at a time.
+const MIN_MOVE_SPAN_LENGTH: usize = 8;
+
+/// Recursively turns the moves whose targets include enough adjacent cells with a multiplier of 1
+/// into [`Command::MoveSpan`] commands, keeping the other targets as they are.
+///
+/// This must be done after all other optimizations, as they do not know about move spans.
+fn coalesce_moves(commands: Vec<Command>) -> Vec<Command> {
+    let coalesce_move = |command: Command| {
+        // Commands cannot be moved out of, as they drop their loops iteratively.
+        let Command::Move(cells) = &command else {
+            return command;
+        };
+        let mut offsets: Vec<isize> = cells.iter().filter(|&&(_, multiplier)| multiplier == 1).map(|&(offset, _)| offset).collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        // Find the longest run of adjacent offsets.
+        let (mut start, mut length) = (0, 0);
+        for run in offsets.chunk_by(|a, b| a + 1 == *b) {
+            if run.len() > length {
+                (start, length) = (run[0], run.len())
+            }
+        }
+        if length < MIN_MOVE_SPAN_LENGTH {
+            return command;
+        }
+        // A cell of the run may be targeted more than once, in which case the other targets
+        // are kept separately.
+        let mut is_covered = vec![false; length];
+        let rest = cells.iter().copied().filter(|&(offset, multiplier)| {
+            let i = offset.wrapping_sub(start) as usize;
+            let is_in_run = multiplier == 1 && i < length && !is_covered[i];
+            if is_in_run {
+                is_covered[i] = true
+            }
+            !is_in_run
+        }).collect();
+        Command::MoveSpan(Box::new(MoveSpan { start, length, rest }))
+    };
+    rebuild_loops(commands, || true, |commands| commands.into_iter().map(coalesce_move).collect(), |body| Command::Loop(body.into_boxed_slice()))
+}
+
+/// The number of sibling commands from which [`optimize_tree`] processes them on multiple threads.
+#[cfg(feature = "parallel")]
+const PARALLEL_THRESHOLD: usize = 1024;
+
+/// Returns a vector of commands that is functionally equivalent to the passed commands.
+///
+/// If `deadline` is passed, loops that are reached after it are left as they are. Since each
+/// optimization is local, the result is still equivalent, only less optimized.
+fn optimize_tree(commands: Vec<Command>, options: &OptimizeOptions, deadline: Option<Instant>) -> Vec<Command> {
+    #[cfg(feature = "parallel")]
+    if commands.len() >= PARALLEL_THRESHOLD {
+        return optimize_tree_in_parallel(commands, options, deadline);
+    }
+    rebuild_loops(commands, || !is_past(deadline), |commands| optimize_sequence(commands, options), |body| optimize_body(body, options))
+}
+
+/// Does what [`optimize_tree`] does, optimizing the top-level loops on multiple threads, which gives
+/// the same commands.
+///
+/// Sibling loops do not interact, so large groups of top-level ones can be optimized concurrently.
+/// Only the chunk reset pass needs to see the commands in order, so it stays sequential.
+#[cfg(feature = "parallel")]
+fn optimize_tree_in_parallel(commands: Vec<Command>, options: &OptimizeOptions, deadline: Option<Instant>) -> Vec<Command> {
+    use rayon::prelude::*;
+    let commands = commands.into_par_iter().map(|mut command| match &mut command {
+        Command::Loop(content) if !is_past(deadline) => {
+            let content = std::mem::take(content).into_vec();
+            optimize_body(rebuild_loops(content, || !is_past(deadline), |commands| optimize_sequence(commands, options), |body| optimize_body(body, options)), options)
+        }
+        _ => command,
+    }).collect();
+    optimize_sequence(commands, options)
+}
+
+/// Optimizes a sequence of commands whose loops have already been optimized.
+fn optimize_sequence(commands: Vec<Command>, options: &OptimizeOptions) -> Vec<Command> {
+    let commands_iter = drop_unused_positions(commands.into_iter()
+        .filter(Command::is_useful));
+    if options.optimize_chunk_resets {
+        optimize_chunk_resets(commands_iter).collect()
+    } else {
+        commands_iter.collect()
+    }
+}
+
+/// Optimizes a loop whose body has already been optimized.
+fn optimize_body(content: Vec<Command>, options: &OptimizeOptions) -> Command {
+    // Optimize trivial loops
+    if options.optimize_loops {
+        optimize_loop(content, options.cell_size)
+    } else {
+        Command::Loop(content.into_boxed_slice())
+    }
+}
+
+
+/// What [`optimize_within_budget`] did to a program, which is left to the caller to report (see
+/// `--verbose`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OptimizeReport {
+    /// Whether optimization stopped early because of [`OptimizeOptions::time_budget`].
+    pub is_truncated: bool,
+    /// The number of loops that were unwrapped because they only contained a loop testing the same
+    /// cell.
+    pub unwrapped_loops: usize,
+    /// The number of commands that were removed because they operated on cells known to be 0.
+    pub removed_constant_commands: usize,
+    /// The number of commands that were removed at the end of the program because they had no
+    /// observable effect.
+    pub removed_tail_commands: usize,
+}
+
+/// Runs all optimizations enabled by the options on a program, and returns the optimized program,
+/// along with what was done to it.
+pub fn optimize_within_budget(commands: Vec<Command>, options: &OptimizeOptions) -> (Vec<Command>, OptimizeReport) {
+    let deadline = options.time_budget.map(|budget| Instant::now() + budget);
+    let mut report = OptimizeReport::default();
+    let mut optimized_commands = optimize_tree(commands, options, deadline);
+    // Constants are propagated from the initial values of the cells, which are only known to be 0 if
+    // they are not set otherwise. Removing resets changes which cells are written, so this is
+    // disabled when the written cells or the writes are observed.
+    if options.optimize_loops && !is_past(deadline) {
+        let are_writes_observed = options.is_written_extent_observed || options.are_writes_observed;
+        report.unwrapped_loops = unwrap_nested_loops(&mut optimized_commands, are_writes_observed);
+    }
+    if options.propagate_constants && !options.is_tape_initialized && !options.is_written_extent_observed && !options.are_writes_observed && !is_past(deadline) {
+        report.removed_constant_commands = propagate_constants(&mut optimized_commands, options.cell_size);
+    }
+    if options.remove_unobservable_tail && !options.is_final_tape_observed && !options.are_writes_observed {
+        report.removed_tail_commands = remove_unobservable_tail(&mut optimized_commands);
+    }
+    if is_past(deadline) {
+        report.is_truncated = true;
+        return (optimized_commands, report);
+    }
+    if options.coalesce_moves {
+        optimized_commands = coalesce_moves(optimized_commands)
+    }
+    if options.fuse_commands {
+        optimized_commands = fuse_commands(optimized_commands)
+    }
+    (optimized_commands, report)
+}
+
+/// Returns a program that is functionally equivalent to the passed one, optimized as the options
+/// say (see [`optimize_within_budget`]).
+pub fn optimize(commands: Vec<Command>, options: &OptimizeOptions) -> Vec<Command> {
+    optimize_within_budget(commands, options).0
+}
+
+
+/// Tests if a deadline has passed.
+fn is_past(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    /// Propagates constants through a program as it is parsed, and returns the number of commands
+    /// removed.
+    fn removed_constant_commands(code: &str) -> usize {
+        let mut commands = parse(code).unwrap();
+        propagate_constants(&mut commands, CellSize::Eight)
+    }
+
+    #[test]
+    fn loops_changing_the_current_cell_more_than_once_are_not_moves() {
+        assert!(matches!(optimize_loop(parse("-<->").unwrap(), CellSize::Eight), Command::Move(_)));
+        // The loop repeats 85 times from 255 rather than 255 times.
+        assert!(matches!(optimize_loop(parse("-<->--").unwrap(), CellSize::Eight), Command::Loop(_)));
+        assert!(matches!(optimize_loop(parse("->+<+").unwrap(), CellSize::Eight), Command::Loop(_)))
+    }
+
+    #[test]
+    fn loops_on_cells_known_to_be_zero_are_removed() {
+        assert_eq!(removed_constant_commands("[-]>[.]<+[-]"), 2);
+        // The cell a loop ends on is known to be 0 after it.
+        assert_eq!(removed_constant_commands("+[-][.]"), 1)
+    }
+
+    #[test]
+    fn loops_forget_the_cells_they_write_to() {
+        assert_eq!(removed_constant_commands("+[->>+<<]>>[-]"), 0);
+        // Other cells are still known.
+        assert_eq!(removed_constant_commands("+[->>+<<]>[-]"), 1)
+    }
+
+    #[test]
+    fn loops_forget_the_cells_written_by_nested_loops() {
+        assert_eq!(removed_constant_commands(",[[->+<]<]>[-]"), 0);
+        assert_eq!(removed_constant_commands(",[-[->+<]]>[-]>[-]"), 1)
+    }
+
+    #[test]
+    fn loops_moving_the_pointer_forget_all_cells() {
+        assert_eq!(removed_constant_commands("+[>]>[-]"), 0);
+        assert_eq!(removed_constant_commands("+[->+<[>]]>>>[-]"), 0)
+    }
+
+    #[test]
+    fn loops_before_the_pointer_moves_forget_cells_relative_to_the_loop() {
+        // The cell written by the loop is the one 2 cells to the right of where it starts.
+        assert_eq!(removed_constant_commands(">+[->>+<<]<[-]>>>[-]"), 1)
+    }
+
+    #[test]
+    fn loops_around_chunk_resets_are_kept() {
+        let mut commands = vec![Command::Loop(vec![Command::ResetChunk(2)].into_boxed_slice())];
+        assert_eq!(unwrap_nested_loops(&mut commands, false), 0);
+        assert!(matches!(&commands[..], [Command::Loop(body)] if matches!(body[..], [Command::ResetChunk(2)])));
+        let mut commands = vec![Command::Loop(vec![Command::Reset].into_boxed_slice())];
+        assert_eq!(unwrap_nested_loops(&mut commands, false), 1);
+        assert!(matches!(commands[..], [Command::Reset]))
+    }
+
+    #[test]
+    fn moves_to_fewer_adjacent_cells_than_the_minimum_are_not_coalesced() {
+        let cells = |length: isize| (1..=length).map(|offset| (offset, 1)).collect::<Box<_>>();
+        let commands = coalesce_moves(vec![Command::Move(cells(MIN_MOVE_SPAN_LENGTH as isize - 1))]);
+        assert!(matches!(&commands[..], [Command::Move(cells)] if cells.len() == MIN_MOVE_SPAN_LENGTH - 1));
+        let commands = coalesce_moves(vec![Command::Move(cells(MIN_MOVE_SPAN_LENGTH as isize))]);
+        assert!(matches!(&commands[..], [Command::MoveSpan(span)] if span.start == 1 && span.length == MIN_MOVE_SPAN_LENGTH && span.rest.is_empty()));
+        // Other multipliers do not count towards the run, and are kept separately.
+        let mut cells = cells(MIN_MOVE_SPAN_LENGTH as isize).into_vec();
+        cells[3].1 = 2;
+        let commands = coalesce_moves(vec![Command::Move(cells.into_boxed_slice())]);
+        assert!(matches!(&commands[..], [Command::Move(_)]))
+    }
+
+    #[test]
+    fn loops_are_only_unwrapped_around_loops_and_writes_to_the_current_cell() {
+        let unwrapped = |code: &str, are_writes_observed: bool| {
+            let mut commands = optimize_tree(parse(code).unwrap(), &OptimizeOptions::default(), None);
+            unwrap_nested_loops(&mut commands, are_writes_observed)
+        };
+        assert_eq!(unwrapped("[[.-]]", true), 1);
+        assert_eq!(unwrapped("[[[>]]]", true), 2);
+        assert_eq!(unwrapped("[[-]]", false), 1);
+        assert_eq!(unwrapped("[[->+<]]", false), 1);
+        // Resets and moves then write to cells even when the current cell is 0.
+        assert_eq!(unwrapped("[[-]]", true), 0);
+        assert_eq!(unwrapped("[[->+<]]", true), 0);
+        // Loops around other commands repeat until the current cell is 0.
+        assert_eq!(unwrapped("[-]", false), 0);
+        assert_eq!(unwrapped("[.[-]]", false), 0);
+        assert_eq!(unwrapped("[[-]>]", false), 0)
+    }
+
+    #[test]
+    fn loops_only_moving_the_pointer_are_scans() {
+        assert!(matches!(optimize_loop(parse(">").unwrap(), CellSize::Eight), Command::Scan(1)));
+        assert!(matches!(optimize_loop(parse("<<<").unwrap(), CellSize::Eight), Command::Scan(-3)));
+        assert!(matches!(optimize_loop(parse("><").unwrap(), CellSize::Eight), Command::Scan(0)));
+        assert!(matches!(optimize_loop(parse(">+").unwrap(), CellSize::Eight), Command::Loop(_)))
+    }
+
+    #[test]
+    fn scans_stop_at_the_first_zero_cell() {
+        use crate::tape::{Tape, TapeOptions};
+        // Cells 0 to 199 are allocated, and all of them are 1 except cell 100 and cell 150.
+        let scan = |start: isize, stride: isize| {
+            let mut tape = Tape::new(&TapeOptions::default());
+            let mut cells = [1; 200];
+            cells[100] = 0;
+            cells[150] = 0;
+            tape.write_slice(0, &cells);
+            tape.right_by(start);
+            tape.scan(stride);
+            assert_eq!(tape.allocated_cells(), 200);
+            tape.pointer()
+        };
+        // Within the allocated cells, past whole chunks.
+        assert_eq!(scan(0, 1), 100);
+        assert_eq!(scan(199, -1), 150);
+        assert_eq!(scan(149, -1), 100);
+        assert_eq!(scan(0, 25), 100);
+        assert_eq!(scan(199, -7), 150);
+        assert_eq!(scan(100, 3), 100);
+        // Cells that are not allocated are 0, and are not allocated by the scan.
+        assert_eq!(scan(151, 1), 200);
+        assert_eq!(scan(99, -1), -1);
+        assert_eq!(scan(151, 7), 200);
+        assert_eq!(scan(99, -7), -6);
+        assert_eq!(scan(300, 1), 300);
+        assert_eq!(scan(-50, -3), -50)
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn optimizing_in_parallel_gives_the_same_commands() {
+        let corpus = [
+            include_str!("../examples/rot13.b"),
+            include_str!("../examples/reverse.b"),
+            include_str!("../tests/programs/bubble_sort.b"),
+            include_str!("../tests/programs/brackets.b"),
+            include_str!("../tests/programs/hello_world.b"),
+        ];
+        for optimize_chunk_resets in [false, true] {
+            let options = OptimizeOptions { optimize_chunk_resets, ..OptimizeOptions::default() };
+            for code in corpus {
+                // Each program follows enough top-level commands for them to be optimized in parallel.
+                let code = format!("{}{code}", "[-]>+[->+<]>".repeat(PARALLEL_THRESHOLD / 4));
+                let parallel = optimize_tree_in_parallel(parse(&code).unwrap(), &options, None);
+                let sequential = rebuild_loops(parse(&code).unwrap(), || true, |commands| optimize_sequence(commands, &options), |body| optimize_body(body, &options));
+                assert_eq!(format!("{:?}", parallel), format!("{:?}", sequential))
+            }
+        }
+    }
+
+    #[test]
+    fn trailing_loops_are_kept_even_if_they_never_end() {
+        let removed = |code: &str| {
+            let mut commands = optimize_tree(parse(code).unwrap(), &OptimizeOptions::default(), None);
+            (remove_unobservable_tail(&mut commands), commands.len())
+        };
+        assert_eq!(removed(".>+<-"), (4, 1));
+        // The loop never ends, which is observable, so it stays along with what it depends on.
+        assert_eq!(removed(".+[]"), (0, 3));
+        assert_eq!(removed(".+[>+]>>+"), (2, 3));
+        assert_eq!(removed(",[.,]+"), (1, 2))
+    }
+}
@@ -0,0 +1,232 @@
+use crate::assertion::Assertion;
+use crate::cell::CellSize;
+use crate::names::CellNames;
+use crate::source::{LineColumns, ParseError, ParseErrorKind};
+use crate::{names, source, Command};
+
+/// A dialect of Brainfuck, which may define instructions in addition to the standard ones.
+///
+/// Dialects are mutually exclusive, as they may give different meanings to the same character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// Standard Brainfuck.
+    Standard,
+    /// Extended Brainfuck Type I, which adds `@` (ends the program), `$` (copies the current cell to
+    /// a storage register), and `!` (copies the storage register to the current cell).
+    Ext1,
+    /// Adds an instruction (`%` by default, see `--random-char`) that stores a random byte in the
+    /// current cell.
+    Random,
+    /// Adds a second tape, with its own pointer, and two instructions: `~` (switches the tape
+    /// instructions operate on) and `^` (exchanges the values of the current cells of both tapes).
+    Dual,
+}
+
+/// The options telling how [`load`] reads source code.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// The dialect the source code is written in.
+    pub dialect: Dialect,
+    /// The character that stores a random byte in the current cell with [`Dialect::Random`].
+    pub random_char: char,
+    /// Whether `?` is loaded as [`Command::DumpTape`].
+    pub debug: bool,
+    /// Whether `.` is loaded as [`Command::TracedOutput`] rather than [`Command::Output`].
+    pub trace_output: bool,
+    /// Whether each command is preceded by a [`Command::Position`] giving the line and column of
+    /// its first instruction, which pauses show.
+    pub trace_positions: bool,
+    /// Whether assertions are loaded as [`Command::Assert`] rather than skipped.
+    pub assertions: bool,
+    /// The size of the cells the program runs on, which the amounts of [`Command::Add`] are reduced
+    /// for (see [`CellSize::wrap`]).
+    pub cell_size: CellSize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            dialect: Dialect::Standard,
+            random_char: '%',
+            debug: false,
+            trace_output: false,
+            trace_positions: false,
+            assertions: false,
+            cell_size: CellSize::Eight,
+        }
+    }
+}
+
+
+/// The keywords of the annotations written between braces in the source code, namely assertions
+/// (see [`Assertion`]), names (see [`CellNames`]), and tests (see `--test`).
+pub const ANNOTATIONS: [&[u8]; 3] = [b"assert", b"name", b"test"];
+
+/// Returns the keyword of the annotation some source code starts with, right after its `{`, if it
+/// starts with one.
+///
+/// A `{` that is never closed by a `}` does not start an annotation, but is part of a comment, so
+/// that standard programs with comments such as `{test of + and -` still load.
+pub fn annotation(code: &[u8]) -> Option<&'static [u8]> {
+    ANNOTATIONS.into_iter()
+        .find(|keyword| code.starts_with(keyword) && code.get(keyword.len()).is_some_and(u8::is_ascii_whitespace))
+        .filter(|_| code.contains(&b'}'))
+}
+
+/// Returns a table telling, for each byte, whether [`load`] has to look at it, given the dialect
+/// and the options. The other bytes are comments.
+fn significant_bytes(options: &ParseOptions) -> [bool; 256] {
+    let mut significant = [false; 256];
+    let mut bytes = b"<>+-[].,{".to_vec();
+    if options.debug {
+        bytes.push(b'?')
+    }
+    match options.dialect {
+        Dialect::Standard => {}
+        Dialect::Ext1 => bytes.extend_from_slice(b"@$!"),
+        Dialect::Random => bytes.extend(u8::try_from(options.random_char)),
+        Dialect::Dual => bytes.extend_from_slice(b"~^"),
+    }
+    for byte in bytes {
+        significant[byte as usize] = true
+    }
+    significant
+}
+
+/// Returns the number of times `byte` is repeated at the start of some source code.
+fn run_length(code: &[u8], byte: u8) -> usize {
+    code.iter().take_while(|&&other| other == byte).count()
+}
+
+/// Loads source code as [commands](Command).
+///
+/// Annotations are skipped, except assertions when [`ParseOptions::assertions`] is set, which are loaded as
+/// [`Command::Assert`] and may refer to cells by the names in `names`.
+///
+/// Comments are skipped in one go using a table of the significant bytes, and runs of the same
+/// instruction (such as `+++`) are counted at once.
+///
+/// The bodies of loops are first pushed along with the other commands, and then moved to a slice of
+/// exactly the right size once the loop ends, so that each loop only needs one allocation. Open
+/// loops are tracked on a stack rather than by recursing, so that deeply nested ones cannot overflow
+/// the call stack.
+///
+/// Unmatched brackets and invalid assertions are reported with their positions.
+pub fn load(code: &[u8], options: &ParseOptions, names: &CellNames) -> Result<Vec<Command>, ParseError> {
+    let significant = significant_bytes(options);
+    let mut commands = Vec::new();
+    // The offset of the `[` of each loop that is not closed yet, along with the index of the first
+    // command of its body.
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    let mut lines = LineColumns::new(code);
+    let mut position = 0;
+    loop {
+        let start = open.last().map_or(0, |&(_, body_start)| body_start);
+        position += code[position..].iter().position(|&byte| significant[byte as usize]).unwrap_or(code.len() - position);
+        let instruction = code.get(position).copied();
+        let (instruction_start, previous_length) = (position, commands.len());
+        position += 1;
+        let mut locate = |commands: &mut Vec<Command>, index: usize| {
+            let (line, column) = lines.locate(instruction_start);
+            commands.insert(index, Command::Position(line, column))
+        };
+        match instruction {
+            None => {
+                // The innermost loop that is not closed is the one reported.
+                return match open.last() {
+                    Some(&(offset, _)) => Err(ParseError::new(ParseErrorKind::UnmatchedOpen, code, offset)),
+                    None => Ok(commands),
+                };
+            }
+            Some(byte @ (b'<' | b'>')) => {
+                let count = 1 + run_length(&code[position..], byte);
+                position += count - 1;
+                let amount = if byte == b'<' { -(count as isize) } else { count as isize };
+                match commands[start..].last_mut() {
+                    Some(Command::Right(total)) => *total += amount,
+                    _ => commands.push(Command::Right(amount)),
+                }
+            }
+            Some(byte @ (b'+' | b'-')) => {
+                let count = 1 + run_length(&code[position..], byte);
+                position += count - 1;
+                // Only the count modulo the number of values of the cells matters, as they wrap around.
+                let amount = if byte == b'-' { (count as u64).wrapping_neg() } else { count as u64 };
+                match commands[start..].last_mut() {
+                    Some(Command::Add(total)) => *total = options.cell_size.wrap(total.wrapping_add(amount)),
+                    _ => commands.push(Command::Add(options.cell_size.wrap(amount))),
+                }
+            }
+            Some(b'[') => {
+                if options.trace_positions {
+                    locate(&mut commands, previous_length)
+                }
+                open.push((position - 1, commands.len()))
+            }
+            Some(b']') => {
+                let Some((_, body_start)) = open.pop() else {
+                    return Err(ParseError::new(ParseErrorKind::UnmatchedClose, code, position - 1));
+                };
+                let loop_content = commands.drain(body_start..).collect();
+                commands.push(Command::Loop(loop_content))
+            }
+            Some(b'.') if options.trace_output => {
+                commands.push(Command::TracedOutput(position - 1))
+            }
+            Some(b'.') => {
+                commands.push(Command::Output)
+            }
+            Some(b',') => {
+                commands.push(Command::Input)
+            }
+            Some(b'?') if options.debug => {
+                commands.push(Command::DumpTape)
+            }
+            Some(b'@') if options.dialect == Dialect::Ext1 => {
+                commands.push(Command::Halt)
+            }
+            Some(b'$') if options.dialect == Dialect::Ext1 => {
+                commands.push(Command::Store)
+            }
+            Some(b'!') if options.dialect == Dialect::Ext1 => {
+                commands.push(Command::Restore)
+            }
+            Some(c) if options.dialect == Dialect::Random && char::from(c) == options.random_char => {
+                commands.push(Command::Random)
+            }
+            Some(b'~') if options.dialect == Dialect::Dual => {
+                commands.push(Command::SwitchTape)
+            }
+            Some(b'^') if options.dialect == Dialect::Dual => {
+                commands.push(Command::ExchangeCells)
+            }
+            Some(b'{') if annotation(&code[position..]).is_some() => {
+                let annotation_start = position - 1;
+                // Annotations are always closed (see `annotation`).
+                let length = code[position..].iter().position(|&byte| byte == b'}').unwrap_or(code.len() - position);
+                // Names are collected before loading (see `names::collect`).
+                if options.assertions && annotation(&code[position..]) == Some(b"assert") {
+                    let text = String::from_utf8_lossy(&code[position + b"assert".len()..position + length]);
+                    let assertion = Assertion::parse(&text, source::line_column(code, annotation_start), names).map_err(|error| {
+                        ParseError::new(ParseErrorKind::InvalidAssertion(error), code, annotation_start)
+                    })?;
+                    commands.push(Command::Assert(Box::new(assertion)))
+                }
+                position += length + 1
+            }
+            _ => {}
+        }
+        // Loops are located at their `[`, and instructions merged with the previous ones at the
+        // position of the first.
+        if options.trace_positions && !matches!(instruction, Some(b'[' | b']')) && commands.len() > previous_length {
+            locate(&mut commands, previous_length)
+        }
+    }
+}
+
+/// Loads standard Brainfuck source code as [commands](Command), with the default options (see
+/// [`load`]).
+pub fn parse(code: &str) -> Result<Vec<Command>, ParseError> {
+    let code = code.as_bytes();
+    load(code, &ParseOptions::default(), &names::collect(code).0)
+}
@@ -0,0 +1,68 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, IsTerminal};
+
+use brainfuck_interpreter::{Cell, Machine, Resume};
+
+/// The number of cells shown on each side of the pointer when pausing at a breakpoint (see
+/// `--break-at-step`).
+const BREAK_WINDOW_RADIUS: isize = 8;
+
+/// Prints the tapes of a machine to `stderr`, along with the positions of their pointers, when the
+/// program executes `?` (see `--debug`).
+pub fn dump<C: Cell>(machine: &Machine<'_, C>) {
+    match &machine.other_tape {
+        Some(other_tape) => {
+            eprintln!("Active tape (pointer {}):\n{}", machine.tape.pointer(), machine.tape.dump(&machine.names));
+            eprintln!("Inactive tape (pointer {}):\n{}", other_tape.pointer(), other_tape.dump(&machine.names))
+        }
+        None => eprintln!("Tape (pointer {}):\n{}", machine.tape.pointer(), machine.tape.dump(&machine.names)),
+    }
+}
+
+/// Prints a line describing the state of a paused machine to `stderr`, including the position in
+/// the source code of the command about to execute, followed by the cells around the pointer if it
+/// paused at a breakpoint (see `--step` and `--break-at-step`). If `stderr` is a terminal, then
+/// waits for the user to press Enter, reading from the terminal directly so that the input of the
+/// program is not consumed. The user may instead type `runto N` to pause again after step N, or `q`
+/// to abort the program.
+pub fn prompt<C: Cell>(machine: &Machine<'_, C>, at_breakpoint: bool) -> Resume {
+    let last_output = match machine.tape.last_output() {
+        Some(value) => format!("0x{:02x}", value),
+        None => "none".to_string(),
+    };
+    // The command about to execute is located when the program was loaded with positions.
+    let position = match machine.position {
+        Some((line, column)) => format!(" (line {}, column {})", line, column),
+        None => String::new(),
+    };
+    eprintln!("Step {}{}: pointer {}, current cell 0x{:02x}, last output {}", machine.steps, position, machine.tape.pointer(), machine.tape.read(), last_output);
+    if at_breakpoint {
+        eprintln!("{}", machine.tape.window(BREAK_WINDOW_RADIUS, &machine.names));
+    }
+    if !io::stderr().is_terminal() {
+        return Resume::Continue;
+    }
+    // Some platforms (such as WASI) have no way to open the terminal.
+    let Ok(terminal) = File::open("/dev/tty") else {
+        return Resume::Continue;
+    };
+    let mut terminal = BufReader::new(terminal);
+    loop {
+        eprint!("Press Enter to continue, runto N to run until step N, or q to abort: ");
+        let mut answer = String::new();
+        terminal.read_line(&mut answer).expect("Unable to read from terminal");
+        let answer = answer.trim();
+        if answer == "q" {
+            return Resume::Abort;
+        }
+        let Some(step) = answer.strip_prefix("runto") else {
+            return Resume::Continue;
+        };
+        match step.trim().parse() {
+            Ok(step) if step > machine.steps => return Resume::RunTo(step),
+            Ok(_) => eprintln!("Step {} has already been executed", step.trim()),
+            Err(_) => eprintln!("Invalid step: {}", step.trim()),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::io;
+use std::io::Write;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+/// Creates a pipe, through which the output of a program is passed to the input of another
+/// program running on another thread (see `--pipe`).
+pub fn pipe() -> (PipeWriter, PipeReader) {
+    let (sender, receiver) = mpsc::channel();
+    (PipeWriter { sender }, PipeReader { receiver, buffer: Vec::new().into_iter() })
+}
+
+/// The writing end of a pipe.
+///
+/// Bytes are sent in chunks of whatever size is written at once, so this is better used through a
+/// buffer.
+#[derive(Debug)]
+pub struct PipeWriter {
+    sender: mpsc::Sender<Vec<u8>>,
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // If the reading end is gone, the next program has ended, so the output is not needed.
+        let _ = self.sender.send(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The reading end of a pipe, which yields the bytes written to the pipe until the writing end is
+/// dropped.
+#[derive(Debug)]
+pub struct PipeReader {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    /// The rest of the last chunk received.
+    buffer: std::vec::IntoIter<u8>,
+}
+
+impl PipeReader {
+    /// Returns the bytes written to the pipe that were not read yet, waiting at most `timeout` for
+    /// some to be written if there are none.
+    pub fn next_chunk(&mut self, timeout: Duration) -> Result<Vec<u8>, RecvTimeoutError> {
+        let rest: Vec<u8> = self.buffer.by_ref().collect();
+        if !rest.is_empty() {
+            return Ok(rest);
+        }
+        self.receiver.recv_timeout(timeout)
+    }
+}
+
+impl Iterator for PipeReader {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        loop {
+            if let Some(byte) = self.buffer.next() {
+                return Some(byte);
+            }
+            self.buffer = self.receiver.recv().ok()?.into_iter();
+        }
+    }
+}
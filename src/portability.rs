@@ -0,0 +1,169 @@
+use std::io;
+use std::io::Write;
+use std::ops::ControlFlow;
+
+use brainfuck_interpreter::{bytecode, execute_command, Cell, CellSize, Command, Machine, Stop};
+
+use crate::protect::written_cells;
+
+/// The number of cells of the tape of the original Brainfuck implementation.
+const ORIGINAL_TAPE_LENGTH: isize = 30000;
+
+/// Something a program did that other interpreters may handle differently (see
+/// `--warn-portability`).
+#[derive(Debug, Clone, Copy)]
+enum Event {
+    PointerBelowZero,
+    PointerBeyondTape,
+    Overflow,
+    Underflow,
+    EndOfInput,
+    NonAsciiOutput,
+}
+
+impl Event {
+    const ALL: [Event; 6] = [Event::PointerBelowZero, Event::PointerBeyondTape, Event::Overflow, Event::Underflow, Event::EndOfInput, Event::NonAsciiOutput];
+
+    /// Describes this event, for a program running on cells of the passed size.
+    fn description(self, cell_size: CellSize) -> &'static str {
+        match self {
+            Event::PointerBelowZero => "the pointer went below cell 0, where many interpreters have no cells",
+            Event::PointerBeyondTape => "the pointer went past cell 29999, where tapes of 30000 cells end",
+            Event::Overflow if cell_size == CellSize::Eight => "a cell wrapped past 255, which interpreters with larger cells do not do",
+            Event::Overflow => "a cell went past 255, where interpreters with byte cells wrap around",
+            Event::Underflow if cell_size == CellSize::Eight => "a cell wrapped below 0, which interpreters with larger cells do not do",
+            Event::Underflow => "a cell went below 0, where interpreters with byte cells wrap around",
+            Event::EndOfInput => "`,` reached the end of the input, where interpreters write 0 or 255 or leave the cell unchanged",
+            Event::NonAsciiOutput => "a byte of 0x80 or more was output, which is not ASCII",
+        }
+    }
+}
+
+/// The steps at which some events first occurred.
+#[derive(Debug)]
+pub struct Portability {
+    first_steps: [Option<u64>; Event::ALL.len()],
+    /// The size of the cells the program runs on.
+    cell_size: CellSize,
+}
+
+impl Portability {
+    pub fn new(cell_size: CellSize) -> Self {
+        Self { first_steps: [None; Event::ALL.len()], cell_size }
+    }
+
+    /// Records an event caused by a command at some position in the source code (see
+    /// [`Machine::position`]), and reports it to `stderr` if it is the first one of its kind.
+    fn record(&mut self, event: Event, step: u64, command: &Command, position: Option<(usize, usize)>) {
+        let first_step = &mut self.first_steps[event as usize];
+        if first_step.is_none() {
+            *first_step = Some(step);
+            let location = match position {
+                Some((line, column)) => format!("{} at line {}, column {}", command.name(), line, column),
+                None => command.name().to_string(),
+            };
+            eprintln!("Portability warning at step {} ({}): {}", step, location, event.description(self.cell_size))
+        }
+    }
+
+    /// Writes the step at which each kind of event first occurred, if it did, in order.
+    pub fn write_summary(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.first_steps.iter().all(Option::is_none) {
+            return writeln!(out, "No portability issue found");
+        }
+        writeln!(out, "Portability issues:")?;
+        let mut events: Vec<(u64, Event)> = Event::ALL.into_iter().zip(self.first_steps).filter_map(|(event, step)| Some((step?, event))).collect();
+        events.sort_by_key(|&(step, _)| step);
+        for (step, event) in events {
+            writeln!(out, "  From step {}: {}", step, event.description(self.cell_size))?
+        }
+        Ok(())
+    }
+}
+
+/// Returns the events that executing a command (other than a loop) on a machine in its current state
+/// causes by changing the values of the cells.
+///
+/// The amounts added to cells are taken as decrements from half the number of values of the cells
+/// on (see [`CellSize::signed`]), as `-` is loaded as adding 255 to bytes. Resets are taken as
+/// counting down, as `[-]` does.
+fn wrapping_events<C: Cell>(command: &Command, machine: &Machine<'_, C>) -> Vec<Event> {
+    let tape = &machine.tape;
+    let signed = |amount| C::SIZE.signed(amount);
+    let value = tape.read().to_i64();
+    // The values that would be added to cells if they did not wrap, with their offsets.
+    let additions: Vec<(isize, i64)> = match command {
+        &Command::Add(amount) | &Command::AddRight(amount, _) => vec![(0, signed(amount))],
+        Command::Move(cells) => cells.iter().map(|&(offset, multiplier)| (offset, value.saturating_mul(signed(multiplier)))).collect(),
+        Command::MoveSpan(span) => {
+            let run = (0..span.length as isize).map(|i| (span.start + i, value));
+            run.chain(span.rest.iter().map(|&(offset, multiplier)| (offset, value.saturating_mul(signed(multiplier))))).collect()
+        }
+        Command::Mul(multiplication) if value != 0 => {
+            let operand = tape.read_relative(multiplication.operand).to_i64();
+            let temporary = tape.read_relative(multiplication.temporary).to_i64();
+            // The first iteration adds the temporary cell back to the operand.
+            let product = operand.saturating_add((value - 1).saturating_mul(operand.saturating_add(temporary)));
+            let targets = multiplication.targets.iter().map(|&(offset, multiplier)| (offset, product.saturating_mul(signed(multiplier))));
+            targets.chain([(multiplication.operand, temporary)]).collect()
+        }
+        _ => Vec::new(),
+    };
+    additions.into_iter().filter_map(|(offset, amount)| {
+        let result = tape.read_relative(offset).to_i64().saturating_add(amount);
+        // The current cell of a move is reset, so its own increments do not matter.
+        let is_reset = offset == 0 && !matches!(command, Command::Add(_) | Command::AddRight(_, _));
+        match result {
+            _ if is_reset => None,
+            256.. => Some(Event::Overflow),
+            ..0 => Some(Event::Underflow),
+            _ => None,
+        }
+    }).collect()
+}
+
+/// Executes commands on a machine like [`execute`](brainfuck_interpreter::execute), while recording the events that other
+/// interpreters may handle differently, such as the pointer going below cell 0 (see
+/// `--warn-portability`).
+///
+/// Each command is checked before and after it is executed, which is why this is only used when
+/// asked to.
+pub fn execute_checked<C: Cell>(commands: &[Command], machine: &mut Machine<'_, C>, portability: &mut Portability) -> ControlFlow<Stop> {
+    bytecode::execute_with(&bytecode::flatten(commands), machine, |command, machine| {
+        // Positions are not steps, but set the position of the commands after them.
+        if let Command::Position(_, _) = command {
+            return execute_command(command, machine);
+        }
+        let (step, position) = (machine.steps + 1, machine.position);
+        for event in wrapping_events(command, machine) {
+            portability.record(event, step, command, position)
+        }
+        if matches!(command, Command::Output | Command::TracedOutput(_)) && machine.tape.read().low_byte() >= 0x80 {
+            portability.record(Event::NonAsciiOutput, step, command, position)
+        }
+        // Brainfuck implementations without optimizations move the pointer to the cells that
+        // optimized commands write to.
+        let pointer = machine.tape.pointer();
+        let mut reached: Vec<isize> = written_cells(command, machine).into_iter().flat_map(|(from, to)| [from, to]).collect();
+        if let Command::Input = command {
+            machine.step()?;
+            match machine.tape.next_input()? {
+                Some(value) => machine.tape.write(C::from_byte(value)),
+                None => {
+                    portability.record(Event::EndOfInput, step, command, position);
+                    machine.tape.end_of_input()?
+                }
+            }
+        } else {
+            execute_command(command, machine)?
+        }
+        reached.extend([pointer, machine.tape.pointer()]);
+        if reached.iter().any(|&cell| cell < 0) {
+            portability.record(Event::PointerBelowZero, step, command, position)
+        }
+        if reached.iter().any(|&cell| cell >= ORIGINAL_TAPE_LENGTH) {
+            portability.record(Event::PointerBeyondTape, step, command, position)
+        }
+        ControlFlow::Continue(())
+    })
+}
@@ -0,0 +1,46 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+
+/// The time spent executing each kind of command, as measured by `--profile-commands`.
+///
+/// Each command is timed individually, so times include the overhead of reading the clock, which is
+/// significant for the cheapest commands. They are still useful to compare kinds of commands.
+#[derive(Debug, Default)]
+pub struct Profile {
+    /// The number of times each kind of command was executed, and the total time it took.
+    entries: BTreeMap<&'static str, (u64, Duration)>,
+}
+
+impl Profile {
+    /// Records an execution of a command of a specific kind.
+    pub fn record(&mut self, kind: &'static str, time: Duration) {
+        self.record_all(kind, 1, time)
+    }
+
+    /// Records several executions of commands of a specific kind, which took some time in total.
+    pub fn record_all(&mut self, kind: &'static str, count: u64, time: Duration) {
+        let (total_count, total) = self.entries.entry(kind).or_default();
+        *total_count += count;
+        *total += time
+    }
+
+    /// Returns the total time recorded for all kinds of commands.
+    pub fn total(&self) -> Duration {
+        self.entries.values().map(|&(_, time)| time).sum()
+    }
+
+    /// Writes a table with the number of executions, the total time, and the mean time of each kind
+    /// of command, from the most to the least expensive.
+    pub fn write_table(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|&(_, &(_, time))| std::cmp::Reverse(time));
+        writeln!(out, "{:16}\t{:>12}\t{:>13}\t{:>10}", "Command", "Count", "Total", "Mean")?;
+        for (kind, &(count, time)) in entries {
+            let mean = time.as_nanos() as f64 / count as f64;
+            writeln!(out, "{:16}\t{:>12}\t{:>10.3} ms\t{:>7.1} ns", kind, count, time.as_secs_f64() * 1000.0, mean)?;
+        }
+        writeln!(out, "{:16}\t{:>12}\t{:>10.3} ms", "Total", "", self.total().as_secs_f64() * 1000.0)
+    }
+}
@@ -0,0 +1,105 @@
+use std::cmp::{max, min};
+use std::ops::{ControlFlow, Range};
+
+use brainfuck_interpreter::{bytecode, execute_command, Cell, Command, Machine, Stop};
+
+use crate::args::parse_range;
+
+/// A range of cells that the program must not write to once it is armed (see `--protect`).
+#[derive(Debug, Clone)]
+pub struct Protection {
+    cells: Range<isize>,
+    /// The step after which writes are refused, so that the program can initialize the cells first.
+    after_step: u64,
+}
+
+impl Protection {
+    /// Parses a protection written as `FROM..TO` (`TO` is excluded), optionally followed by
+    /// `:after-step=N` (or `:after-step N`).
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (range, after_step) = match s.split_once(':') {
+            Some((range, arming)) => {
+                let step = arming.strip_prefix("after-step").ok_or_else(|| format!("expected `after-step=N`, found `{}`", arming))?;
+                let step = step.strip_prefix('=').unwrap_or(step).trim();
+                (range, step.parse().map_err(|_| format!("`{}` is not a step", step))?)
+            }
+            None => (s, 0),
+        };
+        Ok(Self {
+            cells: parse_range(range)?,
+            after_step,
+        })
+    }
+
+    /// Returns the first cell in a range (both included) this protects at a step, if any.
+    fn first_protected(&self, from: isize, to: isize, step: u64) -> Option<isize> {
+        let first = max(from, self.cells.start);
+        (step > self.after_step && first <= min(to, self.cells.end - 1)).then_some(first)
+    }
+}
+
+/// Returns the ranges of cells (both included) a command writes to if it is executed on a machine in
+/// its current state. Loops only write through the commands in their bodies.
+pub fn written_cells<C: Cell>(command: &Command, machine: &Machine<'_, C>) -> Vec<(isize, isize)> {
+    let pointer = machine.tape.pointer();
+    let cell = |offset: isize| (pointer + offset, pointer + offset);
+    match command {
+        Command::Add(_) | Command::AddRight(_, _) | Command::Input | Command::Reset | Command::Restore | Command::Random | Command::ExchangeCells => {
+            vec![cell(0)]
+        }
+        &Command::ResetChunk(max_offset) => {
+            vec![(min(pointer, pointer + max_offset), max(pointer, pointer + max_offset))]
+        }
+        Command::Move(cells) => {
+            cells.iter().map(|&(offset, _)| cell(offset)).chain([cell(0)]).collect()
+        }
+        Command::MoveSpan(span) => {
+            let run = (pointer + span.start, pointer + span.start + span.length as isize - 1);
+            span.rest.iter().map(|&(offset, _)| cell(offset)).chain([run, cell(0)]).collect()
+        }
+        Command::Mul(multiplication) if !machine.tape.is_zero() => {
+            let offsets = multiplication.targets.iter().map(|&(offset, _)| offset);
+            offsets.chain([multiplication.operand, multiplication.temporary, 0]).map(cell).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Executes commands on a machine like [`execute`](brainfuck_interpreter::execute), but stops the program with an error before a
+/// command writes to a protected cell of the first tape.
+///
+/// Each command is checked before it is executed, which is why this is only used when cells are
+/// protected.
+pub fn execute_protected<C: Cell>(commands: &[Command], machine: &mut Machine<'_, C>, protections: &[Protection]) -> ControlFlow<Stop> {
+    // Protections apply to the first tape, which is the other tape of the machine while the program
+    // uses the second one (see the `dual` dialect).
+    let mut is_second_tape = false;
+    bytecode::execute_with(&bytecode::flatten(commands), machine, |command, machine| {
+        if let Command::SwitchTape = command {
+            is_second_tape = !is_second_tape
+        }
+        let step = machine.steps + 1;
+        let written = match &machine.other_tape {
+            Some(first_tape) if is_second_tape => {
+                let pointer = first_tape.pointer();
+                if let Command::ExchangeCells = command { vec![(pointer, pointer)] } else { Vec::new() }
+            }
+            _ => written_cells(command, machine),
+        };
+        let protected = written.iter().find_map(|&(from, to)| {
+            protections.iter().find_map(|protection| protection.first_protected(from, to, step).map(|cell| (cell, protection)))
+        });
+        if let Some((cell, protection)) = protected {
+            let location = match machine.position {
+                Some((line, column)) => format!(" (line {}, column {})", line, column),
+                None => String::new(),
+            };
+            // The output only needs to appear before the error if it can be written.
+            let _ = machine.tape.flush();
+            eprintln!("error: {} at step {}{} writes to {}, which is protected by --protect {}..{}", command.name(), step, location, machine.names.describe(cell), protection.cells.start, protection.cells.end);
+            eprintln!("note: run with --break-at-step {} to inspect the tape before this step", step);
+            return ControlFlow::Break(Stop::ProtectedWrite);
+        }
+        execute_command(command, machine)
+    })
+}
@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::timings;
+
+/// The header of the files written by [`OutputMap::write`], after the line giving the hash of the
+/// program.
+const HEADER: &str = "offset,count,line,column,first_step,last_step";
+
+/// Values output one after the other by the same command.
+#[derive(Debug, Clone, Copy)]
+struct OutputRun {
+    /// The number of values output by the program before this run.
+    offset: u64,
+    /// The number of values in this run.
+    count: u64,
+    /// The offset of the `.` in the source code.
+    position: usize,
+    /// The step at which the first value of this run was output.
+    first_step: u64,
+    /// The step at which the last value of this run was output.
+    last_step: u64,
+}
+
+/// The commands that output each value of a program (see `--output-map`).
+#[derive(Debug, Default)]
+pub struct OutputMap {
+    runs: Vec<OutputRun>,
+    /// The number of values recorded so far.
+    length: u64,
+}
+
+impl OutputMap {
+    /// Records that the `.` at some offset in the source code output a value at some step.
+    pub fn record(&mut self, position: usize, step: u64) {
+        match self.runs.last_mut() {
+            Some(run) if run.position == position => {
+                run.count += 1;
+                run.last_step = step
+            }
+            _ => self.runs.push(OutputRun {
+                offset: self.length,
+                count: 1,
+                position,
+                first_step: step,
+                last_step: step,
+            }),
+        }
+        self.length += 1
+    }
+
+    /// Writes this map to a file as CSV, locating commands by line and column in the source code.
+    pub fn write(&self, path: &Path, code: &[u8]) -> io::Result<()> {
+        let line_starts: Vec<usize> = [0].into_iter().chain(code.iter().enumerate().filter(|&(_, &byte)| byte == b'\n').map(|(index, _)| index + 1)).collect();
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "# program_hash={:016x}", timings::hash(code))?;
+        writeln!(out, "{}", HEADER)?;
+        for run in &self.runs {
+            // The line of a position is the last one starting at or before it.
+            let line = line_starts.partition_point(|&start| start <= run.position);
+            let column = run.position - line_starts[line - 1] + 1;
+            writeln!(out, "{},{},{},{},{},{}", run.offset, run.count, line, column, run.first_step, run.last_step)?
+        }
+        out.flush()
+    }
+}
+
+/// Returns the error of a line of an output map that cannot be read.
+fn invalid(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid line in output map: `{}`", line))
+}
+
+/// Returns whether a map written by [`OutputMap::write`] was written for some source code, rather
+/// than for another version of the program, whose positions it would give.
+pub fn is_map_of(map_path: &Path, code: &[u8]) -> io::Result<bool> {
+    let hash_line = BufReader::new(File::open(map_path)?).lines().next().transpose()?.unwrap_or_default();
+    let hash = hash_line.strip_prefix("# program_hash=").ok_or_else(|| invalid(&hash_line))?;
+    Ok(hash == format!("{:016x}", timings::hash(code)))
+}
+
+/// Finds the command that output the value at some offset in a map written by [`OutputMap::write`],
+/// and writes where it is in the source code and when it was executed.
+///
+/// Returns `false` if the program did not output that many values.
+pub fn locate(map_path: &Path, offset: u64, out: &mut impl Write) -> io::Result<bool> {
+    let mut lines = BufReader::new(File::open(map_path)?).lines();
+    // The first two lines are the hash of the program (see `is_map_of`) and the header.
+    lines.next().transpose()?;
+    lines.next().transpose()?;
+    for line in lines {
+        let line = line?;
+        let fields: Vec<u64> = line.split(',').map(str::parse).collect::<Result<_, _>>().map_err(|_| invalid(&line))?;
+        let &[run_offset, count, source_line, column, first_step, last_step] = fields.as_slice() else {
+            return Err(invalid(&line));
+        };
+        if (run_offset..run_offset + count).contains(&offset) {
+            writeln!(out, "Value {} was output by the `.` at {}:{}", offset, source_line, column)?;
+            if count == 1 {
+                writeln!(out, "It was output at step {}", first_step)?;
+            } else {
+                writeln!(out, "It is value {} of {} output one after the other by this command, from step {} to step {}", offset - run_offset + 1, count, first_step, last_step)?;
+            }
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
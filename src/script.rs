@@ -0,0 +1,138 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+use crate::args::parse_duration;
+use crate::generate::unescape;
+use crate::pipe::{PipeReader, PipeWriter};
+
+/// How long `expect` and `eof` wait for the program unless the script sets another timeout.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A command of a script.
+#[derive(Debug, Clone)]
+enum Action {
+    /// Waits for the program to output some bytes.
+    Expect(Vec<u8>),
+    /// Passes some bytes to the input of the program.
+    Send(Vec<u8>),
+    /// Sets how long the following commands wait for the program.
+    SetTimeout(Duration),
+    /// Ends the input of the program, and waits for it to end.
+    Eof,
+}
+
+/// A script driving an interactive program, sending it input and checking its output (see
+/// `--script`).
+#[derive(Debug, Clone)]
+pub struct Script {
+    /// The commands of the script, with the lines they were read from and their numbers.
+    actions: Vec<(usize, String, Action)>,
+}
+
+impl Script {
+    /// Reads a script from a file, made of one command per line: `expect "TEXT"`, `send "TEXT"`,
+    /// `expect-timeout DURATION`, or `eof`, which must be the last one if present.
+    ///
+    /// Texts may contain the escape sequences of `--generate`. Empty lines and lines starting with
+    /// `#` are ignored.
+    pub fn read(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("unable to read `{}`: {}", path, error))?;
+        let mut actions = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+            if matches!(actions.last(), Some((_, _, Action::Eof))) {
+                return Err(error("`eof` must be the last command".to_string()));
+            }
+            let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+            let text = || {
+                let argument = argument.trim();
+                let text = argument.strip_prefix('"').and_then(|text| text.strip_suffix('"'));
+                let text = text.ok_or_else(|| error(format!("expected a quoted text, found `{}`", argument)))?;
+                unescape(text).map_err(error)
+            };
+            let action = match command {
+                "expect" => Action::Expect(text()?),
+                "send" => Action::Send(text()?),
+                "expect-timeout" => Action::SetTimeout(parse_duration(argument.trim()).map_err(error)?),
+                "eof" if argument.is_empty() => Action::Eof,
+                _ => return Err(error(format!("unknown command `{}`", line))),
+            };
+            actions.push((i + 1, line.to_string(), action))
+        }
+        Ok(Self { actions })
+    }
+
+    /// Runs this script on a program running on another thread, writing to its input and reading
+    /// its output through pipes. Writes a report to `out`, and returns whether the program behaved
+    /// as expected.
+    ///
+    /// Once the program ends after `eof`, `has_failed` is called to tell whether it ended normally.
+    ///
+    /// Each `expect` looks for its text in the output that follows the text matched by the previous
+    /// one, so output in between is skipped.
+    pub fn run(&self, input: PipeWriter, mut output: PipeReader, has_failed: impl FnOnce() -> bool, out: &mut impl Write) -> io::Result<bool> {
+        let mut input = Some(input);
+        let mut has_failed = Some(has_failed);
+        let mut received = Vec::new();
+        // The offset of the output right after the last text that was matched.
+        let mut matched = 0;
+        let mut timeout = DEFAULT_TIMEOUT;
+        for (number, line, action) in &self.actions {
+            let deadline = Instant::now() + timeout;
+            let failure = match action {
+                Action::Expect(text) => loop {
+                    if let Some(position) = (matched..=received.len()).find(|&position| received[position..].starts_with(text)) {
+                        matched = position + text.len();
+                        break None;
+                    }
+                    match output.next_chunk(deadline.saturating_duration_since(Instant::now())) {
+                        Ok(chunk) => received.extend(chunk),
+                        Err(RecvTimeoutError::Timeout) => break Some(format!("The program did not output the expected text within {:?}", timeout)),
+                        Err(RecvTimeoutError::Disconnected) => break Some("The program ended without outputting the expected text".to_string()),
+                    }
+                },
+                Action::Send(text) => {
+                    if let Some(input) = &mut input {
+                        input.write_all(text)?
+                    }
+                    None
+                }
+                &Action::SetTimeout(duration) => {
+                    timeout = duration;
+                    None
+                }
+                Action::Eof => {
+                    drop(input.take());
+                    loop {
+                        match output.next_chunk(deadline.saturating_duration_since(Instant::now())) {
+                            Ok(chunk) => received.extend(chunk),
+                            Err(RecvTimeoutError::Timeout) => break Some(format!("The program did not end within {:?}", timeout)),
+                            Err(RecvTimeoutError::Disconnected) if has_failed.take().is_some_and(|has_failed| has_failed()) => {
+                                break Some("The program failed after the end of its input".to_string())
+                            }
+                            Err(RecvTimeoutError::Disconnected) => break None,
+                        }
+                    }
+                }
+            };
+            if let Some(failure) = failure {
+                writeln!(out, "Script failed at line {}: `{}`", number, line)?;
+                writeln!(out, "{}", failure)?;
+                if let Action::Expect(text) = action {
+                    writeln!(out, "  Expected: \"{}\"", text.escape_ascii())?;
+                }
+                writeln!(out, "  Received from offset {}: \"{}\"", matched, received[matched..].escape_ascii())?;
+                return Ok(false);
+            }
+        }
+        writeln!(out, "Script passed ({} bytes received)", received.len())?;
+        Ok(true)
+    }
+}
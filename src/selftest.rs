@@ -0,0 +1,107 @@
+use std::io;
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::time::Instant;
+
+use brainfuck_interpreter::names::CellNames;
+use brainfuck_interpreter::tape::Eof;
+use brainfuck_interpreter::{load, optimize_within_budget, Stop};
+use crate::args::{ArgValue, Args};
+use crate::run_captured;
+
+/// A program run by `--selftest`, along with its input and the output it is expected to produce.
+struct Check {
+    name: &'static str,
+    code: fn() -> String,
+    input: &'static [u8],
+    /// The expected output and how the program is expected to end, which may depend on `--eof`.
+    expected: fn(Eof) -> (&'static [u8], ControlFlow<Stop>),
+}
+
+/// The depth of the nested loops of the deep nesting check, which is more than the call stack of a
+/// debug build allows when loops are executed recursively (see `--flatten-loops`).
+const NESTING_DEPTH: usize = 20_000;
+
+const CHECKS: [Check; 5] = [
+    Check {
+        name: "hello world",
+        code: || "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.".to_string(),
+        input: b"",
+        expected: |_| (b"Hello World!\n", ControlFlow::Continue(())),
+    },
+    Check {
+        name: "wrapping arithmetic",
+        code: || format!("-.+.{}.-[>+<-]>.", "+".repeat(256)),
+        input: b"",
+        expected: |_| (&[255, 0, 0, 255], ControlFlow::Continue(())),
+    },
+    Check {
+        name: "deep nesting",
+        // Each loop is entered once and moves the pointer, so that none of them is optimized away.
+        code: || format!("+{}[-]{}+.", "[>+".repeat(NESTING_DEPTH), "<-]".repeat(NESTING_DEPTH)),
+        input: b"",
+        expected: |_| (&[1], ControlFlow::Continue(())),
+    },
+    Check {
+        name: "input",
+        code: || ",[.,]".to_string(),
+        input: b"echo\0",
+        expected: |_| (b"echo", ControlFlow::Continue(())),
+    },
+    Check {
+        name: "end of input",
+        code: || "+,.".to_string(),
+        input: b"",
+        expected: |eof| match eof {
+            Eof::Zero => (&[0], ControlFlow::Continue(())),
+            Eof::MinusOne => (&[255], ControlFlow::Continue(())),
+            Eof::Unchanged => (&[1], ControlFlow::Continue(())),
+            Eof::Error => (&[], ControlFlow::Break(Stop::EndOfInput)),
+        },
+    },
+];
+
+/// Runs a few embedded programs with the passed options and compares their outputs to the expected
+/// ones (see `--selftest`), which checks that the interpreter works without needing any file.
+///
+/// Returns whether all checks passed.
+pub fn run(args: &Args, out: &mut impl Write) -> io::Result<bool> {
+    let start = Instant::now();
+    writeln!(out, "Cell size: 8 bits, wrapping around")?;
+    writeln!(out, "Dialect: {}", args.dialect.name())?;
+    writeln!(out, "EOF: {}", args.eof.name())?;
+    let mut passed = true;
+    for check in &CHECKS {
+        let code = (check.code)();
+        let (commands, _) = optimize_within_budget(load(code.as_bytes(), &args.parse_options(), &CellNames::default()).expect("Embedded programs are valid"), &args.optimize_options());
+        let (output, _, result) = run_captured(&commands, Box::new(check.input.iter().copied()), None, None, args, 0);
+        let (expected_output, expected_result) = (check.expected)(args.eof);
+        if output == expected_output && result == expected_result {
+            writeln!(out, "pass: {}", check.name)?
+        } else {
+            passed = false;
+            writeln!(out, "FAIL: {} (expected {:?} and {:?}, got {:?} and {:?})", check.name, expected_output, expected_result, output, result)?
+        }
+    }
+    writeln!(out, "{} in {:.3} ms", if passed { "All checks passed" } else { "Some checks failed" }, start.elapsed().as_secs_f64() * 1000.0)?;
+    Ok(passed)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    // The embedded checks pass with every EOF mode, so that they cannot drift from what
+    // the interpreter does.
+    #[test]
+    fn checks_pass_with_every_eof_mode() {
+        for &(_, name, _) in Eof::VALUES {
+            let args = Args::parse_from(["brainfuck-interpreter", "--selftest", "--eof", name]);
+            let mut out = Vec::new();
+            assert!(run(&args, &mut out).unwrap(), "{}", String::from_utf8_lossy(&out));
+            assert!(String::from_utf8_lossy(&out).contains(&format!("EOF: {}\n", name)))
+        }
+    }
+}
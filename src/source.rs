@@ -0,0 +1,227 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The source code of a program.
+#[derive(Debug)]
+pub enum Source {
+    /// Source code that was read into memory.
+    Read(Vec<u8>),
+    /// Source code that was memory-mapped, so that it does not need to be copied into memory.
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
+/// The first bytes of gzip-compressed data.
+#[cfg(feature = "gzip")]
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The maximum size, in bytes, of a program fetched from a URL.
+#[cfg(feature = "net")]
+const MAX_DOWNLOAD_SIZE: u64 = 16 << 20;
+
+/// Returns whether a path passed as the source file is actually an `http://` or `https://` URL.
+pub fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// Returns the line and column (both starting at 1, and counted in bytes) of the byte at some
+/// offset in source code.
+pub fn line_column(code: &[u8], offset: usize) -> (usize, usize) {
+    let before = &code[..offset];
+    let line_start = before.iter().rposition(|&byte| byte == b'\n').map_or(0, |index| index + 1);
+    (before.iter().filter(|&&byte| byte == b'\n').count() + 1, offset - line_start + 1)
+}
+
+/// Finds the lines and columns of bytes at increasing offsets in source code, like [`line_column`]
+/// does for a single byte, but reading the code only once.
+#[derive(Debug, Clone)]
+pub struct LineColumns<'a> {
+    code: &'a [u8],
+    /// The offset up to which the code has been read.
+    offset: usize,
+    /// The line the byte at `offset` is on, and the offset of its first byte.
+    line: usize,
+    line_start: usize,
+}
+
+impl<'a> LineColumns<'a> {
+    pub fn new(code: &'a [u8]) -> Self {
+        Self { code, offset: 0, line: 1, line_start: 0 }
+    }
+
+    /// Returns the line and column of the byte at some offset, which must not be before the offset
+    /// passed the previous time.
+    pub fn locate(&mut self, offset: usize) -> (usize, usize) {
+        for (i, &byte) in self.code[self.offset..offset].iter().enumerate() {
+            if byte == b'\n' {
+                self.line += 1;
+                self.line_start = self.offset + i + 1
+            }
+        }
+        self.offset = offset;
+        (self.line, offset - self.line_start + 1)
+    }
+}
+
+/// What is wrong with some source code that cannot be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// A `]` closes no loop.
+    UnmatchedClose,
+    /// A `[` is never closed.
+    UnmatchedOpen,
+    /// An assertion cannot be parsed (see `--assertions`), for the specified reason.
+    InvalidAssertion(String),
+}
+
+/// An error in source code, located at the byte causing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// The offset of the byte in the source code.
+    pub offset: usize,
+    /// The line and column of the byte (see [`line_column`]).
+    pub line: usize,
+    pub column: usize,
+}
+
+impl ParseErrorKind {
+    /// Returns a name for this kind of error that does not change, such as for tools reading
+    /// diagnostics: `unmatched-bracket` or `invalid-assertion`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseErrorKind::UnmatchedClose | ParseErrorKind::UnmatchedOpen => "unmatched-bracket",
+            ParseErrorKind::InvalidAssertion(_) => "invalid-assertion",
+        }
+    }
+}
+
+impl ParseError {
+    /// Creates an error caused by the byte at some offset in source code.
+    pub fn new(kind: ParseErrorKind, code: &[u8], offset: usize) -> Self {
+        let (line, column) = line_column(code, offset);
+        Self { kind, offset, line, column }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let description = match self.kind {
+            ParseErrorKind::UnmatchedClose => "unmatched ']'",
+            ParseErrorKind::UnmatchedOpen => "unmatched '['",
+            ParseErrorKind::InvalidAssertion(_) => "invalid assertion",
+        };
+        // The offset helps with generated programs, which often fit on a single line.
+        write!(f, "{} at line {}, column {} (byte {})", description, self.line, self.column, self.offset)?;
+        if let ParseErrorKind::InvalidAssertion(reason) = &self.kind {
+            write!(f, ": {}", reason)?
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the first line of some source code with spaces if it is a shebang (starting with `#!`),
+/// so that programs can be run as scripts without the instructions in the path of the interpreter
+/// (such as `.` and `-`) being executed.
+///
+/// The length of the source code is kept, so that the offsets, lines, and columns that are reported
+/// are those of the file. The code is only copied if it starts with a shebang.
+pub fn blank_shebang(code: &[u8]) -> Cow<'_, [u8]> {
+    if !code.starts_with(b"#!") {
+        return Cow::Borrowed(code);
+    }
+    let end = code.iter().position(|&byte| byte == b'\n').unwrap_or(code.len());
+    let mut code = code.to_vec();
+    code[..end].fill(b' ');
+    Cow::Owned(code)
+}
+
+/// Downloads the program at some URL, without following redirects.
+#[cfg(feature = "net")]
+fn fetch(url: &str) -> io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let error = |message: String| io::Error::other(format!("unable to fetch {}: {}", url, message));
+    let response = ureq::AgentBuilder::new().redirects(0).build().get(url).call().map_err(|e| match e {
+        ureq::Error::Status(status, _) => error(format!("the server responded with status {}", status)),
+        // The error already mentions the URL.
+        ureq::Error::Transport(transport) => io::Error::other(transport.to_string()),
+    })?;
+    if response.status() != 200 {
+        return Err(match response.header("Location") {
+            Some(location) => error(format!("the server responded with status {} (redirecting to {}), but redirects are not followed", response.status(), location)),
+            None => error(format!("the server responded with status {}", response.status())),
+        });
+    }
+    let mut code = Vec::new();
+    response.into_reader().take(MAX_DOWNLOAD_SIZE + 1).read_to_end(&mut code)?;
+    if code.len() as u64 > MAX_DOWNLOAD_SIZE {
+        return Err(error(format!("the program is larger than {} bytes", MAX_DOWNLOAD_SIZE)));
+    }
+    Ok(code)
+}
+
+impl Source {
+    /// Reads the source code in a file.
+    ///
+    /// If `mmap` is `true`, the file is memory-mapped instead, unless this fails (e.g., because the
+    /// file is a pipe), in which case it is silently read normally.
+    ///
+    /// If the `gzip` feature is enabled and the file is compressed with gzip (which is detected
+    /// from its extension or its contents), it is decompressed.
+    ///
+    /// If the path is a URL (see [`is_url`]), the program is downloaded instead, which requires the
+    /// `net` feature.
+    ///
+    /// The errors name the file or the URL, so that they can be reported as they are.
+    pub fn read(path: &Path, mmap: bool) -> io::Result<Self> {
+        let source = Self::read_raw(path, mmap)?;
+        #[cfg(feature = "gzip")]
+        if path.extension().is_some_and(|extension| extension == "gz") || source.as_bytes().starts_with(&GZIP_MAGIC) {
+            use std::io::Read;
+
+            let mut code = Vec::new();
+            flate2::read::MultiGzDecoder::new(source.as_bytes()).read_to_end(&mut code).map_err(|error| {
+                io::Error::new(error.kind(), format!("{} is not a valid gzip file: {}", path.display(), error))
+            })?;
+            return Ok(Self::Read(code));
+        }
+        Ok(source)
+    }
+
+    fn read_raw(path: &Path, mmap: bool) -> io::Result<Self> {
+        if is_url(path) {
+            #[cfg(feature = "net")]
+            return fetch(&path.to_string_lossy()).map(Self::Read);
+            #[cfg(not(feature = "net"))]
+            return Err(io::Error::other("running programs from URLs requires the `net` feature"));
+        }
+        #[cfg(feature = "mmap")]
+        if mmap {
+            if let Ok(map) = fs::File::open(path).and_then(|file| {
+                // SAFETY: The file must not be modified while it is mapped. There is no way to
+                // enforce this, but it is not modified by the interpreter, and anyone modifying a
+                // program while running it should not expect anything sensible to happen.
+                unsafe { memmap2::Mmap::map(&file) }
+            }) {
+                return Ok(Self::Mapped(map));
+            }
+        }
+        #[cfg(not(feature = "mmap"))]
+        let _ = mmap;
+        fs::read(path).map(Self::Read).map_err(|error| io::Error::new(error.kind(), format!("unable to read {}: {}", path.display(), error)))
+    }
+
+    /// Returns the bytes of the source code, which do not have to be valid UTF-8.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Read(code) => code,
+            #[cfg(feature = "mmap")]
+            Self::Mapped(map) => map,
+        }
+    }
+}
@@ -1,42 +1,179 @@
-use std::{fmt, io};
+use std::{fmt, io, thread};
 use std::cmp::{max, min};
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
-use std::io::Read;
+use std::io::{BufReader, Read};
+use std::ops::{ControlFlow, RangeInclusive};
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
 
-fn default_stdin() -> Box<dyn Iterator<Item=u8>> {
-    Box::new(io::stdin().bytes().map(Result::unwrap))
+use serde::{Deserialize, Serialize};
+
+use crate::cell::Cell;
+use crate::names::CellNames;
+use crate::Stop;
+
+/// What `,` does once the input is exhausted, as interpreters disagree on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Eof {
+    /// Writes 0 to the current cell, as most interpreters do.
+    #[default]
+    Zero,
+    /// Writes 255 to the current cell (or the largest value with `--cell-size 16` or `32`, and -1
+    /// with `--cell-size big`).
+    MinusOne,
+    /// Leaves the current cell unchanged.
+    Unchanged,
+    /// Stops the program with exit code 5.
+    Error,
+}
+
+/// The values a tape reads, which stop the program if reading them fails (see [`Tape::input`]).
+pub type Input<'io> = Box<dyn Iterator<Item=io::Result<u8>> + 'io>;
+
+fn default_stdin() -> Input<'static> {
+    // Only lock `stdin` once it is needed, so that tapes reading from somewhere else (see
+    // `Tape::set_input`) never lock it.
+    let mut bytes = None;
+    Box::new(std::iter::from_fn(move || bytes.get_or_insert_with(|| io::stdin().lock().bytes()).next()))
+}
+
+/// Returns an iterator over the bytes of `stdin` that fails with [`io::ErrorKind::TimedOut`] if no
+/// byte is available within `timeout`, which stops the program with [`Stop::InputTimeout`].
+///
+/// Standard input is read by a helper thread, as there is no portable way to wait for it with a
+/// timeout. Fails if the thread cannot be started, as on some platforms (such as WASI).
+fn stdin_with_timeout(timeout: Duration) -> io::Result<Input<'static>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::Builder::new().spawn(move || {
+        for byte in io::stdin().lock().bytes() {
+            let is_error = byte.is_err();
+            if sender.send(byte).is_err() || is_error {
+                break;
+            }
+        }
+    })?;
+    Ok(Box::new(std::iter::from_fn(move || match receiver.recv_timeout(timeout) {
+        Ok(byte) => Some(byte),
+        Err(RecvTimeoutError::Disconnected) => None,
+        Err(RecvTimeoutError::Timeout) => Some(Err(io::ErrorKind::TimedOut.into())),
+    })))
+}
+
+/// Returns an iterator over the bytes of `stdin`. Reading one without a timeout never fails, but
+/// reading one fails with [`io::ErrorKind::TimedOut`] if no byte is available within `timeout`,
+/// if one is passed.
+///
+/// Fails if there is a timeout but the platform cannot wait for input with one, in which case the
+/// caller may read `stdin` without it.
+pub fn stdin(timeout: Option<Duration>) -> io::Result<Input<'static>> {
+    match timeout {
+        Some(timeout) => stdin_with_timeout(timeout),
+        None => Ok(default_stdin()),
+    }
 }
 
-fn default_stdout() -> Box<dyn io::Write> {
+/// The number of cells a scan with a stride of 1 or -1 checks at once (see [`Tape::scan`]), as
+/// checking whether a slice contains 0 is much faster than checking its cells one by one.
+const SCAN_CHUNK_LENGTH: usize = 64;
+
+/// The number of cells that stay allocated when a tape is reset (see [`Tape::reset`]).
+pub const RESET_CAPACITY: usize = 1024;
+
+fn default_stdout() -> Box<dyn io::Write + 'static> {
     Box::new(io::stdout())
 }
 
 #[derive(Debug, Clone, Copy)]
 enum OutputMode {
+    /// Writes values as they are, as bytes. They are only decoded as text by whatever displays
+    /// them, such as a terminal.
     Ascii,
     Hex,
     Silent,
+    /// Writes values as they are, even with `--hex-output`, which is used to pass them to another
+    /// program.
+    Raw,
+}
+
+/// The contents of a tape, in a form suitable for serialization (see `--tape-dump-json`).
+///
+/// Only non-zero cells are listed, so that huge tapes that are mostly zero stay small.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "C: Cell")]
+pub struct TapeState<C = u8> {
+    /// The position of the pointer.
+    pub pointer: isize,
+    /// The values of the non-zero cells, by index.
+    pub cells: BTreeMap<isize, C>,
+    /// The leftmost and rightmost cells that have been written to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extent: Option<(isize, isize)>,
+}
+
+/// The options a [`Tape`] is created with.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TapeOptions {
+    /// Whether values are output as hexadecimal numbers, one per line, instead of as they are (see
+    /// `--hex-output`).
+    pub hex_output: bool,
+    /// Whether values are not output at all (see `--no-output`).
+    pub silent: bool,
+    /// What `,` does once the input is exhausted.
+    pub eof: Eof,
 }
 
 /// A Brainfuck tape.
 ///
-/// It is infinitely expandable in both directions, and each cell contains a `u8`.
-pub struct Tape {
+/// It is infinitely expandable in both directions, and each cell contains a `u8` unless another
+/// [`Cell`] type is passed.
+///
+/// Cells are 0 until they are written to, and only the cells between the leftmost and the rightmost
+/// ones that were accessed are allocated, so the pointer may move anywhere. Values are read from
+/// an iterator and written to a writer, which are `stdin` and `stdout` unless set otherwise. They
+/// may borrow data for the lifetime `'io`, such as an in-memory input.
+pub struct Tape<'io, C: Cell = u8> {
     /// The current position of the pointer.
     pointer: isize,
     /// The values of the cells.
-    values: Vec<u8>,
+    values: Vec<C>,
     /// Index of the value that corresponds to the initial cell.
     origin: isize,
     /// The output mode.
     output_mode: OutputMode,
     /// The iterator [`Tape::input`]  should read from.
-    stdin: Box<dyn Iterator<Item=u8>>,
+    stdin: Input<'io>,
+    /// What [`Tape::input`] does once `stdin` is exhausted.
+    eof: Eof,
     /// The file [`Tape::output`]  should write to.
-    stdout: Box<dyn io::Write>,
+    stdout: Box<dyn io::Write + 'io>,
+    /// The lowest byte of the last value written by [`Tape::output`], if any.
+    last_output: Option<u8>,
+    /// The number of values written by [`Tape::output`] so far.
+    output_count: u64,
+    /// The prompt [`Tape::input`] should write before reading a new line, along with where to
+    /// write it.
+    prompt: Option<(String, Box<dyn io::Write + 'io>)>,
+    /// Whether the next value read by [`Tape::input`] is the first one of a line.
+    is_at_line_start: bool,
+    /// Whether input is read key by key rather than line by line (see [`Tape::set_key_input`]).
+    is_reading_keys: bool,
+    /// The leftmost and rightmost positions of the pointer so far.
+    pointer_extent: (isize, isize),
 }
 
-impl Default for Tape {
+impl<C: Cell> fmt::Debug for Tape<'_, C> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tape")
+            .field("pointer", &self.pointer)
+            .field("allocated_cells", &self.values.len())
+            .field("output_count", &self.output_count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: Cell> Default for Tape<'_, C> {
     fn default() -> Self {
         Self {
             pointer: 0,
@@ -44,30 +181,326 @@ impl Default for Tape {
             origin: 0,
             output_mode: OutputMode::Ascii,
             stdin: default_stdin(),
+            eof: Eof::Zero,
             stdout: default_stdout(),
+            last_output: None,
+            output_count: 0,
+            prompt: None,
+            is_at_line_start: true,
+            is_reading_keys: false,
+            pointer_extent: (0, 0),
         }
     }
 }
 
-impl Tape {
-    pub fn new(hex_output: bool, silent: bool) -> Self {
+impl<'io> Tape<'io> {
+    /// Creates a tape of bytes (see [`Tape::with_cells`] for wider cells).
+    pub fn new(options: &TapeOptions) -> Self {
+        Self::with_cells(options)
+    }
+}
+
+impl<'io, C: Cell> Tape<'io, C> {
+    /// Creates a tape whose cells are of type `C`.
+    pub fn with_cells(options: &TapeOptions) -> Self {
         let output_mode =
-            if silent {
+            if options.silent {
                 OutputMode::Silent
-            } else if hex_output {
+            } else if options.hex_output {
                 OutputMode::Hex
             } else {
                 OutputMode::Ascii
             };
         Self {
             output_mode,
+            eof: options.eof,
             ..Self::default()
         }
     }
 
+    /// Creates a tape that neither reads input nor writes output.
+    ///
+    /// This is used for the inactive tape of the `dual` dialect, whose cells are exchanged with the
+    /// active tape using [`Tape::swap_cells`].
+    pub fn detached() -> Self {
+        Self {
+            pointer: 0,
+            values: Vec::new(),
+            origin: 0,
+            output_mode: OutputMode::Silent,
+            stdin: Box::new(std::iter::empty()),
+            eof: Eof::Zero,
+            stdout: Box::new(io::sink()),
+            last_output: None,
+            output_count: 0,
+            prompt: None,
+            is_at_line_start: true,
+            is_reading_keys: false,
+            pointer_extent: (0, 0),
+        }
+    }
+
+    /// Exchanges the cells and the pointer of this tape with those of another tape. The input and
+    /// the output of each tape are left unchanged.
+    pub fn swap_cells(&mut self, other: &mut Tape<'_, C>) {
+        std::mem::swap(&mut self.pointer, &mut other.pointer);
+        std::mem::swap(&mut self.values, &mut other.values);
+        std::mem::swap(&mut self.origin, &mut other.origin);
+        std::mem::swap(&mut self.pointer_extent, &mut other.pointer_extent);
+    }
+
+    /// Returns the contents of this tape.
+    pub fn state(&self) -> TapeState<C> {
+        let extent = self.written_extent();
+        let cells = match extent {
+            Some((first, last)) => (first..=last).map(|i| (i, self.read_cell(i))).filter(|(_, value)| !value.is_zero()).collect(),
+            None => BTreeMap::new(),
+        };
+        TapeState {
+            pointer: self.pointer,
+            cells,
+            extent,
+        }
+    }
+
+    /// Replaces the contents of this tape with the passed state.
+    pub fn load_state(&mut self, state: &TapeState<C>) {
+        self.reset();
+        self.right_by(state.pointer);
+        if let Some((first, last)) = state.extent {
+            self.extend_to_index(first);
+            self.extend_to_index(last);
+        }
+        for (&index, value) in &state.cells {
+            *self.get_cell(index) = value.clone();
+        }
+    }
+
+    /// Puts this tape back in its initial state: all cells are 0, the pointer is on cell 0, and
+    /// nothing has been output yet. The input and the output are left unchanged.
+    ///
+    /// At most [`RESET_CAPACITY`] cells stay allocated, so that a tape reused after a program
+    /// wandered far away does not keep all the memory it used.
+    pub fn reset(&mut self) {
+        self.values.clear();
+        self.values.shrink_to(RESET_CAPACITY);
+        self.origin = 0;
+        self.pointer = 0;
+        self.pointer_extent = (0, 0);
+        self.last_output = None;
+        self.output_count = 0;
+        self.is_at_line_start = true
+    }
+
+    /// Frees the cells that are 0 at both ends of this tape, such as after a program wandered far
+    /// away and came back, without changing the values of the cells.
+    ///
+    /// The current cell is always kept allocated, even if it is 0, as most accesses are to the
+    /// current cell and only need a single bounds check when it is allocated. Cells that are freed
+    /// no longer count as written (see [`Tape::written_extent`]).
+    pub fn compact(&mut self) {
+        if self.values.is_empty() {
+            self.values.shrink_to_fit();
+            return;
+        }
+        let first = self.values.iter().position(|value| !value.is_zero()).map_or(self.pointer, |i| min(i as isize - self.origin, self.pointer));
+        let last = self.values.iter().rposition(|value| !value.is_zero()).map_or(self.pointer, |i| max(i as isize - self.origin, self.pointer));
+        let mut values = Vec::with_capacity((last - first + 1) as usize);
+        values.extend((first..=last).map(|i| self.read_cell(i)));
+        self.values = values;
+        self.origin = -first
+    }
+
+    /// Makes [`Tape::input`] read from the passed iterator instead of `stdin`.
+    pub fn set_input(&mut self, stdin: Box<dyn Iterator<Item=u8> + 'io>) {
+        self.stdin = Box::new(stdin.map(Ok))
+    }
+
+    /// Makes [`Tape::input`] read from the passed iterator instead of `stdin`, stopping the program
+    /// at the first error.
+    pub fn set_fallible_input(&mut self, stdin: Input<'io>) {
+        self.stdin = stdin
+    }
+
+    /// Makes [`Tape::input`] read from the passed reader instead of `stdin`, stopping the program
+    /// if reading fails. The reader is buffered.
+    pub fn set_reader(&mut self, reader: impl Read + 'io) {
+        self.stdin = Box::new(BufReader::new(reader).bytes())
+    }
+
+    /// Makes [`Tape::output`] write to the passed writer instead of `stdout`, in the same format.
+    pub fn set_output(&mut self, stdout: Box<dyn io::Write + 'io>) {
+        self.stdout = stdout
+    }
+
+    /// Makes [`Tape::output`] write values as they are to the passed file instead of `stdout`.
+    pub fn set_raw_output(&mut self, stdout: Box<dyn io::Write + 'io>) {
+        self.stdout = stdout;
+        self.output_mode = OutputMode::Raw
+    }
+
+    /// Sets the prompt to write to `out` (usually `stderr`) before reading a new line of input.
+    ///
+    /// Input is only read from `stdin` once a whole line is available, so the prompt is written
+    /// exactly when the program would start waiting for the user.
+    pub fn set_prompt(&mut self, prompt: String, out: Box<dyn io::Write + 'io>) {
+        self.prompt = Some((prompt, out))
+    }
+
+    /// Makes [`Tape::input`] read from the iterator returned by `f` when passed the current input.
+    ///
+    /// The iterator passed to `f` ends at the first error of the current input, which is then read
+    /// once the returned iterator ends.
+    pub fn map_input<I: Iterator<Item=u8> + 'io>(&mut self, f: impl FnOnce(Box<dyn Iterator<Item=u8> + 'io>) -> I) {
+        let stdin = std::mem::replace(&mut self.stdin, Box::new(std::iter::empty()));
+        let error = std::rc::Rc::new(std::cell::Cell::new(None));
+        let values = {
+            let error = std::rc::Rc::clone(&error);
+            stdin.map_while(move |value| value.map_err(|e| error.set(Some(e))).ok())
+        };
+        self.stdin = Box::new(f(Box::new(values)).map(Ok).chain(std::iter::from_fn(move || error.take().map(Err))))
+    }
+
+    /// Makes [`Tape::input`] flush the output before reading each value instead of each line, as
+    /// the terminal passes keys as soon as they are pressed (see `--game`).
+    pub fn set_key_input(&mut self) {
+        self.is_reading_keys = true
+    }
+
+    /// Makes [`Tape::input`] stop the program with [`Stop::InputTimeout`] if no value is available
+    /// from `stdin` within `timeout`.
+    ///
+    /// Fails if the platform cannot wait for input with a timeout (see [`stdin`]), in which case
+    /// `stdin` is read without it.
+    pub fn set_input_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        // Release the lock on `stdin` first, as the helper thread needs it.
+        self.stdin = Box::new(std::iter::empty());
+        match stdin_with_timeout(timeout) {
+            Ok(stdin) => {
+                self.stdin = stdin;
+                Ok(())
+            }
+            Err(error) => {
+                self.stdin = default_stdin();
+                Err(error)
+            }
+        }
+    }
+
+    /// Returns a canonical representation of the contents of this tape, which only depends on the
+    /// values of the cells and on the position of the pointer relative to them, and not on how the
+    /// tape is stored.
+    ///
+    /// It is made of the offset of the pointer from the leftmost non-zero cell (or from cell 0 if
+    /// all cells are 0), as 8 little-endian bytes, followed by the values of the cells from the
+    /// leftmost to the rightmost non-zero cells (both included), which are bytes unless cells are
+    /// wider (see [`Cell::extend_canonical`]).
+    pub fn canonical_state(&self) -> Vec<u8> {
+        let first = self.values.iter().position(|value| !value.is_zero());
+        let last = self.values.iter().rposition(|value| !value.is_zero());
+        let (offset, cells) = match (first, last) {
+            (Some(first), Some(last)) => (self.pointer - (first as isize - self.origin), &self.values[first..=last]),
+            _ => (self.pointer, &[][..]),
+        };
+        let mut state = (offset as i64).to_le_bytes().to_vec();
+        for value in cells {
+            value.extend_canonical(&mut state)
+        }
+        state
+    }
+
+    /// Returns the number of cells that have been allocated so far.
+    ///
+    /// This is the number of cells between the leftmost and the rightmost cells that have been
+    /// accessed, as the tape only shrinks when it is reset or compacted.
+    pub fn allocated_cells(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns the number of cells this tape has memory for, which is at least the number of
+    /// allocated cells (see [`Tape::allocated_cells`]).
+    pub fn capacity(&self) -> usize {
+        self.values.capacity()
+    }
+
+    /// Returns the current position of the pointer.
+    pub fn pointer(&self) -> isize {
+        self.pointer
+    }
+
+    /// Returns the number of values written by [`Tape::output`] so far, regardless of the output
+    /// mode.
+    pub fn output_count(&self) -> u64 {
+        self.output_count
+    }
+
+    /// Returns the lowest byte of the last value written by [`Tape::output`], if any.
+    pub fn last_output(&self) -> Option<u8> {
+        self.last_output
+    }
+
+    /// Returns the leftmost and rightmost positions the pointer has reached so far, including the
+    /// cells accessed at an offset from the pointer.
+    pub fn pointer_extent(&self) -> (isize, isize) {
+        let (min_pointer, max_pointer) = self.pointer_extent;
+        match self.written_extent() {
+            Some((min_written, max_written)) => (min(min_pointer, min_written), max(max_pointer, max_written)),
+            None => (min_pointer, max_pointer),
+        }
+    }
+
+    /// Returns the leftmost and rightmost cells that have been written to so far, if any.
+    pub fn written_extent(&self) -> Option<(isize, isize)> {
+        // Only written cells are allocated, and the tape is allocated as tightly as possible.
+        (!self.values.is_empty()).then(|| (self.first_index(), self.last_index()))
+    }
+
     /// Moves the cell pointer to the right by a specific amount.
     pub fn right_by(&mut self, amount: isize) {
         self.pointer += amount;
+        let (min_pointer, max_pointer) = &mut self.pointer_extent;
+        *min_pointer = min(*min_pointer, self.pointer);
+        *max_pointer = max(*max_pointer, self.pointer);
+    }
+
+    /// Moves the pointer by `stride` cells at a time (to the left if negative) until the current
+    /// cell is 0.
+    ///
+    /// Cells that are not allocated are 0, so the scan stops at the first one it reaches, without
+    /// allocating it. Only the allocated cells are searched, and with a stride of 1 or -1, they are
+    /// searched a chunk at a time.
+    pub fn scan(&mut self, stride: isize) {
+        /// Returns the number of cells before the first one that is 0, or the number of cells if
+        /// none is.
+        fn count_non_zero<'a, C: Cell>(mut cells: impl ExactSizeIterator<Item=&'a C>) -> usize {
+            let length = cells.len();
+            cells.position(|value| value.is_zero()).unwrap_or(length)
+        }
+
+        let zero = C::default();
+        let start = self.position(self.pointer);
+        if start >= self.values.len() {
+            return;
+        }
+        let count = match stride {
+            1 => {
+                let cells = &self.values[start..];
+                match cells.chunks(SCAN_CHUNK_LENGTH).position(|chunk| chunk.contains(&zero)) {
+                    Some(i) => i * SCAN_CHUNK_LENGTH + count_non_zero(cells[i * SCAN_CHUNK_LENGTH..].iter()),
+                    None => cells.len(),
+                }
+            }
+            -1 => {
+                let cells = &self.values[..=start];
+                match cells.rchunks(SCAN_CHUNK_LENGTH).position(|chunk| chunk.contains(&zero)) {
+                    Some(i) => i * SCAN_CHUNK_LENGTH + count_non_zero(cells[..cells.len() - i * SCAN_CHUNK_LENGTH].iter().rev()),
+                    None => cells.len(),
+                }
+            }
+            _ if stride > 0 => count_non_zero(self.values[start..].iter().step_by(stride.unsigned_abs())),
+            _ => count_non_zero(self.values[..=start].iter().rev().step_by(stride.unsigned_abs())),
+        };
+        self.right_by(count as isize * stride)
     }
 
     fn first_index(&self) -> isize {
@@ -78,32 +511,44 @@ impl Tape {
         (min(self.values.len(), isize::MAX as usize) as isize) - self.origin - 1
     }
 
+    /// Returns the position in [`Tape::values`] of the cell at some index, which is out of bounds
+    /// (possibly because it wrapped around) if the cell has not been allocated.
+    ///
+    /// The pointer almost always stays within the allocated cells, so checking this position
+    /// against the length of [`Tape::values`] is the only check most accesses need.
+    fn position(&self, index: isize) -> usize {
+        self.origin.wrapping_add(index) as usize
+    }
+
     /// Gets the value of a cell.
-    fn read_cell(&self, index: isize) -> u8 {
-        if (self.first_index()..=self.last_index()).contains(&index) {
-            self.values[(self.origin + index) as usize]
-        } else {
-            0
-        }
+    fn read_cell(&self, index: isize) -> C {
+        self.values.get(self.position(index)).cloned().unwrap_or_default()
     }
 
     /// Returns the value of the cell to the right of the pointer by a specific offset.
-    pub fn read_relative(&self, offset: isize) -> u8 {
+    pub fn read_relative(&self, offset: isize) -> C {
         self.read_cell(self.pointer + offset)
     }
 
-    /// Returns the value of the current cell as a `u8`.
-    pub fn read(&self) -> u8 {
+    /// Returns the value of the current cell.
+    pub fn read(&self) -> C {
         self.read_relative(0)
     }
 
+    /// Tests if the current cell is 0, without copying its value.
+    pub fn is_zero(&self) -> bool {
+        self.values.get(self.position(self.pointer)).is_none_or(Cell::is_zero)
+    }
+
     /// Extends the tape to make the specified index valid in the underlying vector.
     fn extend_to_index(&mut self, index: isize) {
-        if index > self.last_index() {
-            self.values.resize((self.origin + index + 1) as usize, 0)
+        if self.values.is_empty() {
+            self.values.push(C::default());
+            self.origin = -index
+        } else if index > self.last_index() {
+            self.values.resize((self.origin + index + 1) as usize, C::default())
         } else if index < self.first_index() {
-            let mut new_values: Vec<u8> = Vec::new();
-            new_values.resize((-index - self.origin) as usize, 0);
+            let mut new_values = vec![C::default(); (-index - self.origin) as usize];
             new_values.append(&mut self.values);
             self.values = new_values;
             self.origin += -index - self.origin
@@ -111,13 +556,17 @@ impl Tape {
     }
 
     /// Returns a mutable reference to a cell.
-    fn get_cell(&mut self, index: isize) -> &mut u8 {
-        self.extend_to_index(index);
-        &mut self.values[(self.origin + index) as usize]
+    fn get_cell(&mut self, index: isize) -> &mut C {
+        let mut position = self.position(index);
+        if position >= self.values.len() {
+            self.extend_to_index(index);
+            position = self.position(index)
+        }
+        &mut self.values[position]
     }
 
     /// Returns a mutable reference to the slice from `from` to `to` (both included).
-    fn get_slice(&mut self, from: isize, to: isize) -> &mut [u8] {
+    fn get_slice(&mut self, from: isize, to: isize) -> &mut [C] {
         assert!(from <= to);
         self.extend_to_index(to);
         self.extend_to_index(from);
@@ -126,64 +575,224 @@ impl Tape {
     }
 
     /// Sets the value of the cell to the right of the pointer by the specified offset.
-    pub fn write_relative(&mut self, offset: isize, value: u8) {
+    pub fn write_relative(&mut self, offset: isize, value: C) {
         let cell = self.get_cell(self.pointer + offset);
         *cell = value;
     }
 
     /// Sets the value of the current cell.
-    pub fn write(&mut self, value: u8) {
+    pub fn write(&mut self, value: C) {
         self.write_relative(0, value)
     }
 
     /// Fills the values of the cells between the current cell and the cell to the right of the
     /// pointer by the specified offset (both included) with a specific value.
-    pub fn fill(&mut self, max_offset: isize, value: u8) {
+    pub fn fill(&mut self, max_offset: isize, value: C) {
         let from = min(self.pointer, self.pointer + max_offset);
         let to = max(self.pointer, self.pointer + max_offset);
         let slice = self.get_slice(from, to);
         slice.fill(value)
     }
 
+    /// Sets the values of the cells between the current cell and the cell to the right of the
+    /// pointer by the specified offset (both included) to 0.
+    ///
+    /// Cells that are not allocated are already 0, so they are left unallocated, and the written
+    /// cells stay the same as if each cell that is not 0 was reset on its own.
+    pub fn clear(&mut self, max_offset: isize) {
+        let from = max(min(self.pointer, self.pointer + max_offset), self.first_index());
+        let to = min(max(self.pointer, self.pointer + max_offset), self.last_index());
+        if from <= to {
+            self.get_slice(from, to).fill(C::default())
+        }
+    }
+
+    /// Sets the values of the cells starting at a specific index (regardless of the position of the
+    /// pointer).
+    pub fn write_slice(&mut self, from: isize, values: &[C]) {
+        if !values.is_empty() {
+            self.get_slice(from, from + values.len() as isize - 1).clone_from_slice(values)
+        }
+    }
+
     /// Adds a specific amount to the value of the cell to the right of the pointer by the specified
     /// offset.
-    pub fn add(&mut self, offset: isize, amount: u8) {
+    ///
+    /// Amounts are read as [`Command::Add`](crate::Command::Add) stores them (see [`Cell`]).
+    pub fn add(&mut self, offset: isize, amount: u64) {
+        self.get_cell(self.pointer + offset).add(amount)
+    }
+
+    /// Adds the value of a cell to the value of the cell to the right of the pointer by the
+    /// specified offset.
+    pub fn add_cell(&mut self, offset: isize, value: &C) {
         let cell = self.get_cell(self.pointer + offset);
-        *cell = cell.wrapping_add(amount);
+        *cell = cell.plus(value)
+    }
+
+    /// Adds a specific amount to `length` adjacent cells, starting with the cell to the right of the
+    /// pointer by the specified offset.
+    pub fn add_span(&mut self, offset: isize, length: usize, amount: &C) {
+        let from = self.pointer + offset;
+        for cell in self.get_slice(from, from + length as isize - 1) {
+            *cell = cell.plus(amount)
+        }
     }
 
     /// Outputs the value of the current cell to this tape's `stdout`.
-    pub fn output(&mut self) {
-        match self.output_mode {
-            OutputMode::Ascii => write!(self.stdout, "{}", self.read() as char).unwrap(),
-            OutputMode::Hex => writeln!(self.stdout, "0x{:02x}", self.read()).unwrap(),
-            _ => {}
+    ///
+    /// Cells wider than bytes output their lowest byte, except with `--hex-output`.
+    ///
+    /// Returns [`ControlFlow::Break`] with [`Stop::OutputError`] if writing fails.
+    pub fn output(&mut self) -> ControlFlow<Stop> {
+        let value = self.read();
+        self.last_output = Some(value.low_byte());
+        self.output_count += 1;
+        let result = match self.output_mode {
+            OutputMode::Ascii => self.stdout.write_all(&[value.low_byte()]),
+            OutputMode::Hex => value.write_hex(&mut self.stdout),
+            OutputMode::Raw => self.stdout.write_all(&[value.low_byte()]),
+            _ => Ok(()),
+        };
+        match result {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(error) => ControlFlow::Break(Stop::OutputError(error.kind())),
         }
     }
 
-    /// Sets the value of the current cell from this tape's `stdin`.
-    pub fn input(&mut self) {
-        let value = self.stdin.next().unwrap();
-        self.write_relative(0, value)
+    /// Writes some text to this tape's `stdout` as it is, unless it is silent. The text does not
+    /// count as output by the program.
+    pub fn print(&mut self, text: &str) -> io::Result<()> {
+        if !matches!(self.output_mode, OutputMode::Silent) {
+            self.stdout.write_all(text.as_bytes())?
+        }
+        Ok(())
+    }
+
+    /// Outputs a newline to this tape's `stdout` if the last value it output was not already a
+    /// newline. Nothing is output if nothing was output before, or if the output mode is not ASCII
+    /// (as the other modes already output full lines).
+    pub fn finish_line(&mut self) -> io::Result<()> {
+        if let (OutputMode::Ascii, Some(value)) = (self.output_mode, self.last_output) {
+            if value != b'\n' {
+                writeln!(self.stdout)?;
+                self.last_output = Some(b'\n')
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes this tape's `stdout`.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+
+    /// Sets the value of the current cell from this tape's `stdin`, or as set by `--eof` at the end
+    /// of the input.
+    ///
+    /// Returns [`ControlFlow::Break`] if the program should stop, as it reached the end of the
+    /// input with `--eof error` or reading failed.
+    pub fn input(&mut self) -> ControlFlow<Stop> {
+        match self.next_input()? {
+            Some(value) => {
+                self.write_relative(0, C::from_byte(value));
+                ControlFlow::Continue(())
+            }
+            None => self.end_of_input(),
+        }
+    }
+
+    /// Handles `,` at the end of the input like [`Tape::input`], for callers that read the input
+    /// with [`Tape::next_input`].
+    pub fn end_of_input(&mut self) -> ControlFlow<Stop> {
+        match self.eof {
+            Eof::Zero => self.write_relative(0, C::default()),
+            Eof::MinusOne => self.write_relative(0, C::from_amount(u64::MAX)),
+            Eof::Unchanged => {}
+            Eof::Error => return ControlFlow::Break(Stop::EndOfInput),
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Reads the next value from this tape's `stdin` like [`Tape::input`], without writing it to
+    /// the current cell. Returns [`None`] at the end of the input.
+    pub fn next_input(&mut self) -> ControlFlow<Stop, Option<u8>> {
+        if self.is_at_line_start || self.is_reading_keys {
+            // Make sure the output of the program appears before it waits for a new line of input
+            // (and before the prompt), which also lets a program it is piped to process it in the
+            // meantime. Flushing before each value would be much slower when reading files.
+            if let Err(error) = self.stdout.flush() {
+                return ControlFlow::Break(Stop::OutputError(error.kind()));
+            }
+        }
+        if let (true, Some((prompt, out))) = (self.is_at_line_start, &mut self.prompt) {
+            if let Err(error) = out.write_all(prompt.as_bytes()).and_then(|_| out.flush()) {
+                return ControlFlow::Break(Stop::OutputError(error.kind()));
+            }
+        }
+        match self.stdin.next() {
+            Some(Ok(value)) => {
+                self.is_at_line_start = value == b'\n';
+                ControlFlow::Continue(Some(value))
+            }
+            Some(Err(error)) if error.kind() == io::ErrorKind::TimedOut => ControlFlow::Break(Stop::InputTimeout),
+            Some(Err(error)) => ControlFlow::Break(Stop::InputError(error.kind())),
+            None => ControlFlow::Continue(None),
+        }
     }
 }
 
-impl Display for Tape {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        let range = if self.values.is_empty() {
-            0..=0
-        } else {
-            self.first_index()..=self.last_index()
-        };
+impl<C: Cell> Tape<'_, C> {
+    /// Writes the values and indices of the cells in a range, along with their names if some of
+    /// them have one. Columns are widened to fit the names.
+    fn write_cells(&self, f: &mut impl fmt::Write, range: RangeInclusive<isize>, names: &CellNames) -> fmt::Result {
+        let widths: Vec<_> = range.clone().map(|i| names.name(i).map_or(4, |name| name.len().max(4))).collect();
         // Print cell values
-        for i in range.clone() {
-            write!(f, "| 0x{:>02x} ", self.read_cell(i))?;
+        for (i, &width) in range.clone().zip(&widths) {
+            write!(f, "| {:<width$} ", format!("0x{:>02x}", self.read_cell(i)))?;
         }
         writeln!(f, "|")?;
         // Print cell indices
-        for i in range {
-            write!(f, "  {:>4} ", i)?;
+        for (i, &width) in range.clone().zip(&widths) {
+            write!(f, "  {:>width$} ", i)?;
+        }
+        // Print cell names
+        if range.clone().any(|i| names.name(i).is_some()) {
+            writeln!(f)?;
+            for (i, &width) in range.zip(&widths) {
+                write!(f, "  {:>width$} ", names.name(i).unwrap_or(""))?;
+            }
         }
         Ok(())
     }
+
+    /// Formats the cells up to `radius` cells away from the pointer, like [`Tape::dump`] formats
+    /// the whole tape.
+    pub fn window(&self, radius: isize, names: &CellNames) -> String {
+        let mut window = String::new();
+        self.write_cells(&mut window, self.pointer - radius..=self.pointer + radius, names).unwrap();
+        window
+    }
+
+    /// Formats the whole tape like [`Display`], along with the names of the cells.
+    pub fn dump(&self, names: &CellNames) -> String {
+        let mut dump = String::new();
+        self.write_cells(&mut dump, self.range(), names).unwrap();
+        dump
+    }
+
+    /// Returns the range of the cells shown when formatting the whole tape.
+    fn range(&self) -> RangeInclusive<isize> {
+        if self.values.is_empty() {
+            0..=0
+        } else {
+            self.first_index()..=self.last_index()
+        }
+    }
+}
+
+impl<C: Cell> Display for Tape<'_, C> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.write_cells(f, self.range(), &CellNames::default())
+    }
 }
@@ -0,0 +1,68 @@
+use std::{io, process};
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+use brainfuck_interpreter::tape::Input;
+
+/// Connects to the specified address (see `--io-tcp`).
+pub fn connect(address: &str) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect(address).map_err(|error| context(error, format_args!("unable to connect to {}", address)))?;
+    configure(stream)
+}
+
+/// Waits for a single connection on the specified port (see `--io-listen`).
+pub fn accept(port: u16) -> io::Result<TcpStream> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).map_err(|error| context(error, format_args!("unable to listen on port {}", port)))?;
+    let (stream, _) = listener.accept().map_err(|error| context(error, format_args!("unable to accept a connection on port {}", port)))?;
+    configure(stream)
+}
+
+fn configure(stream: TcpStream) -> io::Result<TcpStream> {
+    // The program's output is already buffered until it reads a new line of input, so waiting to
+    // fill bigger segments would only make it less interactive.
+    stream.set_nodelay(true).map_err(|error| context(error, format_args!("unable to configure the connection")))?;
+    Ok(stream)
+}
+
+fn context(error: io::Error, context: std::fmt::Arguments) -> io::Error {
+    io::Error::new(error.kind(), format!("{}: {}", context, error))
+}
+
+/// Returns an iterator over the bytes received through a connection, which ends when the peer
+/// closes its side of the connection.
+pub fn bytes(stream: &TcpStream) -> io::Result<Input<'static>> {
+    let stream = stream.try_clone().map_err(|error| context(error, format_args!("unable to read from the connection")))?;
+    Ok(Box::new(BufReader::new(stream).bytes()))
+}
+
+/// The sending side of a connection, which ends the process once the peer has disconnected, as
+/// there is no one left to see the output of the program.
+#[derive(Debug)]
+pub struct Sender {
+    stream: TcpStream,
+}
+
+impl Sender {
+    pub fn new(stream: TcpStream) -> Self {
+        Self { stream }
+    }
+
+    /// Ends the process if `error` means that the peer has disconnected, and returns it otherwise.
+    fn check(error: io::Error) -> io::Error {
+        if matches!(error.kind(), ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted) {
+            eprintln!("Connection closed by peer");
+            process::exit(0)
+        }
+        error
+    }
+}
+
+impl Write for Sender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf).map_err(Self::check)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush().map_err(Self::check)
+    }
+}
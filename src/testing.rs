@@ -0,0 +1,245 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::iter;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+
+use brainfuck_interpreter::source::Source;
+use brainfuck_interpreter::{annotation, load, optimize_within_budget, source, Dialect, Stop};
+use crate::args::{ArgValue, Args};
+use crate::generate::unescape;
+use crate::{collect_names, expand_macros, run_captured};
+
+/// A test written in the source code of a program as a `{test ...}` annotation (see `--test`).
+#[derive(Debug, Clone)]
+struct TestCase {
+    name: String,
+    /// The line of the annotation in the source code.
+    line: usize,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    /// The number of steps the program may execute, if the annotation sets one.
+    steps: Option<u64>,
+    /// The dialect the program is written in, if the annotation sets one.
+    dialect: Option<Dialect>,
+    /// Whether macros are expanded, if the annotation tells.
+    macros: Option<bool>,
+}
+
+impl TestCase {
+    /// Parses what follows `test` in an annotation, which is made of `KEY="VALUE"` pairs with the
+    /// keys `name`, `input`, `output`, `steps`, `dialect`, and `macros` (`true` or `false`). Only
+    /// `output` is required, and the last three keys override the passed options.
+    ///
+    /// Values may contain the escape sequences of `--generate`, but neither `"` nor `}`, which can
+    /// be written `\x22` and `\x7d`.
+    fn parse(text: &str, default_name: String, line: usize) -> Result<Self, String> {
+        let mut case = Self {
+            name: default_name,
+            line,
+            input: Vec::new(),
+            output: Vec::new(),
+            steps: None,
+            dialect: None,
+            macros: None,
+        };
+        let mut keys = Vec::new();
+        let mut rest = text.trim_start();
+        while !rest.is_empty() {
+            let (key, value) = rest.split_once('=').filter(|(key, _)| !key.contains(char::is_whitespace)).ok_or_else(|| format!("expected `KEY=\"VALUE\"`, found `{}`", rest))?;
+            let (value, remaining) = value.strip_prefix('"').and_then(|value| value.split_once('"')).ok_or_else(|| format!("expected a quoted value for `{}`", key))?;
+            if keys.contains(&key) {
+                return Err(format!("`{}` is set twice", key));
+            }
+            match key {
+                "name" => case.name = value.to_string(),
+                "input" => case.input = unescape(value)?,
+                "output" => case.output = unescape(value)?,
+                "steps" => case.steps = Some(value.parse().map_err(|_| format!("invalid number of steps `{}`", value))?),
+                "dialect" => case.dialect = Some(Dialect::from_name(value).ok_or_else(|| format!("unknown dialect `{}`", value))?),
+                "macros" => case.macros = Some(value.parse().map_err(|_| format!("expected `true` or `false` for `macros`, found `{}`", value))?),
+                _ => return Err(format!("unknown key `{}`", key)),
+            }
+            keys.push(key);
+            rest = remaining.trim_start()
+        }
+        if !keys.contains(&"output") {
+            return Err("the expected output is missing".to_string());
+        }
+        Ok(case)
+    }
+}
+
+/// Collects the tests written in some source code, in order. Tests without a name are named after
+/// their position in the file.
+fn collect(code: &[u8]) -> Result<Vec<TestCase>, String> {
+    let mut cases = Vec::new();
+    let mut position = 0;
+    while let Some(start) = code[position..].iter().position(|&byte| byte == b'{') {
+        position += start + 1;
+        if annotation(&code[position..]) != Some(b"test") {
+            continue;
+        }
+        let (line, column) = source::line_column(code, position - 1);
+        // Annotations are always closed (see `annotation`).
+        let length = code[position..].iter().position(|&byte| byte == b'}').unwrap_or(code.len() - position);
+        let text = String::from_utf8_lossy(&code[position + b"test".len()..position + length]);
+        let case = TestCase::parse(&text, format!("test {}", cases.len() + 1), line).map_err(|error| format!("invalid test at {}:{}: {}", line, column, error))?;
+        cases.push(case);
+        position += length + 1
+    }
+    Ok(cases)
+}
+
+/// The number of lines of the outputs shown from the first line that differs, when a test fails.
+const SHOWN_LINES: usize = 8;
+
+/// The number of bytes shown of each line of the outputs, when a test fails.
+const SHOWN_LINE_LENGTH: usize = 64;
+
+/// Writes the lines of the expected output and of the actual one up to a few lines after the first
+/// one that differs, marking the lines that only appear in either of them with `-` and `+`
+/// respectively.
+fn write_diff(expected: &[u8], actual: &[u8], out: &mut impl Write) -> io::Result<()> {
+    let expected_lines: Vec<&[u8]> = expected.split_inclusive(|&byte| byte == b'\n').collect();
+    let actual_lines: Vec<&[u8]> = actual.split_inclusive(|&byte| byte == b'\n').collect();
+    let line_count = expected_lines.len().max(actual_lines.len());
+    let first_different = (0..line_count).find(|&i| expected_lines.get(i) != actual_lines.get(i)).unwrap_or(line_count);
+    let shown_count = line_count.min(first_different + SHOWN_LINES);
+    let show = |line: &[u8]| {
+        let ellipsis = if line.len() > SHOWN_LINE_LENGTH { "..." } else { "" };
+        format!("\"{}\"{}", line[..line.len().min(SHOWN_LINE_LENGTH)].escape_ascii(), ellipsis)
+    };
+    for i in 0..shown_count {
+        match (expected_lines.get(i), actual_lines.get(i)) {
+            (Some(expected), Some(actual)) if expected == actual => writeln!(out, "      {}", show(expected))?,
+            (expected, actual) => {
+                if let Some(expected) = expected {
+                    writeln!(out, "    - {}", show(expected))?
+                }
+                if let Some(actual) = actual {
+                    writeln!(out, "    + {}", show(actual))?
+                }
+            }
+        }
+    }
+    if shown_count < line_count {
+        writeln!(out, "    ({} more lines)", line_count - shown_count)?
+    }
+    let difference = iter::zip(expected, actual).position(|(a, b)| a != b).unwrap_or(expected.len().min(actual.len()));
+    writeln!(out, "    First difference at byte {} (expected {} bytes, got {})", difference, expected.len(), actual.len())
+}
+
+/// Returns the source files whose tests are run when `--test` is passed with some path, which are
+/// the `.b` files it contains if it is a directory.
+fn files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let file = entry?.path();
+        if file.is_file() && file.extension().is_some_and(|extension| extension == "b") {
+            files.push(file)
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Runs the tests written in the source code of a program, or of the programs in a directory, with
+/// the passed options, and writes which ones pass along with the differences in the outputs of the
+/// ones that fail (see `--test`).
+///
+/// Each test runs the program on its input, whose end is handled as `--eof` says, and fails if the
+/// program does not output exactly the expected bytes before running out of steps.
+///
+/// Returns whether all tests passed.
+pub fn run(path: &Path, args: &Args, seed: u64, out: &mut impl Write) -> io::Result<bool> {
+    let mut passed = 0;
+    let mut failed = 0;
+    for file in files(path)? {
+        // The error names the file.
+        let source = match Source::read(&file, false) {
+            Ok(source) => source,
+            Err(error) => {
+                writeln!(out, "{}", error)?;
+                failed += 1;
+                continue;
+            }
+        };
+        let code = source::blank_shebang(source.as_bytes());
+        let code = &*code;
+        // Tests are collected before expanding macros, so that their lines are those of the file.
+        let cases = match collect(code) {
+            Ok(cases) => cases,
+            Err(error) => {
+                writeln!(out, "{}: {}", file.display(), error)?;
+                failed += 1;
+                continue;
+            }
+        };
+        if cases.is_empty() {
+            // Directories may contain programs without tests.
+            if !path.is_dir() {
+                writeln!(out, "{}: no tests found", file.display())?
+            }
+            continue;
+        }
+        writeln!(out, "{}:", file.display())?;
+        for case in cases {
+            let mut args = args.clone();
+            args.dialect = case.dialect.unwrap_or(args.dialect);
+            args.macros = case.macros.unwrap_or(args.macros);
+            let expanded_code;
+            let code = if args.macros {
+                expanded_code = expand_macros(code);
+                expanded_code.as_bytes()
+            } else {
+                code
+            };
+            let commands = match load(code, &args.parse_options(), &collect_names(code)) {
+                Ok(commands) => optimize_within_budget(commands, &args.optimize_options()).0,
+                Err(error) => {
+                    failed += 1;
+                    writeln!(out, "  FAIL: {} (line {}): {}", case.name, case.line, error)?;
+                    continue;
+                }
+            };
+            let input = Box::new(case.input.clone().into_iter());
+            let steps = case.steps.unwrap_or(args.test_steps);
+            let (output, machine, result) = run_captured(&commands, input, args.max_output, Some(steps), &args, seed);
+            let failure = match result {
+                ControlFlow::Continue(()) | ControlFlow::Break(Stop::Halt) if output == case.output => None,
+                ControlFlow::Continue(()) | ControlFlow::Break(Stop::Halt) => Some("the output differs".to_string()),
+                ControlFlow::Break(Stop::StepLimit) => Some(format!("the program ran out of steps after {} steps", machine.steps)),
+                ControlFlow::Break(Stop::OutputLimit) => Some("the program tried to output more than `--max-output` values".to_string()),
+                ControlFlow::Break(Stop::AssertionFailed) => machine.failed_assertion.clone(),
+                ControlFlow::Break(Stop::ProtectedWrite) => Some("the program wrote to a protected cell".to_string()),
+                ControlFlow::Break(Stop::Abort | Stop::Cancelled) => Some("the program was aborted".to_string()),
+                ControlFlow::Break(Stop::EndOfInput) => Some("the program read past the end of its input".to_string()),
+                ControlFlow::Break(Stop::InputTimeout) => Some("no input was received in time".to_string()),
+                ControlFlow::Break(Stop::InputError(kind)) => Some(format!("reading the input failed: {}", kind)),
+                ControlFlow::Break(Stop::OutputError(kind)) => Some(format!("writing the output failed: {}", kind)),
+                ControlFlow::Break(Stop::TapeDumpError(kind)) => Some(format!("writing a tape dump failed: {}", kind)),
+            };
+            match failure {
+                None => {
+                    passed += 1;
+                    writeln!(out, "  pass: {}", case.name)?
+                }
+                Some(failure) => {
+                    failed += 1;
+                    writeln!(out, "  FAIL: {} (line {}): {}", case.name, case.line, failure)?;
+                    if output != case.output {
+                        write_diff(&case.output, &output, out)?
+                    }
+                }
+            }
+        }
+    }
+    let result = if failed == 0 { "All tests passed" } else { "Some tests failed" };
+    writeln!(out, "{}: {} passed, {} failed", result, passed, failed)?;
+    Ok(failed == 0)
+}
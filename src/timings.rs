@@ -0,0 +1,75 @@
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The header of the CSV files written by [`append_to_csv`].
+const HEADER: &str = "timestamp,program,program_hash,optimizations,load_us,optimize_us,run_us,steps,peak_cells\n";
+
+/// Information about a run of a program, as recorded by `--time-output`.
+#[derive(Debug)]
+pub struct RunRecord<'a> {
+    /// The path to the source file of the program.
+    pub program: &'a Path,
+    /// A hash of the source code of the program, see [`hash`].
+    pub program_hash: u64,
+    /// The names of the enabled optimizations.
+    pub optimizations: Vec<&'static str>,
+    /// The time it took to load the program.
+    pub load: Duration,
+    /// The time it took to optimize the program.
+    pub optimize: Duration,
+    /// The time it took to run the program.
+    pub run: Duration,
+    /// The number of commands executed.
+    pub steps: u64,
+    /// The number of cells allocated on the tape at the end of the run.
+    pub peak_cells: usize,
+}
+
+/// Returns the 64-bit FNV-1a hash of some bytes (such as source code).
+///
+/// Unlike the hashers of the standard library, this is guaranteed to be stable across versions of
+/// Rust, so hashes recorded in files can be compared.
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Quotes a field of a CSV file if needed.
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Appends a row describing a run to a CSV file, creating it (with a header) if it is empty or does
+/// not exist.
+///
+/// The row is written with a single call, so that concurrent runs appending to the same file do not
+/// interleave their rows.
+pub fn append_to_csv(path: &Path, record: &RunRecord) -> io::Result<()> {
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+    let mut text = if file.metadata()?.len() == 0 {
+        HEADER.to_string()
+    } else {
+        String::new()
+    };
+    // The timestamp is only informative, so a clock set before 1970 is not worth failing for.
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    text.push_str(&format!(
+        "{},{},{:016x},{},{},{},{},{},{}\n",
+        timestamp,
+        quote(&record.program.to_string_lossy()),
+        record.program_hash,
+        record.optimizations.join(";"),
+        record.load.as_micros(),
+        record.optimize.as_micros(),
+        record.run.as_micros(),
+        record.steps,
+        record.peak_cells,
+    ));
+    file.write_all(text.as_bytes())
+}
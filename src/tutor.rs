@@ -0,0 +1,166 @@
+use std::io;
+use std::io::{Read, Write};
+
+use brainfuck_interpreter::annotation;
+use brainfuck_interpreter::tape::Tape;
+
+/// The number of instructions above which `--tutor` refuses to explain a program.
+const MAX_INSTRUCTIONS: usize = 200;
+
+/// The number of steps after which `--tutor` stops explaining a program.
+const MAX_STEPS: u64 = 500;
+
+/// The number of steps between two pictures of the tape.
+const PICTURE_INTERVAL: u64 = 10;
+
+/// An instruction of the program, along with its position in the source code.
+struct Instruction {
+    byte: u8,
+    line: usize,
+    column: usize,
+}
+
+impl Instruction {
+    /// Describes the position of this instruction, leaving the line out if the program only has
+    /// one.
+    fn position(&self, is_single_line: bool) -> String {
+        if is_single_line {
+            format!("column {}", self.column)
+        } else {
+            format!("line {}, column {}", self.line, self.column)
+        }
+    }
+}
+
+/// Describes a value, along with the character it stands for.
+fn describe(value: u8) -> String {
+    format!("{} ({:?})", value, char::from(value))
+}
+
+/// Runs a small program while explaining what each instruction does in plain English (see
+/// `--tutor`).
+///
+/// Only the eight standard instructions are explained, whatever the dialect, and annotations are
+/// skipped like when running the program. The program reads from `stdin`, and what it prints is
+/// shown in the explanation rather than output directly. Programs that are too big, or whose
+/// brackets are not closed, are refused with a message saying why.
+pub fn run(code: &[u8], out: &mut impl Write) -> io::Result<()> {
+    let mut instructions = Vec::new();
+    let (mut line, mut column) = (1, 1);
+    // The position right after the `}` of the annotation being skipped, if any.
+    let mut annotation_end = 0;
+    for (position, &byte) in code.iter().enumerate() {
+        if position < annotation_end {
+            // The instructions in annotations are not instructions of the program.
+        } else if byte == b'{' && annotation(&code[position + 1..]).is_some() {
+            // Annotations are always closed (see `annotation`).
+            annotation_end = position + code[position..].iter().position(|&byte| byte == b'}').unwrap_or(code.len() - position) + 1
+        } else if b"+-<>[].,".contains(&byte) {
+            instructions.push(Instruction { byte, line, column })
+        }
+        if byte == b'\n' {
+            line += 1;
+            column = 1
+        } else {
+            column += 1
+        }
+    }
+    if instructions.len() > MAX_INSTRUCTIONS {
+        return writeln!(out, "This program has {} instructions, but tutor mode only explains programs with up to {} instructions. Try a smaller one!", instructions.len(), MAX_INSTRUCTIONS);
+    }
+    let is_single_line = instructions.iter().all(|instruction| instruction.line == 1);
+
+    // The index of the matching bracket of each bracket.
+    let mut matches = vec![0; instructions.len()];
+    let mut open = Vec::new();
+    for (i, instruction) in instructions.iter().enumerate() {
+        match instruction.byte {
+            b'[' => open.push(i),
+            b']' => {
+                let Some(start) = open.pop() else {
+                    return writeln!(out, "The ']' at {} has no matching '[', so the program cannot run.", instruction.position(is_single_line));
+                };
+                matches[start] = i;
+                matches[i] = start
+            }
+            _ => {}
+        }
+    }
+    if let Some(&start) = open.last() {
+        return writeln!(out, "The '[' at {} has no matching ']', so the program cannot run.", instructions[start].position(is_single_line));
+    }
+
+    let mut tape: Tape = Tape::detached();
+    let mut input = io::stdin().lock().bytes();
+    let mut printed = Vec::new();
+    let mut index = 0;
+    let mut step = 0;
+    while let Some(instruction) = instructions.get(index) {
+        if step == MAX_STEPS {
+            writeln!(out, "The program is still running after {} steps, so tutor mode stops here.", MAX_STEPS)?;
+            break;
+        }
+        step += 1;
+        let cell = tape.pointer();
+        let value = tape.read();
+        let what = match instruction.byte {
+            b'+' => {
+                tape.write(value.wrapping_add(1));
+                let wrap = if value == u8::MAX { " (wrapping around)" } else { "" };
+                format!("increments cell {} from {} to {}{}", cell, value, tape.read(), wrap)
+            }
+            b'-' => {
+                tape.write(value.wrapping_sub(1));
+                let wrap = if value == 0 { " (wrapping around)" } else { "" };
+                format!("decrements cell {} from {} to {}{}", cell, value, tape.read(), wrap)
+            }
+            b'>' => {
+                tape.right_by(1);
+                format!("moves the pointer right, from cell {} to cell {}", cell, cell + 1)
+            }
+            b'<' => {
+                tape.right_by(-1);
+                format!("moves the pointer left, from cell {} to cell {}", cell, cell - 1)
+            }
+            b'[' if value == 0 => {
+                let end = &instructions[matches[index]];
+                index = matches[index];
+                format!("checks cell {}, which is 0, so it skips the loop, up to the ']' at {}", cell, end.position(is_single_line))
+            }
+            b'[' => {
+                format!("checks cell {}, which is {}, so it enters the loop", cell, value)
+            }
+            b']' if value != 0 => {
+                let start = &instructions[matches[index]];
+                index = matches[index];
+                format!("checks cell {}, which is {}, so it goes back to the '[' at {}", cell, value, start.position(is_single_line))
+            }
+            b']' => {
+                format!("checks cell {}, which is 0, so it leaves the loop", cell)
+            }
+            b'.' => {
+                printed.push(value);
+                format!("prints cell {}, which is {}", cell, describe(value))
+            }
+            _ => match input.next().transpose()? {
+                Some(byte) => {
+                    tape.write(byte);
+                    format!("reads {} from the input into cell {}", describe(byte), cell)
+                }
+                None => {
+                    writeln!(out, "step {}: ',' at {} finds no more input, so the program stops", step, instruction.position(is_single_line))?;
+                    break;
+                }
+            },
+        };
+        writeln!(out, "step {}: '{}' at {} {}", step, char::from(instruction.byte), instruction.position(is_single_line), what)?;
+        if step % PICTURE_INTERVAL == 0 {
+            writeln!(out, "\nThe tape now looks like this, and the pointer is on cell {}:\n{}\n", tape.pointer(), tape)?;
+        }
+        index += 1
+    }
+    if index == instructions.len() {
+        writeln!(out, "The program ended after {} steps.", step)?;
+    }
+    writeln!(out, "It printed {:?}.", String::from_utf8_lossy(&printed))
+}
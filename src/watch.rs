@@ -0,0 +1,44 @@
+use std::ops::ControlFlow;
+
+use brainfuck_interpreter::{bytecode, execute_command, Cell, Command, Machine, Stop};
+
+/// Returns the value of a cell of the first tape, which is the other tape of the machine while the
+/// program uses the second one (see the `dual` dialect).
+fn read<C: Cell>(machine: &Machine<'_, C>, cell: isize, is_second_tape: bool) -> C {
+    let tape = match &machine.other_tape {
+        Some(other_tape) if is_second_tape => other_tape,
+        _ => &machine.tape,
+    };
+    tape.read_relative(cell - tape.pointer())
+}
+
+/// Executes commands on a machine like [`execute`](brainfuck_interpreter::execute), but reports on
+/// `stderr` each change to a watched cell, along with the step and the position of the command that
+/// made it (see `--watch`).
+///
+/// The watched cells are read after each command, which is why this is only used when asked to.
+pub fn execute_watched<C: Cell>(commands: &[Command], machine: &mut Machine<'_, C>, cells: &[isize]) -> ControlFlow<Stop> {
+    let mut is_second_tape = false;
+    let mut values: Vec<C> = cells.iter().map(|&cell| read(machine, cell, is_second_tape)).collect();
+    bytecode::execute_with(&bytecode::flatten(commands), machine, |command, machine| {
+        let (step, position) = (machine.steps + 1, machine.position);
+        let result = execute_command(command, machine);
+        if let Command::SwitchTape = command {
+            is_second_tape = !is_second_tape
+        }
+        for (&cell, value) in cells.iter().zip(&mut values) {
+            let new_value = read(machine, cell, is_second_tape);
+            if new_value != *value {
+                let location = match position {
+                    Some((line, column)) => format!("{} at line {}, column {}", command.name(), line, column),
+                    None => command.name().to_string(),
+                };
+                // The output only needs to appear before the report if it can be written.
+                let _ = machine.tape.flush();
+                eprintln!("Watch: {} changed from {} to {} at step {} ({})", machine.names.describe(cell), value, new_value, step, location);
+                *value = new_value
+            }
+        }
+        result
+    })
+}
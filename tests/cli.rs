@@ -0,0 +1,1308 @@
+//! Tests of the command-line interface, which run the interpreter as a separate process.
+
+mod common;
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use common::{failure, program, run, stdout, CAT, HELLO_WORLD};
+
+// The interpreter exits with status 124 when no input is received in time.
+#[test]
+fn input_timeout_exits_with_status_124() {
+    let path = program("timeout_cat.b", CAT);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainfuck-interpreter"))
+        .arg(&path)
+        .args(["--input-timeout", "100ms"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Unable to start interpreter");
+    // `stdin` stays open, without any input, until the interpreter exits.
+    let stdin = child.stdin.take();
+    let output = child.wait_with_output().expect("Unable to run interpreter");
+    drop(stdin);
+    assert_eq!(output.status.code(), Some(124));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: no input received within 100ms\n")
+}
+
+// Every mode walking the loops of a program handles deeply nested loops, instead of
+// overflowing the stack.
+#[test]
+fn every_mode_handles_deeply_nested_loops() {
+    const DEPTH: usize = 200_000;
+    let path = program("deep.b", &format!("{}{}+.", "+[>".repeat(DEPTH), "<-]".repeat(DEPTH)));
+    let emitted = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("deep.out");
+    let modes: [&[&str]; 13] = [
+        &[],
+        &["--flatten-loops", "false"],
+        &["--profile-commands"],
+        &["--lint"],
+        &["--lint", "--diagnostics", "json"],
+        &["--analyze"],
+        &["--summary"],
+        &["--equiv", path.to_str().unwrap()],
+        &["--emit-bf"],
+        &["--emit-c"],
+        &["--emit-llvm"],
+        &["--emit-asm"],
+        &["--emit-dot"],
+    ];
+    for mode in modes {
+        let mut args = vec![path.as_os_str()];
+        args.extend(mode.iter().map(OsStr::new));
+        if mode.first().is_some_and(|arg| arg.starts_with("--emit-")) {
+            args.push(emitted.as_os_str())
+        }
+        let output = run(&args, b"");
+        assert!(output.status.success(), "{:?} failed: {}", mode, String::from_utf8_lossy(&output.stderr))
+    }
+    // Node names do not grow with the depth of loops.
+    let dot = fs::read_to_string(&emitted).unwrap();
+    assert!(dot.lines().all(|line| line.len() < 100));
+}
+
+// Cells wider than bytes output their whole value as hexadecimal, past 255.
+#[test]
+fn wide_cells_output_values_past_255() {
+    let path = program("wide_300.b", &format!("{}.>-.", "+".repeat(300)));
+    let run = |cell_size: &str| String::from_utf8(stdout([path.as_os_str(), OsStr::new("--cell-size"), OsStr::new(cell_size), OsStr::new("--hex-output")], b"")).unwrap();
+    assert_eq!(run("8"), "0x2c\n0xff\n");
+    assert_eq!(run("16"), "0x012c\n0xffff\n");
+    assert_eq!(run("32"), "0x0000012c\n0xffffffff\n");
+    assert_eq!(run("big"), "0x12c\n-0x1\n");
+    // Without `--hex-output`, the lowest byte is output.
+    assert_eq!(stdout([path.as_os_str(), OsStr::new("--cell-size"), OsStr::new("16")], b""), [0x2c, 0xff])
+}
+
+// Wide cells support the options of byte cells, such as input, limits, and reports,
+// and skip annotations like they do.
+#[test]
+fn wide_cells_support_the_options_of_byte_cells() {
+    let cat = program("wide_cat.b", CAT);
+    assert_eq!(stdout([cat.as_os_str(), OsStr::new("--cell-size"), OsStr::new("32"), OsStr::new("--input-text"), OsStr::new("ab")], b""), b"ab");
+    let (code, stderr) = failure([cat.as_os_str(), OsStr::new("--cell-size"), OsStr::new("big"), OsStr::new("--eof"), OsStr::new("error")], b"a");
+    assert_eq!((code, stderr.as_str()), (5, "error: the program read past the end of its input at step 4\n"));
+    let endless = program("wide_endless.b", "+[]");
+    let (code, stderr) = failure([endless.as_os_str(), OsStr::new("--cell-size"), OsStr::new("16"), OsStr::new("--max-steps"), OsStr::new("100")], b"");
+    assert_eq!((code, stderr.as_str()), (3, "error: exceeded step limit after 100 steps\n"));
+    let debugged = program("wide_debug.b", "{name 0 my-cell} +++?");
+    let output = run([debugged.as_os_str(), OsStr::new("--cell-size"), OsStr::new("16"), OsStr::new("--debug"), OsStr::new("--summary")], b"");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // The `-` of the name is not an instruction.
+    assert!(stderr.contains("| 0x03 |") && stderr.contains("my-cell"), "{}", stderr);
+    assert!(stderr.contains("Written cells:   0 to 0"), "{}", stderr)
+}
+
+// Wide cells handle deeply nested loops, as they run on the same interpreter as bytes.
+#[test]
+fn wide_cells_handle_deeply_nested_loops() {
+    const DEPTH: usize = 200_000;
+    let path = program("wide_deep.b", &format!("{}{}+.", "+[>".repeat(DEPTH), "<-]".repeat(DEPTH)));
+    for cell_size in ["16", "big"] {
+        assert_eq!(stdout([path.as_os_str(), OsStr::new("--cell-size"), OsStr::new(cell_size)], b""), [1], "{}", cell_size)
+    }
+}
+
+// The modes that translate programs to code using bytes refuse wider cells.
+#[test]
+fn byte_only_modes_refuse_wide_cells() {
+    let path = program("wide_emit.b", "+.");
+    let (code, stderr) = failure([path.as_os_str(), OsStr::new("--cell-size"), OsStr::new("16"), OsStr::new("--emit-c"), OsStr::new("wide.c")], b"");
+    assert_eq!(code, 2);
+    assert!(stderr.contains("`--emit-c` only supports byte cells, so it cannot be used with `--cell-size 16`"), "{}", stderr)
+}
+
+// The tutor skips annotations, whose contents are not instructions of the program.
+#[test]
+fn tutor_skips_annotations() {
+    let path = program("tutor_annotation.b", "{name 0 my-cell}+.");
+    let explanation = String::from_utf8(stdout([path.as_os_str(), OsStr::new("--tutor")], b"")).expect("Unable to read explanation");
+    assert!(explanation.starts_with("step 1: '+' at column 17 increments cell 0 from 0 to 1\n"), "{}", explanation);
+    assert!(explanation.contains("The program ended after 2 steps."), "{}", explanation)
+}
+
+// Piped programs report why they stopped early, and the interpreter exits with their
+// exit code.
+#[test]
+fn piped_programs_report_their_stop() {
+    let (main, piped) = (program("pipe_main.b", "+++[.-]"), program("pipe_loop.b", "+[.]"));
+    let (code, stderr) = failure([main.as_os_str(), OsStr::new("--max-output"), OsStr::new("3"), OsStr::new("--pipe"), piped.as_os_str()], b"");
+    assert_eq!(code, 3);
+    assert!(stderr.contains(&format!("piped program {}", piped.display())), "{}", stderr);
+    let (code, stderr) = failure([main.as_os_str(), OsStr::new("--max-steps"), OsStr::new("100"), OsStr::new("--pipe"), piped.as_os_str()], b"");
+    assert_eq!(code, 3);
+    assert!(stderr.contains("exceeded step limit after"), "{}", stderr)
+}
+
+// Every error stopping a program is printed on a line starting with `error:`.
+#[test]
+fn runtime_errors_start_with_error() {
+    let output_limit = program("error_output_limit.b", "+[.]");
+    let assertion = program("error_assertion.b", "+{assert cell(0)==2}");
+    let protected_write = program("error_protected_write.b", ">+.");
+    let runs: [&[&OsStr]; 3] = [
+        &[output_limit.as_os_str(), OsStr::new("--max-output"), OsStr::new("3")],
+        &[assertion.as_os_str(), OsStr::new("--assertions")],
+        &[protected_write.as_os_str(), OsStr::new("--protect"), OsStr::new("1..2")],
+    ];
+    for args in runs {
+        let (_, stderr) = failure(args, b"");
+        assert!(stderr.starts_with("error: "), "{}", stderr)
+    }
+    assert_eq!(failure(runs[0], b""), (3, "error: exceeded output limit after 3 values\n".to_string()))
+}
+
+// Writes to protected cells are not optimized away, even at the end of the program or
+// when they leave a cell at 0.
+#[test]
+fn writes_to_protected_cells_are_kept() {
+    for source in [">+", ">[-]+."] {
+        let path = program("protected_tail.b", source);
+        let (code, stderr) = failure([path.as_os_str(), OsStr::new("--protect"), OsStr::new("1..2")], b"");
+        assert_eq!(code, 4, "{}", stderr);
+        assert!(stderr.contains("writes to cell 1"), "{}", stderr)
+    }
+}
+
+// A chunk reset starting before an armed protection is caught when it sweeps into it, while a
+// protection armed after the reset lets it through.
+#[test]
+fn chunk_resets_into_protected_cells_are_caught() {
+    let path = program("protected_chunk.b", "+>+>+<<[-]>[-]>[-].");
+    let args = |protection: &'static str| [path.as_os_str(), OsStr::new("--optimize-chunk-resets"), OsStr::new("true"), OsStr::new("--protect"), OsStr::new(protection)];
+    let (code, stderr) = failure(args("2..3:after-step=3"), b"");
+    assert_eq!(code, 4, "{}", stderr);
+    assert!(stderr.starts_with("error: ResetChunk at step 4 (line 1, column 8) writes to cell 2, which is protected by --protect 2..3\n"), "{}", stderr);
+    assert_eq!(stdout(args("2..3:after-step=4"), b""), [0])
+}
+
+// Options that do not work together are refused, including the modes doing something
+// else than running the program, of which only one would happen.
+#[test]
+fn conflicting_options_are_refused() {
+    let path = program("conflicts.b", "+.");
+    let conflicts: [&[&str]; 7] = [
+        &["--lint", "--analyze"],
+        &["--tutor", "--emit-c", "conflicts.c"],
+        &["--test", "--selftest"],
+        &["--hex-output", "--no-output"],
+        &["--optimize-chunk-resets", "true", "--optimize-loops", "false"],
+        &["--input", "a", "--input-text", "b"],
+        &["--input-text", "a", "--input-timeout", "1s"],
+    ];
+    for args in conflicts {
+        let (code, stderr) = failure([path.as_os_str()].into_iter().chain(args.iter().map(OsStr::new)), b"");
+        assert_eq!(code, 2, "{:?}: {}", args, stderr);
+        assert!(stderr.starts_with("error: "), "{:?}: {}", args, stderr)
+    }
+    let emitted = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let (c, bf) = (emitted.join("conflicts.c"), emitted.join("conflicts_emitted.b"));
+    stdout([path.as_os_str(), OsStr::new("--emit-c"), c.as_os_str(), OsStr::new("--emit-bf"), bf.as_os_str()], b"");
+    assert!(c.exists() && bf.exists())
+}
+
+// The printed configuration shows the values of passed options, over their defaults.
+#[test]
+fn effective_config_shows_passed_options() {
+    let config = String::from_utf8(stdout(["--print-effective-config", "--optimize-loops", "false", "--max-steps", "10"], b"")).expect("Unable to read configuration");
+    assert!(config.contains("optimize-loops = false  # passed\n"), "{}", config);
+    assert!(config.contains("max-steps = 10  # passed\n"), "{}", config);
+    assert!(config.contains("coalesce-moves = true\n"), "{}", config)
+}
+
+// `--annotate` stops programs after `--max-steps`, including empty loops, and prints the
+// counts up to there.
+#[test]
+fn annotate_honors_the_step_limit() {
+    for source in ["+[>+<]", "+[]"] {
+        let path = program("annotate_steps.b", source);
+        let output = run([path.as_os_str(), OsStr::new("--annotate"), OsStr::new("--max-steps"), OsStr::new("1000")], b"");
+        assert_eq!(output.status.code(), Some(3), "{}", source);
+        assert!(String::from_utf8_lossy(&output.stderr).starts_with("error: exceeded step limit after"), "{}", source);
+        assert!(String::from_utf8_lossy(&output.stdout).contains("Instructions never executed: 0 of"), "{}", source)
+    }
+}
+
+// `--equiv --compare-tape` finds programs that output the same bytes but leave different tapes,
+// whose writes are not optimized away.
+#[test]
+fn equiv_compares_final_tapes() {
+    let (once, twice) = (program("equiv_once.b", "+"), program("equiv_twice.b", "++"));
+    let output = run([once.as_os_str(), OsStr::new("--equiv"), twice.as_os_str(), OsStr::new("--compare-tape")], b"");
+    assert_eq!(output.status.code(), Some(1), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Programs differ on input \"\""), "{}", String::from_utf8_lossy(&output.stdout));
+    // Without it, only their outputs are compared.
+    assert!(run([once.as_os_str(), OsStr::new("--equiv"), twice.as_os_str()], b"").status.success())
+}
+
+// A `{` that is never closed is part of a comment rather than an annotation, so standard programs
+// still load, and the instructions after it run.
+#[test]
+fn unterminated_annotations_are_comments() {
+    let path = program("unterminated_annotation.b", "comment {test of + - stuff\n+++.");
+    assert_eq!(stdout([path.as_os_str()], b""), [3]);
+    let tests = String::from_utf8(stdout([path.as_os_str(), OsStr::new("--test")], b"")).expect("Unable to read test results");
+    assert!(tests.contains("no tests found"), "{}", tests)
+}
+
+// Errors in the arguments show a short usage line, rather than every option.
+#[test]
+fn argument_errors_show_a_short_usage() {
+    let (code, stderr) = failure(["x.b", "--foo"], b"");
+    assert_eq!(code, 2);
+    assert!(stderr.lines().any(|line| line == "Usage: brainfuck-interpreter <FILE>"), "{}", stderr)
+}
+
+// The shebang of a script is not run, even though its path contains instructions, and errors are
+// located in the file as it is, shebang included.
+#[test]
+fn shebangs_are_skipped_but_counted() {
+    let path = program("shebang.b", "#!/usr/bin/env brain-fuck.exe -x\n+++++++[>++++++++++<-]>.");
+    assert_eq!(stdout([path.as_os_str()], b""), b"F");
+    let unmatched = program("shebang_unmatched.b", "#!/usr/bin/env brain-fuck.exe -x\n+++++++[>++++++++++<-]>.\n]");
+    let (code, stderr) = failure([unmatched.as_os_str()], b"");
+    assert_eq!(code, 1);
+    assert!(stderr.starts_with("error: unmatched ']' at line 3, column 1 (byte 58)"), "{}", stderr)
+}
+
+// `--input-text` and `--input-file` replace `stdin`, and the program reaches the end of its input
+// at the end of the text or file, even if it is empty, unless it is followed by `stdin`.
+#[test]
+fn fixed_input_replaces_stdin() {
+    let cat = program("fixed_input_cat.b", CAT);
+    let empty = program("fixed_input_empty.txt", "");
+    assert_eq!(stdout([cat.as_os_str(), OsStr::new("--input-text"), OsStr::new("hello")], b"ignored"), b"hello");
+    assert_eq!(stdout([cat.as_os_str(), OsStr::new("--input-file"), empty.as_os_str()], b"ignored"), b"");
+    let (code, stderr) = failure([cat.as_os_str(), OsStr::new("--input-file"), empty.as_os_str(), OsStr::new("--eof"), OsStr::new("error")], b"ignored");
+    assert_eq!((code, stderr.as_str()), (5, "error: the program read past the end of its input at step 1\n"));
+    // With `--then-stdin`, an empty file is followed by `stdin` right away.
+    assert_eq!(stdout([cat.as_os_str(), OsStr::new("--input-file"), empty.as_os_str(), OsStr::new("--then-stdin")], b"typed"), b"typed")
+}
+
+// `--script` drives an interactive program, waiting for its prompt before answering it, and fails
+// when the program does not output what is expected.
+#[test]
+fn scripts_drive_interactive_programs() {
+    // Prompts with `?`, and then echoes its input.
+    let echo = program("script_echo.b", "+++++++[>+++++++++<-]>.,[.,]");
+    let passing = program("script_passing.txt", "expect \"?\"\nsend \"hello\\n\"\nexpect \"hello\\n\"\neof\n");
+    assert_eq!(stdout([echo.as_os_str(), OsStr::new("--script"), passing.as_os_str()], b""), b"Script passed (7 bytes received)\n");
+    let failing = program("script_failing.txt", "expect \"?\"\nsend \"hello\"\nexpect-timeout 200ms\nexpect \"bye\"\n");
+    let output = run([echo.as_os_str(), OsStr::new("--script"), failing.as_os_str()], b"");
+    assert_eq!(output.status.code(), Some(1));
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(report.starts_with("Script failed at line 4: `expect \"bye\"`\nThe program did not output the expected text within 200ms\n"), "{}", report);
+    assert!(report.contains("Received from offset 1: \"hello\""), "{}", report)
+}
+
+// The tests embedded in the examples pass, and `--test` fails when a test does not.
+#[test]
+fn examples_pass_their_tests() {
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let report = String::from_utf8(stdout([OsStr::new("--test"), examples.as_os_str()], b"")).unwrap();
+    assert!(report.lines().last().is_some_and(|line| line.starts_with("All tests passed") && line.ends_with(" 0 failed")), "{}", report);
+    let failing = program("test_failing.b", "{test name=\"echo\" input=\"a\" output=\"b\"}\n,.");
+    let output = run([OsStr::new("--test"), failing.as_os_str()], b"");
+    assert_eq!(output.status.code(), Some(1), "{}", String::from_utf8_lossy(&output.stdout))
+}
+
+// `--break-at-step N` pauses right before step N, with the steps counted as in `--summary`, and
+// shows the position of the command about to execute.
+#[test]
+fn breakpoints_pause_at_the_step_counted_by_the_summary() {
+    // The last `.` is on its own line.
+    let path = program("breakpoint.b", &format!("{}\n.", HELLO_WORLD.strip_suffix('.').unwrap()));
+    let summary = |args: &[&str]| {
+        let output = run([path.as_os_str()].into_iter().chain(args.iter().map(OsStr::new)).chain([OsStr::new("--summary")]), b"");
+        assert!(output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+    let stderr = summary(&[]);
+    let steps: u64 = stderr.lines().find_map(|line| line.strip_prefix("Steps:")).unwrap().trim().parse().unwrap();
+    let last_step = steps.to_string();
+    let stderr = summary(&["--break-at-step", &last_step]);
+    assert!(stderr.starts_with(&format!("Step {} (line 2, column 1): pointer 6, current cell 0x0a, last output 0x21\n", steps)), "{}", stderr);
+    assert!(stderr.contains(&format!("Steps:           {}\n", steps)), "{}", stderr);
+    // No step comes after the last one.
+    let after_last_step = (steps + 1).to_string();
+    assert!(summary(&["--break-at-step", &after_last_step]).starts_with("Steps:"))
+}
+
+// A small standard library of macros expands to a working program, in which an `!` followed by a
+// name that is not a macro is part of a comment, and invalid macros stop with an error.
+#[test]
+fn macro_libraries_expand_end_to_end() {
+    let library = "\
+!def zero [-]
+!def add(n) { !rep !n { + } }
+!def right(n) { !rep !n { > } }
+!def left(n) { !rep !n { < } }
+!def print(n) { !zero !add(!n) . }
+!def double { [->++<]>[-<+>]< }
+";
+    let path = program("macro_library.b", &format!("{}Say hi!there\n!print(79) !zero !add(53) !double !add(1) . !right(1) !print(33) !left(1) !print(10)", library));
+    assert_eq!(stdout([path.as_os_str(), OsStr::new("--macros")], b""), b"Ok!\n");
+    let recursive = program("macro_recursive.b", "!def forever { + !forever }\n!forever");
+    let (code, stderr) = failure([recursive.as_os_str(), OsStr::new("--macros")], b"");
+    assert_eq!((code, stderr.as_str()), (1, "error: unable to expand macros: 2:1: macro `forever` is recursive (in forever)\n"))
+}
+
+/// Runs a program with `--warn-portability` on some input, and returns what it printed to `stderr`.
+fn portability_warnings(name: &str, code: &str, input: &[u8]) -> String {
+    let path = program(name, code);
+    let output = run([path.as_os_str(), OsStr::new("--warn-portability")], input);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stderr).unwrap()
+}
+
+// Moving the pointer below cell 0 is reported at the command doing it, even at the end of the
+// program, where it has no other effect.
+#[test]
+fn portability_warns_about_the_pointer_going_below_zero() {
+    let stderr = portability_warnings("portability_below_zero.b", "+.<<", b"");
+    assert!(stderr.starts_with("Portability warning at step 3 (Right at line 1, column 3): the pointer went below cell 0"), "{}", stderr)
+}
+
+// Moving the pointer past the end of a tape of 30000 cells is reported.
+#[test]
+fn portability_warns_about_the_pointer_going_beyond_the_tape() {
+    let stderr = portability_warnings("portability_beyond_tape.b", &format!("{}+.", ">".repeat(30000)), b"");
+    assert!(stderr.starts_with("Portability warning at step 1 (Right at line 1, column 1): the pointer went past cell 29999"), "{}", stderr)
+}
+
+// Incrementing a cell past 255 is reported.
+#[test]
+fn portability_warns_about_overflows() {
+    let stderr = portability_warnings("portability_overflow.b", ",\n+.", b"\xff");
+    assert!(stderr.starts_with("Portability warning at step 2 (Add at line 2, column 1): a cell wrapped past 255"), "{}", stderr)
+}
+
+// Decrementing a cell below 0 is reported, even when the cell is reset right after.
+#[test]
+fn portability_warns_about_underflows() {
+    let stderr = portability_warnings("portability_underflow.b", "-[-]", b"");
+    assert!(stderr.starts_with("Portability warning at step 1 (Add at line 1, column 1): a cell wrapped below 0"), "{}", stderr)
+}
+
+// Reading past the end of the input is reported.
+#[test]
+fn portability_warns_about_the_end_of_input() {
+    let stderr = portability_warnings("portability_end_of_input.b", "+,.", b"");
+    assert!(stderr.starts_with("Portability warning at step 2 (Input at line 1, column 2): `,` reached the end of the input"), "{}", stderr)
+}
+
+// Outputting a byte that is not ASCII is reported.
+#[test]
+fn portability_warns_about_non_ascii_output() {
+    let stderr = portability_warnings("portability_non_ascii.b", ",  .", b"\x80");
+    assert!(stderr.starts_with("Portability warning at step 2 (Output at line 1, column 4): a byte of 0x80 or more was output"), "{}", stderr);
+    assert!(stderr.ends_with("Portability issues:\n  From step 2: a byte of 0x80 or more was output, which is not ASCII\n"), "{}", stderr)
+}
+
+// Breakpoints pause at the same command whether `--summary` is passed or not, even though it
+// disables optimizations that remove commands, such as the reset of a cell known to be 0.
+#[test]
+fn breakpoints_do_not_depend_on_the_summary() {
+    let path = program("breakpoint_summary.b", "[-]+++[>+<-]>.");
+    let pause = |step: &str, summary: bool| {
+        let mut args = vec![path.as_os_str(), OsStr::new("--break-at-step"), OsStr::new(step)];
+        if summary {
+            args.push(OsStr::new("--summary"))
+        }
+        let output = run(args, b"");
+        assert!(output.status.success());
+        String::from_utf8(output.stderr).unwrap().lines().next().unwrap_or_default().to_string()
+    };
+    assert_eq!(pause("1", false), "Step 1 (line 1, column 1): pointer 0, current cell 0x00, last output none");
+    for step in ["1", "2", "3", "4", "5"] {
+        assert_eq!(pause(step, false), pause(step, true), "step {}", step)
+    }
+    assert_eq!(pause("5", false), "Step 5 (line 1, column 14): pointer 1, current cell 0x03, last output none")
+}
+
+// A source file that cannot be read is reported on an `error:` line naming it, and the
+// interpreter exits with status 1 rather than panicking.
+#[test]
+fn unreadable_source_files_are_errors() {
+    let missing = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("missing_source.b");
+    let (code, stderr) = failure([missing.as_os_str()], b"");
+    assert_eq!(code, 1, "{}", stderr);
+    assert!(stderr.starts_with(&format!("error: unable to read {}: ", missing.display())), "{}", stderr);
+    // Piped programs are read the same way.
+    let main = program("missing_pipe_main.b", "+.");
+    let (code, stderr) = failure([main.as_os_str(), OsStr::new("--pipe"), missing.as_os_str()], b"");
+    assert_eq!(code, 1, "{}", stderr);
+    assert!(stderr.starts_with(&format!("error: unable to read {}: ", missing.display())), "{}", stderr)
+}
+
+// Gzip-compressed source files are decompressed, whatever their extension.
+#[cfg(feature = "gzip")]
+#[test]
+fn gzipped_source_files_run() {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(HELLO_WORLD.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+    for name in ["hello.b.gz", "hello_gzip.b"] {
+        let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+        fs::write(&path, &compressed).unwrap();
+        assert_eq!(stdout([path.as_os_str()], b""), b"Hello World!\n", "{}", name)
+    }
+}
+
+// A corrupt gzip file is reported on an `error:` line naming it, rather than run or panicking.
+#[cfg(feature = "gzip")]
+#[test]
+fn corrupt_gzip_files_are_errors() {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("corrupt.b.gz");
+    fs::write(&path, b"\x1f\x8b\x08\x00 not really compressed").unwrap();
+    let (code, stderr) = failure([path.as_os_str()], b"");
+    assert_eq!(code, 1, "{}", stderr);
+    assert!(stderr.starts_with(&format!("error: {} is not a valid gzip file: ", path.display())), "{}", stderr);
+    assert_eq!(stderr.lines().count(), 1, "{}", stderr)
+}
+
+/// Serves HTTP on a local port from another thread, answering each request with the status line,
+/// the headers, and the body returned by `respond` for its path, and returns the URL of the server.
+#[cfg(feature = "net")]
+fn serve(respond: fn(&str) -> (&'static str, String, Vec<u8>)) -> String {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = BufReader::new(stream.unwrap());
+            let mut request = String::new();
+            stream.read_line(&mut request).unwrap();
+            let path = request.split(' ').nth(1).unwrap_or("/").to_string();
+            // The headers of the request end with an empty line.
+            let mut line = String::new();
+            while stream.read_line(&mut line).unwrap() > 2 {
+                line.clear()
+            }
+            let (status, headers, body) = respond(&path);
+            let stream = stream.get_mut();
+            let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n", status, body.len(), headers);
+            let _ = stream.write_all(&body);
+        }
+    });
+    url
+}
+
+// Programs are downloaded from URLs, and failing to download one is reported on an `error:` line,
+// whether the server responds with an error, redirects, or sends too large a program.
+#[cfg(feature = "net")]
+#[test]
+fn programs_are_downloaded_from_urls() {
+    let url = serve(|path| match path {
+        "/hello.b" => ("200 OK", String::new(), HELLO_WORLD.as_bytes().to_vec()),
+        "/moved.b" => ("302 Found", "Location: /hello.b\r\n".to_string(), Vec::new()),
+        "/large.b" => ("200 OK", String::new(), vec![b'+'; (16 << 20) + 1]),
+        _ => ("404 Not Found", String::new(), b"not found".to_vec()),
+    });
+    let fetch = |path: &str| failure([format!("{}{}", url, path).as_str(), "--max-steps", "100000"], b"");
+    assert_eq!(stdout([format!("{}/hello.b", url).as_str(), "--max-steps", "100000"], b""), b"Hello World!\n");
+    assert_eq!(fetch("/missing.b"), (1, format!("error: unable to fetch {}/missing.b: the server responded with status 404\n", url)));
+    assert_eq!(fetch("/moved.b"), (1, format!("error: unable to fetch {}/moved.b: the server responded with status 302 (redirecting to /hello.b), but redirects are not followed\n", url)));
+    assert_eq!(fetch("/large.b"), (1, format!("error: unable to fetch {}/large.b: the program is larger than {} bytes\n", url, 16 << 20)));
+    // Downloaded programs are refused without limits.
+    let (code, stderr) = failure([format!("{}/hello.b", url).as_str()], b"");
+    assert_eq!(code, 1);
+    assert!(stderr.starts_with("error: refusing to run a program downloaded from a URL without limits"), "{}", stderr)
+}
+
+// A server refusing connections is reported on an `error:` line, rather than a panic.
+#[cfg(feature = "net")]
+#[test]
+fn unreachable_servers_are_errors() {
+    // The port is free once the listener is dropped.
+    let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let (code, stderr) = failure([format!("http://{}/hello.b", address).as_str(), "--max-steps", "100"], b"");
+    assert_eq!(code, 1, "{}", stderr);
+    assert!(stderr.starts_with("error: ") && stderr.contains(&address.to_string()), "{}", stderr);
+    assert_eq!(stderr.lines().count(), 1, "{}", stderr)
+}
+
+// Input sources are read in the order they are passed, each once the previous one is exhausted.
+#[test]
+fn input_sources_are_chained_in_order() {
+    let cat = program("chained_cat.b", CAT);
+    let file = program("chained_input.txt", "file,");
+    let file_source = format!("file:{}", file.display());
+    let args = |sources: &[&str]| {
+        let mut args = vec![cat.as_os_str().to_os_string()];
+        for source in sources {
+            args.extend([OsStr::new("--input-source").to_os_string(), OsStr::new(source).to_os_string()])
+        }
+        args
+    };
+    assert_eq!(stdout(args(&["text:text,", &file_source, "stdin"]), b"stdin"), b"text,file,stdin");
+    assert_eq!(stdout(args(&["stdin", &file_source, "text:text"]), b"stdin,"), b"stdin,file,text");
+    // The end of the input is only reached after the last source.
+    let (code, stderr) = failure([args(&[&file_source, "text:"]), vec!["--eof".into(), "error".into()]].concat(), b"");
+    assert_eq!((code, stderr.as_str()), (5, "error: the program read past the end of its input at step 12\n"))
+}
+
+// A missing input file is reported on an `error:` line naming it before the program runs, rather
+// than a panic.
+#[test]
+fn missing_input_files_are_errors() {
+    let path = program("missing_input_cat.b", "+.,.");
+    let missing = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("missing_input.txt");
+    let source = format!("file:{}", missing.display());
+    let runs: [Vec<&OsStr>; 2] = [
+        vec![path.as_os_str(), OsStr::new("--input-file"), missing.as_os_str()],
+        vec![path.as_os_str(), OsStr::new("--input-source"), OsStr::new("text:a"), OsStr::new("--input-source"), OsStr::new(&source)],
+    ];
+    for args in runs {
+        let output = run(&args, b"");
+        assert_eq!(output.status.code(), Some(1));
+        assert!(output.stdout.is_empty(), "{:?}", output.stdout);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.starts_with(&format!("error: unable to open input file {}: ", missing.display())), "{}", stderr);
+        assert_eq!(stderr.lines().count(), 1, "{}", stderr)
+    }
+}
+
+// With `--io-tcp`, the program reads from and writes to the connection instead of `stdin` and
+// `stdout`.
+#[test]
+fn programs_talk_over_tcp_connections() {
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpListener};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let peer = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"over tcp").unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        received
+    });
+    let path = program("tcp_cat.b", CAT);
+    let output = run([path.as_os_str(), OsStr::new("--io-tcp"), OsStr::new(&address)], b"from stdin");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stdout.is_empty(), "{:?}", output.stdout);
+    assert_eq!(peer.join().unwrap(), b"over tcp")
+}
+
+// A connection that cannot be made is reported on an `error:` line naming the address, rather than
+// a panic.
+#[test]
+fn refused_tcp_connections_are_errors() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    drop(listener);
+    let path = program("refused_tcp_cat.b", CAT);
+    let (code, stderr) = failure([path.as_os_str(), OsStr::new("--io-tcp"), OsStr::new(&address)], b"");
+    assert_eq!(code, 1);
+    assert!(stderr.starts_with(&format!("error: unable to connect to {}: ", address)), "{}", stderr);
+    assert_eq!(stderr.lines().count(), 1, "{}", stderr)
+}
+
+// With `--diagnostics json`, each diagnostic is printed as a JSON object on its own line, and
+// nothing else is printed.
+#[test]
+fn diagnostics_are_printed_as_json_lines() {
+    let path = program("json_diagnostics.b", "[-]+\n[]>>\n");
+    let output = run([path.as_os_str(), OsStr::new("--lint"), OsStr::new("--diagnostics"), OsStr::new("json"), OsStr::new("--deny"), OsStr::new("empty-loop")], b"");
+    assert_eq!(output.status.code(), Some(1));
+    let diagnostics: Vec<serde_json::Value> = String::from_utf8(output.stdout).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let summary: Vec<_> = diagnostics.iter().map(|diagnostic| {
+        let field = |name: &str| diagnostic[name].clone();
+        (field("code"), field("severity"), [field("start"), field("end"), field("line"), field("column"), field("end_line"), field("end_column")])
+    }).collect();
+    assert_eq!(summary, [
+        ("dead-loop".into(), "warning".into(), [0, 3, 1, 1, 1, 4].map(Into::into)),
+        ("empty-loop".into(), "error".into(), [5, 7, 2, 1, 2, 3].map(Into::into)),
+        ("unobservable-tail".into(), "warning".into(), [7, 9, 2, 3, 2, 5].map(Into::into)),
+    ]);
+    assert!(diagnostics.iter().all(|diagnostic| diagnostic["file"] == path.to_str().unwrap() && diagnostic["message"].is_string()));
+
+    // Errors preventing the program from being loaded are printed the same way, when it is checked
+    // or run.
+    let path = program("json_unmatched.b", "+\n+]");
+    for mode in [&["--lint"][..], &[]] {
+        let output = run([path.as_os_str(), OsStr::new("--diagnostics"), OsStr::new("json")].into_iter().chain(mode.iter().map(OsStr::new)), b"");
+        assert_eq!(output.status.code(), Some(1));
+        assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+        let diagnostic: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(diagnostic, serde_json::json!({
+            "code": "unmatched-bracket", "severity": "error", "message": "unmatched ']'", "file": path.to_str().unwrap(),
+            "start": 3, "end": 4, "line": 2, "column": 2, "end_line": 2, "end_column": 3,
+        }))
+    }
+}
+
+/// Runs a program setting the current cell to 5, then outputting what it reads past the end of its
+/// input, with `--eof` set to a mode.
+fn read_past_the_end(mode: &str) -> std::process::Output {
+    run([program(&format!("eof_{mode}.b"), "+++++,."), PathBuf::from("--eof"), PathBuf::from(mode)], b"")
+}
+
+// With `--eof zero`, reading past the end of the input writes 0 to the current cell.
+#[test]
+fn eof_zero_writes_zero() {
+    let output = read_past_the_end("zero");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\0")
+}
+
+// With `--eof unchanged`, reading past the end of the input leaves the current cell unchanged.
+#[test]
+fn eof_unchanged_keeps_the_cell() {
+    let output = read_past_the_end("unchanged");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\x05")
+}
+
+// With `--eof minus-one`, reading past the end of the input writes 255 to the current cell.
+#[test]
+fn eof_minus_one_writes_255() {
+    let output = read_past_the_end("minus-one");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\xff")
+}
+
+// With `--eof error`, reading past the end of the input stops the program with exit code 5,
+// before it outputs anything. The 5 increments are a single step once optimized.
+#[test]
+fn eof_error_exits_with_status_5() {
+    let output = read_past_the_end("error");
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(output.stdout, b"");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: the program read past the end of its input at step 2\n")
+}
+
+// With `--debug`, each `?` prints the tape with the position of the pointer at that point of the
+// program, even though the commands around it are optimized.
+#[test]
+fn tape_dumps_show_the_pointer() {
+    let output = run([program("dump_pointer.b", "++>+?<?").as_os_str(), OsStr::new("--debug")], b"");
+    assert!(output.status.success());
+    let dump = |pointer| format!("Tape (pointer {pointer}):\n| 0x02 | 0x01 |\n     0      1 \n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), dump(1) + &dump(0))
+}
+
+// `--step N` prints the state of the program every N steps, and continues on its own when `stderr`
+// is not a terminal.
+#[test]
+fn stepping_continues_without_a_terminal() {
+    let output = run([program("step.b", "+>+>+\n>+.").as_os_str(), OsStr::new("--step"), OsStr::new("2")], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\x01");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "\
+Step 2 (line 1, column 3): pointer 1, current cell 0x00, last output none
+Step 4 (line 2, column 2): pointer 3, current cell 0x00, last output none
+")
+}
+
+// With `--verbose`, running out of `--opt-time-budget` is reported, and the program still runs.
+#[test]
+fn exceeding_the_optimization_budget_is_reported() {
+    let output = run([program("opt_time_budget.b", HELLO_WORLD).as_os_str(), OsStr::new("--opt-time-budget"), OsStr::new("0ms"), OsStr::new("--verbose")], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Hello World!\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Optimization stopped after exceeding the time budget\n")
+}
+
+// The tape written by `--tape-dump-json`, with cells at negative indices, is read back by
+// `--tape-init-json` as it was, and dumped again identically.
+#[test]
+fn tape_json_round_trips() {
+    let directory = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let (dumped, redumped) = (directory.join("tape_round_trip.json"), directory.join("tape_round_trip_again.json"));
+    let output = run([program("tape_dump.b", "<<<++++++++++++>>>-<").as_os_str(), OsStr::new("--tape-dump-json"), dumped.as_os_str()], b"");
+    assert!(output.status.success());
+    let json = fs::read_to_string(&dumped).unwrap();
+    assert_eq!(json, "{\n  \"pointer\": -1,\n  \"cells\": {\n    \"-3\": 12,\n    \"0\": 255\n  },\n  \"extent\": [\n    -3,\n    0\n  ]\n}\n");
+    // The pointer is restored, and so are the cells on both sides of 0.
+    assert_eq!(stdout([program("tape_init.b", ">.<<<.").as_os_str(), OsStr::new("--tape-init-json"), dumped.as_os_str()], b""), b"\xff\x0c");
+    let output = run([program("tape_redump.b", "").as_os_str(), OsStr::new("--tape-init-json"), dumped.as_os_str(), OsStr::new("--tape-dump-json"), redumped.as_os_str()], b"");
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(redumped).unwrap(), json)
+}
+
+// `--newline-at-exit` ends the output with a newline only if it is not empty and does not already
+// end with one, in ASCII mode and in the file passed to `--output`, but not in hex mode, whose
+// lines are already complete, nor when the program fails.
+#[test]
+fn newlines_are_added_at_exit_only_when_needed() {
+    let with_newline = |name: &str, code: &str, args: &[&str]| {
+        let path = program(&format!("newline_{name}.b"), code);
+        run([path.as_os_str(), OsStr::new("--newline-at-exit")].into_iter().chain(args.iter().map(OsStr::new)), b"")
+    };
+    assert_eq!(with_newline("missing", "+++++++++[>++++++++<-]>.", &[]).stdout, b"H\n");
+    assert_eq!(with_newline("present", "++++++++++.", &[]).stdout, b"\n");
+    assert_eq!(with_newline("empty", "+", &[]).stdout, b"");
+    assert_eq!(with_newline("hex", "+++++++++[>++++++++<-]>.", &["--hex-output"]).stdout, b"0x48\n");
+    let failed = with_newline("failed", "+++++++++[>++++++++<-]>.,", &["--eof", "error"]);
+    assert_eq!((failed.status.code(), failed.stdout.as_slice()), (Some(5), &b"H"[..]));
+    let file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("newline_output.txt");
+    let output = with_newline("file", "+++++++++[>++++++++<-]>.", &["--output", file.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!((output.stdout.as_slice(), fs::read(&file).unwrap().as_slice()), (&b""[..], &b"H\n"[..]))
+}
+
+// `--prompt` is not written when the input is not a terminal.
+#[test]
+fn prompts_are_not_written_for_piped_input() {
+    let output = run([program("prompt_cat.b", CAT).as_os_str(), OsStr::new("--prompt"), OsStr::new("> ")], b"a\nb\n");
+    assert!(output.status.success());
+    assert_eq!((output.stdout.as_slice(), output.stderr.as_slice()), (&b"a\nb\n"[..], &b""[..]))
+}
+
+// `--max-output N` stops a program printing forever after exactly N values, which are all
+// output, and counts values rather than the bytes written in hex mode.
+#[test]
+fn output_limits_stop_infinite_printers() {
+    let printer = program("infinite_printer.b", "+[.]");
+    let limited = |args: &[&str]| run([printer.as_os_str(), OsStr::new("--max-output"), OsStr::new("1000")].into_iter().chain(args.iter().map(OsStr::new)), b"");
+    let output = limited(&[]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stdout, [1; 1000]);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: exceeded output limit after 1000 values\n");
+    let output = limited(&["--hex-output"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0x01\n".repeat(1000))
+}
+
+// `--time-output` writes a header to a new CSV file, then appends one well-formed row per run.
+#[test]
+fn timings_are_appended_as_csv_rows() {
+    let path = program("timed.b", HELLO_WORLD);
+    let csv = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("timings.csv");
+    let _ = fs::remove_file(&csv);
+    for args in [&[][..], &["--optimize-loops", "false"]] {
+        let output = run([path.as_os_str(), OsStr::new("--time-output"), csv.as_os_str()].into_iter().chain(args.iter().map(OsStr::new)), b"");
+        assert!(output.status.success())
+    }
+    let csv = fs::read_to_string(csv).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("timestamp,program,program_hash,optimizations,load_us,optimize_us,run_us,steps,peak_cells"));
+    let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+    assert_eq!(rows.len(), 2, "{}", csv);
+    for row in &rows {
+        assert_eq!(row.len(), 9, "{:?}", row);
+        assert_eq!((row[1], row[2].len()), (path.to_str().unwrap(), 16));
+        assert!(row[2].chars().all(|digit| digit.is_ascii_hexdigit()), "{:?}", row);
+        for field in [row[0], row[4], row[5], row[6], row[7], row[8]] {
+            assert!(field.parse::<u64>().is_ok(), "{:?}", row)
+        }
+    }
+    assert_eq!(rows[0][3], "loops;coalesce-moves;fuse-commands");
+    assert_eq!(rows[1][3], "coalesce-moves;fuse-commands");
+    // Without optimized loops, more steps are executed.
+    assert!(rows[0][7].parse::<u64>().unwrap() < rows[1][7].parse::<u64>().unwrap(), "{}", csv)
+}
+
+// With `--dialect random`, `%` gives the same bytes for the same `--seed`, which `--verbose`
+// prints, and `%` is not optimized away even when followed by writes to its cell.
+#[test]
+fn random_bytes_are_reproducible_from_the_seed() {
+    let path = program("random.b", "%.%.%.%[-]%.");
+    let random = |seed: &str| {
+        let output = run([path.as_os_str(), OsStr::new("--dialect"), OsStr::new("random"), OsStr::new("--seed"), OsStr::new(seed), OsStr::new("--verbose")], b"");
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stderr), format!("Random seed: {}\n", seed));
+        output.stdout
+    };
+    let mut rng = brainfuck_interpreter::rng::Rng::new(42);
+    let bytes: Vec<u8> = (0..5).map(|_| rng.next_byte()).collect();
+    assert_eq!(random("42"), [bytes[0], bytes[1], bytes[2], bytes[4]]);
+    assert_eq!(random("42"), [0xbd, 0x28, 0x47, bytes[4]]);
+    assert_ne!(random("43"), random("42"))
+}
+
+// With `--dialect dual`, each tape keeps its own pointer and cells, `^` exchanges the current cells
+// of both tapes, and `?` prints both tapes.
+#[test]
+fn dual_tapes_keep_their_own_pointers() {
+    let output = run([program("dual.b", "++>~+++?^.~.").as_os_str(), OsStr::new("--dialect"), OsStr::new("dual"), OsStr::new("--debug")], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\x00\x03");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "\
+Active tape (pointer 0):
+| 0x03 |
+     0 
+Inactive tape (pointer 1):
+| 0x02 |
+     0 
+")
+}
+
+// `--print-exit-cell` prints the value of the current cell on its own line after the output of the
+// program, such as the sum computed by an adder, without changing the exit code.
+#[test]
+fn exit_cells_are_printed_after_the_output() {
+    let adder = program("adder.b", ",>,[-<+>]<");
+    assert_eq!(stdout([adder.as_os_str(), OsStr::new("--print-exit-cell")], b"\x03\x05"), b"8\n");
+    let printing_adder = program("printing_adder.b", ",>,[-<+>]<.");
+    assert_eq!(stdout([printing_adder.as_os_str(), OsStr::new("--print-exit-cell")], b"\x20\x21"), b"A\n65\n");
+    let (code, _) = failure([adder.as_os_str(), OsStr::new("--print-exit-cell"), OsStr::new("--eof"), OsStr::new("error")], b"\x03");
+    assert_eq!(code, 5)
+}
+
+// `--profile-commands` prints a table whose counts add up to the steps of the summary, and whose
+// total is about the time spent running the program.
+#[test]
+fn command_profiles_add_up() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs/bubble_sort.b");
+    let output = run([path.as_os_str(), OsStr::new("--profile-commands"), OsStr::new("--time"), OsStr::new("--summary")], b"brainfuck");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"abcfiknru");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let milliseconds = |field: &str| field.trim().strip_suffix(" ms").unwrap().parse::<f64>().unwrap();
+    let running = stderr.lines().find_map(|line| line.split_once("Running")).map(|(_, time)| milliseconds(time)).unwrap();
+    let steps: u64 = stderr.lines().find_map(|line| line.strip_prefix("Steps:")).unwrap().trim().parse().unwrap();
+    let table: Vec<Vec<&str>> = stderr.lines()
+        .skip_while(|line| !line.starts_with("Command"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("Steps:"))
+        .map(|line| line.split('\t').map(str::trim).collect())
+        .collect();
+    let (total, rows) = table.split_last().unwrap();
+    assert_eq!(total[0], "Total", "{}", stderr);
+    assert!(rows.iter().any(|row| row[0] == "Loop") && rows.iter().any(|row| row[0] == "Scan"), "{}", stderr);
+    assert_eq!(rows.iter().map(|row| row[1].parse::<u64>().unwrap()).sum::<u64>(), steps, "{}", stderr);
+    let total = milliseconds(total[2]);
+    let sum: f64 = rows.iter().map(|row| milliseconds(row[2])).sum();
+    assert!((sum - total).abs() <= 0.001 * rows.len() as f64, "{}", stderr);
+    assert!(total <= running && total >= running / 2.0, "{}", stderr)
+}
+
+// `--seed-tape` makes a program relying on cells it never initializes being 0 fail in a way that
+// can be reproduced from the seed it prints, while a program clearing its cells first still works.
+#[test]
+fn seeded_tapes_reveal_uninitialized_cells() {
+    let relying = program("seed_tape_relying.b", "+++++++++[>+++++++++<-]>-.");
+    let clearing = program("seed_tape_clearing.b", "[-]>[-]<+++++++++[>+++++++++<-]>-.");
+    for path in [&relying, &clearing] {
+        assert_eq!(stdout([path.as_os_str()], b""), b"P");
+    }
+    let seeded = |path: &PathBuf| {
+        let output = run([path.as_os_str(), OsStr::new("--seed-tape"), OsStr::new("7")], b"");
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "Tape seed: 7\n");
+        output.stdout
+    };
+    assert_eq!(seeded(&relying), b"\xcf");
+    assert_eq!(seeded(&relying), b"\xcf");
+    assert_eq!(seeded(&clearing), b"P")
+}
+
+// Piping ROT13 into itself gives back the input, and each further stage applies it once more.
+#[test]
+fn piped_rot13_round_trips() {
+    let rot13 = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/rot13.b");
+    let piped = |stages: usize| {
+        let mut args = vec![rot13.as_os_str()];
+        for _ in 1..stages {
+            args.extend([OsStr::new("--pipe"), rot13.as_os_str()])
+        }
+        stdout(args, b"Hello, World! xyz\n")
+    };
+    assert_eq!(piped(2), b"Hello, World! xyz\n");
+    assert_eq!(piped(3), b"Uryyb, Jbeyq! klm\n");
+    assert_eq!(piped(4), b"Hello, World! xyz\n")
+}
+
+// `--print-tape-hash` prints the same hash for the same final tape, whichever back end runs the
+// program and wherever the tape is, and a different one if a cell or the pointer differs.
+#[test]
+fn tape_hashes_depend_only_on_the_final_tape() {
+    let hash = |name: &str, code: &str, args: &[&str]| {
+        let output = run([program(&format!("tape_hash_{name}.b"), code).as_os_str(), OsStr::new("--print-tape-hash")].into_iter().chain(args.iter().map(OsStr::new)), b"");
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let hash = stderr.strip_prefix("Tape hash: ").and_then(|hash| hash.strip_suffix('\n')).unwrap().to_string();
+        assert!(hash.len() == 16 && hash.chars().all(|digit| digit.is_ascii_hexdigit()), "{}", stderr);
+        hash
+    };
+    let code = "++++++[>++++++<-]>[->+>+<<]>>>+<";
+    let expected = hash("tree", code, &["--flatten-loops", "false"]);
+    assert_eq!(hash("flattened", code, &[]), expected);
+    assert_eq!(hash("unoptimized", code, &["--optimize-loops", "false", "--fuse-commands", "false", "--coalesce-moves", "false"]), expected);
+    // Zero cells at both ends do not count, and only the offset of the pointer matters.
+    assert_eq!(hash("shifted", &format!("<<<<+-{code}"), &[]), expected);
+    assert_eq!(hash("cleared", &format!("+[-]{code}>>>[-]<<<"), &[]), expected);
+    assert_ne!(hash("changed", &format!("{code}+"), &[]), expected);
+    assert_ne!(hash("moved", &format!("{code}>"), &[]), expected)
+}
+
+// `--analyze` reports exact bounds for programs whose loops move the pointer back to where they
+// started, and names the loops that make them unbounded otherwise.
+#[test]
+fn analyses_bound_the_pointer() {
+    let analyze = |name: &str, code: &str| {
+        let path = program(&format!("analyze_{name}.b"), code);
+        String::from_utf8(stdout([path.as_os_str(), OsStr::new("--analyze")], b"")).unwrap()
+    };
+    let report = |min: &str, max: &str, negative: &str, imprecise: &str| format!("\
+Minimum pointer offset: {min}
+Maximum pointer offset: {max}
+Negative cells may be reached: {negative}
+Imprecise loops: {imprecise}
+");
+    assert_eq!(analyze("straight", ">>+<<<"), report("-1", "2", "yes", "none"));
+    assert_eq!(analyze("balanced", "+[->>+<<]>"), report("0", "2", "no", "none"));
+    assert_eq!(analyze("scan", ">+[>+]"), report("0", "unbounded (because of the loop at 1:3)", "no", "1:3"));
+    assert_eq!(analyze("nested", "+\n>[<[-]]<<[<]>"), report("unbounded (because of the loop at 2:2)", "1", "yes", "2:2, 2:10"))
+}
+
+/// The code, severity, start, and end of a diagnostic.
+type Linted = (String, String, u64, u64);
+
+/// Checks a program with `--lint`, along with other arguments, and returns the exit code along with
+/// the diagnostics.
+fn lint(name: &str, code: &str, args: &[&str]) -> (Option<i32>, Vec<Linted>) {
+    let path = program(&format!("lint_{name}.b"), code);
+    let output = run([path.as_os_str(), OsStr::new("--lint"), OsStr::new("--diagnostics"), OsStr::new("json")].into_iter().chain(args.iter().map(OsStr::new)), b"");
+    let diagnostics = String::from_utf8(output.stdout).unwrap().lines().map(|line| {
+        let diagnostic: serde_json::Value = serde_json::from_str(line).unwrap();
+        let text = |field: &str| diagnostic[field].as_str().unwrap().to_string();
+        (text("code"), text("severity"), diagnostic["start"].as_u64().unwrap(), diagnostic["end"].as_u64().unwrap())
+    }).collect();
+    (output.status.code(), diagnostics)
+}
+
+// The `empty-loop` lint finds `[]`, and can be made an error with `--deny` or silenced with
+// `--allow`.
+#[test]
+fn empty_loops_are_linted() {
+    assert_eq!(lint("empty_loop", "+[].", &[]), (Some(0), vec![("empty-loop".into(), "warning".into(), 1, 3)]));
+    assert_eq!(lint("empty_loop", "+[].", &["--deny", "empty-loop"]), (Some(1), vec![("empty-loop".into(), "error".into(), 1, 3)]));
+    assert_eq!(lint("empty_loop", "+[].", &["--allow", "empty-loop"]), (Some(0), vec![]))
+}
+
+// The `dead-loop` lint finds loops starting on a cell that is always 0.
+#[test]
+fn dead_loops_are_linted() {
+    assert_eq!(lint("dead_loop", "[-].", &[]), (Some(0), vec![("dead-loop".into(), "warning".into(), 0, 3)]));
+    assert_eq!(lint("live_loop", ",[-].", &[]), (Some(0), vec![]))
+}
+
+// The `infinite-loop` lint finds loops whose body cannot change the current cell.
+#[test]
+fn infinite_loops_are_linted() {
+    assert_eq!(lint("infinite_loop", "+[>+<].", &[]), (Some(0), vec![("infinite-loop".into(), "warning".into(), 1, 6)]));
+    assert_eq!(lint("finite_loop", "+[>+<-].", &[]), (Some(0), vec![]))
+}
+
+// The `dead-store` lint finds changes to a cell that are overwritten before it is read.
+#[test]
+fn dead_stores_are_linted() {
+    assert_eq!(lint("dead_store", "++,.", &[]), (Some(0), vec![("dead-store".into(), "warning".into(), 0, 2)]));
+    assert_eq!(lint("live_store", "++.,.", &[]), (Some(0), vec![]))
+}
+
+// The `unobservable-tail` lint finds commands at the end of the program that have no effect.
+#[test]
+fn unobservable_tails_are_linted() {
+    assert_eq!(lint("unobservable_tail", ".+>", &[]), (Some(0), vec![("unobservable-tail".into(), "warning".into(), 1, 3)]));
+    assert_eq!(lint("observable_tail", "+>.", &[]), (Some(0), vec![]))
+}
+
+// The `wrap-around` lint finds cells going below 0 or past 255 from 0.
+#[test]
+fn wrap_arounds_are_linted() {
+    assert_eq!(lint("wrap_around", "-.", &[]), (Some(0), vec![("wrap-around".into(), "warning".into(), 0, 1)]));
+    assert_eq!(lint("wrap_around_up", &format!("{}.", "+".repeat(256)), &[]), (Some(0), vec![("wrap-around".into(), "warning".into(), 0, 256)]));
+    assert_eq!(lint("no_wrap_around", &format!("{}.", "+".repeat(255)), &[]), (Some(0), vec![]))
+}
+
+// The `negative-cell` lint finds the pointer moving left of the initial cell.
+#[test]
+fn negative_cells_are_linted() {
+    assert_eq!(lint("negative_cell", "<+.", &[]), (Some(0), vec![("negative-cell".into(), "warning".into(), 0, 1)]));
+    assert_eq!(lint("positive_cell", "><+.", &[]), (Some(0), vec![]))
+}
+
+// Programs whose comments are not valid UTF-8 run exactly as the same programs without those
+// comments, and their errors are located in bytes.
+#[test]
+fn comments_need_not_be_utf8() {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs/latin1_comments.b");
+    let code = fs::read(&fixture).unwrap();
+    assert!(String::from_utf8(code.clone()).is_err());
+    let cleaned: Vec<u8> = code.iter().copied().filter(|byte| b"+-<>[].,\n".contains(byte)).collect();
+    let cleaned = program("latin1_comments_cleaned.b", std::str::from_utf8(&cleaned).unwrap());
+    let summary = |path: &Path| {
+        let output = run([path.as_os_str(), OsStr::new("--summary")], b"");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        (output.stdout, output.stderr)
+    };
+    assert_eq!(summary(&fixture), summary(&cleaned));
+    let unmatched = program("latin1_unmatched.b", "");
+    fs::write(&unmatched, b"caf\xe9 \xff\n+]").unwrap();
+    let (code, stderr) = failure([unmatched.as_os_str()], b"");
+    assert_eq!(code, 1);
+    assert!(stderr.starts_with("error: unmatched ']' at line 2, column 2 (byte 8)"), "{}", stderr)
+}
+
+// The narration of the tutor is stable, for a program with a loop and for one reading input on
+// several lines.
+#[test]
+fn tutor_narration_matches_golden_files() {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for (name, input) in [("tutor_loop", &b""[..]), ("tutor_input", b"a")] {
+        let narration = stdout([golden.join(format!("{name}.b")).as_os_str(), OsStr::new("--tutor")], input);
+        assert_eq!(String::from_utf8_lossy(&narration), String::from_utf8_lossy(&fs::read(golden.join(format!("{name}.txt"))).unwrap()), "{}", name)
+    }
+}
+
+// Synthetic inputs have the number of bytes their generator describes, which `,[>,]` consumes
+// until the first 0, as counted by the pointer range of `--summary`. Invalid generators are
+// reported before the program runs.
+#[test]
+fn synthetic_inputs_have_the_described_length() {
+    let path = program("synthetic_input.b", ",[>,]");
+    let consumed = |args: &[&str]| {
+        let output = run([path.as_os_str(), OsStr::new("--summary")].into_iter().chain(args.iter().map(OsStr::new)), b"ignored");
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        stderr.lines().find_map(|line| line.strip_prefix("Pointer range:   0 to ")).unwrap().parse::<u64>().unwrap()
+    };
+    assert_eq!(consumed(&["--input-repeat", "abc*3"]), 9);
+    assert_eq!(consumed(&["--input-repeat", "header|x*1024|\n"]), 1031);
+    assert_eq!(consumed(&["--input-repeat", "a*100000"]), 100_000);
+    // A zero byte ends the loop right away.
+    assert_eq!(consumed(&["--input-zero", "5"]), 0);
+    let random = |seed: &str| consumed(&["--input-random", "1000", "--seed", seed]);
+    assert_eq!(random("1"), random("1"));
+    let (code, stderr) = failure([path.as_os_str(), OsStr::new("--input-repeat"), OsStr::new("a*x")], b"");
+    assert_eq!(code, 2);
+    assert!(stderr.contains("invalid repetition count `x`"), "{}", stderr)
+}
+
+/// A program computing 8! in cell 1 by repeated additions, and ending on it.
+const FACTORIAL: &str = "\
+++++++++>+<                   cell 0 counts down from 8 and cell 1 is the product
+[
+  >[->>+<<]<                  move the product to cell 3
+  [->>+>>+<<<<]>>[-<<+>>]<<   copy the counter to cell 4
+  >>>>[-<[-<<+>+>]<[->+<]>>]  add cell 3 to the product as many times
+  <[-]<<<-
+]>";
+
+// With `--cell-size big`, cells never wrap around, so a factorial differs from the one computed
+// with bytes, which is only correct modulo 256.
+#[test]
+fn big_cells_compute_factorials() {
+    let path = program("factorial.b", FACTORIAL);
+    let factorial = |cell_size: &str| stdout([path.as_os_str(), OsStr::new("--cell-size"), OsStr::new(cell_size), OsStr::new("--print-exit-cell")], b"");
+    assert_eq!(factorial("big"), b"40320\n");
+    assert_eq!(factorial("32"), b"40320\n");
+    assert_eq!(factorial("8"), b"128\n")
+}
+
+// `--diff` finds where the outputs of two programs first differ, even late in their output, and
+// exits with status 1, but with status 0 if the outputs are identical.
+#[test]
+fn diffs_report_the_first_differing_byte() {
+    let old = program("diff_old.b", "++++++++[>++++++++<-]>+..........>++++++++++.<.");
+    let new = program("diff_new.b", "++++++++[>++++++++<-]>+..........>++++++++++.<+..");
+    let output = run([old.as_os_str(), OsStr::new("--diff"), new.as_os_str()], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!(
+        "Outputs first differ at offset 11:\n  {}: 0x41, output at step 19\n  {}: 0x42, output at step 20\nDiffering bytes: 2 (the outputs are 12 and 13 bytes long)\n",
+        old.display(),
+        new.display(),
+    ));
+    assert_eq!(stdout([old.as_os_str(), OsStr::new("--diff"), old.as_os_str()], b""), b"Outputs are identical (12 bytes)\n")
+}
+
+// With `--assertions`, a program whose assertions hold runs as usual, even around commands that
+// are optimized, and the first assertion that does not hold stops it with its position and the
+// values it compared.
+#[test]
+fn assertions_stop_programs_where_they_fail() {
+    let passing = program("assertion_passing.b", "++++++++{assert cell(0)==8}[>++++++++<-]>+{assert ptr==1}{assert cell(1)>cell(0)}.[-]{assert cell(1)==0}");
+    assert_eq!(stdout([passing.as_os_str(), OsStr::new("--assertions")], b""), b"A");
+    let failing = program("assertion_failing.b", "++{assert cell(0)==2}\n>+++++{assert cell(1)<cell(0)}.");
+    let (code, stderr) = failure([failing.as_os_str(), OsStr::new("--assertions")], b"");
+    assert_eq!((code, stderr.as_str()), (4, "error: assertion `cell(1)<cell(0)` failed at 2:7 (cell(1) is 5, cell(0) is 2)\n"));
+    // Without `--assertions`, the failing assertion is ignored.
+    assert_eq!(stdout([failing.as_os_str()], b""), b"\x05")
+}
+
+// Tape dumps show the names given to cells under their indices, including names relative to the
+// pointer.
+#[test]
+fn tape_dumps_show_cell_names() {
+    let path = program("named_dump.b", "{name 0 counter}{name 2 total}+++>>++{name -1 temp}?");
+    let output = run([path.as_os_str(), OsStr::new("--debug")], b"");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "\
+Tape (pointer 2):
+| 0x03    | 0x00 | 0x02  |
+        0      1       2 
+  counter   temp   total 
+")
+}
+
+// `--watch` reports each change to a cell given by its name or its index, with the command that
+// made it, after the output so far.
+#[test]
+fn watched_cells_report_their_changes() {
+    let path = program("watch.b", "{name 0 counter}{name 1 total}\n+++[>++<-]>.");
+    let output = run([path.as_os_str(), OsStr::new("--watch"), OsStr::new("counter"), OsStr::new("--watch"), OsStr::new("1")], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\x06");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "\
+Watch: cell 0 (`counter`) changed from 0 to 3 at step 1 (Add at line 2, column 1)
+Watch: cell 0 (`counter`) changed from 3 to 0 at step 2 (Move at line 2, column 4)
+Watch: cell 1 (`total`) changed from 0 to 6 at step 2 (Move at line 2, column 4)
+");
+    let (code, stderr) = failure([path.as_os_str(), OsStr::new("--watch"), OsStr::new("unknown")], b"");
+    assert_eq!((code, stderr.as_str()), (1, "error: --watch unknown: invalid cell index or unknown name `unknown`\n"))
+}
+
+// With `--keymap`, the program reads the bytes the sequences of mapped keys are mapped to, one
+// after the other when there are several, and the other bytes as they are.
+#[test]
+fn keymaps_replace_the_sequences_of_keys() {
+    let cat = program("keymap_cat.b", CAT);
+    let keymap = program("keymap.txt", "# Arrows\nUp = w\nDown = ss\n\n\\x03 =\n");
+    let output = stdout([cat.as_os_str(), OsStr::new("--keymap"), keymap.as_os_str()], b"\x1b[Ax\x1b[B\x03\x1b[C");
+    assert_eq!(output.escape_ascii().to_string(), "wxss\\x1b[C")
+}
+
+// In game mode, the form feeds a program outputs clear the screen, even when its output is not a
+// terminal.
+#[test]
+fn form_feeds_clear_the_screen_in_game_mode() {
+    let path = program("game.b", "++++++++++++.>++++++++[<++++++++>-]<-----------.>++++++++++++.");
+    assert_eq!(stdout([path.as_os_str(), OsStr::new("--game")], b"").escape_ascii().to_string(), "\\x1b[2J\\x1b[HA\\x1b[2J\\x1b[H")
+}
+
+// With `--per-line`, a program runs once for each line on a blank tape, which the ROT13 example
+// reads with its newline, and the outputs of the runs follow one another, optionally numbered.
+#[test]
+fn programs_run_once_per_line() {
+    let rot13 = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/rot13.b");
+    let lines = b"Hello World\nxyz NOP\n42 @\n";
+    let per_line = [rot13.as_os_str(), OsStr::new("--per-line"), OsStr::new("--keep-newline")];
+    assert_eq!(String::from_utf8_lossy(&stdout(per_line, lines)), "Uryyb Jbeyq\nklm ABC\n42 @\n");
+    let numbered = [&per_line[..], &[OsStr::new("--number-lines")]].concat();
+    assert_eq!(String::from_utf8_lossy(&stdout(numbered, lines)), "1:Uryyb Jbeyq\n2:klm ABC\n3:42 @\n");
+    // Without `--keep-newline`, each run reads the end of its input at the end of the line.
+    let counter = program("per_line_counter.b", ">+<,[.,]>.");
+    assert_eq!(stdout([counter.as_os_str(), OsStr::new("--per-line")], b"ab\ncd\n"), b"ab\x01cd\x01")
+}
+
+// `--output-map` records which `.` output each value, including the values a `.` in a loop outputs
+// one after the other, and `--where-output` finds it for a value in each region.
+#[test]
+fn output_maps_find_the_command_of_each_value() {
+    let path = program("output_map.b", "++++++++[>++++++++<-]>+.\n>+++[<+.>-]\n<.");
+    let map = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("output_map.csv");
+    assert_eq!(stdout([path.as_os_str(), OsStr::new("--output-map"), map.as_os_str()], b""), b"ABCDD");
+    let rows = fs::read_to_string(&map).unwrap();
+    assert_eq!(rows.lines().skip(1).collect::<Vec<_>>(), ["offset,count,line,column,first_step,last_step", "0,1,1,24,5,5", "1,3,2,8,11,21", "4,1,3,2,25,25"]);
+    let where_output = |offset: &str| stdout([path.as_os_str(), OsStr::new("--output-map"), map.as_os_str(), OsStr::new("--where-output"), OsStr::new(offset)], b"");
+    assert_eq!(String::from_utf8_lossy(&where_output("0")), "Value 0 was output by the `.` at 1:24\nIt was output at step 5\n");
+    assert_eq!(
+        String::from_utf8_lossy(&where_output("2")),
+        "Value 2 was output by the `.` at 2:8\nIt is value 2 of 3 output one after the other by this command, from step 11 to step 21\n",
+    );
+    assert_eq!(String::from_utf8_lossy(&where_output("4")), "Value 4 was output by the `.` at 3:2\nIt was output at step 25\n");
+    let (code, stderr) = failure([path.as_os_str(), OsStr::new("--output-map"), map.as_os_str(), OsStr::new("--where-output"), OsStr::new("5")], b"");
+    assert_eq!(code, 1);
+    assert!(stderr.contains("fewer than 6 values"), "{}", stderr)
+}
+
+// The execution counts of `--annotate` are stable, for a program with a loop and instructions that
+// are never executed, and `--hot-threshold` changes which lines are marked as hot.
+#[test]
+fn annotations_match_golden_file() {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let path = golden.join("annotate.b");
+    let annotated = String::from_utf8_lossy(&stdout([path.as_os_str(), OsStr::new("--annotate")], b"")).into_owned();
+    assert_eq!(annotated, String::from_utf8_lossy(&fs::read(golden.join("annotate.txt")).unwrap()));
+    let annotated = String::from_utf8_lossy(&stdout([path.as_os_str(), OsStr::new("--annotate"), OsStr::new("--hot-threshold"), OsStr::new("5")], b"")).into_owned();
+    let hot_lines: Vec<_> = annotated.lines().filter(|line| line.contains("*:")).map(|line| line.split(':').nth(1).unwrap().trim()).collect();
+    assert_eq!(hot_lines, ["3", "5"])
+}
+
+// With `--output`, the output goes to a file instead of `stdout`, in hexadecimal with
+// `--hex-output`, and it is flushed before the program waits for input and when it fails.
+// `--output` cannot be used with `--no-output`.
+#[test]
+fn output_is_written_to_files() {
+    let path = program("output_file.b", "++++++++[>++++++++<-]>+.+.");
+    let file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("output_file.txt");
+    assert_eq!(stdout([path.as_os_str(), OsStr::new("--output"), file.as_os_str()], b""), b"");
+    assert_eq!(fs::read(&file).unwrap(), b"AB");
+    assert_eq!(stdout([path.as_os_str(), OsStr::new("--output"), file.as_os_str(), OsStr::new("--hex-output")], b""), b"");
+    assert_eq!(fs::read_to_string(&file).unwrap(), "0x41\n0x42\n");
+    let endless = program("output_file_endless.b", "+[.]");
+    let (code, _) = failure([endless.as_os_str(), OsStr::new("--output"), file.as_os_str(), OsStr::new("--max-output"), OsStr::new("3")], b"");
+    assert_eq!((code, fs::read(&file).unwrap()), (3, b"\x01\x01\x01".to_vec()));
+    let (code, _) = failure([path.as_os_str(), OsStr::new("--output"), file.as_os_str(), OsStr::new("--no-output")], b"");
+    assert_eq!(code, 2);
+    // The output before `,` is in the file while the program waits for its input.
+    let prompt = program("output_file_prompt.b", "++++++++[>++++++++<-]>+.,.");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainfuck-interpreter"))
+        .arg(&prompt)
+        .arg("--output")
+        .arg(&file)
+        .stdin(Stdio::piped())
+        .spawn()
+        .expect("Unable to start interpreter");
+    let mut stdin = child.stdin.take().expect("Unable to open stdin");
+    let start = std::time::Instant::now();
+    while fs::read(&file).unwrap_or_default() != b"A" {
+        assert!(start.elapsed().as_secs() < 10, "the output was not flushed before reading input");
+        std::thread::sleep(std::time::Duration::from_millis(10))
+    }
+    std::io::Write::write_all(&mut stdin, b"x\n").unwrap();
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+    assert_eq!(fs::read(&file).unwrap(), b"Ax")
+}
+
+// In the `dual` dialect, only the cells of the first tape are protected, including from exchanges
+// made while the program uses the second tape, and violations tell the line and column of the
+// command.
+#[test]
+fn protections_apply_to_the_first_tape() {
+    let second_tape = program("protected_second_tape.b", "~>+~+.");
+    assert_eq!(stdout([second_tape.as_os_str(), OsStr::new("--dialect"), OsStr::new("dual"), OsStr::new("--protect"), OsStr::new("1..2")], b""), [1]);
+    for (source, expected) in [
+        ("~>+~\n>+", "error: Add at step 6 (line 2, column 2) writes to cell 1, which is protected by --protect 1..2\n"),
+        (">~^", "error: ExchangeCells at step 3 (line 1, column 3) writes to cell 1, which is protected by --protect 1..2\n"),
+    ] {
+        let path = program("protected_first_tape.b", source);
+        let (code, stderr) = failure([path.as_os_str(), OsStr::new("--dialect"), OsStr::new("dual"), OsStr::new("--protect"), OsStr::new("1..2")], b"");
+        assert_eq!(code, 4, "{}", stderr);
+        assert!(stderr.starts_with(expected), "{}", stderr)
+    }
+}
+
+// Walking the commands as a tree with `--flatten-loops false` gives the same output and counts the
+// same steps as running them flattened.
+#[test]
+fn walked_programs_run_like_flattened_ones() {
+    let programs = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    for (name, input) in [("hello_world.b", &b""[..]), ("bubble_sort.b", b"brainfuck"), ("brackets.b", b"")] {
+        let path = programs.join(name);
+        let flattened = run([path.as_os_str(), OsStr::new("--summary")], input);
+        let walked = run([path.as_os_str(), OsStr::new("--summary"), OsStr::new("--flatten-loops"), OsStr::new("false")], input);
+        assert!(flattened.status.success() && walked.status.success(), "{}", name);
+        assert_eq!(walked.stdout, flattened.stdout, "{}", name);
+        let summary = String::from_utf8_lossy(&flattened.stderr);
+        assert!(summary.starts_with("Steps:"), "{}", summary);
+        assert_eq!(String::from_utf8_lossy(&walked.stderr), summary, "{}", name)
+    }
+}
@@ -0,0 +1,57 @@
+//! Helpers shared by the integration tests, which run the interpreter as a separate process.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Prints "Hello World!" and a new line.
+pub const HELLO_WORLD: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+/// Outputs its input until its end.
+pub const CAT: &str = ",[.,]";
+
+/// Writes a program to a file in the temporary directory of the tests, and returns its path.
+pub fn program(name: &str, code: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::write(&path, code).expect("Unable to write program");
+    path
+}
+
+/// Runs the interpreter with the specified arguments, writing the specified bytes to its `stdin`.
+pub fn run<I: AsRef<std::ffi::OsStr>>(args: impl IntoIterator<Item = I>, input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainfuck-interpreter"))
+        .args(args)
+        .env("RUST_BACKTRACE", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Unable to start interpreter");
+    let mut stdin = child.stdin.take().expect("Unable to open stdin");
+    // The program may exit without reading its whole input.
+    let _ = stdin.write_all(input);
+    drop(stdin);
+    child.wait_with_output().expect("Unable to run interpreter")
+}
+
+/// Runs the interpreter as [`run`], asserts that it succeeds, and returns what it printed to
+/// `stdout`.
+pub fn stdout<I: AsRef<std::ffi::OsStr>>(args: impl IntoIterator<Item = I>, input: &[u8]) -> Vec<u8> {
+    let output = run(args, input);
+    assert!(output.status.success(), "interpreter failed: {}", String::from_utf8_lossy(&output.stderr));
+    output.stdout
+}
+
+/// Runs the interpreter as [`run`], and returns its exit code and what it printed to `stderr`.
+pub fn failure<I: AsRef<std::ffi::OsStr>>(args: impl IntoIterator<Item = I>, input: &[u8]) -> (i32, String) {
+    let output = run(args, input);
+    (output.status.code().expect("Interpreter was killed"), String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+/// Returns whether a C compiler is available as `cc`, which compiled programs are tested with.
+pub fn has_c_compiler() -> bool {
+    Command::new("cc").arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status().is_ok_and(|status| status.success())
+}
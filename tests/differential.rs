@@ -0,0 +1,230 @@
+//! Differential tests, which run random programs both as they are and optimized, and check that
+//! they behave the same.
+
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
+
+use brainfuck_interpreter::rng::Rng;
+use brainfuck_interpreter::tape::{Tape, TapeOptions};
+use brainfuck_interpreter::{bytecode, execute, optimize, parse, Command, Machine, MachineOptions, OptimizeOptions, Stop};
+
+/// The number of steps after which programs stop at the next iteration of a loop.
+const STEPS: u64 = 10_000;
+
+/// What a program did on an input.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    output: Vec<u8>,
+    /// Whether the program ended before running out of steps.
+    ended: bool,
+    /// The position of the pointer and the cells that are not 0 once the program stopped.
+    tape: (isize, BTreeMap<isize, u8>),
+}
+
+impl Outcome {
+    /// Runs commands on an input by walking their tree, like the reference interpreter, or once
+    /// flattened, like the optimized one.
+    fn run(commands: &[Command], input: &[u8], flatten: bool) -> Self {
+        let mut output = Vec::new();
+        let mut tape = Tape::new(&TapeOptions::default());
+        tape.set_reader(input);
+        tape.set_raw_output(Box::new(&mut output));
+        let mut machine = Machine::new(tape, &MachineOptions { max_steps: Some(STEPS), ..MachineOptions::default() });
+        let result = if flatten { bytecode::execute(&bytecode::flatten(commands), &mut machine) } else { execute(commands, &mut machine) };
+        assert!(matches!(result, ControlFlow::Continue(()) | ControlFlow::Break(Stop::StepLimit)), "{:?}", result);
+        let state = machine.tape.state();
+        let tape = (state.pointer, state.cells);
+        drop(machine);
+        Self { output, ended: result.is_continue(), tape }
+    }
+
+    /// Returns whether an optimized program agrees with the reference interpreter (or with the
+    /// program optimized with fewer passes), which had this outcome. Optimized programs never
+    /// execute more steps, so they must end if the reference did, and if it ran out of steps, they
+    /// only agree on what they output so far.
+    fn agrees_with(&self, optimized: &Outcome) -> bool {
+        match (self.ended, optimized.ended) {
+            (true, true) => self == optimized,
+            (true, false) => false,
+            (false, _) => optimized.output.starts_with(&self.output),
+        }
+    }
+}
+
+/// Returns a random program of about `length` instructions with balanced brackets, whose loops
+/// are nested at most `max_depth` levels deep.
+fn random_program(rng: &mut Rng, length: usize, max_depth: usize) -> String {
+    let mut code = String::new();
+    let mut depth = 0;
+    while code.len() < length || depth > 0 {
+        let instruction = match rng.next_u64() % 16 {
+            0 if depth < max_depth && code.len() < length => {
+                depth += 1;
+                '['
+            }
+            1 | 2 if depth > 0 => {
+                depth -= 1;
+                ']'
+            }
+            3 => '.',
+            4 => ',',
+            5..=7 => '>',
+            8..=10 => '<',
+            11 | 12 => '+',
+            _ => '-',
+        };
+        code.push(instruction)
+    }
+    code
+}
+
+/// Removes instructions from a program for which `fails` returns `true` while it keeps failing,
+/// and returns the smallest such program. Loops are removed whole, or only their brackets.
+fn shrink(mut code: String, fails: impl Fn(&str) -> bool) -> String {
+    'shrink: loop {
+        for start in 0..code.len() {
+            let end = match code.as_bytes()[start] {
+                b'[' => {
+                    let mut depth = 0;
+                    start + code[start..].bytes().position(|instruction| {
+                        depth += match instruction { b'[' => 1, b']' => -1, _ => 0 };
+                        depth == 0
+                    }).unwrap()
+                }
+                b']' => continue,
+                _ => start,
+            };
+            let without_loop = format!("{}{}", &code[..start], &code[end + 1..]);
+            let without_brackets = format!("{}{}{}", &code[..start], &code[start + 1..end.max(start + 1)], &code[end + 1..]);
+            for candidate in [without_loop, without_brackets] {
+                if candidate.len() < code.len() && fails(&candidate) {
+                    code = candidate;
+                    continue 'shrink;
+                }
+            }
+        }
+        return code;
+    }
+}
+
+/// Removes parts of an input for which `fails` returns `true` while it keeps failing, halving
+/// their length each time no part can be removed, and returns the smallest such input.
+fn shrink_input(mut input: Vec<u8>, fails: impl Fn(&[u8]) -> bool) -> Vec<u8> {
+    let mut length = input.len() / 2;
+    while length > 0 {
+        let mut start = 0;
+        while start + length <= input.len() {
+            let candidate = [&input[..start], &input[start + length..]].concat();
+            if fails(&candidate) {
+                input = candidate
+            } else {
+                start += length
+            }
+        }
+        length /= 2
+    }
+    input
+}
+
+/// Returns a random input of at most `max_length` bytes.
+fn random_input(rng: &mut Rng, max_length: u64) -> Vec<u8> {
+    (0..rng.next_u64() % (max_length + 1)).map(|_| rng.next_byte()).collect()
+}
+
+// Random programs optimized with all the passes and flattened behave like the reference
+// interpreter running them as they are, with the same number of steps, with and without chunk
+// resets.
+#[test]
+fn optimized_programs_agree_with_the_reference_interpreter() {
+    let mut rng = Rng::new(417);
+    let mut ended = 0;
+    for _ in 0..2000 {
+        let code = random_program(&mut rng, 24, 3);
+        let input = random_input(&mut rng, 8);
+        let reference = Outcome::run(&parse(&code).unwrap(), &input, false);
+        ended += usize::from(reference.ended);
+        for optimize_chunk_resets in [false, true] {
+            let options = OptimizeOptions { optimize_chunk_resets, is_final_tape_observed: true, ..OptimizeOptions::default() };
+            let optimized = Outcome::run(&optimize(parse(&code).unwrap(), &options), &input, true);
+            assert!(reference.agrees_with(&optimized), "{:?} on {:?} with {:?}:\n{:?}\n{:?}", code, input, options, reference, optimized)
+        }
+    }
+    // Most programs end, so that their final tapes are compared too.
+    assert!(ended > 1000, "{}", ended)
+}
+
+/// An optimization pass, which is enabled by the option this function returns.
+type Pass = (&'static str, fn(&mut OptimizeOptions) -> &mut bool);
+
+// Each optimization pass keeps the behavior of random programs, given the other passes, including
+// their final tape unless the pass removes the commands that have no effect on the output. Failing
+// programs are shrunk to a minimal counterexample.
+#[test]
+fn each_pass_keeps_the_behavior_of_programs() {
+    let passes: [Pass; 6] = [
+        ("optimize_loops", |options| &mut options.optimize_loops),
+        ("optimize_chunk_resets", |options| &mut options.optimize_chunk_resets),
+        ("propagate_constants", |options| &mut options.propagate_constants),
+        ("remove_unobservable_tail", |options| &mut options.remove_unobservable_tail),
+        ("coalesce_moves", |options| &mut options.coalesce_moves),
+        ("fuse_commands", |options| &mut options.fuse_commands),
+    ];
+    let mut rng = Rng::new(418);
+    for (name, option) in passes {
+        let is_tail_removed = name == "remove_unobservable_tail";
+        let mut before = OptimizeOptions { optimize_chunk_resets: true, is_final_tape_observed: !is_tail_removed, ..OptimizeOptions::default() };
+        *option(&mut before) = false;
+        let mut after = before.clone();
+        *option(&mut after) = true;
+        let outcome = |code: &str, input: &[u8], options: &OptimizeOptions| {
+            let mut outcome = Outcome::run(&optimize(parse(code).unwrap(), options), input, true);
+            if is_tail_removed {
+                outcome.tape = Default::default()
+            }
+            outcome
+        };
+        for _ in 0..500 {
+            let code = random_program(&mut rng, 24, 3);
+            let input = random_input(&mut rng, 8);
+            let fails = |code: &str| !outcome(code, &input, &before).agrees_with(&outcome(code, &input, &after));
+            if fails(&code) {
+                let code = shrink(code, fails);
+                panic!("{} changes the behavior of {:?} on {:?}:\n{:?}\n{:?}", name, code, input, outcome(&code, &input, &before), outcome(&code, &input, &after))
+            }
+        }
+    }
+}
+
+// Shrinking removes instructions and loops while the program keeps failing.
+#[test]
+fn failing_programs_are_shrunk() {
+    assert_eq!(shrink("+>[-<.>]<[,[.]]".to_string(), |code| code.contains(",.")), ",.");
+    assert_eq!(shrink("+[-[>]+]".to_string(), |code| code.contains("[>]")), "[>]")
+}
+
+// Sample programs behave the same when walked as they are and when optimized and flattened, on
+// random inputs. Failing inputs are shrunk to a minimal counterexample.
+#[test]
+fn sample_programs_agree_on_random_inputs() {
+    let mut rng = Rng::new(421);
+    for path in ["examples/rot13.b", "tests/programs/bubble_sort.b", "tests/programs/brackets.b"] {
+        let code = std::fs::read_to_string(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(path)).unwrap();
+        let reference = parse(&code).unwrap();
+        let optimized = optimize(parse(&code).unwrap(), &OptimizeOptions { optimize_chunk_resets: true, is_final_tape_observed: true, ..OptimizeOptions::default() });
+        let fails = |input: &[u8]| !Outcome::run(&reference, input, false).agrees_with(&Outcome::run(&optimized, input, true));
+        for _ in 0..50 {
+            let input = random_input(&mut rng, 32);
+            if fails(&input) {
+                let input = shrink_input(input, fails);
+                panic!("{} differs on \"{}\":\n{:?}\n{:?}", path, input.escape_ascii(), Outcome::run(&reference, &input, false), Outcome::run(&optimized, &input, true))
+            }
+        }
+    }
+}
+
+// Shrinking an input removes the parts that do not make it fail.
+#[test]
+fn failing_inputs_are_shrunk() {
+    assert_eq!(shrink_input(b"abcdefgh".to_vec(), |input| input.contains(&b'c') && input.contains(&b'f')), b"cf");
+    assert_eq!(shrink_input(b"abc".to_vec(), |input| !input.is_empty()).len(), 1)
+}
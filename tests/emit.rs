@@ -0,0 +1,169 @@
+//! Tests of the programs written by the `--emit-*` options.
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use common::{program, run, stdout, CAT, HELLO_WORLD};
+
+/// A program that moves about 8000 cells to the right, and outputs `A` there.
+const GROW: &str = "-[-[->>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<]>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>]>++++++++[<++++++++>-]<+.";
+
+/// Translates a program with `--emit-{language}`, and returns the path of the translation, which
+/// has the specified extension.
+fn emit(language: &str, extension: &str, name: &str, code: &str, args: &[&str]) -> PathBuf {
+    let source = program(&format!("{name}.b"), code);
+    let translation = source.with_extension(extension);
+    let option = format!("--emit-{language}");
+    stdout([source.as_os_str(), option.as_ref(), translation.as_os_str()].into_iter().chain(args.iter().map(|arg| arg.as_ref())), b"");
+    translation
+}
+
+/// Translates a program to C with `--emit-c`, and returns the path of the C file.
+fn emit_c(name: &str, code: &str, args: &[&str]) -> PathBuf {
+    emit("c", "c", name, code, args)
+}
+
+/// Checks that translating `tests/golden/emit_{language}.b` gives the file next to it with the
+/// specified extension.
+fn assert_matches_golden_file(language: &str, extension: &str) {
+    let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let name = format!("emit_{language}");
+    let translation = emit(language, extension, &name, &fs::read_to_string(golden.join(format!("{name}.b"))).unwrap(), &[]);
+    assert_eq!(fs::read_to_string(translation).unwrap(), fs::read_to_string(golden.join(name).with_extension(extension)).unwrap())
+}
+
+/// Compiles a C file with `cc`, runs it with the specified input, and returns its output.
+fn compile_and_run(c: &PathBuf, input: &[u8]) -> Vec<u8> {
+    let executable = c.with_extension("exe");
+    let status = Command::new("cc").args(["-std=c99", "-O1", "-o"]).arg(&executable).arg(c).status().expect("Unable to run cc");
+    assert!(status.success(), "cc failed to compile {}", c.display());
+    let output = run_executable(&executable, input);
+    assert!(output.status.success());
+    output.stdout
+}
+
+fn run_executable(executable: &PathBuf, input: &[u8]) -> std::process::Output {
+    run_command(&mut Command::new(executable), input)
+}
+
+/// Runs a command with the specified input, and returns its output.
+fn run_command(command: &mut Command, input: &[u8]) -> std::process::Output {
+    use std::io::Write;
+    use std::process::Stdio;
+    let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().expect("Unable to run compiled program");
+    // The program may end before reading all of its input, which then cannot be written.
+    let _ = child.stdin.take().expect("Unable to open stdin").write_all(input);
+    child.wait_with_output().expect("Unable to run compiled program")
+}
+
+// The translation to C is stable.
+#[test]
+fn emit_c_matches_golden_file() {
+    assert_matches_golden_file("c", "c")
+}
+
+// The graph of the commands is stable.
+#[test]
+fn emit_dot_matches_golden_file() {
+    assert_matches_golden_file("dot", "dot")
+}
+
+// The optimized program lowered back to Brainfuck is stable.
+#[test]
+fn emit_bf_matches_golden_file() {
+    assert_matches_golden_file("bf", "bf")
+}
+
+// The optimized program lowered back to Brainfuck outputs the same bytes as the program itself.
+#[test]
+fn emitted_bf_behaves_as_the_program() {
+    let golden = fs::read_to_string(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden/emit_bf.b")).unwrap();
+    let rot13 = include_str!("../examples/rot13.b");
+    for (name, code, input) in [
+        ("bf_hello", HELLO_WORLD, &b""[..]),
+        ("bf_cat", CAT, b"Hi \x00\x80\xff!"),
+        ("bf_rot13", rot13, b"Hello, World!\n"),
+        ("bf_golden_letters", &golden, b"ab"),
+        ("bf_golden_numbers", &golden, b"\x03\x05"),
+        ("bf_chunks", ">+>+>+<<<,[>[-]>[-]>[-]<<<-]>>>.<.", b"\x01"),
+    ] {
+        let expected = stdout([program(&format!("{name}.b"), code)], input);
+        let bf = emit("bf", "bf", name, code, &[]);
+        assert_eq!(stdout([bf], input), expected, "{name}")
+    }
+}
+
+// The translation to LLVM IR is stable.
+#[test]
+fn emit_llvm_matches_golden_file() {
+    assert_matches_golden_file("llvm", "ll")
+}
+
+// Programs translated to LLVM IR output the same bytes as the interpreter, when run with `lli`.
+#[test]
+fn emitted_llvm_behaves_as_the_interpreter() {
+    if Command::new("lli").arg("--version").output().is_err() {
+        eprintln!("skipping: lli is not available");
+        return;
+    }
+    let input = b"Hi \x00\x80\xff!";
+    for (name, code) in [("llvm_hello", HELLO_WORLD), ("llvm_cat", CAT), ("llvm_move_left", "<<<+[>>>+<<<-]>>>.,[<.>,]")] {
+        let expected = stdout([program(&format!("{name}.b"), code)], input);
+        let ll = emit("llvm", "ll", name, code, &[]);
+        let output = run_command(Command::new("lli").arg(&ll), input);
+        assert!(output.status.success(), "{name}");
+        assert_eq!(output.stdout, expected, "{name}")
+    }
+}
+
+// The translation to x86-64 assembly is stable.
+#[test]
+fn emit_asm_matches_golden_file() {
+    assert_matches_golden_file("asm", "s")
+}
+
+// Programs translated to x86-64 assembly output the same bytes as the interpreter once assembled,
+// including when they move left of the first cell and the tape grows.
+#[test]
+fn emitted_asm_behaves_as_the_interpreter() {
+    if !cfg!(all(target_arch = "x86_64", unix)) || !common::has_c_compiler() {
+        eprintln!("skipping: cc is not available for x86-64");
+        return;
+    }
+    let input = b"Hi \x00\x80\xff!";
+    for (name, code) in [("asm_hello", HELLO_WORLD), ("asm_cat", CAT), ("asm_move_left", "<<<+[>>>+<<<-]>>>.,[<.>,]"), ("asm_grow", GROW)] {
+        let expected = stdout([program(&format!("{name}.b"), code)], input);
+        let assembly = emit("asm", "s", name, code, &[]);
+        let executable = assembly.with_extension("exe");
+        let status = Command::new("cc").arg("-o").arg(&executable).arg(&assembly).status().expect("Unable to run cc");
+        assert!(status.success(), "cc failed to assemble {}", assembly.display());
+        let output = run_executable(&executable, input);
+        assert!(output.status.success(), "{name}");
+        assert_eq!(output.stdout, expected, "{name}")
+    }
+}
+
+// Compiled programs output the same bytes as the interpreter, including values of 0x80
+// or more.
+#[test]
+fn emitted_c_behaves_as_the_interpreter() {
+    if !common::has_c_compiler() {
+        eprintln!("skipping: cc is not available");
+        return;
+    }
+    let input = b"Hi \x00\x80\xff!";
+    for (name, code, args) in [
+        ("c_hello", HELLO_WORLD, &[][..]),
+        ("c_cat", CAT, &[][..]),
+        ("c_cat_eof", ",+[-.,+]", &["--eof", "minus-one"][..]),
+        ("c_move_left", "<<<+[>>>+<<<-]>>>.,[<.>,]", &[][..]),
+        ("c_high", "-.---.>+++++++[<------>-]<.", &[][..]),
+    ] {
+        let path = program(&format!("{name}.b"), code);
+        let expected = run([path.as_os_str()].into_iter().chain(args.iter().map(|arg| arg.as_ref())), input).stdout;
+        assert_eq!(compile_and_run(&emit_c(name, code, args), input), expected, "{name}")
+    }
+}
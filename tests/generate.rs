@@ -0,0 +1,32 @@
+//! Tests of the programs printed by `--generate`.
+
+mod common;
+
+use common::{program, stdout};
+
+/// Generates a program printing the specified text, runs it, and returns what it printed.
+fn round_trip(name: &str, text: &str, golf: bool) -> Vec<u8> {
+    let mut args = vec!["--generate", text];
+    if golf {
+        args.push("--golf")
+    }
+    let code = String::from_utf8(stdout(args, b"")).expect("Generated program is not text");
+    stdout([program(name, &code)], b"")
+}
+
+// Escape sequences stand for single bytes, which the generated program outputs as they
+// are.
+#[test]
+fn generated_program_outputs_escaped_bytes() {
+    assert_eq!(round_trip("generate_high.b", r"A\xff", false), b"A\xff");
+    assert_eq!(round_trip("generate_high_golf.b", r"A\xff", true), b"A\xff");
+    assert_eq!(round_trip("generate_escapes.b", r"\x00\x80\n\\é", false), b"\x00\x80\n\\\xc3\xa9");
+}
+
+// `-` generates a program printing `stdin`, whatever its bytes.
+#[test]
+fn generated_program_outputs_stdin() {
+    let input: Vec<u8> = (0..=255).collect();
+    let code = String::from_utf8(stdout(["--generate", "-"], &input)).unwrap();
+    assert_eq!(stdout([program("generate_stdin.b", &code)], b""), input)
+}
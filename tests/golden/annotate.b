@@ -0,0 +1,5 @@
+Multiplies 3 by 4
++++[
+  >++++<-
+]
+>[-]<[never executed]
@@ -0,0 +1,151 @@
+    .data
+bf_tape:    .quad 0
+bf_size:    .quad 0
+bf_origin:  .quad 0
+bf_low:     .quad 0
+bf_high:    .quad -1
+bf_register: .byte 0
+bf_other_tape:   .quad 0
+bf_other_size:   .quad 0
+bf_other_origin: .quad 0
+bf_other_low:    .quad 0
+bf_other_high:   .quad -1
+bf_other_index:  .quad 0
+
+    .text
+# Makes the index in %rdi valid, growing the tape if needed. Updates %r12 if the tape moves.
+bf_ensure:
+    cmpq bf_low(%rip), %rdi
+    jl 1f
+    cmpq bf_high(%rip), %rdi
+    jg 1f
+    ret
+1:
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    # Add `size + |index| + 1` cells on each side, which is enough for the index to be valid.
+    movq %rdi, %rax
+    negq %rax
+    cmovlq %rdi, %rax
+    addq bf_size(%rip), %rax
+    incq %rax
+    movq %rax, %r13
+    leaq (%rax,%rax), %r14
+    addq bf_size(%rip), %r14
+    movq %r14, %rdi
+    movl $1, %esi
+    call calloc@PLT
+    movq %rax, %r15
+    leaq (%rax,%r13), %rdi
+    movq bf_tape(%rip), %rsi
+    movq bf_size(%rip), %rdx
+    call memcpy@PLT
+    movq bf_tape(%rip), %rdi
+    call free@PLT
+    movq %r15, bf_tape(%rip)
+    movq %r14, bf_size(%rip)
+    addq bf_origin(%rip), %r13
+    movq %r13, bf_origin(%rip)
+    leaq (%r15,%r13), %r12
+    movq %r13, %rax
+    negq %rax
+    movq %rax, bf_low(%rip)
+    subq %r13, %r14
+    decq %r14
+    movq %r14, bf_high(%rip)
+    popq %r15
+    popq %r14
+    popq %r13
+    ret
+
+# Reads a byte from the standard input into the current cell, and exits with status 1 at EOF.
+bf_input:
+    subq $8, %rsp
+    call getchar@PLT
+    cmpl $-1, %eax
+    je 1f
+    movb %al, (%r12,%rbx)
+    addq $8, %rsp
+    ret
+1:
+    movl $1, %edi
+    call exit@PLT
+
+# Switches the active tape, allocating the current cell of the new tape if needed.
+bf_switch:
+    subq $8, %rsp
+    movq bf_tape(%rip), %rax
+    xchgq %rax, bf_other_tape(%rip)
+    movq %rax, bf_tape(%rip)
+    movq bf_size(%rip), %rax
+    xchgq %rax, bf_other_size(%rip)
+    movq %rax, bf_size(%rip)
+    movq bf_origin(%rip), %rax
+    xchgq %rax, bf_other_origin(%rip)
+    movq %rax, bf_origin(%rip)
+    movq bf_low(%rip), %rax
+    xchgq %rax, bf_other_low(%rip)
+    movq %rax, bf_low(%rip)
+    movq bf_high(%rip), %rax
+    xchgq %rax, bf_other_high(%rip)
+    movq %rax, bf_high(%rip)
+    xchgq %rbx, bf_other_index(%rip)
+    movq bf_tape(%rip), %r12
+    addq bf_origin(%rip), %r12
+    movq %rbx, %rdi
+    call bf_ensure
+    addq $8, %rsp
+    ret
+
+    .globl main
+main:
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    xorl %edi, %edi
+    call time@PLT
+    movl %eax, %edi
+    call srand@PLT
+    xorl %ebx, %ebx
+    xorl %edi, %edi
+    call bf_ensure
+    # Add(2)
+    addb $2, (%r12,%rbx)
+    # Move([(1, 3)])
+    leaq 0(%rbx), %rdi
+    call bf_ensure
+    leaq 1(%rbx), %rdi
+    call bf_ensure
+    movzbl (%r12,%rbx), %ecx
+    imull $3, %ecx, %eax
+    addb %al, 1(%r12,%rbx)
+    movb $0, (%r12,%rbx)
+    # Right(1)
+    addq $1, %rbx
+    movq %rbx, %rdi
+    call bf_ensure
+    # Output
+    movzbl (%r12,%rbx), %edi
+    call putchar@PLT
+    # Input
+    call bf_input
+    # Loop
+.Lloop1_test:
+    cmpb $0, (%r12,%rbx)
+    je .Lloop1_end
+    # Output
+    movzbl (%r12,%rbx), %edi
+    call putchar@PLT
+    # Input
+    call bf_input
+    jmp .Lloop1_test
+.Lloop1_end:
+.Lhalt:
+    popq %r13
+    popq %r12
+    popq %rbx
+    xorl %eax, %eax
+    ret
+
+    .section .note.GNU-stack,"",@progbits
@@ -0,0 +1,4 @@
+Multiplies two input values
+,>,<[->[->+>+<<]>>[-<<+>>]<<<]
+prints the product then resets it with an odd step
+>>.[+++]<[>]+++--+<>[<++>-]<.
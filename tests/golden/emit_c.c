@@ -0,0 +1,73 @@
+#include <stddef.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+static unsigned char *tape;
+static ptrdiff_t size;
+static ptrdiff_t origin;
+static ptrdiff_t p;
+static unsigned char *other_tape;
+static ptrdiff_t other_size;
+static ptrdiff_t other_origin;
+static ptrdiff_t other_p;
+
+static inline unsigned char *cell(ptrdiff_t index) {
+    if (index + origin < 0 || index + origin >= size) {
+        /* Add `size + |index| + 1` cells on each side, which is enough for `index` to be valid. */
+        ptrdiff_t shift = size + (index < 0 ? -index : index) + 1;
+        unsigned char *new_tape = calloc(size + 2 * shift, 1);
+        if (new_tape == NULL) {
+            fputs("error: unable to grow the tape\n", stderr);
+            exit(1);
+        }
+        if (size > 0) {
+            memcpy(new_tape + shift, tape, size);
+        }
+        free(tape);
+        tape = new_tape;
+        size += 2 * shift;
+        origin += shift;
+    }
+    return &tape[origin + index];
+}
+
+static inline void switch_tapes(void) {
+    unsigned char *t = tape;
+    ptrdiff_t s = size, o = origin, q = p;
+    tape = other_tape;
+    size = other_size;
+    origin = other_origin;
+    p = other_p;
+    other_tape = t;
+    other_size = s;
+    other_origin = o;
+    other_p = q;
+}
+
+static inline void input(void) {
+    int value = getchar();
+    if (value == EOF) {
+        *cell(p) = 0;
+    } else {
+        *cell(p) = (unsigned char) value;
+    }
+}
+
+int main(void) {
+    *cell(p) += 2;
+    {
+        unsigned char value = *cell(p);
+        *cell(p + 1) += value * 3;
+        *cell(p) = 0;
+    }
+    p += 1;
+    putchar(*cell(p));
+    input();
+    while (*cell(p)) {
+        putchar(*cell(p));
+        input();
+    }
+    return 0;
+}
@@ -0,0 +1,153 @@
+declare i32 @getchar()
+declare i32 @putchar(i32)
+declare i8* @calloc(i64, i64)
+declare void @free(i8*)
+declare void @exit(i32)
+declare i32 @rand()
+declare void @srand(i32)
+declare i64 @time(i64*)
+declare void @llvm.memcpy.p0i8.p0i8.i64(i8*, i8*, i64, i1)
+declare void @llvm.memset.p0i8.i64(i8*, i8, i64, i1)
+
+@tape = internal global i8* null
+@size = internal global i64 0
+@origin = internal global i64 0
+@register = internal global i8 0
+@other_tape = internal global i8* null
+@other_size = internal global i64 0
+@other_origin = internal global i64 0
+
+define internal i8* @cell(i64 %index) {
+entry:
+  %origin = load i64, i64* @origin
+  %size = load i64, i64* @size
+  %i = add i64 %index, %origin
+  %in_bounds = icmp ult i64 %i, %size
+  br i1 %in_bounds, label %found, label %grow
+
+found:
+  %tape = load i8*, i8** @tape
+  %cell = getelementptr i8, i8* %tape, i64 %i
+  ret i8* %cell
+
+grow:
+  ; Add `size + |index| + 1` cells on each side, which is enough for `index` to be valid.
+  %negative = icmp slt i64 %index, 0
+  %negated = sub i64 0, %index
+  %abs = select i1 %negative, i64 %negated, i64 %index
+  %shift.0 = add i64 %size, %abs
+  %shift = add i64 %shift.0, 1
+  %added = shl i64 %shift, 1
+  %new_size = add i64 %size, %added
+  %new_tape = call i8* @calloc(i64 %new_size, i64 1)
+  %old_tape = load i8*, i8** @tape
+  %destination = getelementptr i8, i8* %new_tape, i64 %shift
+  call void @llvm.memcpy.p0i8.p0i8.i64(i8* %destination, i8* %old_tape, i64 %size, i1 false)
+  call void @free(i8* %old_tape)
+  %new_origin = add i64 %origin, %shift
+  store i8* %new_tape, i8** @tape
+  store i64 %new_size, i64* @size
+  store i64 %new_origin, i64* @origin
+  %result = call i8* @cell(i64 %index)
+  ret i8* %result
+}
+
+define internal void @input(i8* %cell) {
+entry:
+  %char = call i32 @getchar()
+  %eof = icmp eq i32 %char, -1
+  br i1 %eof, label %error, label %store
+
+error:
+  call void @exit(i32 1)
+  unreachable
+
+store:
+  %value = trunc i32 %char to i8
+  store i8 %value, i8* %cell
+  ret void
+}
+
+define internal void @switch() {
+entry:
+  %tape = load i8*, i8** @tape
+  %other_tape = load i8*, i8** @other_tape
+  store i8* %other_tape, i8** @tape
+  store i8* %tape, i8** @other_tape
+  %size = load i64, i64* @size
+  %other_size = load i64, i64* @other_size
+  store i64 %other_size, i64* @size
+  store i64 %size, i64* @other_size
+  %origin = load i64, i64* @origin
+  %other_origin = load i64, i64* @other_origin
+  store i64 %other_origin, i64* @origin
+  store i64 %origin, i64* @other_origin
+  ret void
+}
+
+define i32 @main() {
+entry:
+  %ptr = alloca i64
+  store i64 0, i64* %ptr
+  %other_ptr = alloca i64
+  store i64 0, i64* %other_ptr
+  %now = call i64 @time(i64* null)
+  %seed = trunc i64 %now to i32
+  call void @srand(i32 %seed)
+  ; Add(2)
+  %t1 = load i64, i64* %ptr
+  %t2 = call i8* @cell(i64 %t1)
+  %t3 = load i8, i8* %t2
+  %t4 = add i8 %t3, 2
+  store i8 %t4, i8* %t2
+  ; Move([(1, 3)])
+  %t5 = load i64, i64* %ptr
+  %t6 = call i8* @cell(i64 %t5)
+  %t7 = load i8, i8* %t6
+  %t8 = mul i8 %t7, 3
+  %t9 = load i64, i64* %ptr
+  %t10 = add i64 %t9, 1
+  %t11 = call i8* @cell(i64 %t10)
+  %t12 = load i8, i8* %t11
+  %t13 = add i8 %t12, %t8
+  store i8 %t13, i8* %t11
+  %t14 = load i64, i64* %ptr
+  %t15 = call i8* @cell(i64 %t14)
+  store i8 0, i8* %t15
+  ; Right(1)
+  %t16 = load i64, i64* %ptr
+  %t17 = add i64 %t16, 1
+  store i64 %t17, i64* %ptr
+  ; Output
+  %t18 = load i64, i64* %ptr
+  %t19 = call i8* @cell(i64 %t18)
+  %t20 = load i8, i8* %t19
+  %t21 = zext i8 %t20 to i32
+  call i32 @putchar(i32 %t21)
+  ; Input
+  %t22 = load i64, i64* %ptr
+  %t23 = call i8* @cell(i64 %t22)
+  call void @input(i8* %t23)
+  ; Loop
+  br label %loop1.test
+loop1.test:
+  %t24 = load i64, i64* %ptr
+  %t25 = call i8* @cell(i64 %t24)
+  %t26 = load i8, i8* %t25
+  %t27 = icmp ne i8 %t26, 0
+  br i1 %t27, label %loop1.body, label %loop1.end
+loop1.body:
+  ; Output
+  %t28 = load i64, i64* %ptr
+  %t29 = call i8* @cell(i64 %t28)
+  %t30 = load i8, i8* %t29
+  %t31 = zext i8 %t30 to i32
+  call i32 @putchar(i32 %t31)
+  ; Input
+  %t32 = load i64, i64* %ptr
+  %t33 = call i8* @cell(i64 %t32)
+  call void @input(i8* %t33)
+  br label %loop1.test
+loop1.end:
+  ret i32 0
+}
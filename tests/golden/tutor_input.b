@@ -0,0 +1,4 @@
+read a letter
+,+.
+and wrap
+>-.
\ No newline at end of file
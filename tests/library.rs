@@ -0,0 +1,533 @@
+//! Tests of the library, which runs programs in memory.
+
+mod common;
+
+use std::io;
+use std::io::{Read, Write};
+use std::num::NonZeroU64;
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+use brainfuck_interpreter::source::ParseErrorKind;
+use brainfuck_interpreter::tape::{Eof, Tape, TapeOptions, RESET_CAPACITY};
+use brainfuck_interpreter::{bytecode, execute, load, names, optimize, optimize_within_budget, parse, CellSize, Command, Hooks, Interpreter, Machine, MachineOptions, OptimizeOptions, ParseOptions, Resume, StepHook, StepInfo, Stop};
+use num_bigint::BigInt;
+
+use common::{CAT, HELLO_WORLD};
+
+/// A reader or writer that always fails with the specified error.
+struct Failing(io::ErrorKind);
+
+impl Read for Failing {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Err(self.0.into())
+    }
+}
+
+impl Write for Failing {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(self.0.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Programs run on in-memory input and output.
+#[test]
+fn hello_world_runs_in_memory() {
+    let mut output = Vec::new();
+    let result = Interpreter::from_source(HELLO_WORLD).unwrap().run(io::empty(), &mut output);
+    assert_eq!(result, ControlFlow::Continue(()));
+    assert_eq!(output, b"Hello World!\n")
+}
+
+// The input and the output may be borrowed rather than `'static`.
+#[test]
+fn cat_runs_on_borrowed_input() {
+    let input = Vec::from(&b"Hello, \xff world"[..]);
+    let mut output = Vec::new();
+    let interpreter = Interpreter::from_source(CAT).unwrap();
+    assert_eq!(interpreter.run(input.as_slice(), &mut output), ControlFlow::Continue(()));
+    assert_eq!(output, input);
+    // An interpreter can run any number of times.
+    output.clear();
+    assert_eq!(interpreter.run(&b"again"[..], &mut output), ControlFlow::Continue(()));
+    assert_eq!(output, b"again")
+}
+
+// Reading and writing errors stop the program instead of being ignored.
+#[test]
+fn io_errors_stop_the_program() {
+    let interpreter = Interpreter::from_source(CAT).unwrap();
+    assert_eq!(interpreter.run(Failing(io::ErrorKind::PermissionDenied), io::sink()), ControlFlow::Break(Stop::InputError(io::ErrorKind::PermissionDenied)));
+    assert_eq!(interpreter.run(&b"a"[..], Failing(io::ErrorKind::BrokenPipe)), ControlFlow::Break(Stop::OutputError(io::ErrorKind::BrokenPipe)))
+}
+
+// Tapes are created from options.
+#[test]
+fn tape_options_set_the_end_of_input_behavior() {
+    for (eof, expected) in [(Eof::Zero, ControlFlow::Continue(0)), (Eof::MinusOne, ControlFlow::Continue(255)), (Eof::Unchanged, ControlFlow::Continue(7)), (Eof::Error, ControlFlow::Break(Stop::EndOfInput))] {
+        let mut tape = Tape::new(&TapeOptions { eof, ..TapeOptions::default() });
+        tape.set_input(Box::new(std::iter::empty()));
+        tape.write(7);
+        assert_eq!(tape.input().map_continue(|()| tape.read()), expected, "{:?}", eof)
+    }
+}
+
+// The library never prompts on the terminal itself, but calls `on_pause`.
+#[test]
+fn pausing_calls_the_pause_function() {
+    fn abort_after_two_pauses(machine: &Machine<'_>, _: bool) -> Resume {
+        if machine.steps >= 4 { Resume::Abort } else { Resume::Continue }
+    }
+    let commands = parse("+>+>+>+>+>+>").unwrap();
+    let options = MachineOptions { step_interval: NonZeroU64::new(2), on_pause: Some(abort_after_two_pauses), ..MachineOptions::default() };
+    let mut machine = Machine::new(Tape::new(&TapeOptions::default()), &options);
+    assert_eq!(bytecode::execute(&bytecode::flatten(&commands), &mut machine), ControlFlow::Break(Stop::Abort));
+    assert_eq!(machine.steps, 4);
+    // Without it, pausing does nothing.
+    let options = MachineOptions { on_pause: None, ..options };
+    let mut machine = Machine::new(Tape::new(&TapeOptions::default()), &options);
+    assert_eq!(bytecode::execute(&bytecode::flatten(&commands), &mut machine), ControlFlow::Continue(()))
+}
+
+// Deeply nested loops are parsed, optimized, run both ways, and dropped without
+// overflowing the stack of the test thread, which is only 2 MiB.
+#[test]
+fn deeply_nested_loops_do_not_overflow_the_stack() {
+    const DEPTH: usize = 200_000;
+    let code = format!("{}{}+.", "+[>".repeat(DEPTH), "<-]".repeat(DEPTH));
+    let mut output = Vec::new();
+    let interpreter = Interpreter::new(optimize(parse(&code).unwrap(), &OptimizeOptions::default()));
+    assert_eq!(interpreter.run(io::empty(), &mut output), ControlFlow::Continue(()));
+    let commands = parse(&code).unwrap();
+    assert_eq!(output, [1]);
+    let mut flattened = Machine::new(Tape::new(&TapeOptions { silent: true, ..TapeOptions::default() }), &MachineOptions::default());
+    assert_eq!(bytecode::execute(&bytecode::flatten(&commands), &mut flattened), ControlFlow::Continue(()));
+    let mut nested = Machine::new(Tape::new(&TapeOptions { silent: true, ..TapeOptions::default() }), &MachineOptions::default());
+    assert_eq!(execute(&commands, &mut nested), ControlFlow::Continue(()));
+    assert_eq!(nested.steps, flattened.steps);
+}
+
+// Walking the commands and running them flattened count a step for each command, and for each
+// time a loop is entered, but not for each time it is repeated.
+#[test]
+fn loops_count_a_step_when_entered() {
+    /// Returns the steps a program counts when its commands are walked and when they are flattened.
+    fn steps(code: &str) -> (u64, u64) {
+        let commands = parse(code).unwrap();
+        let mut nested = Machine::new(Tape::new(&TapeOptions::default()), &MachineOptions::default());
+        assert_eq!(execute(&commands, &mut nested), ControlFlow::Continue(()));
+        let mut flattened = Machine::new(Tape::new(&TapeOptions::default()), &MachineOptions::default());
+        assert_eq!(bytecode::execute(&bytecode::flatten(&commands), &mut flattened), ControlFlow::Continue(()));
+        (nested.steps, flattened.steps)
+    }
+    // `+++` is a single command, and so is each of `>`, `+`, `<`, and `-` in the loop.
+    assert_eq!(steps("+++[>+<-]"), (1 + 1 + 3 * 4, 1 + 1 + 3 * 4));
+    // A loop that is skipped is entered too.
+    assert_eq!(steps("[-]"), (1, 1))
+}
+
+// Wide cells are loaded, optimized, and run like bytes, by the same interpreter.
+#[test]
+fn wide_cells_run_through_the_optimizer_and_the_interpreter() {
+    /// Runs a program on cells of type `C`, and returns the final value of the current cell, with
+    /// the values it output as hexadecimal.
+    fn run<C: brainfuck_interpreter::Cell>(code: &str, cell_size: CellSize) -> (C, String) {
+        let code = code.as_bytes();
+        let commands = load(code, &ParseOptions { cell_size, ..ParseOptions::default() }, &names::collect(code).0).unwrap();
+        let commands = optimize(commands, &OptimizeOptions { cell_size, ..OptimizeOptions::default() });
+        let mut output = Vec::new();
+        let mut tape = Tape::<C>::with_cells(&TapeOptions { hex_output: true, ..TapeOptions::default() });
+        tape.set_output(Box::new(&mut output));
+        let mut machine = Machine::new(tape, &MachineOptions::default());
+        assert_eq!(bytecode::execute(&bytecode::flatten(&commands), &mut machine), ControlFlow::Continue(()));
+        let value = machine.tape.read();
+        drop(machine);
+        (value, String::from_utf8(output).unwrap())
+    }
+
+    // 300 is moved to the next cell, and then decremented past 0.
+    let code = format!("{}[->+<]>.[-]-.", "+".repeat(300));
+    assert_eq!(run::<u8>(&code, CellSize::Eight), (255, "0x2c\n0xff\n".to_string()));
+    assert_eq!(run::<u16>(&code, CellSize::Sixteen), (u16::MAX, "0x012c\n0xffff\n".to_string()));
+    assert_eq!(run::<u32>(&code, CellSize::ThirtyTwo), (u32::MAX, "0x0000012c\n0xffffffff\n".to_string()));
+    // Loops are not optimized for cells that cannot wrap around, as `[-]` never ends on -1.
+    let (value, output) = run::<BigInt>(&code, CellSize::Big);
+    assert_eq!((value, output.as_str()), (BigInt::from(-1), "0x12c\n-0x1\n"));
+}
+
+// Invalid assertions are reported at the position of their annotation rather than panicking.
+#[test]
+fn invalid_assertions_are_parse_errors() {
+    let code = b"+\n {assert cell(0) =< 1}";
+    let error = load(code, &ParseOptions { assertions: true, ..ParseOptions::default() }, &names::collect(code).0).unwrap_err();
+    assert_eq!((&error.kind, error.line, error.column, error.offset), (&ParseErrorKind::InvalidAssertion("invalid operand `cell(0) =`".to_string()), 2, 2, 3));
+    assert_eq!(error.to_string(), "invalid assertion at line 2, column 2 (byte 3): invalid operand `cell(0) =`");
+    // Without `--assertions`, they are skipped like the other annotations.
+    assert!(load(code, &ParseOptions::default(), &names::collect(code).0).is_ok())
+}
+
+// A `[` that is never closed is reported at the innermost unclosed bracket, even when it is deeply
+// nested in other unclosed loops.
+#[test]
+fn deeply_nested_unmatched_brackets_are_located() {
+    const DEPTH: usize = 100_000;
+    let code = format!("+\n{}\n  [-[+]", "[>".repeat(DEPTH));
+    let error = parse(&code).unwrap_err();
+    let offset = 2 + 2 * DEPTH + 1 + 2;
+    assert_eq!(code.as_bytes()[offset], b'[');
+    assert_eq!((&error.kind, error.line, error.column, error.offset), (&ParseErrorKind::UnmatchedOpen, 3, 3, offset));
+    assert_eq!(error.to_string(), format!("unmatched '[' at line 3, column 3 (byte {})", offset))
+}
+
+// Names are collected along with warnings for the names given again, which the library leaves to
+// the caller rather than printing them.
+#[test]
+fn names_are_collected_with_their_warnings() {
+    let (names, warnings) = names::collect(b"{name 3 counter} >> {name -1 temp} {name 3 total}");
+    assert_eq!((names.name(1), names.index("total"), names.index("counter")), (Some("temp"), Some(3), None));
+    assert_eq!(warnings, ["cell 3 is renamed from `counter` to `total` at 1:36"]);
+    assert_eq!((names.resolve("temp"), names.resolve("-4")), (Ok(1), Ok(-4)));
+    assert_eq!(names.describe(3), "cell 3 (`total`)");
+    assert!(names.resolve("unknown").is_err())
+}
+
+// The library reports failed assertions and unwritable tape dumps to the caller, rather than
+// printing them or panicking.
+#[test]
+fn failures_are_returned_to_the_caller() {
+    let code = b"+{assert cell(0)==2}";
+    let commands = load(code, &ParseOptions { assertions: true, ..ParseOptions::default() }, &names::collect(code).0).unwrap();
+    let mut machine = Machine::new(Tape::new(&TapeOptions::default()), &MachineOptions::default());
+    assert_eq!(execute(&commands, &mut machine), ControlFlow::Break(Stop::AssertionFailed));
+    assert_eq!(machine.failed_assertion.as_deref(), Some("assertion `cell(0)==2` failed at 1:2 (cell(0) is 1)"));
+    let commands = load(b"?", &ParseOptions { debug: true, ..ParseOptions::default() }, &names::CellNames::default()).unwrap();
+    let directory = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("missing_directory");
+    let options = MachineOptions { tape_dump_json: Some(directory.join("dump.json")), ..MachineOptions::default() };
+    let mut machine = Machine::new(Tape::new(&TapeOptions::default()), &options);
+    assert_eq!(execute(&commands, &mut machine), ControlFlow::Break(Stop::TapeDumpError(io::ErrorKind::NotFound)))
+}
+
+// Multiplication loops are optimized as single commands, which compute the same products as the
+// loops, including for the operands 0, 1, and 255.
+#[test]
+fn multiplications_match_their_loops() {
+    /// Runs commands on a tape starting with the count, the operand, the result, and the temporary
+    /// cell of a multiplication, and returns these cells once it ends.
+    fn run(commands: &[Command], cells: [u8; 4]) -> [u8; 4] {
+        let mut tape = Tape::new(&TapeOptions::default());
+        tape.write_slice(0, &cells);
+        let mut machine = Machine::new(tape, &MachineOptions::default());
+        assert_eq!(execute(commands, &mut machine), ControlFlow::Continue(()));
+        [0, 1, 2, 3].map(|offset| machine.tape.read_relative(offset))
+    }
+
+    let code = "[->[->+>+<<]>>[-<<+>>]<<<]";
+    let options = OptimizeOptions { is_tape_initialized: true, is_final_tape_observed: true, ..OptimizeOptions::default() };
+    let optimized = optimize(parse(code).unwrap(), &options);
+    assert!(matches!(optimized.as_slice(), [Command::Mul(_)]), "{:?}", optimized);
+    let looped = parse(code).unwrap();
+    // Every pair of operands is checked against the product, and a sample against the loops.
+    let sample: Vec<u8> = [0, 1, 2, 127, 128, 254, 255].into_iter().chain((0..=255).step_by(51)).collect();
+    for count in 0..=255 {
+        for operand in 0..=255 {
+            let result = run(&optimized, [count, operand, 7, 0]);
+            assert_eq!(result, [0, operand, 7u8.wrapping_add(count.wrapping_mul(operand)), 0], "{} * {}", count, operand);
+            if sample.contains(&count) && sample.contains(&operand) {
+                assert_eq!(result, run(&looped, [count, operand, 7, 0]), "{} * {}", count, operand);
+                // The temporary cell is added back to the operand by each iteration, even if it starts
+                // with a value.
+                assert_eq!(run(&optimized, [count, operand, 7, 3]), run(&looped, [count, operand, 7, 3]), "{} * {}", count, operand)
+            }
+        }
+    }
+}
+
+// Propagating constants removes commands from programs without changing their output or their
+// final tape.
+#[test]
+fn constant_propagation_keeps_the_behavior_of_programs() {
+    /// Runs a program on some input, and returns its output along with the position of the pointer
+    /// and the cells around it once it ends.
+    fn run(commands: &[Command], input: &[u8]) -> (Vec<u8>, isize, Vec<u8>) {
+        let mut output = Vec::new();
+        let mut tape = Tape::new(&TapeOptions::default());
+        tape.set_reader(input);
+        tape.set_raw_output(Box::new(&mut output));
+        let mut machine = Machine::new(tape, &MachineOptions::default());
+        assert_eq!(execute(commands, &mut machine), ControlFlow::Continue(()));
+        let (pointer, cells) = (machine.tape.pointer(), (-16..16).map(|offset| machine.tape.read_relative(offset)).collect());
+        drop(machine);
+        (output, pointer, cells)
+    }
+
+    let corpus: [(&str, &[u8]); 9] = [
+        (HELLO_WORLD, b""),
+        (CAT, b"Hello, world"),
+        (include_str!("../examples/rot13.b"), b"Hello World\n"),
+        // Loops on cells known to be 0, before and after other loops.
+        ("[-]>[->+<]++[>+++<-]>[-<+>]<.>>[.]", b""),
+        // A loop writing to a cell that was known, which is then output.
+        (">>+++<<,[->>+<<]>>.[-]<<[-].", b"\x05"),
+        // A nested loop writing to a cell that the outer loop does not touch itself.
+        (",[>,[->>+<<]<-]>>>.[.-]", b"\x02\x03\x04"),
+        // Loops moving the pointer by an amount that depends on the cells.
+        ("+>+>+>>+<<<<[>]>[-]+.<<[<]>.", b""),
+        (",[>,]<[<]>[.>]>>[-]", b"abc"),
+        // Multiplications and scans on cells that are known, and then no longer.
+        ("++[->+++<]>[->++<]>.<<[>]<,[->++<]>>.[<]", b"\x07"),
+    ];
+    // The final tape is observed, so that the commands at the end of the programs are kept.
+    let options = OptimizeOptions { is_final_tape_observed: true, ..OptimizeOptions::default() };
+    let mut removed_commands = 0;
+    for (code, input) in corpus {
+        let (optimized, report) = optimize_within_budget(parse(code).unwrap(), &options);
+        let (unpropagated, _) = optimize_within_budget(parse(code).unwrap(), &OptimizeOptions { propagate_constants: false, ..options });
+        let commands = parse(code).unwrap();
+        removed_commands += report.removed_constant_commands;
+        let expected = run(&commands, input);
+        assert_eq!(run(&optimized, input), expected, "{}", code);
+        assert_eq!(run(&unpropagated, input), expected, "{}", code);
+    }
+    assert!(removed_commands > 0)
+}
+
+// Compacting or resetting a tape after the pointer wandered far away frees the memory of the cells
+// it went through.
+#[test]
+fn compacting_and_resetting_free_memory() {
+    const EXCURSION: isize = 1_000_000;
+    let mut tape = Tape::new(&TapeOptions::default());
+    tape.add(0, 1);
+    tape.right_by(EXCURSION);
+    tape.add(0, 1);
+    assert!(tape.capacity() > EXCURSION as usize);
+    tape.add(0, 1u64.wrapping_neg());
+    tape.right_by(-EXCURSION);
+    tape.compact();
+    assert_eq!(tape.allocated_cells(), 1);
+    assert!(tape.capacity() < RESET_CAPACITY, "{}", tape.capacity());
+    assert_eq!(tape.read(), 1);
+    tape.right_by(EXCURSION);
+    tape.add(0, 1);
+    tape.reset();
+    assert!(tape.capacity() <= RESET_CAPACITY, "{}", tape.capacity());
+    assert_eq!((tape.pointer(), tape.read()), (0, 0))
+}
+
+// Programs loaded with the positions of their commands count the same steps as without them,
+// whatever the optimizations, and the machine knows the position of the command about to execute.
+#[test]
+fn positions_do_not_change_the_steps() {
+    /// How a program ended, the number of steps it executed, and its output.
+    type Outcome = (ControlFlow<Stop>, u64, Vec<u8>);
+
+    /// Runs a program, and returns its outcome along with the position of the last command it
+    /// executed.
+    fn run(code: &str, trace_positions: bool, options: &OptimizeOptions, input: &[u8]) -> (Outcome, Option<(usize, usize)>) {
+        let commands = load(code.as_bytes(), &ParseOptions { trace_positions, ..ParseOptions::default() }, &names::collect(code.as_bytes()).0).unwrap();
+        let commands = optimize(commands, options);
+        let mut output = Vec::new();
+        let mut tape = Tape::new(&TapeOptions::default());
+        tape.set_reader(input);
+        tape.set_raw_output(Box::new(&mut output));
+        let mut machine = Machine::new(tape, &MachineOptions { max_steps: Some(1000), ..MachineOptions::default() });
+        let result = bytecode::execute(&bytecode::flatten(&commands), &mut machine);
+        let (steps, position) = (machine.steps, machine.position);
+        drop(machine);
+        ((result, steps, output), position)
+    }
+
+    let corpus: [(&str, &[u8]); 6] = [
+        (HELLO_WORLD, b""),
+        (include_str!("../examples/rot13.b"), b"Hello World\n"),
+        (",[->[->+>+<<]>>[-<<+>>]<<<]>>>.", b"\x05\x07"),
+        ("+>+>+<<[-]>[-]>[-]>+<[[-]]<<[>]+.", b""),
+        ("+ +>\n>[-]<<[-\n>+<]>.", b""),
+        // An empty loop never ends, and uses up all the steps left.
+        ("+[+-]", b""),
+    ];
+    let default = OptimizeOptions::default();
+    let options = [
+        default.clone(),
+        OptimizeOptions { optimize_chunk_resets: true, ..default.clone() },
+        OptimizeOptions { optimize_loops: false, ..default.clone() },
+        OptimizeOptions { fuse_commands: false, coalesce_moves: false, ..default.clone() },
+        OptimizeOptions { is_final_tape_observed: true, are_writes_observed: true, ..default.clone() },
+    ];
+    for (code, input) in corpus {
+        for options in &options {
+            let (outcome, position) = run(code, true, options, input);
+            let (expected_outcome, no_position) = run(code, false, options, input);
+            assert_eq!(outcome, expected_outcome, "{} with {:?}", code, options);
+            assert!(position.is_some() && no_position.is_none(), "{}", code)
+        }
+    }
+    assert_eq!(run(corpus[4].0, true, &default, b"").1, Some((3, 6)));
+    // Runs of instructions merged across comments and lines are at their first instruction.
+    let options = OptimizeOptions { fuse_commands: false, is_final_tape_observed: true, ..default };
+    assert_eq!(run("+ +>\n>", true, &options, b"").1, Some((1, 4)))
+}
+
+// Hooks observe the output as it is also written, replace the input, and are called every few
+// steps with the position of the program, which they can stop.
+#[test]
+fn hooks_observe_and_cancel_programs() {
+    let mut output = Vec::new();
+    let mut observed = Vec::new();
+    let mut input = b"hook".iter().copied();
+    let hooks = Hooks { on_output: Some(&mut |value| observed.push(value)), on_input: Some(&mut || input.next()), ..Hooks::default() };
+    let interpreter = Interpreter::from_source(CAT).unwrap();
+    assert_eq!(interpreter.run_with(&b"reader"[..], &mut output, hooks), ControlFlow::Continue(()));
+    assert_eq!((output.as_slice(), observed.as_slice()), (&b"hook"[..], &b"hook"[..]));
+
+    // The hook is called with the position of the command about to execute, and cancels the
+    // program after a threshold.
+    let code = b"+\n[>+<\n]";
+    let commands = load(code, &ParseOptions { trace_positions: true, ..ParseOptions::default() }, &names::collect(code).0).unwrap();
+    let interpreter = Interpreter::new(optimize(commands, &OptimizeOptions { is_written_extent_observed: true, ..OptimizeOptions::default() }));
+    let mut calls = Vec::new();
+    let mut on_step = |info: StepInfo| {
+        calls.push(info);
+        if info.steps >= 1000 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+    };
+    let hooks = Hooks { on_step: Some(StepHook { interval: NonZeroU64::new(10).unwrap(), function: &mut on_step }), ..Hooks::default() };
+    assert_eq!(interpreter.run_with(io::empty(), io::sink(), hooks), ControlFlow::Break(Stop::Cancelled));
+    assert_eq!(calls.len(), 100);
+    assert!(calls.iter().enumerate().all(|(index, info)| info.steps == 10 * (index as u64 + 1)), "{:?}", calls);
+    assert!(calls.iter().all(|info| matches!(info.position, Some((2, 1..=4)))), "{:?}", calls)
+}
+
+// One interpreter runs the same program on different inputs on several threads at the same time.
+#[test]
+fn interpreters_are_shared_across_threads() {
+    let interpreter = std::sync::Arc::new(Interpreter::from_source(include_str!("../examples/rot13.b")).unwrap());
+    let threads: Vec<_> = (0..8).map(|index| {
+        let interpreter = interpreter.clone();
+        std::thread::spawn(move || {
+            let input = format!("Thread {}: Hello World\n", index);
+            let mut output = Vec::new();
+            assert_eq!(interpreter.run(input.as_bytes(), &mut output), ControlFlow::Continue(()));
+            output
+        })
+    }).collect();
+    for (index, thread) in threads.into_iter().enumerate() {
+        assert_eq!(String::from_utf8(thread.join().unwrap()).unwrap(), format!("Guernq {}: Uryyb Jbeyq\n", index))
+    }
+}
+
+// Setting the cancellation flag from another thread stops a program that never ends, including
+// in an empty loop, promptly and with the number of steps and the position it stopped at.
+#[test]
+fn cancelling_stops_programs_that_never_end() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    for code in ["+\n[>+<]", "+\n[]"] {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let options = MachineOptions { cancel: Some(cancel.clone()), ..MachineOptions::default() };
+        let running = std::thread::spawn(move || {
+            let commands = load(code.as_bytes(), &ParseOptions { trace_positions: true, ..ParseOptions::default() }, &names::CellNames::default()).unwrap();
+            let mut machine = Machine::new(Tape::new(&TapeOptions { silent: true, ..TapeOptions::default() }), &options);
+            let result = bytecode::execute(&bytecode::flatten(&commands), &mut machine);
+            (result, machine.steps, machine.position)
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        let cancelled_at = Instant::now();
+        cancel.store(true, Ordering::Relaxed);
+        let (result, steps, position) = running.join().unwrap();
+        assert!(cancelled_at.elapsed() < Duration::from_secs(1), "{:?}", cancelled_at.elapsed());
+        assert_eq!(result, ControlFlow::Break(Stop::Cancelled), "{}", code);
+        assert!(steps >= 2 && matches!(position, Some((2, _))), "{} stopped after {} steps at {:?}", code, steps, position)
+    }
+
+    // The interpreter takes the flag through its options, and checks it as soon as the loop is
+    // entered when it is already set.
+    let cancel = Arc::new(AtomicBool::new(true));
+    let mut interpreter = Interpreter::from_source("+[.]").unwrap();
+    interpreter.options = MachineOptions { cancel: Some(cancel), cancel_interval: NonZeroU64::new(10), ..MachineOptions::default() };
+    let mut output = Vec::new();
+    assert_eq!(interpreter.run(io::empty(), &mut output), ControlFlow::Break(Stop::Cancelled));
+    assert!(output.is_empty(), "{:?}", output)
+}
+
+// Once the time budget is exceeded, optimization stops and reports it, and the program optimized so
+// far still behaves as the original one.
+#[test]
+fn optimization_stops_after_the_time_budget() {
+    let code = format!("{}{}", "[-]>[->+<]<".repeat(10_000), HELLO_WORLD);
+    let loops = |commands: &[Command]| commands.iter().filter(|command| matches!(command, Command::Loop(_))).count();
+    let run = |commands| {
+        let mut output = Vec::new();
+        assert_eq!(Interpreter::new(commands).run(io::empty(), &mut output), ControlFlow::Continue(()));
+        output
+    };
+    let (truncated, report) = optimize_within_budget(parse(&code).unwrap(), &OptimizeOptions { time_budget: Some(Duration::ZERO), ..OptimizeOptions::default() });
+    assert!(report.is_truncated);
+    // No loop was optimized.
+    assert!(loops(&truncated) >= 20_000, "{}", loops(&truncated));
+    assert_eq!(run(truncated), b"Hello World!\n");
+    let (optimized, report) = optimize_within_budget(parse(&code).unwrap(), &OptimizeOptions { time_budget: Some(Duration::from_secs(60)), ..OptimizeOptions::default() });
+    assert!(!report.is_truncated);
+    assert!(loops(&optimized) < 10, "{}", loops(&optimized));
+    assert_eq!(run(optimized), b"Hello World!\n")
+}
+
+// The extremes of the pointer and of the written cells include the cells scans go through and the
+// cells chunk resets write to, whether the program is optimized or not.
+#[test]
+fn extremes_include_scans_and_chunk_resets() {
+    for (code, pointer_extent, written_extent) in [
+        ("+>+>+>+<<<[>]<[-]<[-]<[-]", (0, 4), Some((0, 3))),
+        ("<<<+[<]", (-4, 0), Some((-3, -3))),
+        (">>>>[-]<[-]<[-]<<", (0, 4), None),
+        (">>>+<[-]>[-]>[-]", (0, 4), Some((3, 3))),
+        ("+[>>]<<<<<[<]", (-3, 2), Some((0, 0))),
+        (">>>[<]", (0, 3), None),
+    ] {
+        // The extremes are observed with `--summary`, which also observes the final tape.
+        let options = OptimizeOptions { optimize_chunk_resets: true, is_written_extent_observed: true, is_final_tape_observed: true, ..OptimizeOptions::default() };
+        let optimized = optimize(parse(code).unwrap(), &options);
+        let mut machine = Machine::new(Tape::new(&TapeOptions::default()), &MachineOptions::default());
+        assert_eq!(bytecode::execute(&bytecode::flatten(&optimized), &mut machine), ControlFlow::Continue(()));
+        assert_eq!((machine.tape.pointer_extent(), machine.tape.written_extent()), (pointer_extent, written_extent), "{} optimized", code);
+        let mut machine = Machine::new(Tape::new(&TapeOptions::default()), &MachineOptions::default());
+        assert_eq!(execute(&parse(code).unwrap(), &mut machine), ControlFlow::Continue(()));
+        assert_eq!((machine.tape.pointer_extent(), machine.tape.written_extent()), (pointer_extent, written_extent), "{}", code)
+    }
+}
+
+/// A writer appending to a buffer shared with other writers.
+#[derive(Clone, Default)]
+struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// The prompt is written once before each line the program reads, including empty ones, after the
+// output so far even when it is buffered.
+#[test]
+fn prompts_are_written_before_each_line() {
+    let transcript = Shared::default();
+    let mut tape = Tape::new(&TapeOptions::default());
+    tape.set_reader(&b"ab\ncd\n\nef"[..]);
+    tape.set_output(Box::new(io::BufWriter::new(transcript.clone())));
+    tape.set_prompt("> ".to_string(), Box::new(transcript.clone()));
+    let mut machine = Machine::new(tape, &MachineOptions::default());
+    assert_eq!(execute(&parse(",[.,]").unwrap(), &mut machine), ControlFlow::Continue(()));
+    machine.tape.flush().unwrap();
+    drop(machine);
+    let transcript = transcript.0.take();
+    assert_eq!(String::from_utf8_lossy(&transcript), "> ab\n> cd\n> \n> ef")
+}
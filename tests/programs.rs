@@ -0,0 +1,62 @@
+//! Runs the programs of `tests/programs`, each on the input in the file with the same name and the
+//! `.in` extension, if any, and compares their output with the `.expected` file. New programs are
+//! found automatically.
+//!
+//! Each program may execute at most [`STEPS`] steps, or the number in its `.steps` file, so that a
+//! program that never ends fails instead of hanging.
+
+use std::fs;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+
+use brainfuck_interpreter::names::CellNames;
+use brainfuck_interpreter::{load, optimize, Interpreter, MachineOptions, OptimizeOptions, ParseOptions};
+
+/// The number of steps programs may execute unless their `.steps` file says otherwise.
+const STEPS: u64 = 10_000_000;
+
+/// Returns the paths of the programs to run.
+fn programs() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut programs: Vec<_> = fs::read_dir(directory).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "b"))
+        .collect();
+    programs.sort();
+    programs
+}
+
+/// Runs a program optimized with the passed options, and returns its output.
+fn run(path: &Path, options: &OptimizeOptions) -> Vec<u8> {
+    // Programs may have comments that are not valid UTF-8.
+    let code = fs::read(path).unwrap();
+    let input = fs::read(path.with_extension("in")).unwrap_or_default();
+    let steps = fs::read_to_string(path.with_extension("steps")).map_or(STEPS, |steps| steps.trim().parse().unwrap());
+    let mut interpreter = Interpreter::new(optimize(load(&code, &ParseOptions::default(), &CellNames::default()).unwrap(), options));
+    interpreter.options = MachineOptions { max_steps: Some(steps), ..MachineOptions::default() };
+    let mut output = Vec::new();
+    assert_eq!(interpreter.run(input.as_slice(), &mut output), ControlFlow::Continue(()), "{}", path.display());
+    output
+}
+
+// Every program outputs what is expected, without optimizations and with all of them.
+#[test]
+fn programs_output_what_is_expected() {
+    let unoptimized = OptimizeOptions {
+        optimize_loops: false,
+        propagate_constants: false,
+        remove_unobservable_tail: false,
+        coalesce_moves: false,
+        fuse_commands: false,
+        ..OptimizeOptions::default()
+    };
+    let optimized = OptimizeOptions { optimize_chunk_resets: true, ..OptimizeOptions::default() };
+    let programs = programs();
+    assert!(programs.len() >= 5, "{:?}", programs);
+    for path in programs {
+        let expected = fs::read(path.with_extension("expected")).unwrap();
+        for options in [&unoptimized, &optimized] {
+            assert_eq!(String::from_utf8_lossy(&run(&path, options)), String::from_utf8_lossy(&expected), "{} with {:?}", path.display(), options)
+        }
+    }
+}
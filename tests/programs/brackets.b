@@ -0,0 +1,16 @@
+Brackets nested in every way
+[] [[]] [[[.]]] [.[.[.]].] are all skipped as the first cell is zero
+
++[[[-]]] nested loops on the same cell end together
++[>[-]<-] a loop around a loop on a blank cell
++[>+[>+[>+[>+[-]<-]<-]<-]<-] loops nested four levels deep end on blank cells
++++++[>[-]+++[>+<-]<-] a loop entering and skipping loops five times adds fifteen
+>>[-]>>
+
+++++++++[>++++++++<-]> sixty four
++. outputs A
+[<+<+>>-]<<[>>+<<-]>> copies it to the left
++. outputs B
+<<<+++++++++++++[>+++++<-]>. outputs A again
+[-]>[-]<<[-]>>>[-]<<<
+++++++++++. a new line
@@ -0,0 +1,15 @@
+Prints the size of the cells in bits
+
+++++++++[>++++++++<-]>[<++++>-]
++<[>-<
+    [>++++<-]>[<++++++++>-]<[>++++++++<-]
+    +>[>
+        ++++++++++[>+++++<-]>+.-.[-]<
+    <[-]<->] <[>>
+        +++++++[>+++++++<-]>.+++++.[-]<
+<<-]] >[>
+    ++++++++[>+++++++<-]>.[-]<
+<-]<
++++++++++++[>+++>+++++++++>+++++++++>+<<<<-]>-.>-.+++++++.+++++++++++.<.
+>>.++.+++++++..<-.>>-.
+[[-]<]
@@ -0,0 +1,3 @@
+Prints Hello World with comments that are not valid UTF8
+Latin 1 comments such as caf or guillemets are ignored too
+++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.